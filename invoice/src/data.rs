@@ -106,6 +106,12 @@ impl Allocation {
     pub fn token_index(self) -> TokenIndex { self.0 }
 
     pub fn fraction(self) -> OwnedFraction { self.1 }
+
+    /// Decodes allocation from the contract data state, returning `None` if
+    /// the state doesn't encode an allocation.
+    pub fn from_data_state(state: DataState) -> Option<Allocation> {
+        Allocation::from_strict_serialized(state.into()).ok()
+    }
 }
 
 impl StrictSerialize for Allocation {}
@@ -0,0 +1,449 @@
+// RGB standard library for working with smart contracts on Bitcoin & Lightning
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2019-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2019-2024 LNP/BP Standards Association. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Single-file backup of all stock data which can't be re-computed.
+//!
+//! A backup contains the stash data and the secret seals, but not the
+//! contract state and index, which are rebuilt on restore. Backups may be
+//! incremental: such a backup contains only the data added or updated since
+//! its base backup and must be restored on top of it. Incremental backups
+//! can't record removals, so after some data are removed from the stock the
+//! next backup must be a full one. [`BackupManifest`] summarizes all data
+//! contained in a chain of backups and is used to create the next incremental
+//! backup without access to the previous ones.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use aluvm::library::{Lib, LibId};
+use amplify::confinement::{Confined, LargeOrdMap, MediumBlob, U32};
+use amplify::{ByteArray, Bytes32};
+use armor::{ArmorHeader, AsciiArmor, StrictArmor};
+use baid58::{Baid58ParseError, Chunking, FromBaid58, ToBaid58, CHUNKING_32};
+use commit_verify::{CommitEncode, CommitEngine, CommitId, CommitmentId, DigestExt, Sha256};
+use rgb::{
+    AttachId, BundleId, ContractId, Extension, Genesis, GraphSeal, OpId, Schema, SchemaId,
+    TransitionBundle, XChain, XWitnessId,
+};
+use strict_encoding::{StreamWriter, StrictDeserialize, StrictEncode, StrictSerialize};
+use strict_types::TypeSystem;
+
+use super::{ASCII_ARMOR_CONTRACT_, ASCII_ARMOR_VERSION};
+use crate::containers::{ContainerVer, ContentId, ContentSigs, SealWitness};
+use crate::interface::{ContractSuppl, Iface, IfaceId, IfaceImpl, ImplId, SupplId};
+use crate::persistence::SecretSealInfo;
+use crate::LIB_NAME_RGB_STD;
+
+pub const ASCII_ARMOR_BACKUP_BASE: &str = "Base-Backup";
+
+/// Backup identifier.
+///
+/// Backup identifier commits to the base backup id and all data provided
+/// within the backup.
+#[derive(Wrapper, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, From)]
+#[wrapper(Deref, BorrowSlice, Hex, Index, RangeOps)]
+#[derive(StrictType, StrictDumb, StrictEncode, StrictDecode)]
+#[strict_type(lib = LIB_NAME_RGB_STD)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate", transparent)
+)]
+pub struct BackupId(
+    #[from]
+    #[from([u8; 32])]
+    Bytes32,
+);
+
+impl From<Sha256> for BackupId {
+    fn from(hasher: Sha256) -> Self { hasher.finish().into() }
+}
+
+impl CommitmentId for BackupId {
+    const TAG: &'static str = "urn:lnp-bp:rgb:backup#2024-10-16";
+}
+
+impl ToBaid58<32> for BackupId {
+    const HRI: &'static str = "backup";
+    const CHUNKING: Option<Chunking> = CHUNKING_32;
+    fn to_baid58_payload(&self) -> [u8; 32] { self.to_byte_array() }
+    fn to_baid58_string(&self) -> String { self.to_string() }
+}
+impl FromBaid58<32> for BackupId {}
+impl Display for BackupId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if !f.alternate() {
+            f.write_str("urn:lnp-bp:backup:")?;
+        }
+        if f.sign_minus() {
+            write!(f, "{:.2}", self.to_baid58())
+        } else {
+            write!(f, "{:#.2}", self.to_baid58())
+        }
+    }
+}
+impl FromStr for BackupId {
+    type Err = Baid58ParseError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::from_baid58_maybe_chunked_str(s.trim_start_matches("urn:lnp-bp:"), ':', '#')
+    }
+}
+impl BackupId {
+    pub const fn from_array(id: [u8; 32]) -> Self { BackupId(Bytes32::from_array(id)) }
+    pub fn to_mnemonic(&self) -> String { self.to_baid58().mnemonic() }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum BackupError {
+    /// no backups were provided.
+    Empty,
+
+    /// backup {0} is incremental and can't be used without its base backup.
+    Incremental(BackupId),
+
+    /// backup {backup} is based on {base}, while the previous backup is
+    /// {expected}.
+    BaseMismatch {
+        backup: BackupId,
+        base: BackupId,
+        expected: BackupId,
+    },
+
+    /// backup {0} is a full backup and can't be applied on top of other
+    /// backups.
+    NotIncremental(BackupId),
+}
+
+/// Key of a piece of data stored in a backup.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+#[derive(StrictType, StrictDumb, StrictEncode, StrictDecode)]
+#[strict_type(lib = LIB_NAME_RGB_STD, tags = order, dumb = BackupItem::Types)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+pub enum BackupItem {
+    Types,
+    Lib(LibId),
+    Schema(SchemaId),
+    Iface(IfaceId),
+    IfaceImpl(ImplId),
+    Suppl(SupplId),
+    Genesis(ContractId),
+    Extension(OpId),
+    Bundle(BundleId),
+    Witness(XWitnessId),
+    Attachment(AttachId),
+    Sigs(ContentId),
+    SecretSeal(XChain<GraphSeal>),
+}
+
+/// Computes digest of the backup item data, used to detect data which have
+/// changed since the base backup.
+pub(crate) fn backup_digest(data: &impl StrictEncode) -> Bytes32 {
+    let mut buf = Vec::new();
+    data.strict_write(StreamWriter::new::<U32>(&mut buf))
+        .expect("in-memory writer can't fail");
+    let mut hasher = Sha256::default();
+    hasher.input_raw(&buf);
+    Bytes32::from_byte_array(hasher.finish())
+}
+
+/// Backup of the stock data.
+#[derive(Clone, Default, Debug, Display)]
+#[display(AsciiArmor::to_ascii_armored_string)]
+#[derive(StrictType, StrictEncode, StrictDecode)]
+#[strict_type(lib = LIB_NAME_RGB_STD)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+pub struct Backup {
+    /// Version.
+    pub version: ContainerVer,
+
+    /// Backup which must be restored before this one, if the backup is
+    /// incremental.
+    pub base: Option<BackupId>,
+
+    /// Type system, if it has changed since the base backup.
+    pub types: Option<TypeSystem>,
+    pub libs: LargeOrdMap<LibId, Lib>,
+    pub schemata: LargeOrdMap<SchemaId, Schema>,
+    pub ifaces: LargeOrdMap<IfaceId, Iface>,
+    pub iimpls: LargeOrdMap<ImplId, IfaceImpl>,
+    pub supplements: LargeOrdMap<SupplId, ContractSuppl>,
+
+    pub geneses: LargeOrdMap<ContractId, Genesis>,
+    pub extensions: LargeOrdMap<OpId, Extension>,
+    pub bundles: LargeOrdMap<BundleId, TransitionBundle>,
+    pub witnesses: LargeOrdMap<XWitnessId, SealWitness>,
+    pub attachments: LargeOrdMap<AttachId, MediumBlob>,
+    pub signatures: LargeOrdMap<ContentId, ContentSigs>,
+    pub secret_seals: LargeOrdMap<XChain<GraphSeal>, SecretSealInfo>,
+}
+
+impl StrictSerialize for Backup {}
+impl StrictDeserialize for Backup {}
+
+impl CommitEncode for Backup {
+    type CommitmentId = BackupId;
+
+    fn commit_encode(&self, e: &mut CommitEngine) {
+        e.commit_to_serialized(&self.version);
+        e.commit_to_serialized(&self.base);
+        e.commit_to_map(&LargeOrdMap::from_collection_unsafe(self.digests()));
+    }
+}
+
+impl Backup {
+    #[inline]
+    pub fn backup_id(&self) -> BackupId { self.commit_id() }
+
+    #[inline]
+    pub fn is_incremental(&self) -> bool { self.base.is_some() }
+
+    /// Computes digests of all data items contained in the backup.
+    pub fn digests(&self) -> BTreeMap<BackupItem, Bytes32> {
+        fn add<'a, K: Clone + 'a, V: StrictEncode + 'a>(
+            digests: &mut BTreeMap<BackupItem, Bytes32>,
+            items: impl IntoIterator<Item = (&'a K, &'a V)>,
+            key: impl Fn(K) -> BackupItem,
+        ) {
+            for (id, data) in items {
+                digests.insert(key(id.clone()), backup_digest(data));
+            }
+        }
+
+        let mut digests = BTreeMap::new();
+        if let Some(types) = &self.types {
+            digests.insert(BackupItem::Types, backup_digest(types));
+        }
+        add(&mut digests, &self.libs, BackupItem::Lib);
+        add(&mut digests, &self.schemata, BackupItem::Schema);
+        add(&mut digests, &self.ifaces, BackupItem::Iface);
+        add(&mut digests, &self.iimpls, BackupItem::IfaceImpl);
+        add(&mut digests, &self.supplements, BackupItem::Suppl);
+        add(&mut digests, &self.geneses, BackupItem::Genesis);
+        add(&mut digests, &self.extensions, BackupItem::Extension);
+        add(&mut digests, &self.bundles, BackupItem::Bundle);
+        add(&mut digests, &self.witnesses, BackupItem::Witness);
+        add(&mut digests, &self.attachments, BackupItem::Attachment);
+        add(&mut digests, &self.signatures, BackupItem::Sigs);
+        add(&mut digests, &self.secret_seals, BackupItem::SecretSeal);
+        digests
+    }
+}
+
+impl StrictArmor for Backup {
+    type Id = BackupId;
+    const PLATE_TITLE: &'static str = "RGB BACKUP";
+
+    fn armor_id(&self) -> Self::Id { self.backup_id() }
+    fn armor_headers(&self) -> Vec<ArmorHeader> {
+        let mut headers = vec![ArmorHeader::new(ASCII_ARMOR_VERSION, self.version.to_string())];
+        if let Some(base) = self.base {
+            headers.push(ArmorHeader::new(ASCII_ARMOR_BACKUP_BASE, base.to_string()));
+        }
+        for contract_id in self.geneses.keys() {
+            headers.push(ArmorHeader::new(ASCII_ARMOR_CONTRACT_, contract_id.to_string()));
+        }
+        headers
+    }
+}
+
+/// Summary of the data contained in a chain of backups, starting with a full
+/// backup and followed by incremental backups.
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[derive(StrictType, StrictDumb, StrictEncode, StrictDecode)]
+#[strict_type(lib = LIB_NAME_RGB_STD)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+pub struct BackupManifest {
+    /// Id of the last backup in the chain.
+    pub backup_id: BackupId,
+    pub digests: LargeOrdMap<BackupItem, Bytes32>,
+}
+
+impl StrictSerialize for BackupManifest {}
+impl StrictDeserialize for BackupManifest {}
+
+impl BackupManifest {
+    /// Constructs manifest for a full backup.
+    pub fn new(backup: &Backup) -> Result<Self, BackupError> {
+        let backup_id = backup.backup_id();
+        if backup.is_incremental() {
+            return Err(BackupError::Incremental(backup_id));
+        }
+        Ok(BackupManifest {
+            backup_id,
+            digests: Confined::from_collection_unsafe(backup.digests()),
+        })
+    }
+
+    /// Constructs manifest for a chain of backups, checking that each of the
+    /// backups is based on the previous one.
+    pub fn with<'a>(backups: impl IntoIterator<Item = &'a Backup>) -> Result<Self, BackupError> {
+        let mut backups = backups.into_iter();
+        let mut manifest = BackupManifest::new(backups.next().ok_or(BackupError::Empty)?)?;
+        for backup in backups {
+            manifest.apply(backup)?;
+        }
+        Ok(manifest)
+    }
+
+    /// Updates manifest with the data from an incremental backup made on top
+    /// of the last backup covered by the manifest.
+    pub fn apply(&mut self, backup: &Backup) -> Result<(), BackupError> {
+        let backup_id = backup.backup_id();
+        let Some(base) = backup.base else {
+            return Err(BackupError::NotIncremental(backup_id));
+        };
+        if base != self.backup_id {
+            return Err(BackupError::BaseMismatch {
+                backup: backup_id,
+                base,
+                expected: self.backup_id,
+            });
+        }
+        let mut digests = self.digests.clone().into_inner();
+        digests.extend(backup.digests());
+        self.digests = Confined::from_collection_unsafe(digests);
+        self.backup_id = backup_id;
+        Ok(())
+    }
+
+    /// Detects whether the data item is already present in the backup chain
+    /// with the same content.
+    pub fn contains(&self, item: &BackupItem, digest: Bytes32) -> bool {
+        self.digests.get(item) == Some(&digest)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bp::seals::txout::CloseMethod;
+    use bp::Txid;
+
+    use super::*;
+    use crate::interface::resolver::DumbResolver;
+    use crate::persistence::{
+        MemIndex, MemStash, MemState, StashDataError, StashReadProvider, Stock, StockError,
+    };
+
+    type MemStock = Stock<MemStash, MemState, MemIndex>;
+
+    fn seal(blinding: u64) -> XChain<GraphSeal> {
+        XChain::Bitcoin(GraphSeal::with_blinding(
+            CloseMethod::TapretFirst,
+            Txid::coinbase(),
+            1u32,
+            blinding,
+        ))
+    }
+
+    fn secret_seals(stock: &MemStock) -> Vec<XChain<GraphSeal>> {
+        stock.as_stash_provider().secret_seals().unwrap().collect()
+    }
+
+    #[test]
+    fn incremental_restore() {
+        let mut stock = MemStock::default();
+        stock.store_secret_seal(seal(1)).unwrap();
+        let full = stock.backup().unwrap();
+        assert!(!full.is_incremental());
+        let mut manifest = BackupManifest::new(&full).unwrap();
+
+        stock.store_secret_seal(seal(2)).unwrap();
+        let incremental = stock.backup_since(&manifest).unwrap();
+        assert_eq!(incremental.base, Some(full.backup_id()));
+        assert!(incremental.types.is_none());
+        assert_eq!(incremental.secret_seals.keys().collect::<Vec<_>>(), vec![&seal(2)]);
+        manifest.apply(&incremental).unwrap();
+
+        let mut restored = MemStock::default();
+        assert!(matches!(
+            restored.restore([incremental.clone()], &mut DumbResolver),
+            Err(StockError::InvalidInput(BackupError::Incremental(_)))
+        ));
+        assert!(secret_seals(&restored).is_empty());
+
+        let chain = restored
+            .restore([full, incremental], &mut DumbResolver)
+            .unwrap();
+        assert_eq!(chain, manifest);
+        assert_eq!(secret_seals(&restored), secret_seals(&stock));
+        let next = restored.backup_since(&chain).unwrap();
+        assert!(next.secret_seals.is_empty());
+    }
+
+    #[test]
+    fn backup_chain() {
+        let mut stock = MemStock::default();
+        let full = stock.backup().unwrap();
+        let manifest = BackupManifest::new(&full).unwrap();
+        stock.store_secret_seal(seal(1)).unwrap();
+        let first = stock.backup_since(&manifest).unwrap();
+        let other = stock.backup().unwrap();
+
+        assert_eq!(BackupManifest::with(Vec::<&Backup>::new()), Err(BackupError::Empty));
+        assert_eq!(
+            BackupManifest::with([&full, &other]),
+            Err(BackupError::NotIncremental(other.backup_id()))
+        );
+        let mut manifest = BackupManifest::with([&full, &first]).unwrap();
+        assert_eq!(
+            manifest.apply(&first),
+            Err(BackupError::BaseMismatch {
+                backup: first.backup_id(),
+                base: full.backup_id(),
+                expected: first.backup_id(),
+            })
+        );
+    }
+
+    #[test]
+    fn removal_since_base() {
+        let mut stock = MemStock::default();
+        stock
+            .store_secret_seal_with(seal(1), SecretSealInfo::with(0, Some(1), None))
+            .unwrap();
+        stock.store_secret_seal(seal(2)).unwrap();
+        let manifest = BackupManifest::new(&stock.backup().unwrap()).unwrap();
+        assert!(stock.backup_since(&manifest).is_ok());
+
+        assert_eq!(stock.prune_secret_seals().unwrap(), bset![seal(1)]);
+        assert!(matches!(
+            stock.backup_since(&manifest),
+            Err(StockError::StashData(StashDataError::BackupRemoval))
+        ));
+        let full = stock.backup().unwrap();
+        assert_eq!(full.secret_seals.keys().collect::<Vec<_>>(), vec![&seal(2)]);
+    }
+}
@@ -26,10 +26,10 @@ use amplify::confinement::U32 as FILE_MAX_LEN;
 use armor::{AsciiArmor, StrictArmor};
 use strict_encoding::{StreamReader, StreamWriter, StrictDecode, StrictEncode};
 
-use crate::containers::{Contract, Kit, Transfer};
+use crate::containers::{Backup, Contract, Kit, Transfer};
 
-const RGB_PREFIX: [u8; 4] = *b"RGB\x00";
-const MAGIC_LEN: usize = 3;
+pub(crate) const RGB_PREFIX: [u8; 4] = *b"RGB\x00";
+pub(crate) const MAGIC_LEN: usize = 3;
 
 #[derive(Clone, Eq, PartialEq, Debug, Display, Error, From)]
 #[display(doc_comments)]
@@ -113,6 +113,10 @@ impl FileContent for Transfer {
     const MAGIC: [u8; MAGIC_LEN] = *b"TFR";
 }
 
+impl FileContent for Backup {
+    const MAGIC: [u8; MAGIC_LEN] = *b"BAK";
+}
+
 // TODO: Add disclosure
 // TODO: Add batch and fascia
 
@@ -131,6 +135,9 @@ pub enum UniversalFile {
 
     #[from]
     Transfer(Transfer),
+
+    #[from]
+    Backup(Backup),
     // TODO: Add disclosure
     // TODO: Add batch and fascia
 }
@@ -149,6 +156,7 @@ impl UniversalFile {
             x if x == Kit::MAGIC => Kit::strict_read(&mut reader)?.into(),
             x if x == Contract::MAGIC => Contract::strict_read(&mut reader)?.into(),
             x if x == Transfer::MAGIC => Transfer::strict_read(&mut reader)?.into(),
+            x if x == Backup::MAGIC => Backup::strict_read(&mut reader)?.into(),
             _ => return Err(LoadError::InvalidMagic),
         })
     }
@@ -159,6 +167,7 @@ impl UniversalFile {
             UniversalFile::Kit(_) => Kit::MAGIC,
             UniversalFile::Contract(_) => Contract::MAGIC,
             UniversalFile::Transfer(_) => Transfer::MAGIC,
+            UniversalFile::Backup(_) => Backup::MAGIC,
         };
         writer.write_all(&magic)?;
 
@@ -168,6 +177,7 @@ impl UniversalFile {
             UniversalFile::Kit(content) => content.strict_write(writer),
             UniversalFile::Contract(content) => content.strict_write(writer),
             UniversalFile::Transfer(content) => content.strict_write(writer),
+            UniversalFile::Backup(content) => content.strict_write(writer),
         }
     }
 
@@ -190,6 +200,7 @@ impl Display for UniversalFile {
             UniversalFile::Kit(content) => Display::fmt(&content.display_ascii_armored(), f),
             UniversalFile::Contract(content) => Display::fmt(&content.display_ascii_armored(), f),
             UniversalFile::Transfer(content) => Display::fmt(&content.display_ascii_armored(), f),
+            UniversalFile::Backup(content) => Display::fmt(&content.display_ascii_armored(), f),
         }
     }
 }
@@ -37,15 +37,21 @@ mod partials;
 mod indexed;
 mod file;
 mod kit;
+mod backup;
 
 pub use anchors::{
     AnchorSet, AnchoredBundles, BundledWitness, PubWitness, SealWitness, ToWitnessId, XPubWitness,
 };
+pub(crate) use backup::backup_digest;
+pub use backup::{
+    Backup, BackupError, BackupId, BackupItem, BackupManifest, ASCII_ARMOR_BACKUP_BASE,
+};
 pub use consignment::{
     Consignment, ConsignmentId, Contract, Transfer, ValidConsignment, ValidContract, ValidTransfer,
 };
 pub use disclosure::Disclosure;
 pub use file::{FileContent, LoadError, UniversalFile};
+pub(crate) use file::{MAGIC_LEN, RGB_PREFIX};
 pub use indexed::IndexedConsignment;
 pub use kit::{Kit, KitId, ValidKit};
 pub use partials::{
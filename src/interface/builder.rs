@@ -32,7 +32,7 @@ use rgb::{
     validation, AltLayer1, AltLayer1Set, AssetTag, AssetTags, Assign, AssignmentType, Assignments,
     BlindingFactor, ContractId, DataState, ExposedSeal, FungibleType, Genesis, GenesisSeal,
     GlobalState, GraphSeal, Input, Layer1, Opout, OwnedStateSchema, RevealedAttach, RevealedData,
-    RevealedValue, Schema, Transition, TransitionType, TypedAssigns, XChain, XOutpoint,
+    RevealedValue, Schema, Transition, TransitionType, TypedAssigns, VoidState, XChain, XOutpoint,
 };
 use strict_encoding::{FieldName, SerializeError, StrictSerialize};
 use strict_types::{decode, TypeSystem};
@@ -558,6 +558,29 @@ impl TransitionBuilder {
         Ok(self)
     }
 
+    pub fn add_rights_raw(
+        mut self,
+        type_id: AssignmentType,
+        seal: impl Into<BuilderSeal<GraphSeal>>,
+    ) -> Result<Self, BuilderError> {
+        self.builder = self.builder.add_rights_raw(type_id, seal)?;
+        Ok(self)
+    }
+
+    pub fn add_attachment_raw(
+        mut self,
+        type_id: AssignmentType,
+        seal: impl Into<BuilderSeal<GraphSeal>>,
+        attachment: AttachedState,
+        salt: u64,
+    ) -> Result<Self, BuilderError> {
+        let revealed_state = RevealedAttach::with_salt(attachment.id, attachment.media_type, salt);
+        self.builder = self
+            .builder
+            .add_attachment_raw(type_id, seal, revealed_state)?;
+        Ok(self)
+    }
+
     pub fn add_data_default(
         self,
         seal: impl Into<BuilderSeal<GraphSeal>>,
@@ -784,7 +807,7 @@ impl<Seal: ExposedSeal> OperationBuilder<Seal> {
         seal: impl Into<BuilderSeal<Seal>>,
     ) -> Result<Self, BuilderError> {
         let state_schema = self.state_schema(type_id);
-        if *state_schema != OwnedStateSchema::Fungible(FungibleType::Unsigned64Bit) {
+        if *state_schema != OwnedStateSchema::Declarative {
             return Err(BuilderError::InvalidState(type_id));
         }
 
@@ -904,7 +927,7 @@ impl<Seal: ExposedSeal> OperationBuilder<Seal> {
         state: RevealedAttach,
     ) -> Result<Self, BuilderError> {
         let state_schema = self.state_schema(type_id);
-        if let OwnedStateSchema::Structured(_) = *state_schema {
+        if let OwnedStateSchema::Attachment(_) = *state_schema {
             let seal = seal.into();
             match self.attachments.get_mut(&type_id) {
                 Some(assignments) => {
@@ -1009,13 +1032,56 @@ impl<Seal: ExposedSeal> OperationBuilder<Seal> {
             (id, state_data)
         });
 
+        let owned_rights = self.rights.into_iter().map(|(id, set)| {
+            let vec_rights = set.into_iter().map(|seal| match seal {
+                BuilderSeal::Revealed(seal) => Assign::Revealed {
+                    seal,
+                    state: VoidState::default(),
+                    lock: none!(),
+                },
+                BuilderSeal::Concealed(seal) => Assign::ConfidentialSeal {
+                    seal,
+                    state: VoidState::default(),
+                    lock: none!(),
+                },
+            });
+            let state_rights = Confined::try_from_iter(vec_rights).expect("at least one element");
+            let state_rights = TypedAssigns::Declarative(state_rights);
+            (id, state_rights)
+        });
+        let owned_attachments = self.attachments.into_iter().map(|(id, vec)| {
+            let vec_attach = vec.into_iter().map(|(seal, value)| match seal {
+                BuilderSeal::Revealed(seal) => Assign::Revealed {
+                    seal,
+                    state: value,
+                    lock: none!(),
+                },
+                BuilderSeal::Concealed(seal) => Assign::ConfidentialSeal {
+                    seal,
+                    state: value,
+                    lock: none!(),
+                },
+            });
+            let state_attach = Confined::try_from_iter(vec_attach).expect("at least one element");
+            let state_attach = TypedAssigns::Attachment(state_attach);
+            (id, state_attach)
+        });
+
         let owned_state = Confined::try_from_iter(owned_state).expect("same size");
         let owned_data = Confined::try_from_iter(owned_data).expect("same size");
+        let owned_rights = Confined::try_from_iter(owned_rights).expect("same size");
+        let owned_attachments = Confined::try_from_iter(owned_attachments).expect("same size");
 
         let mut assignments = Assignments::from_inner(owned_state);
         assignments
             .extend(Assignments::from_inner(owned_data).into_inner())
             .expect("");
+        assignments
+            .extend(Assignments::from_inner(owned_rights).into_inner())
+            .expect("");
+        assignments
+            .extend(Assignments::from_inner(owned_attachments).into_inner())
+            .expect("");
 
         (self.schema, self.iface, self.iimpl, self.global, assignments, self.types, self.asset_tags)
     }
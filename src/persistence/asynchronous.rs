@@ -0,0 +1,1884 @@
+// RGB standard library for working with smart contracts on Bitcoin & Lightning
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2019-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2019-2024 LNP/BP Standards Association. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Asynchronous counterparts of the persistence provider traits and the stock
+//! facade working over them.
+//!
+//! Asynchronous providers return owned data, so they may be backed by remote
+//! storage or by a database accessed with an asynchronous driver.
+//! [`AsyncStock`] performs each operation with an in-memory working copy of
+//! the stock, which contains only the data of the contracts involved into the
+//! operation: the data are loaded from the providers before the operation, and
+//! the changes made by it are written back to the providers in a single
+//! transaction afterwards.
+//!
+//! The facade doesn't lock the data between loading and writing them back, so
+//! the caller must not run concurrent operations updating the same contracts.
+
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::Infallible;
+use std::error::Error;
+use std::fmt::Debug;
+use std::future::{self, Future};
+
+use aluvm::library::{Lib, LibId};
+use amplify::confinement::{self, Confined, MediumBlob};
+use bp::seals::txout::CloseMethod;
+use bp::Vout;
+use invoice::RgbInvoice;
+use rgb::{
+    validation, Assign, AssignmentType, AttachId, BundleId, ContractHistory, ContractId,
+    ExposedState, Extension, Genesis, GenesisSeal, GraphSeal, OpId, Opout, Schema, SchemaId,
+    SecretSeal, TransitionBundle, XChain, XOutputSeal, XWitnessId,
+};
+use strict_encoding::TypeName;
+use strict_types::{SemId, TypeSystem};
+
+use super::{
+    ComposeError, ConsignError, FasciaError, IndexInconsistency, IndexProvider, IndexReadError,
+    IndexReadProvider, IndexWriteError, IndexWriteProvider, MemIndex, MemStash, MemState,
+    SchemaIfaces, SecretSealInfo, Spender, StashInconsistency, StashProviderError,
+    StashReadProvider, StashWriteProvider, StateReadProvider, StateWriteProvider, Stock,
+    StockError,
+};
+use crate::containers::{
+    Backup, BackupManifest, Batch, ContainerVer, ContentId, ContentSigs, Contract, Fascia,
+    SealWitness, Transfer, ValidContract, ValidTransfer,
+};
+use crate::interface::{ContractSuppl, Iface, IfaceId, IfaceImpl, IfaceRef, VelocityHint};
+use crate::resolvers::ResolveHeight;
+
+type WorkingStock = Stock<MemStash, MemState, RecordingIndex>;
+
+#[derive(Clone, PartialEq, Eq, Debug, Display, Error, From)]
+#[display(inner)]
+pub enum AsyncStockError<
+    S: AsyncStashProvider,
+    H: AsyncStateProvider,
+    P: AsyncIndexProvider,
+    E: Error = Infallible,
+> {
+    StashRead(<S as AsyncStashReadProvider>::Error),
+    StashWrite(<S as AsyncStashWriteProvider>::Error),
+    IndexRead(<P as AsyncIndexReadProvider>::Error),
+    IndexWrite(<P as AsyncIndexWriteProvider>::Error),
+    StateRead(<H as AsyncStateReadProvider>::Error),
+    StateWrite(<H as AsyncStateWriteProvider>::Error),
+
+    #[from]
+    #[display(doc_comments)]
+    /// {0}
+    ///
+    /// It may happen due to RGB standard library bug, or indicate internal
+    /// stash inconsistency and compromised stash data storage.
+    StashInconsistency(StashInconsistency),
+
+    #[from]
+    #[display(doc_comments)]
+    /// {0}
+    ///
+    /// It may happen due to RGB standard library bug, or indicate internal
+    /// stash inconsistency and compromised index storage.
+    IndexInconsistency(IndexInconsistency),
+
+    /// Error of the operation performed with the in-memory working copy of the
+    /// stock.
+    #[from]
+    Local(StockError<MemStash, MemState, RecordingIndex, E>),
+
+    #[display(doc_comments)]
+    /// data of the contracts exceed size limits of the working copy of the
+    /// stock: {0}
+    WorkingCopySize(confinement::Error),
+}
+
+impl<S: AsyncStashProvider, H: AsyncStateProvider, P: AsyncIndexProvider, E: Error>
+    From<StashProviderError<<S as AsyncStashReadProvider>::Error>> for AsyncStockError<S, H, P, E>
+{
+    fn from(err: StashProviderError<<S as AsyncStashReadProvider>::Error>) -> Self {
+        match err {
+            StashProviderError::Inconsistency(e) => Self::StashInconsistency(e),
+            StashProviderError::Connectivity(e) => Self::StashRead(e),
+        }
+    }
+}
+
+impl<S: AsyncStashProvider, H: AsyncStateProvider, P: AsyncIndexProvider, E: Error>
+    From<IndexReadError<<P as AsyncIndexReadProvider>::Error>> for AsyncStockError<S, H, P, E>
+{
+    fn from(err: IndexReadError<<P as AsyncIndexReadProvider>::Error>) -> Self {
+        match err {
+            IndexReadError::Inconsistency(e) => Self::IndexInconsistency(e),
+            IndexReadError::Connectivity(e) => Self::IndexRead(e),
+        }
+    }
+}
+
+impl<S: AsyncStashProvider, H: AsyncStateProvider, P: AsyncIndexProvider, E: Error>
+    From<IndexWriteError<<P as AsyncIndexWriteProvider>::Error>> for AsyncStockError<S, H, P, E>
+{
+    fn from(err: IndexWriteError<<P as AsyncIndexWriteProvider>::Error>) -> Self {
+        match err {
+            IndexWriteError::Inconsistency(e) => Self::IndexInconsistency(e),
+            IndexWriteError::Connectivity(e) => Self::IndexWrite(e),
+        }
+    }
+}
+
+//////////
+// STASH
+//////////
+
+pub trait AsyncStashProvider: Debug + AsyncStashReadProvider + AsyncStashWriteProvider {}
+
+/// Asynchronous counterpart of [`StashReadProvider`] returning owned data.
+pub trait AsyncStashReadProvider {
+    /// Error type which must indicate problems on data retrieval.
+    type Error: Clone + Eq + Error;
+
+    /// Returns types with the given semantic ids together with all the types
+    /// they depend on.
+    fn types(
+        &self,
+        ids: BTreeSet<SemId>,
+    ) -> impl Future<Output = Result<TypeSystem, StashProviderError<Self::Error>>> + Send;
+    fn lib_ids(&self) -> impl Future<Output = Result<BTreeSet<LibId>, Self::Error>> + Send;
+    fn lib(
+        &self,
+        id: LibId,
+    ) -> impl Future<Output = Result<Lib, StashProviderError<Self::Error>>> + Send;
+
+    fn ifaces(
+        &self,
+    ) -> impl Future<Output = Result<BTreeMap<IfaceId, TypeName>, Self::Error>> + Send;
+    fn iface(
+        &self,
+        iface: IfaceRef,
+    ) -> impl Future<Output = Result<Iface, StashProviderError<Self::Error>>> + Send;
+    fn schema_ids(&self) -> impl Future<Output = Result<BTreeSet<SchemaId>, Self::Error>> + Send;
+    fn schema(
+        &self,
+        schema_id: SchemaId,
+    ) -> impl Future<Output = Result<SchemaIfaces, StashProviderError<Self::Error>>> + Send;
+
+    fn contract_ids(
+        &self,
+    ) -> impl Future<Output = Result<BTreeSet<ContractId>, Self::Error>> + Send;
+    fn contract_supplements(
+        &self,
+        contract_id: ContractId,
+    ) -> impl Future<Output = Result<Vec<ContractSuppl>, Self::Error>> + Send;
+    fn genesis(
+        &self,
+        contract_id: ContractId,
+    ) -> impl Future<Output = Result<Genesis, StashProviderError<Self::Error>>> + Send;
+    fn witness_ids(&self)
+        -> impl Future<Output = Result<BTreeSet<XWitnessId>, Self::Error>> + Send;
+    fn bundle_ids(&self) -> impl Future<Output = Result<BTreeSet<BundleId>, Self::Error>> + Send;
+    fn bundle(
+        &self,
+        bundle_id: BundleId,
+    ) -> impl Future<Output = Result<TransitionBundle, StashProviderError<Self::Error>>> + Send;
+    fn extension_ids(&self) -> impl Future<Output = Result<BTreeSet<OpId>, Self::Error>> + Send;
+    /// Returns all state extensions of the contract.
+    fn contract_extensions(
+        &self,
+        contract_id: ContractId,
+    ) -> impl Future<Output = Result<BTreeMap<OpId, Extension>, Self::Error>> + Send;
+    fn extension(
+        &self,
+        op_id: OpId,
+    ) -> impl Future<Output = Result<Extension, StashProviderError<Self::Error>>> + Send;
+    fn witness(
+        &self,
+        witness_id: XWitnessId,
+    ) -> impl Future<Output = Result<SealWitness, StashProviderError<Self::Error>>> + Send;
+    fn attachment_ids(
+        &self,
+    ) -> impl Future<Output = Result<BTreeSet<AttachId>, Self::Error>> + Send;
+    fn attachment(
+        &self,
+        id: AttachId,
+    ) -> impl Future<Output = Result<MediumBlob, StashProviderError<Self::Error>>> + Send;
+    fn sigs(
+        &self,
+    ) -> impl Future<Output = Result<BTreeMap<ContentId, ContentSigs>, Self::Error>> + Send;
+
+    fn seal_secret(
+        &self,
+        secret: XChain<SecretSeal>,
+    ) -> impl Future<Output = Result<Option<XChain<GraphSeal>>, Self::Error>> + Send;
+    fn secret_seal_records(
+        &self,
+    ) -> impl Future<Output = Result<BTreeMap<XChain<GraphSeal>, SecretSealInfo>, Self::Error>> + Send;
+}
+
+/// Asynchronous counterpart of [`StashWriteProvider`].
+pub trait AsyncStashWriteProvider {
+    type Error: Clone + Eq + Error;
+
+    /// Starts a transaction: all following writes must be either prepared
+    /// with [`Self::prepare_transaction`] and then committed with
+    /// [`Self::commit_transaction`], or reverted with
+    /// [`Self::rollback_transaction`].
+    fn begin_transaction(&mut self) -> impl Future<Output = Result<(), Self::Error>> + Send;
+    /// Performs all the work required to commit the transaction which may
+    /// fail. A prepared transaction can still be reverted.
+    fn prepare_transaction(&mut self) -> impl Future<Output = Result<(), Self::Error>> + Send;
+    /// Completes a prepared transaction. Committed transactions are never
+    /// reverted.
+    fn commit_transaction(&mut self) -> impl Future<Output = Result<(), Self::Error>> + Send;
+    /// Reverts all writes made since the transaction was started, restoring
+    /// the data to exactly the same state as they were before.
+    fn rollback_transaction(&mut self) -> impl Future<Output = Result<(), Self::Error>> + Send;
+
+    fn replace_schema(
+        &mut self,
+        schema: Schema,
+    ) -> impl Future<Output = Result<bool, Self::Error>> + Send;
+    fn replace_iface(
+        &mut self,
+        iface: Iface,
+    ) -> impl Future<Output = Result<bool, Self::Error>> + Send;
+    fn replace_iimpl(
+        &mut self,
+        iimpl: IfaceImpl,
+    ) -> impl Future<Output = Result<bool, Self::Error>> + Send;
+    fn replace_genesis(
+        &mut self,
+        genesis: Genesis,
+    ) -> impl Future<Output = Result<bool, Self::Error>> + Send;
+    fn replace_extension(
+        &mut self,
+        extension: Extension,
+    ) -> impl Future<Output = Result<bool, Self::Error>> + Send;
+    fn replace_bundle(
+        &mut self,
+        bundle: TransitionBundle,
+    ) -> impl Future<Output = Result<bool, Self::Error>> + Send;
+    fn replace_witness(
+        &mut self,
+        witness: SealWitness,
+    ) -> impl Future<Output = Result<bool, Self::Error>> + Send;
+    fn replace_attachment(
+        &mut self,
+        id: AttachId,
+        attach: MediumBlob,
+    ) -> impl Future<Output = Result<bool, Self::Error>> + Send;
+    fn replace_lib(&mut self, lib: Lib) -> impl Future<Output = Result<bool, Self::Error>> + Send;
+    fn consume_types(
+        &mut self,
+        types: TypeSystem,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send;
+    fn add_suppl(
+        &mut self,
+        suppl: ContractSuppl,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send;
+    fn import_sigs(
+        &mut self,
+        content_id: ContentId,
+        sigs: ContentSigs,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send;
+
+    /// Adds secret seal together with information about it. Returns `false`
+    /// and keeps the existing information if the seal is already known.
+    fn add_secret_seal_with(
+        &mut self,
+        seal: XChain<GraphSeal>,
+        info: SecretSealInfo,
+    ) -> impl Future<Output = Result<bool, Self::Error>> + Send;
+    fn remove_secret_seal(
+        &mut self,
+        seal: XChain<GraphSeal>,
+    ) -> impl Future<Output = Result<bool, Self::Error>> + Send;
+}
+
+//////////
+// STATE
+//////////
+
+pub trait AsyncStateProvider: Debug + AsyncStateReadProvider + AsyncStateWriteProvider {}
+
+/// Asynchronous counterpart of [`StateReadProvider`] returning owned data.
+pub trait AsyncStateReadProvider {
+    type Error: Clone + Eq + Error;
+
+    fn contract_state(
+        &self,
+        contract_id: ContractId,
+    ) -> impl Future<Output = Result<Option<ContractHistory>, Self::Error>> + Send;
+}
+
+/// Asynchronous counterpart of [`StateWriteProvider`].
+///
+/// Unlike the synchronous provider, contract state is computed by the caller
+/// and replaced as a whole.
+pub trait AsyncStateWriteProvider {
+    type Error: Clone + Eq + Error;
+
+    /// Starts a transaction: all following writes must be either prepared
+    /// with [`Self::prepare_transaction`] and then committed with
+    /// [`Self::commit_transaction`], or reverted with
+    /// [`Self::rollback_transaction`].
+    fn begin_transaction(&mut self) -> impl Future<Output = Result<(), Self::Error>> + Send;
+    /// Performs all the work required to commit the transaction which may
+    /// fail. A prepared transaction can still be reverted.
+    fn prepare_transaction(&mut self) -> impl Future<Output = Result<(), Self::Error>> + Send;
+    /// Completes a prepared transaction. Committed transactions are never
+    /// reverted.
+    fn commit_transaction(&mut self) -> impl Future<Output = Result<(), Self::Error>> + Send;
+    /// Reverts all writes made since the transaction was started, restoring
+    /// the data to exactly the same state as they were before.
+    fn rollback_transaction(&mut self) -> impl Future<Output = Result<(), Self::Error>> + Send;
+
+    /// Replaces state of a single contract, returning whether the contract
+    /// had no state before.
+    fn replace_state(
+        &mut self,
+        contract_id: ContractId,
+        history: ContractHistory,
+    ) -> impl Future<Output = Result<bool, Self::Error>> + Send;
+
+    /// Removes state of a single contract.
+    fn remove_state(
+        &mut self,
+        contract_id: ContractId,
+    ) -> impl Future<Output = Result<bool, Self::Error>> + Send;
+
+    /// Removes state of all contracts.
+    fn clear_state(&mut self) -> impl Future<Output = Result<(), Self::Error>> + Send;
+}
+
+//////////
+// INDEX
+//////////
+
+pub trait AsyncIndexProvider: Debug + AsyncIndexReadProvider + AsyncIndexWriteProvider {}
+
+/// Asynchronous counterpart of [`IndexReadProvider`] returning owned data.
+pub trait AsyncIndexReadProvider {
+    type Error: Clone + Eq + Error;
+
+    fn contracts_assigning(
+        &self,
+        outputs: BTreeSet<XOutputSeal>,
+    ) -> impl Future<Output = Result<BTreeSet<ContractId>, Self::Error>> + Send;
+
+    fn public_opouts(
+        &self,
+        contract_id: ContractId,
+    ) -> impl Future<Output = Result<BTreeSet<Opout>, IndexReadError<Self::Error>>> + Send;
+
+    fn opouts_by_outputs(
+        &self,
+        contract_id: ContractId,
+        outputs: BTreeSet<XOutputSeal>,
+    ) -> impl Future<Output = Result<BTreeSet<Opout>, IndexReadError<Self::Error>>> + Send;
+
+    fn opouts_by_terminals(
+        &self,
+        terminals: BTreeSet<XChain<SecretSeal>>,
+    ) -> impl Future<Output = Result<BTreeSet<Opout>, Self::Error>> + Send;
+
+    fn bundle_id_for_op(
+        &self,
+        opid: OpId,
+    ) -> impl Future<Output = Result<BundleId, IndexReadError<Self::Error>>> + Send;
+
+    fn bundle_info(
+        &self,
+        bundle_id: BundleId,
+    ) -> impl Future<Output = Result<(XWitnessId, ContractId), IndexReadError<Self::Error>>> + Send;
+
+    /// Returns all bundles of the contract together with their witnesses.
+    fn contract_bundles(
+        &self,
+        contract_id: ContractId,
+    ) -> impl Future<Output = Result<BTreeMap<BundleId, XWitnessId>, IndexReadError<Self::Error>>> + Send;
+
+    /// Returns all known operations spending the output. More than a single
+    /// spender means that the output was double-spent by conflicting witness
+    /// transactions.
+    ///
+    /// Fails with [`IndexInconsistency::SpendersNotIndexed`] if the spenders
+    /// were not indexed.
+    fn spenders(
+        &self,
+        opout: Opout,
+    ) -> impl Future<Output = Result<BTreeSet<Spender>, IndexReadError<Self::Error>>> + Send;
+
+    /// Lists all bundles for which the index holds witness or contract
+    /// information.
+    fn bundle_ids(&self) -> impl Future<Output = Result<BTreeSet<BundleId>, Self::Error>> + Send;
+}
+
+/// Asynchronous counterpart of [`IndexWriteProvider`].
+///
+/// Unlike the synchronous provider, assignments are indexed one by one, with
+/// seals already resolved into outputs or concealed terminal seals (see
+/// [`IndexRecord`]).
+pub trait AsyncIndexWriteProvider {
+    type Error: Clone + Eq + Error;
+
+    /// Starts a transaction: all following writes must be either prepared
+    /// with [`Self::prepare_transaction`] and then committed with
+    /// [`Self::commit_transaction`], or reverted with
+    /// [`Self::rollback_transaction`].
+    fn begin_transaction(&mut self) -> impl Future<Output = Result<(), Self::Error>> + Send;
+    /// Performs all the work required to commit the transaction which may
+    /// fail. A prepared transaction can still be reverted.
+    fn prepare_transaction(&mut self) -> impl Future<Output = Result<(), Self::Error>> + Send;
+    /// Completes a prepared transaction. Committed transactions are never
+    /// reverted.
+    fn commit_transaction(&mut self) -> impl Future<Output = Result<(), Self::Error>> + Send;
+    /// Reverts all writes made since the transaction was started, restoring
+    /// the data to exactly the same state as they were before.
+    fn rollback_transaction(&mut self) -> impl Future<Output = Result<(), Self::Error>> + Send;
+
+    fn register_contract(
+        &mut self,
+        contract_id: ContractId,
+    ) -> impl Future<Output = Result<bool, Self::Error>> + Send;
+
+    fn register_bundle(
+        &mut self,
+        bundle_id: BundleId,
+        witness_id: XWitnessId,
+        contract_id: ContractId,
+    ) -> impl Future<Output = Result<bool, IndexWriteError<Self::Error>>> + Send;
+
+    fn register_operation(
+        &mut self,
+        opid: OpId,
+        bundle_id: BundleId,
+    ) -> impl Future<Output = Result<bool, IndexWriteError<Self::Error>>> + Send;
+
+    fn register_spender(
+        &mut self,
+        opout: Opout,
+        spender: Spender,
+    ) -> impl Future<Output = Result<bool, IndexWriteError<Self::Error>>> + Send;
+
+    /// Indexes operation output assigned to a seal with a known output.
+    fn index_output(
+        &mut self,
+        contract_id: ContractId,
+        output: XOutputSeal,
+        opout: Opout,
+    ) -> impl Future<Output = Result<(), IndexWriteError<Self::Error>>> + Send;
+
+    /// Indexes operation output assigned to a concealed seal.
+    fn index_terminal(
+        &mut self,
+        seal: XChain<SecretSeal>,
+        opout: Opout,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send;
+
+    /// Removes all index data related to a contract. The `opids` must list all
+    /// known operations of the contract, including its genesis.
+    fn remove_contract(
+        &mut self,
+        contract_id: ContractId,
+        opids: BTreeSet<OpId>,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send;
+
+    /// Removes all index data.
+    fn clear_index(&mut self) -> impl Future<Output = Result<(), Self::Error>> + Send;
+}
+
+/// Change made to the index, as recorded by [`RecordingIndex`].
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum IndexRecord {
+    Contract(ContractId),
+    Bundle {
+        bundle_id: BundleId,
+        witness_id: XWitnessId,
+        contract_id: ContractId,
+    },
+    Operation {
+        opid: OpId,
+        bundle_id: BundleId,
+    },
+    Spender {
+        opout: Opout,
+        spender: Spender,
+    },
+    Output {
+        contract_id: ContractId,
+        output: XOutputSeal,
+        opout: Opout,
+    },
+    Terminal {
+        seal: XChain<SecretSeal>,
+        opout: Opout,
+    },
+    ContractRemoved {
+        contract_id: ContractId,
+        opids: BTreeSet<OpId>,
+    },
+    Cleared,
+}
+
+/// In-memory index keeping a journal of all changes made to it, which can be
+/// replayed to an [`AsyncIndexWriteProvider`].
+#[derive(Clone, Debug, Default)]
+pub struct RecordingIndex {
+    index: MemIndex,
+    journal: Vec<IndexRecord>,
+    tx_start: usize,
+}
+
+impl RecordingIndex {
+    pub fn new() -> Self { RecordingIndex::default() }
+
+    /// Returns all changes made to the index, in the order they were made.
+    /// Changes reverted by a transaction rollback are not included.
+    pub fn journal(&self) -> &[IndexRecord] { &self.journal }
+}
+
+impl IndexProvider for RecordingIndex {}
+
+impl IndexReadProvider for RecordingIndex {
+    type Error = <MemIndex as IndexReadProvider>::Error;
+
+    fn contracts_assigning(
+        &self,
+        outputs: BTreeSet<XOutputSeal>,
+    ) -> Result<impl Iterator<Item = ContractId> + '_, Self::Error> {
+        IndexReadProvider::contracts_assigning(&self.index, outputs)
+    }
+
+    fn public_opouts(
+        &self,
+        contract_id: ContractId,
+    ) -> Result<BTreeSet<Opout>, IndexReadError<Self::Error>> {
+        IndexReadProvider::public_opouts(&self.index, contract_id)
+    }
+
+    fn opouts_by_outputs(
+        &self,
+        contract_id: ContractId,
+        outputs: impl IntoIterator<Item = impl Into<XOutputSeal>>,
+    ) -> Result<BTreeSet<Opout>, IndexReadError<Self::Error>> {
+        IndexReadProvider::opouts_by_outputs(&self.index, contract_id, outputs)
+    }
+
+    fn opouts_by_terminals(
+        &self,
+        terminals: impl IntoIterator<Item = XChain<SecretSeal>>,
+    ) -> Result<BTreeSet<Opout>, Self::Error> {
+        IndexReadProvider::opouts_by_terminals(&self.index, terminals)
+    }
+
+    fn bundle_id_for_op(&self, opid: OpId) -> Result<BundleId, IndexReadError<Self::Error>> {
+        IndexReadProvider::bundle_id_for_op(&self.index, opid)
+    }
+
+    fn bundle_info(
+        &self,
+        bundle_id: BundleId,
+    ) -> Result<(XWitnessId, ContractId), IndexReadError<Self::Error>> {
+        IndexReadProvider::bundle_info(&self.index, bundle_id)
+    }
+
+    fn spenders(&self, opout: Opout) -> Result<BTreeSet<Spender>, IndexReadError<Self::Error>> {
+        IndexReadProvider::spenders(&self.index, opout)
+    }
+
+    fn operations(&self) -> Result<impl Iterator<Item = (OpId, BundleId)> + '_, Self::Error> {
+        IndexReadProvider::operations(&self.index)
+    }
+
+    fn bundle_ids(&self) -> Result<impl Iterator<Item = BundleId> + '_, Self::Error> {
+        IndexReadProvider::bundle_ids(&self.index)
+    }
+}
+
+impl IndexWriteProvider for RecordingIndex {
+    type Error = <MemIndex as IndexWriteProvider>::Error;
+
+    // Journal is reverted by truncating it to the length it had when the
+    // transaction was started
+    fn begin_transaction(&mut self) -> Result<(), Self::Error> {
+        IndexWriteProvider::begin_transaction(&mut self.index)?;
+        self.tx_start = self.journal.len();
+        Ok(())
+    }
+
+    fn prepare_transaction(&mut self) -> Result<(), Self::Error> {
+        IndexWriteProvider::prepare_transaction(&mut self.index)
+    }
+
+    fn commit_transaction(&mut self) -> Result<(), Self::Error> {
+        IndexWriteProvider::commit_transaction(&mut self.index)
+    }
+
+    fn rollback_transaction(&mut self) -> Result<(), Self::Error> {
+        IndexWriteProvider::rollback_transaction(&mut self.index)?;
+        self.journal.truncate(self.tx_start);
+        Ok(())
+    }
+
+    fn register_contract(&mut self, contract_id: ContractId) -> Result<bool, Self::Error> {
+        let res = IndexWriteProvider::register_contract(&mut self.index, contract_id)?;
+        self.journal.push(IndexRecord::Contract(contract_id));
+        Ok(res)
+    }
+
+    fn register_bundle(
+        &mut self,
+        bundle_id: BundleId,
+        witness_id: XWitnessId,
+        contract_id: ContractId,
+    ) -> Result<bool, IndexWriteError<Self::Error>> {
+        let res = IndexWriteProvider::register_bundle(
+            &mut self.index,
+            bundle_id,
+            witness_id,
+            contract_id,
+        )?;
+        self.journal.push(IndexRecord::Bundle {
+            bundle_id,
+            witness_id,
+            contract_id,
+        });
+        Ok(res)
+    }
+
+    fn register_operation(
+        &mut self,
+        opid: OpId,
+        bundle_id: BundleId,
+    ) -> Result<bool, IndexWriteError<Self::Error>> {
+        let res = IndexWriteProvider::register_operation(&mut self.index, opid, bundle_id)?;
+        self.journal
+            .push(IndexRecord::Operation { opid, bundle_id });
+        Ok(res)
+    }
+
+    fn register_spender(
+        &mut self,
+        opout: Opout,
+        spender: Spender,
+    ) -> Result<bool, IndexWriteError<Self::Error>> {
+        let res = IndexWriteProvider::register_spender(&mut self.index, opout, spender)?;
+        self.journal.push(IndexRecord::Spender { opout, spender });
+        Ok(res)
+    }
+
+    fn index_genesis_assignments<State: ExposedState>(
+        &mut self,
+        contract_id: ContractId,
+        vec: &[Assign<State, GenesisSeal>],
+        opid: OpId,
+        type_id: AssignmentType,
+    ) -> Result<(), IndexWriteError<Self::Error>> {
+        IndexWriteProvider::index_genesis_assignments(
+            &mut self.index,
+            contract_id,
+            vec,
+            opid,
+            type_id,
+        )?;
+
+        for (no, a) in vec.iter().enumerate() {
+            let opout = Opout::new(opid, type_id, no as u16);
+            if let Assign::ConfidentialState { seal, .. } | Assign::Revealed { seal, .. } = a {
+                let output = seal
+                    .to_output_seal()
+                    .expect("genesis seals always have outpoint");
+                self.journal.push(IndexRecord::Output {
+                    contract_id,
+                    output,
+                    opout,
+                });
+            }
+            if let Assign::Confidential { seal, .. } | Assign::ConfidentialSeal { seal, .. } = a {
+                self.journal
+                    .push(IndexRecord::Terminal { seal: *seal, opout });
+            }
+        }
+        Ok(())
+    }
+
+    fn index_transition_assignments<State: ExposedState>(
+        &mut self,
+        contract_id: ContractId,
+        vec: &[Assign<State, GraphSeal>],
+        opid: OpId,
+        type_id: AssignmentType,
+        witness_id: XWitnessId,
+    ) -> Result<(), IndexWriteError<Self::Error>> {
+        IndexWriteProvider::index_transition_assignments(
+            &mut self.index,
+            contract_id,
+            vec,
+            opid,
+            type_id,
+            witness_id,
+        )?;
+
+        for (no, assign) in vec.iter().enumerate() {
+            let opout = Opout::new(opid, type_id, no as u16);
+            if let Assign::ConfidentialState { seal, .. } | Assign::Revealed { seal, .. } = assign {
+                let output = seal
+                    .try_to_output_seal(witness_id)
+                    .unwrap_or_else(|_| unreachable!("seal chain is checked by the memory index"));
+                self.journal.push(IndexRecord::Output {
+                    contract_id,
+                    output,
+                    opout,
+                });
+            }
+            if let Assign::Confidential { seal, .. } | Assign::ConfidentialSeal { seal, .. } =
+                assign
+            {
+                self.journal
+                    .push(IndexRecord::Terminal { seal: *seal, opout });
+            }
+        }
+        Ok(())
+    }
+
+    fn remove_contract(
+        &mut self,
+        contract_id: ContractId,
+        opids: &BTreeSet<OpId>,
+    ) -> Result<(), Self::Error> {
+        IndexWriteProvider::remove_contract(&mut self.index, contract_id, opids)?;
+        self.journal.push(IndexRecord::ContractRemoved {
+            contract_id,
+            opids: opids.clone(),
+        });
+        Ok(())
+    }
+
+    fn clear_index(&mut self) -> Result<(), Self::Error> {
+        IndexWriteProvider::clear_index(&mut self.index)?;
+        self.journal.push(IndexRecord::Cleared);
+        Ok(())
+    }
+}
+
+//////////
+// STOCK
+//////////
+
+/// Stock working over asynchronous providers.
+///
+/// Stock observers are not supported: the operations of the facade don't
+/// produce [`super::StockEvent`]s.
+#[derive(Debug)]
+pub struct AsyncStock<S: AsyncStashProvider, H: AsyncStateProvider, P: AsyncIndexProvider> {
+    stash: S,
+    state: H,
+    index: P,
+}
+
+impl<S: AsyncStashProvider, H: AsyncStateProvider, P: AsyncIndexProvider> AsyncStock<S, H, P> {
+    pub fn with(stash_provider: S, state_provider: H, index_provider: P) -> Self {
+        AsyncStock {
+            stash: stash_provider,
+            state: state_provider,
+            index: index_provider,
+        }
+    }
+
+    #[doc(hidden)]
+    pub fn as_stash_provider(&self) -> &S { &self.stash }
+
+    #[doc(hidden)]
+    pub fn as_state_provider(&self) -> &H { &self.state }
+
+    #[doc(hidden)]
+    pub fn as_index_provider(&self) -> &P { &self.index }
+
+    pub async fn export_contract(
+        &self,
+        contract_id: ContractId,
+    ) -> Result<Contract, AsyncStockError<S, H, P, ConsignError>> {
+        let stock = self.load([contract_id], []).await?;
+        Ok(stock.export_contract(contract_id)?)
+    }
+
+    pub async fn transfer(
+        &self,
+        contract_id: ContractId,
+        outputs: impl AsRef<[XOutputSeal]>,
+        secret_seals: impl AsRef<[XChain<SecretSeal>]>,
+    ) -> Result<Transfer, AsyncStockError<S, H, P, ConsignError>> {
+        let stock = self.load([contract_id], []).await?;
+        Ok(stock.transfer(contract_id, outputs, secret_seals)?)
+    }
+
+    /// Composes a batch of state transitions updating state for the provided
+    /// set of previous outputs, satisfying requirements of the invoice, paying
+    /// the change back and including the necessary blank state transitions.
+    ///
+    /// See [`Stock::compose`] for the details.
+    #[allow(clippy::result_large_err)]
+    pub async fn compose(
+        &self,
+        invoice: &RgbInvoice,
+        prev_outputs: impl IntoIterator<Item = impl Into<XOutputSeal>>,
+        method: CloseMethod,
+        beneficiary_vout: Option<impl Into<Vout>>,
+        allocator: impl Fn(ContractId, AssignmentType, VelocityHint) -> Option<Vout>,
+    ) -> Result<Batch, AsyncStockError<S, H, P, ComposeError>> {
+        let prev_outputs = prev_outputs
+            .into_iter()
+            .map(|o| o.into())
+            .collect::<BTreeSet<XOutputSeal>>();
+        // Blank transitions are created for all contracts assigning state to the
+        // previous outputs
+        let mut contract_ids = self
+            .index
+            .contracts_assigning(prev_outputs.clone())
+            .await
+            .map_err(AsyncStockError::IndexRead)?;
+        contract_ids.extend(invoice.contract);
+
+        let stock = self.load(contract_ids, []).await?;
+        Ok(stock.compose(invoice, prev_outputs, method, beneficiary_vout, allocator)?)
+    }
+
+    pub async fn import_contract<R: ResolveHeight>(
+        &mut self,
+        contract: ValidContract,
+        resolver: &mut R,
+    ) -> Result<validation::Status, AsyncStockError<S, H, P>> {
+        let contract_id = contract.contract_id();
+        let secrets = contract
+            .terminal_secrets()
+            .map(|(_, secret)| secret)
+            .collect::<Vec<_>>();
+        self.update([contract_id], secrets, |stock| stock.import_contract(contract, resolver))
+            .await
+    }
+
+    pub async fn accept_transfer<R: ResolveHeight>(
+        &mut self,
+        transfer: ValidTransfer,
+        resolver: &mut R,
+    ) -> Result<validation::Status, AsyncStockError<S, H, P>> {
+        let contract_id = transfer.contract_id();
+        let secrets = transfer
+            .terminal_secrets()
+            .map(|(_, secret)| secret)
+            .collect::<Vec<_>>();
+        self.update([contract_id], secrets, |stock| stock.accept_transfer(transfer, resolver))
+            .await
+    }
+
+    /// Imports fascia into the stash, index and inventory.
+    ///
+    /// See [`Stock::consume_fascia`] for the details.
+    pub async fn consume_fascia(
+        &mut self,
+        fascia: Fascia,
+    ) -> Result<(), AsyncStockError<S, H, P, FasciaError>> {
+        let contract_ids = fascia.bundles.keys().copied().collect::<Vec<_>>();
+        self.update(contract_ids, [], |stock| stock.consume_fascia(fascia))
+            .await
+    }
+
+    fn local<E: Error>(
+        err: StockError<MemStash, MemState, RecordingIndex>,
+    ) -> AsyncStockError<S, H, P, E>
+    where StockError<MemStash, MemState, RecordingIndex>:
+            Into<StockError<MemStash, MemState, RecordingIndex, E>> {
+        AsyncStockError::Local(err.into())
+    }
+
+    /// Loads data of the contracts into a new working copy of the stock.
+    /// Contracts unknown to the stash are skipped.
+    ///
+    /// Besides the contract data, the working copy receives all interfaces and
+    /// the types used by the contract schemata and the interfaces they
+    /// implement. Of the secret seals known to the stash, only the ones
+    /// concealed into `secrets` are loaded, with the default information about
+    /// them.
+    async fn load<E: Error>(
+        &self,
+        contract_ids: impl IntoIterator<Item = ContractId>,
+        secrets: impl IntoIterator<Item = XChain<SecretSeal>>,
+    ) -> Result<WorkingStock, AsyncStockError<S, H, P, E>>
+    where
+        StockError<MemStash, MemState, RecordingIndex>:
+            Into<StockError<MemStash, MemState, RecordingIndex, E>>,
+    {
+        let known = self
+            .stash
+            .contract_ids()
+            .await
+            .map_err(AsyncStockError::StashRead)?;
+        let contract_ids = contract_ids
+            .into_iter()
+            .filter(|id| known.contains(id))
+            .collect::<BTreeSet<_>>();
+
+        let mut ifaces = BTreeMap::new();
+        for iface_id in self
+            .stash
+            .ifaces()
+            .await
+            .map_err(AsyncStockError::StashRead)?
+            .into_keys()
+        {
+            ifaces.insert(iface_id, self.stash.iface(IfaceRef::Id(iface_id)).await?);
+        }
+
+        let mut libs = BTreeMap::new();
+        let mut schemata = BTreeMap::new();
+        let mut iimpls = BTreeMap::new();
+        let mut supplements = BTreeMap::new();
+        let mut geneses = BTreeMap::new();
+        let mut histories = BTreeMap::new();
+        for contract_id in &contract_ids {
+            let genesis = self.stash.genesis(*contract_id).await?;
+            if let Entry::Vacant(entry) = schemata.entry(genesis.schema_id) {
+                let schema_ifaces = self.stash.schema(genesis.schema_id).await?;
+                for id in schema_ifaces.schema.libs() {
+                    if !libs.contains_key(&id) {
+                        libs.insert(id, self.stash.lib(id).await?);
+                    }
+                }
+                for (_, iimpl) in schema_ifaces.iimpls {
+                    iimpls.insert(iimpl.impl_id(), iimpl);
+                }
+                entry.insert(schema_ifaces.schema);
+            }
+            for suppl in self
+                .stash
+                .contract_supplements(*contract_id)
+                .await
+                .map_err(AsyncStockError::StashRead)?
+            {
+                supplements.insert(suppl.suppl_id(), suppl);
+            }
+            if let Some(history) = self
+                .state
+                .contract_state(*contract_id)
+                .await
+                .map_err(AsyncStockError::StateRead)?
+            {
+                histories.insert(*contract_id, history);
+            }
+            geneses.insert(*contract_id, genesis);
+        }
+
+        let mut extensions = BTreeMap::new();
+        let mut bundles = BTreeMap::new();
+        let mut witnesses = BTreeMap::new();
+        for contract_id in &contract_ids {
+            extensions.extend(
+                self.stash
+                    .contract_extensions(*contract_id)
+                    .await
+                    .map_err(AsyncStockError::StashRead)?,
+            );
+            for (bundle_id, witness_id) in self.index.contract_bundles(*contract_id).await? {
+                bundles.insert(bundle_id, self.stash.bundle(bundle_id).await?);
+                if !witnesses.contains_key(&witness_id) {
+                    witnesses.insert(witness_id, self.stash.witness(witness_id).await?);
+                }
+            }
+        }
+
+        let type_ids = schemata
+            .values()
+            .flat_map(Schema::types)
+            .chain(
+                iimpls
+                    .values()
+                    .filter_map(|iimpl| ifaces.get(&iimpl.iface_id))
+                    .flat_map(Iface::types),
+            )
+            .collect();
+        let types = self.stash.types(type_ids).await?;
+
+        let mut secret_seals = BTreeMap::new();
+        for secret in secrets {
+            if let Some(seal) = self
+                .stash
+                .seal_secret(secret)
+                .await
+                .map_err(AsyncStockError::StashRead)?
+            {
+                secret_seals.insert(seal, SecretSealInfo::default());
+            }
+        }
+
+        let size = AsyncStockError::<S, H, P, E>::WorkingCopySize;
+        let backup = Backup {
+            version: ContainerVer::V2,
+            base: None,
+            types: Some(types),
+            libs: Confined::try_from(libs).map_err(size)?,
+            schemata: Confined::try_from(schemata).map_err(size)?,
+            ifaces: Confined::try_from(ifaces).map_err(size)?,
+            iimpls: Confined::try_from(iimpls).map_err(size)?,
+            supplements: Confined::try_from(supplements).map_err(size)?,
+            geneses: Confined::try_from(geneses).map_err(size)?,
+            extensions: Confined::try_from(extensions).map_err(size)?,
+            bundles: Confined::try_from(bundles).map_err(size)?,
+            witnesses: Confined::try_from(witnesses).map_err(size)?,
+            attachments: none!(),
+            signatures: none!(),
+            secret_seals: Confined::try_from(secret_seals).map_err(size)?,
+        };
+
+        let mut stock = WorkingStock::default();
+        stock.load(backup, histories).map_err(Self::local)?;
+        Ok(stock)
+    }
+
+    /// Runs `f` over the working copy of the stock containing the contracts
+    /// and the secret seals concealed into `secrets`, and writes all changes
+    /// it has made back to the providers.
+    async fn update<T, E: Error>(
+        &mut self,
+        contract_ids: impl IntoIterator<Item = ContractId>,
+        secrets: impl IntoIterator<Item = XChain<SecretSeal>>,
+        f: impl FnOnce(
+            &mut WorkingStock,
+        ) -> Result<T, StockError<MemStash, MemState, RecordingIndex, E>>,
+    ) -> Result<T, AsyncStockError<S, H, P, E>>
+    where
+        StockError<MemStash, MemState, RecordingIndex>:
+            Into<StockError<MemStash, MemState, RecordingIndex, E>>,
+    {
+        let contract_ids = contract_ids.into_iter().collect::<BTreeSet<_>>();
+        let mut stock = self.load(contract_ids.iter().copied(), secrets).await?;
+        let base = stock.backup().map_err(Self::local)?;
+        let base = BackupManifest::new(&base).expect("full backup");
+        let journal_len = stock.as_index_provider().journal().len();
+
+        let res = f(&mut stock)?;
+
+        let changes = stock.backup_since(&base).map_err(Self::local)?;
+        let mut histories = BTreeMap::new();
+        for contract_id in contract_ids {
+            let history = StateReadProvider::contract_state(stock.as_state_provider(), contract_id)
+                .map_err(|err| Self::local(StockError::StateRead(err)))?;
+            if let Some(history) = history {
+                histories.insert(contract_id, history.clone());
+            }
+        }
+        let journal = stock.as_index_provider().journal()[journal_len..].to_vec();
+
+        self.store(changes, histories, journal).await?;
+        Ok(res)
+    }
+
+    /// Writes changes to all providers as a single transaction.
+    ///
+    /// All providers are prepared before any of them is committed, so if
+    /// writing the changes or preparing any of the providers fails, no changes
+    /// are made to any of them.
+    async fn store<E: Error>(
+        &mut self,
+        changes: Backup,
+        histories: BTreeMap<ContractId, ContractHistory>,
+        journal: Vec<IndexRecord>,
+    ) -> Result<(), AsyncStockError<S, H, P, E>> {
+        self.stash
+            .begin_transaction()
+            .await
+            .map_err(AsyncStockError::StashWrite)?;
+        if let Err(err) = self.state.begin_transaction().await {
+            self.stash.rollback_transaction().await.ok();
+            return Err(AsyncStockError::StateWrite(err));
+        }
+        if let Err(err) = self.index.begin_transaction().await {
+            self.state.rollback_transaction().await.ok();
+            self.stash.rollback_transaction().await.ok();
+            return Err(AsyncStockError::IndexWrite(err));
+        }
+
+        let res = match self.write(changes, histories, journal).await {
+            Ok(()) => self.prepare().await,
+            Err(err) => Err(err),
+        };
+        if res.is_err() {
+            // Rollback failures are not reported since they would hide the
+            // original error.
+            self.index.rollback_transaction().await.ok();
+            self.state.rollback_transaction().await.ok();
+            self.stash.rollback_transaction().await.ok();
+            return res;
+        }
+
+        // Prepared transactions are not reverted, so all providers are committed
+        // even if some of them fail.
+        let index = self.index.commit_transaction().await;
+        let state = self.state.commit_transaction().await;
+        let stash = self.stash.commit_transaction().await;
+        index.map_err(AsyncStockError::IndexWrite)?;
+        state.map_err(AsyncStockError::StateWrite)?;
+        stash.map_err(AsyncStockError::StashWrite)
+    }
+
+    /// Prepares transaction of all providers to be committed.
+    async fn prepare<E: Error>(&mut self) -> Result<(), AsyncStockError<S, H, P, E>> {
+        self.index
+            .prepare_transaction()
+            .await
+            .map_err(AsyncStockError::IndexWrite)?;
+        self.state
+            .prepare_transaction()
+            .await
+            .map_err(AsyncStockError::StateWrite)?;
+        self.stash
+            .prepare_transaction()
+            .await
+            .map_err(AsyncStockError::StashWrite)
+    }
+
+    async fn write<E: Error>(
+        &mut self,
+        changes: Backup,
+        histories: BTreeMap<ContractId, ContractHistory>,
+        journal: Vec<IndexRecord>,
+    ) -> Result<(), AsyncStockError<S, H, P, E>> {
+        let stash = &mut self.stash;
+        if let Some(types) = changes.types {
+            stash
+                .consume_types(types)
+                .await
+                .map_err(AsyncStockError::StashWrite)?;
+        }
+        for (_, lib) in changes.libs {
+            stash
+                .replace_lib(lib)
+                .await
+                .map_err(AsyncStockError::StashWrite)?;
+        }
+        for (_, schema) in changes.schemata {
+            stash
+                .replace_schema(schema)
+                .await
+                .map_err(AsyncStockError::StashWrite)?;
+        }
+        for (_, iface) in changes.ifaces {
+            stash
+                .replace_iface(iface)
+                .await
+                .map_err(AsyncStockError::StashWrite)?;
+        }
+        for (_, iimpl) in changes.iimpls {
+            stash
+                .replace_iimpl(iimpl)
+                .await
+                .map_err(AsyncStockError::StashWrite)?;
+        }
+        for (_, suppl) in changes.supplements {
+            stash
+                .add_suppl(suppl)
+                .await
+                .map_err(AsyncStockError::StashWrite)?;
+        }
+        for (_, genesis) in changes.geneses {
+            stash
+                .replace_genesis(genesis)
+                .await
+                .map_err(AsyncStockError::StashWrite)?;
+        }
+        for (_, extension) in changes.extensions {
+            stash
+                .replace_extension(extension)
+                .await
+                .map_err(AsyncStockError::StashWrite)?;
+        }
+        for (_, witness) in changes.witnesses {
+            stash
+                .replace_witness(witness)
+                .await
+                .map_err(AsyncStockError::StashWrite)?;
+        }
+        for (_, bundle) in changes.bundles {
+            stash
+                .replace_bundle(bundle)
+                .await
+                .map_err(AsyncStockError::StashWrite)?;
+        }
+        for (id, attach) in changes.attachments {
+            stash
+                .replace_attachment(id, attach)
+                .await
+                .map_err(AsyncStockError::StashWrite)?;
+        }
+        for (content_id, sigs) in changes.signatures {
+            // Do not bother if we can't import all the sigs
+            stash.import_sigs(content_id, sigs).await.ok();
+        }
+        for (seal, info) in changes.secret_seals {
+            stash
+                .add_secret_seal_with(seal, info)
+                .await
+                .map_err(AsyncStockError::StashWrite)?;
+        }
+
+        for (contract_id, history) in histories {
+            self.state
+                .replace_state(contract_id, history)
+                .await
+                .map_err(AsyncStockError::StateWrite)?;
+        }
+
+        let index = &mut self.index;
+        for record in journal {
+            match record {
+                IndexRecord::Contract(contract_id) => {
+                    index
+                        .register_contract(contract_id)
+                        .await
+                        .map_err(AsyncStockError::IndexWrite)?;
+                }
+                IndexRecord::Bundle {
+                    bundle_id,
+                    witness_id,
+                    contract_id,
+                } => {
+                    index
+                        .register_bundle(bundle_id, witness_id, contract_id)
+                        .await?;
+                }
+                IndexRecord::Operation { opid, bundle_id } => {
+                    index.register_operation(opid, bundle_id).await?;
+                }
+                IndexRecord::Spender { opout, spender } => {
+                    index.register_spender(opout, spender).await?;
+                }
+                IndexRecord::Output {
+                    contract_id,
+                    output,
+                    opout,
+                } => {
+                    index.index_output(contract_id, output, opout).await?;
+                }
+                IndexRecord::Terminal { seal, opout } => {
+                    index
+                        .index_terminal(seal, opout)
+                        .await
+                        .map_err(AsyncStockError::IndexWrite)?;
+                }
+                IndexRecord::ContractRemoved { contract_id, opids } => {
+                    index
+                        .remove_contract(contract_id, opids)
+                        .await
+                        .map_err(AsyncStockError::IndexWrite)?;
+                }
+                IndexRecord::Cleared => {
+                    index
+                        .clear_index()
+                        .await
+                        .map_err(AsyncStockError::IndexWrite)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/////////////////////
+// MEMORY PROVIDERS
+/////////////////////
+
+impl AsyncStashProvider for MemStash {}
+
+impl AsyncStashReadProvider for MemStash {
+    type Error = <MemStash as StashReadProvider>::Error;
+
+    fn types(
+        &self,
+        ids: BTreeSet<SemId>,
+    ) -> impl Future<Output = Result<TypeSystem, StashProviderError<Self::Error>>> + Send {
+        future::ready(
+            StashReadProvider::type_system(self)
+                .map_err(StashProviderError::Connectivity)
+                .and_then(|types| {
+                    types
+                        .extract(ids)
+                        .map_err(|err| StashInconsistency::TypeAbsent(err).into())
+                }),
+        )
+    }
+
+    fn lib_ids(&self) -> impl Future<Output = Result<BTreeSet<LibId>, Self::Error>> + Send {
+        future::ready(StashReadProvider::lib_ids(self).map(Iterator::collect))
+    }
+
+    fn lib(
+        &self,
+        id: LibId,
+    ) -> impl Future<Output = Result<Lib, StashProviderError<Self::Error>>> + Send {
+        future::ready(StashReadProvider::lib(self, id).cloned())
+    }
+
+    fn ifaces(
+        &self,
+    ) -> impl Future<Output = Result<BTreeMap<IfaceId, TypeName>, Self::Error>> + Send {
+        future::ready(StashReadProvider::ifaces(self).map(Iterator::collect))
+    }
+
+    fn iface(
+        &self,
+        iface: IfaceRef,
+    ) -> impl Future<Output = Result<Iface, StashProviderError<Self::Error>>> + Send {
+        future::ready(StashReadProvider::iface(self, iface).cloned())
+    }
+
+    fn schema_ids(&self) -> impl Future<Output = Result<BTreeSet<SchemaId>, Self::Error>> + Send {
+        future::ready(
+            StashReadProvider::schemata(self)
+                .map(|iter| iter.map(|schema| schema.schema.schema_id()).collect()),
+        )
+    }
+
+    fn schema(
+        &self,
+        schema_id: SchemaId,
+    ) -> impl Future<Output = Result<SchemaIfaces, StashProviderError<Self::Error>>> + Send {
+        future::ready(StashReadProvider::schema(self, schema_id).cloned())
+    }
+
+    fn contract_ids(
+        &self,
+    ) -> impl Future<Output = Result<BTreeSet<ContractId>, Self::Error>> + Send {
+        future::ready(StashReadProvider::contract_ids(self).map(Iterator::collect))
+    }
+
+    fn contract_supplements(
+        &self,
+        contract_id: ContractId,
+    ) -> impl Future<Output = Result<Vec<ContractSuppl>, Self::Error>> + Send {
+        future::ready(
+            StashReadProvider::contract_supplements(self, contract_id).map(Iterator::collect),
+        )
+    }
+
+    fn genesis(
+        &self,
+        contract_id: ContractId,
+    ) -> impl Future<Output = Result<Genesis, StashProviderError<Self::Error>>> + Send {
+        future::ready(StashReadProvider::genesis(self, contract_id).cloned())
+    }
+
+    fn witness_ids(
+        &self,
+    ) -> impl Future<Output = Result<BTreeSet<XWitnessId>, Self::Error>> + Send {
+        future::ready(StashReadProvider::witness_ids(self).map(Iterator::collect))
+    }
+
+    fn bundle_ids(&self) -> impl Future<Output = Result<BTreeSet<BundleId>, Self::Error>> + Send {
+        future::ready(StashReadProvider::bundle_ids(self).map(Iterator::collect))
+    }
+
+    fn bundle(
+        &self,
+        bundle_id: BundleId,
+    ) -> impl Future<Output = Result<TransitionBundle, StashProviderError<Self::Error>>> + Send
+    {
+        future::ready(StashReadProvider::bundle(self, bundle_id).cloned())
+    }
+
+    fn extension_ids(&self) -> impl Future<Output = Result<BTreeSet<OpId>, Self::Error>> + Send {
+        future::ready(StashReadProvider::extension_ids(self).map(Iterator::collect))
+    }
+
+    fn contract_extensions(
+        &self,
+        contract_id: ContractId,
+    ) -> impl Future<Output = Result<BTreeMap<OpId, Extension>, Self::Error>> + Send {
+        future::ready(Ok(self
+            .debug_extensions()
+            .iter()
+            .filter(|(_, extension)| extension.contract_id == contract_id)
+            .map(|(opid, extension)| (*opid, extension.clone()))
+            .collect()))
+    }
+
+    fn extension(
+        &self,
+        op_id: OpId,
+    ) -> impl Future<Output = Result<Extension, StashProviderError<Self::Error>>> + Send {
+        future::ready(StashReadProvider::extension(self, op_id).cloned())
+    }
+
+    fn witness(
+        &self,
+        witness_id: XWitnessId,
+    ) -> impl Future<Output = Result<SealWitness, StashProviderError<Self::Error>>> + Send {
+        future::ready(StashReadProvider::witness(self, witness_id).cloned())
+    }
+
+    fn attachment_ids(
+        &self,
+    ) -> impl Future<Output = Result<BTreeSet<AttachId>, Self::Error>> + Send {
+        future::ready(StashReadProvider::attachment_ids(self).map(Iterator::collect))
+    }
+
+    fn attachment(
+        &self,
+        id: AttachId,
+    ) -> impl Future<Output = Result<MediumBlob, StashProviderError<Self::Error>>> + Send {
+        future::ready(StashReadProvider::attachment(self, id).cloned())
+    }
+
+    fn sigs(
+        &self,
+    ) -> impl Future<Output = Result<BTreeMap<ContentId, ContentSigs>, Self::Error>> + Send {
+        future::ready(
+            StashReadProvider::sigs(self)
+                .map(|iter| iter.map(|(id, sigs)| (id, sigs.clone())).collect()),
+        )
+    }
+
+    fn seal_secret(
+        &self,
+        secret: XChain<SecretSeal>,
+    ) -> impl Future<Output = Result<Option<XChain<GraphSeal>>, Self::Error>> + Send {
+        future::ready(StashReadProvider::seal_secret(self, secret))
+    }
+
+    fn secret_seal_records(
+        &self,
+    ) -> impl Future<Output = Result<BTreeMap<XChain<GraphSeal>, SecretSealInfo>, Self::Error>> + Send
+    {
+        future::ready(StashReadProvider::secret_seal_records(self).map(Iterator::collect))
+    }
+}
+
+impl AsyncStashWriteProvider for MemStash {
+    type Error = <MemStash as StashWriteProvider>::Error;
+
+    fn begin_transaction(&mut self) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        future::ready(StashWriteProvider::begin_transaction(self))
+    }
+
+    fn prepare_transaction(&mut self) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        future::ready(StashWriteProvider::prepare_transaction(self))
+    }
+
+    fn commit_transaction(&mut self) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        future::ready(StashWriteProvider::commit_transaction(self))
+    }
+
+    fn rollback_transaction(&mut self) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        future::ready(StashWriteProvider::rollback_transaction(self))
+    }
+
+    fn replace_schema(
+        &mut self,
+        schema: Schema,
+    ) -> impl Future<Output = Result<bool, Self::Error>> + Send {
+        future::ready(StashWriteProvider::replace_schema(self, schema))
+    }
+
+    fn replace_iface(
+        &mut self,
+        iface: Iface,
+    ) -> impl Future<Output = Result<bool, Self::Error>> + Send {
+        future::ready(StashWriteProvider::replace_iface(self, iface))
+    }
+
+    fn replace_iimpl(
+        &mut self,
+        iimpl: IfaceImpl,
+    ) -> impl Future<Output = Result<bool, Self::Error>> + Send {
+        future::ready(StashWriteProvider::replace_iimpl(self, iimpl))
+    }
+
+    fn replace_genesis(
+        &mut self,
+        genesis: Genesis,
+    ) -> impl Future<Output = Result<bool, Self::Error>> + Send {
+        future::ready(StashWriteProvider::replace_genesis(self, genesis))
+    }
+
+    fn replace_extension(
+        &mut self,
+        extension: Extension,
+    ) -> impl Future<Output = Result<bool, Self::Error>> + Send {
+        future::ready(StashWriteProvider::replace_extension(self, extension))
+    }
+
+    fn replace_bundle(
+        &mut self,
+        bundle: TransitionBundle,
+    ) -> impl Future<Output = Result<bool, Self::Error>> + Send {
+        future::ready(StashWriteProvider::replace_bundle(self, bundle))
+    }
+
+    fn replace_witness(
+        &mut self,
+        witness: SealWitness,
+    ) -> impl Future<Output = Result<bool, Self::Error>> + Send {
+        future::ready(StashWriteProvider::replace_witness(self, witness))
+    }
+
+    fn replace_attachment(
+        &mut self,
+        id: AttachId,
+        attach: MediumBlob,
+    ) -> impl Future<Output = Result<bool, Self::Error>> + Send {
+        future::ready(StashWriteProvider::replace_attachment(self, id, attach))
+    }
+
+    fn replace_lib(&mut self, lib: Lib) -> impl Future<Output = Result<bool, Self::Error>> + Send {
+        future::ready(StashWriteProvider::replace_lib(self, lib))
+    }
+
+    fn consume_types(
+        &mut self,
+        types: TypeSystem,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        future::ready(StashWriteProvider::consume_types(self, types))
+    }
+
+    fn add_suppl(
+        &mut self,
+        suppl: ContractSuppl,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        future::ready(StashWriteProvider::add_suppl(self, suppl))
+    }
+
+    fn import_sigs(
+        &mut self,
+        content_id: ContentId,
+        sigs: ContentSigs,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        future::ready(StashWriteProvider::import_sigs(self, content_id, sigs.into_iter()))
+    }
+
+    fn add_secret_seal_with(
+        &mut self,
+        seal: XChain<GraphSeal>,
+        info: SecretSealInfo,
+    ) -> impl Future<Output = Result<bool, Self::Error>> + Send {
+        future::ready(StashWriteProvider::add_secret_seal_with(self, seal, info))
+    }
+
+    fn remove_secret_seal(
+        &mut self,
+        seal: XChain<GraphSeal>,
+    ) -> impl Future<Output = Result<bool, Self::Error>> + Send {
+        future::ready(StashWriteProvider::remove_secret_seal(self, seal))
+    }
+}
+
+impl AsyncStateProvider for MemState {}
+
+impl AsyncStateReadProvider for MemState {
+    type Error = <MemState as StateReadProvider>::Error;
+
+    fn contract_state(
+        &self,
+        contract_id: ContractId,
+    ) -> impl Future<Output = Result<Option<ContractHistory>, Self::Error>> + Send {
+        future::ready(
+            StateReadProvider::contract_state(self, contract_id).map(|history| history.cloned()),
+        )
+    }
+}
+
+impl AsyncStateWriteProvider for MemState {
+    type Error = <MemState as StateWriteProvider>::Error;
+
+    fn begin_transaction(&mut self) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        future::ready(StateWriteProvider::begin_transaction(self))
+    }
+
+    fn prepare_transaction(&mut self) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        future::ready(StateWriteProvider::prepare_transaction(self))
+    }
+
+    fn commit_transaction(&mut self) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        future::ready(StateWriteProvider::commit_transaction(self))
+    }
+
+    fn rollback_transaction(&mut self) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        future::ready(StateWriteProvider::rollback_transaction(self))
+    }
+
+    fn replace_state(
+        &mut self,
+        contract_id: ContractId,
+        history: ContractHistory,
+    ) -> impl Future<Output = Result<bool, Self::Error>> + Send {
+        future::ready(self.replace_history(contract_id, history))
+    }
+
+    fn remove_state(
+        &mut self,
+        contract_id: ContractId,
+    ) -> impl Future<Output = Result<bool, Self::Error>> + Send {
+        future::ready(StateWriteProvider::remove_state(self, contract_id))
+    }
+
+    fn clear_state(&mut self) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        future::ready(StateWriteProvider::clear_state(self))
+    }
+}
+
+impl AsyncIndexProvider for MemIndex {}
+
+impl AsyncIndexReadProvider for MemIndex {
+    type Error = <MemIndex as IndexReadProvider>::Error;
+
+    fn contracts_assigning(
+        &self,
+        outputs: BTreeSet<XOutputSeal>,
+    ) -> impl Future<Output = Result<BTreeSet<ContractId>, Self::Error>> + Send {
+        future::ready(IndexReadProvider::contracts_assigning(self, outputs).map(Iterator::collect))
+    }
+
+    fn public_opouts(
+        &self,
+        contract_id: ContractId,
+    ) -> impl Future<Output = Result<BTreeSet<Opout>, IndexReadError<Self::Error>>> + Send {
+        future::ready(IndexReadProvider::public_opouts(self, contract_id))
+    }
+
+    fn opouts_by_outputs(
+        &self,
+        contract_id: ContractId,
+        outputs: BTreeSet<XOutputSeal>,
+    ) -> impl Future<Output = Result<BTreeSet<Opout>, IndexReadError<Self::Error>>> + Send {
+        future::ready(IndexReadProvider::opouts_by_outputs(self, contract_id, outputs))
+    }
+
+    fn opouts_by_terminals(
+        &self,
+        terminals: BTreeSet<XChain<SecretSeal>>,
+    ) -> impl Future<Output = Result<BTreeSet<Opout>, Self::Error>> + Send {
+        future::ready(IndexReadProvider::opouts_by_terminals(self, terminals))
+    }
+
+    fn bundle_id_for_op(
+        &self,
+        opid: OpId,
+    ) -> impl Future<Output = Result<BundleId, IndexReadError<Self::Error>>> + Send {
+        future::ready(IndexReadProvider::bundle_id_for_op(self, opid))
+    }
+
+    fn bundle_info(
+        &self,
+        bundle_id: BundleId,
+    ) -> impl Future<Output = Result<(XWitnessId, ContractId), IndexReadError<Self::Error>>> + Send
+    {
+        future::ready(IndexReadProvider::bundle_info(self, bundle_id))
+    }
+
+    fn contract_bundles(
+        &self,
+        contract_id: ContractId,
+    ) -> impl Future<Output = Result<BTreeMap<BundleId, XWitnessId>, IndexReadError<Self::Error>>> + Send
+    {
+        let bundles = self
+            .debug_bundle_contract_index()
+            .iter()
+            .filter(|(_, id)| **id == contract_id)
+            .map(|(bundle_id, _)| {
+                self.debug_bundle_witness_index()
+                    .get(bundle_id)
+                    .map(|witness_id| (*bundle_id, *witness_id))
+                    .ok_or(IndexInconsistency::BundleWitnessUnknown(*bundle_id).into())
+            })
+            .collect::<Result<BTreeMap<_, _>, IndexReadError<Self::Error>>>();
+        future::ready(bundles)
+    }
+
+    fn spenders(
+        &self,
+        opout: Opout,
+    ) -> impl Future<Output = Result<BTreeSet<Spender>, IndexReadError<Self::Error>>> + Send {
+        future::ready(IndexReadProvider::spenders(self, opout))
+    }
+
+    fn bundle_ids(&self) -> impl Future<Output = Result<BTreeSet<BundleId>, Self::Error>> + Send {
+        future::ready(IndexReadProvider::bundle_ids(self).map(Iterator::collect))
+    }
+}
+
+impl AsyncIndexWriteProvider for MemIndex {
+    type Error = <MemIndex as IndexWriteProvider>::Error;
+
+    fn begin_transaction(&mut self) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        future::ready(IndexWriteProvider::begin_transaction(self))
+    }
+
+    fn prepare_transaction(&mut self) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        future::ready(IndexWriteProvider::prepare_transaction(self))
+    }
+
+    fn commit_transaction(&mut self) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        future::ready(IndexWriteProvider::commit_transaction(self))
+    }
+
+    fn rollback_transaction(&mut self) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        future::ready(IndexWriteProvider::rollback_transaction(self))
+    }
+
+    fn register_contract(
+        &mut self,
+        contract_id: ContractId,
+    ) -> impl Future<Output = Result<bool, Self::Error>> + Send {
+        future::ready(IndexWriteProvider::register_contract(self, contract_id))
+    }
+
+    fn register_bundle(
+        &mut self,
+        bundle_id: BundleId,
+        witness_id: XWitnessId,
+        contract_id: ContractId,
+    ) -> impl Future<Output = Result<bool, IndexWriteError<Self::Error>>> + Send {
+        future::ready(IndexWriteProvider::register_bundle(self, bundle_id, witness_id, contract_id))
+    }
+
+    fn register_operation(
+        &mut self,
+        opid: OpId,
+        bundle_id: BundleId,
+    ) -> impl Future<Output = Result<bool, IndexWriteError<Self::Error>>> + Send {
+        future::ready(IndexWriteProvider::register_operation(self, opid, bundle_id))
+    }
+
+    fn register_spender(
+        &mut self,
+        opout: Opout,
+        spender: Spender,
+    ) -> impl Future<Output = Result<bool, IndexWriteError<Self::Error>>> + Send {
+        future::ready(IndexWriteProvider::register_spender(self, opout, spender))
+    }
+
+    fn index_output(
+        &mut self,
+        contract_id: ContractId,
+        output: XOutputSeal,
+        opout: Opout,
+    ) -> impl Future<Output = Result<(), IndexWriteError<Self::Error>>> + Send {
+        future::ready(MemIndex::index_output(self, contract_id, output, opout))
+    }
+
+    fn index_terminal(
+        &mut self,
+        seal: XChain<SecretSeal>,
+        opout: Opout,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        future::ready(MemIndex::index_terminal(self, seal, opout))
+    }
+
+    fn remove_contract(
+        &mut self,
+        contract_id: ContractId,
+        opids: BTreeSet<OpId>,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        future::ready(IndexWriteProvider::remove_contract(self, contract_id, &opids))
+    }
+
+    fn clear_index(&mut self) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        future::ready(IndexWriteProvider::clear_index(self))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::pin::pin;
+    use std::sync::Arc;
+    use std::task::{Context, Poll, Wake, Waker};
+
+    use amplify::ByteArray;
+    use bp::Txid;
+
+    use super::*;
+
+    type MemAsyncStock = AsyncStock<MemStash, MemState, MemIndex>;
+
+    struct NoopWaker;
+
+    impl Wake for NoopWaker {
+        fn wake(self: Arc<Self>) {}
+    }
+
+    // Futures of the in-memory providers are always ready, so they are polled
+    // without an async runtime
+    fn block_on<F: Future>(fut: F) -> F::Output {
+        let waker = Waker::from(Arc::new(NoopWaker));
+        let mut cx = Context::from_waker(&waker);
+        let mut fut = pin!(fut);
+        loop {
+            if let Poll::Ready(res) = fut.as_mut().poll(&mut cx) {
+                return res;
+            }
+        }
+    }
+
+    fn seal(blinding: u64) -> XChain<GraphSeal> {
+        XChain::Bitcoin(GraphSeal::with_blinding(
+            CloseMethod::TapretFirst,
+            Txid::coinbase(),
+            1u32,
+            blinding,
+        ))
+    }
+
+    fn secret_seals(stock: &WorkingStock) -> Vec<XChain<GraphSeal>> {
+        StashReadProvider::secret_seals(stock.as_stash_provider())
+            .unwrap()
+            .collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut stock = MemAsyncStock::with(default!(), default!(), default!());
+        let added = block_on(stock.update::<_, Infallible>([], [], |stock| {
+            stock.store_secret_seal(seal(1))?;
+            stock.store_secret_seal(seal(2))
+        }))
+        .unwrap();
+        assert!(added);
+
+        // Only the seals of the requested secrets are loaded
+        let working = block_on(stock.load::<Infallible>([], [])).unwrap();
+        assert!(secret_seals(&working).is_empty());
+        let working =
+            block_on(stock.load::<Infallible>([], [seal(1).conceal(), seal(3).conceal()])).unwrap();
+        assert_eq!(secret_seals(&working), vec![seal(1)]);
+
+        // Failed update doesn't change the providers
+        let res = block_on(stock.update::<(), Infallible>([], [], |stock| {
+            stock.store_secret_seal(seal(3))?;
+            Err(StockError::StashInconsistency(StashInconsistency::ContractAbsent(
+                ContractId::from_byte_array([1u8; 32]),
+            )))
+        }));
+        assert!(matches!(res, Err(AsyncStockError::Local(StockError::StashInconsistency(_)))));
+
+        let seals = [seal(1), seal(2), seal(3)].map(|seal| seal.conceal());
+        let working = block_on(stock.load::<Infallible>([], seals)).unwrap();
+        let mut expected = vec![seal(1), seal(2)];
+        expected.sort();
+        assert_eq!(secret_seals(&working), expected);
+    }
+}
@@ -0,0 +1,155 @@
+// RGB standard library for working with smart contracts on Bitcoin & Lightning
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2019-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2019-2024 LNP/BP Standards Association. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use aluvm::library::LibId;
+use rgb::{BundleId, ContractId, GraphSeal, OpId, SchemaId, XChain, XWitnessId};
+
+use crate::interface::IfaceId;
+
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Display)]
+#[display(lowercase)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+pub enum AuditSeverity {
+    /// The data are not used by the stock, but their presence is not an error.
+    Warning,
+    /// The data are inconsistent and some of stock operations will fail.
+    Error,
+}
+
+/// Problem with stock data detected by [`super::Stock::audit`].
+#[derive(Clone, Eq, PartialEq, Debug, Display)]
+#[display(doc_comments)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+pub enum AuditIssue {
+    /// genesis of contract {contract_id} uses schema {schema_id::<0} absent in
+    /// the stash.
+    ContractSchemaAbsent {
+        contract_id: ContractId,
+        schema_id: SchemaId,
+    },
+
+    /// contract {0} has genesis in the stash, but no state.
+    ContractWithoutState(ContractId),
+
+    /// contract {0} has genesis in the stash, but is not indexed.
+    ContractNotIndexed(ContractId),
+
+    /// bundle {0} is not anchored by any of witnesses known to the stash.
+    BundleWithoutWitness(BundleId),
+
+    /// index references bundle {bundle_id} for operation {opid}, but the
+    /// bundle is absent in the stash.
+    DanglingOperation { opid: OpId, bundle_id: BundleId },
+
+    /// index contains information about bundle {0}, which is absent in the
+    /// stash.
+    DanglingBundle(BundleId),
+
+    /// index references witness {witness_id} for bundle {bundle_id}, but the
+    /// witness is absent in the stash.
+    DanglingWitness {
+        bundle_id: BundleId,
+        witness_id: XWitnessId,
+    },
+
+    /// index assigns bundle {bundle_id} to contract {contract_id}, which is
+    /// absent in the stash.
+    DanglingContract {
+        bundle_id: BundleId,
+        contract_id: ContractId,
+    },
+
+    /// index lacks witness or contract information for bundle {0}.
+    IncompleteBundleInfo(BundleId),
+
+    /// secret seal {0} is not referenced by any known terminal.
+    OrphanSecretSeal(XChain<GraphSeal>),
+
+    /// stash contains supplement for contract {0}, which is unknown.
+    SupplUnknownContract(ContractId),
+
+    /// schema {schema_id::<0} contains implementation of interface
+    /// {iface_id::<0} made for a different schema {iimpl_schema_id::<0}.
+    IimplSchemaMismatch {
+        schema_id: SchemaId,
+        iface_id: IfaceId,
+        iimpl_schema_id: SchemaId,
+    },
+
+    /// schema {schema_id::<0} implements interface {iface_id::<0}, which is
+    /// absent in the stash.
+    IimplIfaceAbsent {
+        schema_id: SchemaId,
+        iface_id: IfaceId,
+    },
+
+    /// schema {schema_id::<0} uses library {lib_id}, which is absent in the
+    /// stash.
+    LibAbsent { schema_id: SchemaId, lib_id: LibId },
+}
+
+impl AuditIssue {
+    pub fn severity(&self) -> AuditSeverity {
+        match self {
+            AuditIssue::OrphanSecretSeal(_) => AuditSeverity::Warning,
+            _ => AuditSeverity::Error,
+        }
+    }
+}
+
+/// Report on stock data consistency produced by [`super::Stock::audit`].
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+pub struct AuditReport {
+    pub issues: Vec<AuditIssue>,
+}
+
+impl AuditReport {
+    /// Detects whether the report contains no errors (it still may contain
+    /// warnings).
+    pub fn is_consistent(&self) -> bool { self.errors().next().is_none() }
+
+    pub fn errors(&self) -> impl Iterator<Item = &AuditIssue> {
+        self.issues
+            .iter()
+            .filter(|issue| issue.severity() == AuditSeverity::Error)
+    }
+
+    pub fn warnings(&self) -> impl Iterator<Item = &AuditIssue> {
+        self.issues
+            .iter()
+            .filter(|issue| issue.severity() == AuditSeverity::Warning)
+    }
+
+    pub(super) fn push(&mut self, issue: AuditIssue) { self.issues.push(issue) }
+}
@@ -0,0 +1,419 @@
+// RGB standard library for working with smart contracts on Bitcoin & Lightning
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2019-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2019-2024 LNP/BP Standards Association. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Append-only hash-chained log of the changes made to a [`super::Stock`].
+//!
+//! Each log entry commits to the identifier of the previous entry, so any
+//! modification, removal or reordering of the entries is detected by
+//! [`AuditLog::verify_chain`]. The log is kept by the stock once attached with
+//! [`super::Stock::attach_audit_log`]; the entries are appended only after the
+//! changes are committed to all providers. The log may be replayed against the
+//! stash with [`super::Stock::verify_audit_log`].
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use amplify::confinement::{LargeOrdSet, LargeVec, MediumOrdMap, MediumOrdSet, TinyOrdSet, U32};
+use amplify::Bytes32;
+use commit_verify::{CommitId, CommitmentId, DigestExt, Sha256};
+use rgb::validation::Validity;
+use rgb::{validation, BundleId, ContractId, SchemaId, SecretSeal, XChain, XWitnessId};
+use strict_encoding::{StrictDeserialize, StrictSerialize};
+
+use crate::containers::{BackupId, ConsignmentId, KitId};
+use crate::interface::IfaceId;
+use crate::LIB_NAME_RGB_STD;
+
+/// Audit log entry identifier.
+///
+/// Entry identifier commits to the identifier of the previous entry and to
+/// all data of the entry.
+#[derive(Wrapper, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, From, Display)]
+#[wrapper(Deref, BorrowSlice, Hex, Index, RangeOps)]
+#[display(LowerHex)]
+#[derive(StrictType, StrictDumb, StrictEncode, StrictDecode)]
+#[strict_type(lib = LIB_NAME_RGB_STD)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate", transparent)
+)]
+pub struct AuditEntryId(
+    #[from]
+    #[from([u8; 32])]
+    Bytes32,
+);
+
+impl From<Sha256> for AuditEntryId {
+    fn from(hasher: Sha256) -> Self { hasher.finish().into() }
+}
+
+impl CommitmentId for AuditEntryId {
+    const TAG: &'static str = "urn:lnp-bp:rgb:audit-entry#2024-10-16";
+}
+
+/// Result of the validation of the data imported into the stock.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Display)]
+#[derive(StrictType, StrictDumb, StrictEncode, StrictDecode)]
+#[strict_type(lib = LIB_NAME_RGB_STD, tags = repr, into_u8, try_from_u8)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+#[display(lowercase)]
+#[repr(u8)]
+pub enum AuditValidity {
+    /// The change doesn't involve validation of external data.
+    #[strict_type(dumb)]
+    NotApplicable = 0,
+
+    /// The imported data were fully valid.
+    Valid = 1,
+
+    /// The imported data were accepted with a validity other than
+    /// [`Validity::Valid`], for instance with unresolved witness transactions.
+    Partial = 2,
+}
+
+impl From<&validation::Status> for AuditValidity {
+    fn from(status: &validation::Status) -> Self {
+        if status.validity() == Validity::Valid {
+            AuditValidity::Valid
+        } else {
+            AuditValidity::Partial
+        }
+    }
+}
+
+/// State-changing operation performed on a stock.
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[derive(StrictType, StrictDumb, StrictEncode, StrictDecode)]
+#[strict_type(lib = LIB_NAME_RGB_STD, tags = order, dumb = StockMutation::StockMerged { contract_ids: none!() })]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+pub enum StockMutation {
+    /// Kit was imported with [`super::Stock::import_kit`].
+    KitImported {
+        kit_id: KitId,
+        ifaces: TinyOrdSet<IfaceId>,
+        schemata: TinyOrdSet<SchemaId>,
+    },
+
+    /// Contract consignment was imported with
+    /// [`super::Stock::import_contract`].
+    ContractImported {
+        contract_id: ContractId,
+        consignment_id: ConsignmentId,
+        witness_ids: LargeOrdSet<XWitnessId>,
+    },
+
+    /// Transfer consignment was accepted with
+    /// [`super::Stock::accept_transfer`].
+    TransferAccepted {
+        contract_id: ContractId,
+        consignment_id: ConsignmentId,
+        witness_ids: LargeOrdSet<XWitnessId>,
+    },
+
+    /// Fascia was consumed with [`super::Stock::consume_fascia`].
+    FasciaConsumed {
+        witness_id: XWitnessId,
+        bundles: MediumOrdMap<ContractId, BundleId>,
+    },
+
+    /// Secret seals were added to the stash.
+    SecretSealsStored {
+        seals: LargeOrdSet<XChain<SecretSeal>>,
+    },
+
+    /// Expired secret seals were removed with
+    /// [`super::Stock::prune_secret_seals`].
+    SecretSealsPruned {
+        seals: LargeOrdSet<XChain<SecretSeal>>,
+    },
+
+    /// Contract was removed with [`super::Stock::forget_contract`], together
+    /// with the listed witnesses.
+    ContractRemoved {
+        contract_id: ContractId,
+        witness_ids: LargeOrdSet<XWitnessId>,
+    },
+
+    /// Data of another stock containing the listed contracts were merged with
+    /// [`super::Stock::merge`].
+    StockMerged {
+        contract_ids: MediumOrdSet<ContractId>,
+    },
+
+    /// Data from the backups were restored with [`super::Stock::restore`].
+    BackupRestored { backup_ids: LargeVec<BackupId> },
+
+    /// Contract was exported and removed with
+    /// [`super::Stock::archive_contract`], together with the listed
+    /// witnesses.
+    ContractArchived {
+        contract_id: ContractId,
+        witness_ids: LargeOrdSet<XWitnessId>,
+    },
+
+    /// Contract state was updated after re-resolving the listed witnesses with
+    /// [`super::Stock::update_witnesses`].
+    WitnessesUpdated {
+        witness_ids: LargeOrdSet<XWitnessId>,
+    },
+
+    /// Index and/or contract state were re-computed from the stash with
+    /// [`super::Stock::rebuild`], [`super::Stock::rebuild_index`] or
+    /// [`super::Stock::rebuild_state`].
+    StockRebuilt { index: bool, state: bool },
+}
+
+/// Audit log entry.
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[derive(StrictType, StrictDumb, StrictEncode, StrictDecode)]
+#[strict_type(lib = LIB_NAME_RGB_STD)]
+#[derive(CommitEncode)]
+#[commit_encode(strategy = strict, id = AuditEntryId)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+pub struct AuditEntry {
+    /// Identifier of the previous entry, or `None` for the first entry of the
+    /// log.
+    pub prev: Option<AuditEntryId>,
+    /// UNIX timestamp of the change.
+    pub timestamp: i64,
+    pub validity: AuditValidity,
+    pub mutation: StockMutation,
+}
+
+impl AuditEntry {
+    #[inline]
+    pub fn entry_id(&self) -> AuditEntryId { self.commit_id() }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum AuditLogError {
+    /// audit log entry #{0} doesn't follow the previous entry.
+    BrokenChain(usize),
+
+    /// audit log has too many entries.
+    TooManyEntries,
+
+    /// data of the audit log entry exceed the collection limits.
+    EntryOverflow,
+
+    /// contract {0} recorded in the audit log is absent from the stash.
+    ContractAbsent(ContractId),
+
+    /// contract {0} removed according to the audit log is present in the stash.
+    ContractPresent(ContractId),
+
+    /// witness {0} recorded in the audit log is absent from the stash.
+    WitnessAbsent(XWitnessId),
+
+    /// bundle {0} recorded in the audit log is absent from the stash.
+    BundleAbsent(BundleId),
+
+    /// interface {0} recorded in the audit log is absent from the stash.
+    IfaceAbsent(IfaceId),
+
+    /// schema {0} recorded in the audit log is absent from the stash.
+    SchemaAbsent(SchemaId),
+
+    /// secret seal {0} recorded in the audit log is absent from the stash.
+    SealAbsent(XChain<SecretSeal>),
+
+    /// secret seal {0} removed according to the audit log is present in the
+    /// stash.
+    SealPresent(XChain<SecretSeal>),
+}
+
+/// Append-only hash-chained log of the stock changes.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+#[derive(StrictType, StrictDumb, StrictEncode, StrictDecode)]
+#[strict_type(lib = LIB_NAME_RGB_STD)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+pub struct AuditLog {
+    entries: LargeVec<AuditEntry>,
+}
+
+impl StrictSerialize for AuditLog {}
+impl StrictDeserialize for AuditLog {}
+
+impl AuditLog {
+    pub fn new() -> Self { AuditLog::default() }
+
+    /// Constructs log from previously persisted entries, checking that they
+    /// form a valid chain.
+    pub fn with(entries: impl IntoIterator<Item = AuditEntry>) -> Result<Self, AuditLogError> {
+        let entries =
+            LargeVec::try_from_iter(entries).map_err(|_| AuditLogError::TooManyEntries)?;
+        let log = AuditLog { entries };
+        log.verify_chain()?;
+        Ok(log)
+    }
+
+    #[inline]
+    pub fn entries(&self) -> &[AuditEntry] { self.entries.as_slice() }
+
+    #[inline]
+    pub fn len(&self) -> usize { self.entries.len() }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool { self.entries.is_empty() }
+
+    /// Checks whether the log has reached the maximal number of entries, such
+    /// that no more changes can be appended.
+    #[inline]
+    pub fn is_full(&self) -> bool { self.entries.len() >= U32 }
+
+    /// Returns identifier of the last entry, which commits to the whole log.
+    pub fn head(&self) -> Option<AuditEntryId> { self.entries.last().map(AuditEntry::entry_id) }
+
+    /// Appends a new entry to the log, returning its identifier.
+    pub fn append(
+        &mut self,
+        timestamp: i64,
+        validity: AuditValidity,
+        mutation: StockMutation,
+    ) -> Result<AuditEntryId, AuditLogError> {
+        let entry = AuditEntry {
+            prev: self.head(),
+            timestamp,
+            validity,
+            mutation,
+        };
+        let id = entry.entry_id();
+        self.entries
+            .push(entry)
+            .map_err(|_| AuditLogError::TooManyEntries)?;
+        Ok(id)
+    }
+
+    /// Checks that each of the entries commits to the previous one.
+    pub fn verify_chain(&self) -> Result<(), AuditLogError> {
+        let mut prev = None;
+        for (no, entry) in self.entries.iter().enumerate() {
+            if entry.prev != prev {
+                return Err(AuditLogError::BrokenChain(no));
+            }
+            prev = Some(entry.entry_id());
+        }
+        Ok(())
+    }
+
+    /// Replays the log, computing which data must be present in or absent from
+    /// the stash after all the logged changes.
+    pub(super) fn replay(&self) -> AuditReplay {
+        let mut replay = AuditReplay::default();
+        for entry in &self.entries {
+            match &entry.mutation {
+                StockMutation::KitImported {
+                    ifaces, schemata, ..
+                } => {
+                    replay.ifaces.extend(ifaces);
+                    replay.schemata.extend(schemata);
+                }
+                StockMutation::ContractImported {
+                    contract_id,
+                    witness_ids,
+                    ..
+                } |
+                StockMutation::TransferAccepted {
+                    contract_id,
+                    witness_ids,
+                    ..
+                } => {
+                    replay.contracts.insert(*contract_id, true);
+                    replay.witnesses.extend(witness_ids);
+                }
+                StockMutation::FasciaConsumed {
+                    witness_id,
+                    bundles,
+                } => {
+                    replay.witnesses.insert(*witness_id);
+                    for (contract_id, bundle_id) in bundles.iter() {
+                        replay.bundles.insert(*bundle_id, *contract_id);
+                    }
+                }
+                StockMutation::SecretSealsStored { seals } => {
+                    replay.seals.extend(seals.iter().map(|seal| (*seal, true)));
+                }
+                StockMutation::SecretSealsPruned { seals } => {
+                    replay.seals.extend(seals.iter().map(|seal| (*seal, false)));
+                }
+                StockMutation::ContractRemoved {
+                    contract_id,
+                    witness_ids,
+                } |
+                StockMutation::ContractArchived {
+                    contract_id,
+                    witness_ids,
+                } => {
+                    replay.contracts.insert(*contract_id, false);
+                    replay
+                        .bundles
+                        .retain(|_, bundle_contract| bundle_contract != contract_id);
+                    for witness_id in witness_ids {
+                        replay.witnesses.remove(witness_id);
+                    }
+                }
+                StockMutation::StockMerged { contract_ids } => {
+                    replay
+                        .contracts
+                        .extend(contract_ids.iter().map(|id| (*id, true)));
+                }
+                StockMutation::BackupRestored { .. } => {
+                    // Backups may bring back any of the removed data
+                    replay.contracts.retain(|_, present| *present);
+                    replay.seals.retain(|_, present| *present);
+                }
+                StockMutation::WitnessesUpdated { .. } | StockMutation::StockRebuilt { .. } => {
+                    // Only the contract state and index are changed
+                }
+            }
+        }
+        replay
+    }
+}
+
+/// Data which must be present in (`true`) or absent from (`false`) the stash
+/// according to the audit log.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub(super) struct AuditReplay {
+    pub(super) ifaces: BTreeSet<IfaceId>,
+    pub(super) schemata: BTreeSet<SchemaId>,
+    pub(super) contracts: BTreeMap<ContractId, bool>,
+    pub(super) witnesses: BTreeSet<XWitnessId>,
+    pub(super) bundles: BTreeMap<BundleId, ContractId>,
+    pub(super) seals: BTreeMap<XChain<SecretSeal>, bool>,
+}
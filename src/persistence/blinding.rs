@@ -0,0 +1,295 @@
+// RGB standard library for working with smart contracts on Bitcoin & Lightning
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2019-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2019-2024 LNP/BP Standards Association. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Deterministic derivation of seal and Pedersen commitment blinding factors
+//! from a wallet seed.
+//!
+//! Blinding factors are derived from the seed, contract id, assignment type
+//! and a counter, which is increased each time a new factor is taken for the
+//! same contract and assignment type. Thus, a wallet which has lost its
+//! secret seals can re-derive them from the seed (see
+//! [`super::Stock::recover_seals`]). Invoices paying to such seals are created
+//! with [`super::Stock::seeded_invoice`].
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt::{self, Debug, Formatter};
+
+use amplify::{ByteArray, Wrapper};
+use bp::seals::txout::CloseMethod;
+use commit_verify::{DigestExt, Sha256};
+use rgb::{AssignmentType, BlindingFactor, ContractId, GraphSeal, XChain, XOutpoint};
+
+/// Errors deriving blinding factors from a wallet seed.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum BlindingError {
+    /// all blinding factors for assignment type #{1} of contract {0} were
+    /// already used.
+    CounterOverflow(ContractId, AssignmentType),
+}
+
+const SEAL_TAG: &[u8] = b"urn:lnp-bp:rgb:blinding:seal#2024-04-01";
+const PEDERSEN_TAG: &[u8] = b"urn:lnp-bp:rgb:blinding:pedersen#2024-04-01";
+
+/// Source of blinding factors derived deterministically from a wallet seed.
+///
+/// Each call to [`SeedBlinder::next_seal_blinding`] or
+/// [`SeedBlinder::next_pedersen_blinding`] takes the next counter value for
+/// the contract and assignment type. The counters must be persisted by the
+/// wallet (see [`SeedBlinder::counters`]); if they are lost, seals can still be
+/// found with a recovery scan.
+pub struct SeedBlinder {
+    seed: [u8; 32],
+    counters: RefCell<BTreeMap<(ContractId, AssignmentType), u32>>,
+}
+
+impl Debug for SeedBlinder {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SeedBlinder")
+            .field("seed", &"..")
+            .field("counters", &self.counters.borrow())
+            .finish()
+    }
+}
+
+impl SeedBlinder {
+    /// Constructs blinder with all counters starting from zero.
+    pub fn new(seed: [u8; 32]) -> Self { Self::with_counters(seed, none!()) }
+
+    /// Constructs blinder continuing from the previously persisted counters.
+    pub fn with_counters(
+        seed: [u8; 32],
+        counters: BTreeMap<(ContractId, AssignmentType), u32>,
+    ) -> Self {
+        SeedBlinder {
+            seed,
+            counters: RefCell::new(counters),
+        }
+    }
+
+    /// Returns the next counter value to be used for each of the contracts and
+    /// assignment types, which should be persisted by the wallet.
+    pub fn counters(&self) -> BTreeMap<(ContractId, AssignmentType), u32> {
+        self.counters.borrow().clone()
+    }
+
+    /// Ensures that the next counter for the contract and assignment type is
+    /// not less than `counter`.
+    pub fn advance(&self, contract_id: ContractId, assignment_type: AssignmentType, counter: u32) {
+        let mut counters = self.counters.borrow_mut();
+        let next = counters.entry((contract_id, assignment_type)).or_default();
+        *next = (*next).max(counter);
+    }
+
+    fn next_counter(
+        &self,
+        contract_id: ContractId,
+        assignment_type: AssignmentType,
+    ) -> Result<u32, BlindingError> {
+        let mut counters = self.counters.borrow_mut();
+        let next = counters.entry((contract_id, assignment_type)).or_default();
+        let counter = *next;
+        *next = next
+            .checked_add(1)
+            .ok_or(BlindingError::CounterOverflow(contract_id, assignment_type))?;
+        Ok(counter)
+    }
+
+    fn hash(
+        &self,
+        tag: &[u8],
+        contract_id: ContractId,
+        assignment_type: AssignmentType,
+        counter: u32,
+        attempt: u8,
+    ) -> [u8; 32] {
+        let mut hasher = Sha256::default();
+        hasher.input_raw(tag);
+        hasher.input_raw(&self.seed);
+        hasher.input_raw(&contract_id.to_byte_array());
+        hasher.input_raw(&assignment_type.to_inner().to_le_bytes());
+        hasher.input_raw(&counter.to_le_bytes());
+        hasher.input_raw(&[attempt]);
+        hasher.finish()
+    }
+
+    /// Derives seal blinding factor for a specific counter value.
+    pub fn seal_blinding(
+        &self,
+        contract_id: ContractId,
+        assignment_type: AssignmentType,
+        counter: u32,
+    ) -> u64 {
+        let hash = self.hash(SEAL_TAG, contract_id, assignment_type, counter, 0);
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&hash[..8]);
+        u64::from_le_bytes(bytes)
+    }
+
+    /// Derives Pedersen commitment blinding factor for a specific counter
+    /// value.
+    pub fn pedersen_blinding(
+        &self,
+        contract_id: ContractId,
+        assignment_type: AssignmentType,
+        counter: u32,
+    ) -> BlindingFactor {
+        // A hash value may exceed the curve order with a negligible
+        // probability, in which case we re-hash with the next attempt number.
+        for attempt in 0..=u8::MAX {
+            let hash = self.hash(PEDERSEN_TAG, contract_id, assignment_type, counter, attempt);
+            if let Ok(blinding) = BlindingFactor::try_from(hash) {
+                return blinding;
+            }
+        }
+        unreachable!("256 consecutive hashes are outside of the secp256k1 field")
+    }
+
+    /// Derives seal blinding factor using the next counter value.
+    pub fn next_seal_blinding(
+        &self,
+        contract_id: ContractId,
+        assignment_type: AssignmentType,
+    ) -> Result<u64, BlindingError> {
+        let counter = self.next_counter(contract_id, assignment_type)?;
+        Ok(self.seal_blinding(contract_id, assignment_type, counter))
+    }
+
+    /// Derives Pedersen commitment blinding factor using the next counter
+    /// value.
+    pub fn next_pedersen_blinding(
+        &self,
+        contract_id: ContractId,
+        assignment_type: AssignmentType,
+    ) -> Result<BlindingFactor, BlindingError> {
+        let counter = self.next_counter(contract_id, assignment_type)?;
+        Ok(self.pedersen_blinding(contract_id, assignment_type, counter))
+    }
+
+    /// Constructs seal definition for a specific counter value.
+    pub fn seal(
+        &self,
+        contract_id: ContractId,
+        assignment_type: AssignmentType,
+        counter: u32,
+        method: CloseMethod,
+        outpoint: XOutpoint,
+    ) -> XChain<GraphSeal> {
+        let blinding = self.seal_blinding(contract_id, assignment_type, counter);
+        outpoint.map_ref(|o| GraphSeal::with_blinding(method, o.txid, o.vout, blinding))
+    }
+
+    /// Derives seal blinding factor using the next counter value.
+    pub fn next_seal_blinding(
+        &self,
+        contract_id: ContractId,
+        assignment_type: AssignmentType,
+    ) -> Result<u64, BlindingError> {
+        let counter = self.next_counter(contract_id, assignment_type)?;
+        Ok(self.seal_blinding(contract_id, assignment_type, counter))
+    }
+
+    /// Constructs seal definition using the next counter value.
+    pub fn next_seal(
+        &self,
+        contract_id: ContractId,
+        assignment_type: AssignmentType,
+        method: CloseMethod,
+        outpoint: XOutpoint,
+    ) -> Result<XChain<GraphSeal>, BlindingError> {
+        let counter = self.next_counter(contract_id, assignment_type)?;
+        Ok(self.seal(contract_id, assignment_type, counter, method, outpoint))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bp::{Outpoint, Txid};
+
+    use super::*;
+
+    fn contract() -> (ContractId, AssignmentType) {
+        (ContractId::from_byte_array([1u8; 32]), AssignmentType::from_inner(4000))
+    }
+
+    #[test]
+    fn counters() {
+        let (contract_id, assignment_type) = contract();
+        let blinder = SeedBlinder::new([2u8; 32]);
+        let first = blinder
+            .next_seal_blinding(contract_id, assignment_type)
+            .unwrap();
+        let second = blinder
+            .next_seal_blinding(contract_id, assignment_type)
+            .unwrap();
+        assert_ne!(first, second);
+        assert_eq!(first, blinder.seal_blinding(contract_id, assignment_type, 0));
+        assert_eq!(second, blinder.seal_blinding(contract_id, assignment_type, 1));
+        assert_eq!(blinder.counters(), bmap! { (contract_id, assignment_type) => 2 });
+
+        let restored = SeedBlinder::with_counters([2u8; 32], blinder.counters());
+        assert_eq!(
+            restored.next_seal_blinding(contract_id, assignment_type),
+            Ok(blinder.seal_blinding(contract_id, assignment_type, 2))
+        );
+    }
+
+    #[test]
+    fn recover_seal() {
+        let (contract_id, assignment_type) = contract();
+        let outpoint = XChain::Bitcoin(Outpoint::new(Txid::coinbase(), 1u32));
+        let seal = SeedBlinder::new([2u8; 32])
+            .next_seal(contract_id, assignment_type, CloseMethod::TapretFirst, outpoint)
+            .unwrap();
+
+        let recovered = SeedBlinder::new([2u8; 32]).seal(
+            contract_id,
+            assignment_type,
+            0,
+            CloseMethod::TapretFirst,
+            outpoint,
+        );
+        assert_eq!(recovered, seal);
+        let other = SeedBlinder::new([4u8; 32]).seal(
+            contract_id,
+            assignment_type,
+            0,
+            CloseMethod::TapretFirst,
+            outpoint,
+        );
+        assert_ne!(other, seal);
+    }
+
+    #[test]
+    fn counter_overflow() {
+        let (contract_id, assignment_type) = contract();
+        let blinder = SeedBlinder::with_counters(
+            [2u8; 32],
+            bmap! { (contract_id, assignment_type) => u32::MAX },
+        );
+        assert_eq!(
+            blinder.next_pedersen_blinding(contract_id, assignment_type),
+            Err(BlindingError::CounterOverflow(contract_id, assignment_type))
+        );
+        assert_eq!(blinder.counters()[&(contract_id, assignment_type)], u32::MAX);
+    }
+}
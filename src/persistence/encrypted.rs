@@ -0,0 +1,701 @@
+// RGB standard library for working with smart contracts on Bitcoin & Lightning
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2019-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2019-2024 LNP/BP Standards Association. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Encrypted-at-rest file system persistence for the stock and in-memory
+//! providers.
+//!
+//! Each data file is encrypted as a whole with XChaCha20-Poly1305, using a key
+//! which is either provided directly or derived from a password with Argon2id
+//! and a random salt. The encrypted file starts with a plaintext header made of
+//! the `RGB\0` prefix, `ENC` magic bytes, envelope version, key derivation
+//! method and its parameters, generation of the stock data, salt and nonce; the
+//! header is authenticated together with the ciphertext and the file name, so
+//! any modification of the file, as well as its renaming or replacing with a
+//! file from some other stored generation, is detected on load. The decrypted
+//! data are the same as the content of the unencrypted data file, including its
+//! own versioned header.
+//!
+//! The stock directory layout is the same as for the unencrypted storage (see
+//! [`super::fs`]), but the manifest is encrypted with the same key as the data
+//! files, which authenticates it.
+//!
+//! Keys are derived once per load or store operation, which is represented by
+//! [`StockCipher`]; all files written by an operation share the salt.
+//! Unencrypted stock can be encrypted by loading it with [`LoadFs`] and
+//! storing with [`StoreEncryptedFs`]; the key of encrypted stock is changed
+//! with [`rekey`].
+//!
+//! [`LoadFs`]: super::fs::LoadFs
+
+use std::cell::RefCell;
+use std::fmt::{self, Debug, Formatter};
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+use std::rc::Rc;
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use rand::RngCore;
+use zeroize::{Zeroize, Zeroizing};
+
+use crate::containers::{MAGIC_LEN, RGB_PREFIX};
+use crate::persistence::fs::{
+    store_stock_data, verified_manifest, FsError, ManifestCodec, StockManifest, VersionedFile,
+    STOCK_MANIFEST_FILE,
+};
+use crate::persistence::{
+    IndexProvider, MemIndex, MemStash, MemState, StashProvider, StateProvider, Stock,
+};
+
+const ENCRYPTED_MAGIC: [u8; MAGIC_LEN] = *b"ENC";
+const ENCRYPTED_VERSION: u16 = 1;
+
+const KDF_RAW: u8 = 0;
+const KDF_ARGON2ID: u8 = 1;
+
+const KDF_PARAMS_LEN: usize = 12;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+const HEADER_LEN: usize =
+    RGB_PREFIX.len() + MAGIC_LEN + 2 + 1 + KDF_PARAMS_LEN + 8 + SALT_LEN + NONCE_LEN;
+
+/// Parameters of the key derivation: Argon2id memory size in KiB, number of
+/// iterations and degree of parallelism.
+///
+/// The parameters are stored in the header of each encrypted file, so files
+/// encrypted with other parameters are still decrypted after the parameters
+/// used for new files are changed.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+struct KdfParams {
+    m_cost: u32,
+    t_cost: u32,
+    p_cost: u32,
+}
+
+impl KdfParams {
+    const ARGON2ID: Self = KdfParams {
+        m_cost: 19 * 1024,
+        t_cost: 2,
+        p_cost: 1,
+    };
+
+    /// Parameters of the raw keys, which are not derived.
+    const NONE: Self = KdfParams {
+        m_cost: 0,
+        t_cost: 0,
+        p_cost: 0,
+    };
+
+    fn to_bytes(self) -> [u8; KDF_PARAMS_LEN] {
+        let mut bytes = [0u8; KDF_PARAMS_LEN];
+        bytes[..4].copy_from_slice(&self.m_cost.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.t_cost.to_le_bytes());
+        bytes[8..].copy_from_slice(&self.p_cost.to_le_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Self {
+        let u32 = |pos: usize| {
+            u32::from_le_bytes([bytes[pos], bytes[pos + 1], bytes[pos + 2], bytes[pos + 3]])
+        };
+        KdfParams {
+            m_cost: u32(0),
+            t_cost: u32(4),
+            p_cost: u32(8),
+        }
+    }
+}
+
+/// Key used to encrypt stock data files.
+///
+/// The key material is zeroized when the value is dropped.
+#[derive(Clone, Eq, PartialEq)]
+pub enum StoreKey {
+    /// Password from which the encryption key is derived with Argon2id using
+    /// a random salt.
+    Password(String),
+    /// Raw 256-bit encryption key, used as is.
+    Raw([u8; 32]),
+}
+
+impl Drop for StoreKey {
+    fn drop(&mut self) {
+        match self {
+            StoreKey::Password(password) => password.zeroize(),
+            StoreKey::Raw(key) => key.zeroize(),
+        }
+    }
+}
+
+impl Debug for StoreKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreKey::Password(_) => f.write_str("StoreKey::Password(..)"),
+            StoreKey::Raw(_) => f.write_str("StoreKey::Raw(..)"),
+        }
+    }
+}
+
+impl StoreKey {
+    fn kdf(&self) -> u8 {
+        match self {
+            StoreKey::Password(_) => KDF_ARGON2ID,
+            StoreKey::Raw(_) => KDF_RAW,
+        }
+    }
+
+    /// Returns key derivation parameters used for the newly encrypted files.
+    fn kdf_params(&self) -> KdfParams {
+        match self {
+            StoreKey::Password(_) => KdfParams::ARGON2ID,
+            StoreKey::Raw(_) => KdfParams::NONE,
+        }
+    }
+
+    fn derive(&self, salt: &[u8], params: KdfParams) -> Result<Zeroizing<[u8; 32]>, FsError> {
+        let mut key = Zeroizing::new([0u8; 32]);
+        match self {
+            StoreKey::Raw(raw) => key.copy_from_slice(raw),
+            StoreKey::Password(password) => {
+                let params = Params::new(params.m_cost, params.t_cost, params.p_cost, Some(32))
+                    .map_err(|err| FsError::KeyDerivation(err.to_string()))?;
+                Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+                    .hash_password_into(password.as_bytes(), salt, &mut key[..])
+                    .map_err(|err| FsError::KeyDerivation(err.to_string()))?
+            }
+        }
+        Ok(key)
+    }
+}
+
+/// Encryption key of a single load or store operation.
+///
+/// The key is derived from [`StoreKey`] on the first use and then reused for
+/// the rest of the files, including by the copies of the cipher made for a
+/// specific generation of stock data with [`StockCipher::with_generation`].
+/// When decrypting, the key is derived again only if a file uses a different
+/// salt or key derivation parameters.
+#[derive(Clone, Debug)]
+pub struct StockCipher<'key> {
+    key: &'key StoreKey,
+    kdf_params: KdfParams,
+    generation: u64,
+    derived: Rc<RefCell<Option<DerivedKey>>>,
+}
+
+struct DerivedKey {
+    salt: [u8; SALT_LEN],
+    params: KdfParams,
+    key: Zeroizing<[u8; 32]>,
+}
+
+impl Debug for DerivedKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result { f.write_str("DerivedKey(..)") }
+}
+
+impl<'key> StockCipher<'key> {
+    /// Constructs cipher for the stock data of the generation `0`.
+    pub fn new(key: &'key StoreKey) -> Self {
+        StockCipher {
+            key,
+            kdf_params: key.kdf_params(),
+            generation: 0,
+            derived: none!(),
+        }
+    }
+
+    /// Constructs cipher for the stock data of the given generation sharing
+    /// the key derived by this cipher.
+    pub fn with_generation(&self, generation: u64) -> Self {
+        StockCipher {
+            key: self.key,
+            kdf_params: self.kdf_params,
+            generation,
+            derived: self.derived.clone(),
+        }
+    }
+
+    /// Returns generation of the stock data processed by this cipher.
+    pub fn generation(&self) -> u64 { self.generation }
+
+    /// Encrypts content of the stock data file with the given name, producing
+    /// content of the encrypted file.
+    pub fn encrypt(&self, name: &str, data: &[u8]) -> Result<Vec<u8>, FsError> {
+        self.encrypt_with(name, self.generation, data)
+    }
+
+    /// Decrypts content of the encrypted stock data file with the given name,
+    /// checking that neither the header nor the encrypted data were modified
+    /// and that the file belongs to the generation of this cipher.
+    pub fn decrypt(&self, name: &str, file: &[u8]) -> Result<Vec<u8>, FsError> {
+        let (generation, data) = self.decrypt_with(name, name, file)?;
+        if generation != self.generation {
+            return Err(FsError::Decryption(name.to_owned()));
+        }
+        Ok(data)
+    }
+
+    fn context(name: &str) -> Vec<u8> {
+        let mut context = Vec::with_capacity(name.len() + 2);
+        context.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        context.extend_from_slice(name.as_bytes());
+        context
+    }
+
+    fn cipher(
+        &self,
+        salt: &[u8; SALT_LEN],
+        params: KdfParams,
+    ) -> Result<XChaCha20Poly1305, FsError> {
+        let mut derived = self.derived.borrow_mut();
+        if derived
+            .as_ref()
+            .map(|derived| (derived.salt, derived.params)) !=
+            Some((*salt, params))
+        {
+            *derived = Some(DerivedKey {
+                salt: *salt,
+                params,
+                key: self.key.derive(salt, params)?,
+            });
+        }
+        let key = &derived.as_ref().expect("key is derived above").key;
+        Ok(XChaCha20Poly1305::new(Key::from_slice(&key[..])))
+    }
+
+    fn encrypt_with(&self, name: &str, generation: u64, data: &[u8]) -> Result<Vec<u8>, FsError> {
+        let mut rng = rand::thread_rng();
+        let salt = match &*self.derived.borrow() {
+            Some(derived) if derived.params == self.kdf_params => derived.salt,
+            _ => {
+                let mut salt = [0u8; SALT_LEN];
+                if self.key.kdf() != KDF_RAW {
+                    rng.fill_bytes(&mut salt);
+                }
+                salt
+            }
+        };
+        let mut nonce = [0u8; NONCE_LEN];
+        rng.fill_bytes(&mut nonce);
+
+        let mut file = Vec::with_capacity(HEADER_LEN + data.len() + 16);
+        file.extend_from_slice(&RGB_PREFIX);
+        file.extend_from_slice(&ENCRYPTED_MAGIC);
+        file.extend_from_slice(&ENCRYPTED_VERSION.to_le_bytes());
+        file.push(self.key.kdf());
+        file.extend_from_slice(&self.kdf_params.to_bytes());
+        file.extend_from_slice(&generation.to_le_bytes());
+        file.extend_from_slice(&salt);
+        file.extend_from_slice(&nonce);
+        debug_assert_eq!(file.len(), HEADER_LEN);
+
+        let cipher = self.cipher(&salt, self.kdf_params)?;
+        let aad = [file.as_slice(), &Self::context(name)].concat();
+        let payload = Payload {
+            msg: data,
+            aad: &aad,
+        };
+        let ciphertext = cipher
+            .encrypt(XNonce::from_slice(&nonce), payload)
+            .expect("encryption of in-memory data can't fail");
+        file.extend(ciphertext);
+        Ok(file)
+    }
+
+    /// Decrypts the file, returning generation of the stock data it belongs
+    /// to. The `name` is used only for error reporting, while `stored_name` is
+    /// the name with which the file was encrypted.
+    fn decrypt_with(
+        &self,
+        name: &str,
+        stored_name: &str,
+        file: &[u8],
+    ) -> Result<(u64, Vec<u8>), FsError> {
+        let prefix_len = RGB_PREFIX.len();
+        if file.len() < HEADER_LEN ||
+            file[..prefix_len] != RGB_PREFIX ||
+            file[prefix_len..prefix_len + MAGIC_LEN] != ENCRYPTED_MAGIC
+        {
+            return Err(FsError::NotEncrypted(name.to_owned()));
+        }
+        let mut pos = prefix_len + MAGIC_LEN;
+        let version = u16::from_le_bytes([file[pos], file[pos + 1]]);
+        if version != ENCRYPTED_VERSION {
+            return Err(FsError::UnsupportedVersion {
+                file: name.to_owned(),
+                version,
+            });
+        }
+        pos += 2;
+        let kdf = file[pos];
+        if kdf != KDF_RAW && kdf != KDF_ARGON2ID {
+            return Err(FsError::UnknownKdf {
+                file: name.to_owned(),
+                kdf,
+            });
+        }
+        if kdf != self.key.kdf() {
+            return Err(FsError::Decryption(name.to_owned()));
+        }
+        pos += 1;
+        let params = KdfParams::from_bytes(&file[pos..pos + KDF_PARAMS_LEN]);
+        pos += KDF_PARAMS_LEN;
+        let mut generation = [0u8; 8];
+        generation.copy_from_slice(&file[pos..pos + 8]);
+        pos += 8;
+        let mut salt = [0u8; SALT_LEN];
+        salt.copy_from_slice(&file[pos..pos + SALT_LEN]);
+        pos += SALT_LEN;
+        let nonce = &file[pos..pos + NONCE_LEN];
+
+        let cipher = self.cipher(&salt, params)?;
+        let aad = [&file[..HEADER_LEN], &Self::context(stored_name)].concat();
+        let payload = Payload {
+            msg: &file[HEADER_LEN..],
+            aad: &aad,
+        };
+        let data = cipher
+            .decrypt(XNonce::from_slice(nonce), payload)
+            .map_err(|_| FsError::Decryption(name.to_owned()))?;
+        Ok((u64::from_le_bytes(generation), data))
+    }
+}
+
+impl ManifestCodec for StockCipher<'_> {
+    fn seal(&self, generation: u64, data: Vec<u8>) -> Result<Vec<u8>, FsError> {
+        self.encrypt_with(STOCK_MANIFEST_FILE, generation, &data)
+    }
+
+    fn open(&self, name: &str, file: Vec<u8>) -> Result<Vec<u8>, FsError> {
+        let (generation, data) = self.decrypt_with(name, STOCK_MANIFEST_FILE, &file)?;
+        // The generation from the authenticated header must be the one of the
+        // manifest itself
+        let manifest = StockManifest::from_file_data(name.to_owned(), data.clone())?;
+        if manifest.generation != generation {
+            return Err(FsError::Decryption(name.to_owned()));
+        }
+        Ok(data)
+    }
+}
+
+pub trait LoadEncryptedFs: Sized {
+    fn load_encrypted(path: impl AsRef<Path>, cipher: &StockCipher) -> Result<Self, FsError>;
+}
+
+pub trait StoreEncryptedFs {
+    fn store_encrypted(&self, path: impl AsRef<Path>, cipher: &StockCipher) -> Result<(), FsError>;
+}
+
+fn read_encrypted<T: VersionedFile>(
+    dir: &Path,
+    name: &str,
+    cipher: &StockCipher,
+) -> Result<T, FsError> {
+    let data = cipher.decrypt(name, &fs::read(dir.join(name))?)?;
+    T::from_file_data(name.to_owned(), data)
+}
+
+fn write_encrypted<T: VersionedFile>(
+    val: &T,
+    dir: &Path,
+    name: &str,
+    cipher: &StockCipher,
+) -> Result<(), FsError> {
+    let data = cipher.encrypt(name, &val.to_file_data()?)?;
+    let mut file = File::create(dir.join(name))?;
+    file.write_all(&data)?;
+    file.flush()?;
+    Ok(())
+}
+
+impl<S: StashProvider, H: StateProvider, I: IndexProvider> LoadEncryptedFs for Stock<S, H, I>
+where
+    S: LoadEncryptedFs,
+    H: LoadEncryptedFs,
+    I: LoadEncryptedFs,
+{
+    /// Loads encrypted stock from the directory, verifying the stock manifest
+    /// and data files against it.
+    fn load_encrypted(path: impl AsRef<Path>, cipher: &StockCipher) -> Result<Self, FsError> {
+        let path = path.as_ref();
+        let manifest = verified_manifest(path, cipher)?
+            .ok_or_else(|| FsError::ManifestAbsent(path.display().to_string()))?;
+        let dir = path.join(manifest.data_dir());
+        let cipher = cipher.with_generation(manifest.generation);
+
+        let stash = S::load_encrypted(&dir, &cipher)?;
+        let state = H::load_encrypted(&dir, &cipher)?;
+        let index = I::load_encrypted(&dir, &cipher)?;
+
+        Ok(Stock::with(stash, state, index))
+    }
+}
+
+impl<S: StashProvider, H: StateProvider, I: IndexProvider> StoreEncryptedFs for Stock<S, H, I>
+where
+    S: StoreEncryptedFs,
+    H: StoreEncryptedFs,
+    I: StoreEncryptedFs,
+{
+    /// Stores encrypted stock into the directory such that a failure at any
+    /// point leaves the previously stored data intact.
+    fn store_encrypted(&self, path: impl AsRef<Path>, cipher: &StockCipher) -> Result<(), FsError> {
+        store_stock_data(path.as_ref(), cipher, |dir, generation| {
+            let cipher = cipher.with_generation(generation);
+            self.as_stash_provider().store_encrypted(dir, &cipher)?;
+            self.as_state_provider().store_encrypted(dir, &cipher)?;
+            self.as_index_provider().store_encrypted(dir, &cipher)
+        })
+    }
+}
+
+/// Re-encrypts all data files of the stock stored in the directory with a new
+/// key.
+///
+/// The operation doesn't decode the data and works for any providers; it is
+/// atomic in the same way as storing the stock: on failure the stock remains
+/// encrypted with the old key.
+pub fn rekey(path: impl AsRef<Path>, old: &StoreKey, new: &StoreKey) -> Result<(), FsError> {
+    let path = path.as_ref();
+    let old = StockCipher::new(old);
+    let manifest = verified_manifest(path, &old)?
+        .ok_or_else(|| FsError::ManifestAbsent(path.display().to_string()))?;
+    let src = path.join(manifest.data_dir());
+    let old = old.with_generation(manifest.generation);
+
+    let mut files = Vec::new();
+    for entry in fs::read_dir(&src)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_file() {
+            continue;
+        }
+        let name = entry.file_name().to_string_lossy().to_string();
+        let data = Zeroizing::new(old.decrypt(&name, &fs::read(entry.path())?)?);
+        files.push((name, data));
+    }
+
+    let new = StockCipher::new(new);
+    store_stock_data(path, &new, |dir, generation| {
+        let new = new.with_generation(generation);
+        for (name, data) in files {
+            let data = new.encrypt(&name, &data)?;
+            let mut file = File::create(dir.join(name))?;
+            file.write_all(&data)?;
+            file.flush()?;
+        }
+        Ok(())
+    })
+}
+
+impl LoadEncryptedFs for MemStash {
+    fn load_encrypted(path: impl AsRef<Path>, cipher: &StockCipher) -> Result<Self, FsError> {
+        read_encrypted(path.as_ref(), "stash.dat", cipher)
+    }
+}
+
+impl StoreEncryptedFs for MemStash {
+    fn store_encrypted(&self, path: impl AsRef<Path>, cipher: &StockCipher) -> Result<(), FsError> {
+        write_encrypted(self, path.as_ref(), "stash.dat", cipher)
+    }
+}
+
+impl LoadEncryptedFs for MemState {
+    fn load_encrypted(path: impl AsRef<Path>, cipher: &StockCipher) -> Result<Self, FsError> {
+        read_encrypted(path.as_ref(), "state.dat", cipher)
+    }
+}
+
+impl StoreEncryptedFs for MemState {
+    fn store_encrypted(&self, path: impl AsRef<Path>, cipher: &StockCipher) -> Result<(), FsError> {
+        write_encrypted(self, path.as_ref(), "state.dat", cipher)
+    }
+}
+
+impl LoadEncryptedFs for MemIndex {
+    fn load_encrypted(path: impl AsRef<Path>, cipher: &StockCipher) -> Result<Self, FsError> {
+        read_encrypted(path.as_ref(), "index.dat", cipher)
+    }
+}
+
+impl StoreEncryptedFs for MemIndex {
+    fn store_encrypted(&self, path: impl AsRef<Path>, cipher: &StockCipher) -> Result<(), FsError> {
+        write_encrypted(self, path.as_ref(), "index.dat", cipher)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::path::PathBuf;
+
+    use super::*;
+
+    type MemStock = Stock<MemStash, MemState, MemIndex>;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("rgb-std-{name}-{}", std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        dir
+    }
+
+    fn data_dir(path: &Path) -> PathBuf {
+        fs::read_dir(path)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .find(|path| path.is_dir())
+            .unwrap()
+    }
+
+    #[test]
+    fn store_load() {
+        let dir = test_dir("encrypted-store-load");
+        let key = StoreKey::Password(s!("password"));
+        MemStock::default()
+            .store_encrypted(&dir, &StockCipher::new(&key))
+            .unwrap();
+        MemStock::default()
+            .store_encrypted(&dir, &StockCipher::new(&key))
+            .unwrap();
+        assert!(MemStock::load_encrypted(&dir, &StockCipher::new(&key)).is_ok());
+
+        let wrong = StoreKey::Password(s!("wrong"));
+        assert!(matches!(
+            MemStock::load_encrypted(&dir, &StockCipher::new(&wrong)),
+            Err(FsError::Decryption(_))
+        ));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn file_binding() {
+        let key = StoreKey::Raw([1u8; 32]);
+        let cipher = StockCipher::new(&key).with_generation(1);
+        let file = cipher.encrypt("stash.dat", b"data").unwrap();
+        assert_eq!(cipher.decrypt("stash.dat", &file).unwrap(), b"data");
+
+        assert!(matches!(cipher.decrypt("state.dat", &file), Err(FsError::Decryption(_))));
+        assert!(matches!(
+            cipher.with_generation(2).decrypt("stash.dat", &file),
+            Err(FsError::Decryption(_))
+        ));
+
+        let mut tampered = file.clone();
+        tampered[HEADER_LEN - 1] ^= 1;
+        assert!(matches!(cipher.decrypt("stash.dat", &tampered), Err(FsError::Decryption(_))));
+        let mut tampered = file;
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(matches!(cipher.decrypt("stash.dat", &tampered), Err(FsError::Decryption(_))));
+    }
+
+    #[test]
+    fn kdf_params() {
+        let key = StoreKey::Password(s!("password"));
+        let mut cipher = StockCipher::new(&key);
+        cipher.kdf_params = KdfParams {
+            m_cost: 8 * 1024,
+            t_cost: 1,
+            p_cost: 1,
+        };
+        let file = cipher.encrypt("stash.dat", b"data").unwrap();
+        assert_eq!(StockCipher::new(&key).decrypt("stash.dat", &file).unwrap(), b"data");
+
+        let mut tampered = file;
+        tampered[RGB_PREFIX.len() + MAGIC_LEN + 3] ^= 1;
+        assert!(StockCipher::new(&key)
+            .decrypt("stash.dat", &tampered)
+            .is_err());
+    }
+
+    #[test]
+    fn manifest_generation() {
+        let key = StoreKey::Raw([5u8; 32]);
+        let cipher = StockCipher::new(&key);
+        let manifest = StockManifest {
+            generation: 1,
+            files: none!(),
+        };
+        let data = manifest.to_file_data().unwrap();
+
+        let file = cipher.seal(1, data.clone()).unwrap();
+        assert_eq!(cipher.open(STOCK_MANIFEST_FILE, file).unwrap(), data);
+        let file = cipher.seal(2, data).unwrap();
+        assert!(matches!(cipher.open(STOCK_MANIFEST_FILE, file), Err(FsError::Decryption(_))));
+    }
+
+    #[test]
+    fn tampered_manifest() {
+        let dir = test_dir("encrypted-tampered-manifest");
+        let key = StoreKey::Raw([2u8; 32]);
+        MemStock::default()
+            .store_encrypted(&dir, &StockCipher::new(&key))
+            .unwrap();
+
+        let path = dir.join(STOCK_MANIFEST_FILE);
+        let mut manifest = fs::read(&path).unwrap();
+        *manifest.last_mut().unwrap() ^= 1;
+        fs::write(&path, manifest).unwrap();
+        assert!(matches!(
+            MemStock::load_encrypted(&dir, &StockCipher::new(&key)),
+            Err(FsError::Decryption(_))
+        ));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn replaced_data_file() {
+        let dir = test_dir("encrypted-replaced-file");
+        let key = StoreKey::Raw([3u8; 32]);
+        MemStock::default()
+            .store_encrypted(&dir, &StockCipher::new(&key))
+            .unwrap();
+        let old = fs::read(data_dir(&dir).join("stash.dat")).unwrap();
+        MemStock::default()
+            .store_encrypted(&dir, &StockCipher::new(&key))
+            .unwrap();
+
+        fs::write(data_dir(&dir).join("stash.dat"), old).unwrap();
+        assert!(matches!(
+            MemStock::load_encrypted(&dir, &StockCipher::new(&key)),
+            Err(FsError::ChecksumMismatch(_))
+        ));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rekey_stock() {
+        let dir = test_dir("encrypted-rekey");
+        let old = StoreKey::Raw([4u8; 32]);
+        let new = StoreKey::Password(s!("password"));
+        MemStock::default()
+            .store_encrypted(&dir, &StockCipher::new(&old))
+            .unwrap();
+
+        rekey(&dir, &old, &new).unwrap();
+        assert!(MemStock::load_encrypted(&dir, &StockCipher::new(&new)).is_ok());
+        assert!(matches!(
+            MemStock::load_encrypted(&dir, &StockCipher::new(&old)),
+            Err(FsError::Decryption(_))
+        ));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,184 @@
+// RGB standard library for working with smart contracts on Bitcoin & Lightning
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2019-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2019-2024 LNP/BP Standards Association. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt::{self, Debug, Formatter};
+
+use rgb::{BundleId, ContractId, OpId, Opout, XOutputSeal, XWitnessId};
+
+/// Change of the stock data reported to the [`StockObserver`]s.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+pub enum StockEvent {
+    /// Genesis of a previously unknown contract was added.
+    ContractAdded { contract_id: ContractId },
+
+    /// Witness previously unknown to the stash was added.
+    WitnessAdded { witness_id: XWitnessId },
+
+    /// Transition bundle previously unknown to the stash was added.
+    BundleAdded {
+        contract_id: ContractId,
+        bundle_id: BundleId,
+        witness_id: XWitnessId,
+    },
+
+    /// Contract supplement was added.
+    SupplAdded { contract_id: ContractId },
+
+    /// State was assigned to a seal revealed in the contract state.
+    AllocationReceived {
+        contract_id: ContractId,
+        opout: Opout,
+        seal: XOutputSeal,
+    },
+
+    /// State assigned to a seal revealed in the contract state was spent by a
+    /// state transition.
+    AllocationSpent {
+        contract_id: ContractId,
+        opout: Opout,
+        seal: XOutputSeal,
+        spender: OpId,
+    },
+}
+
+impl StockEvent {
+    /// Returns id of the contract to which the event relates, if any.
+    pub fn contract_id(&self) -> Option<ContractId> {
+        match self {
+            StockEvent::ContractAdded { contract_id } |
+            StockEvent::BundleAdded { contract_id, .. } |
+            StockEvent::SupplAdded { contract_id } |
+            StockEvent::AllocationReceived { contract_id, .. } |
+            StockEvent::AllocationSpent { contract_id, .. } => Some(*contract_id),
+            StockEvent::WitnessAdded { .. } => None,
+        }
+    }
+}
+
+/// Receiver of the stock change notifications.
+///
+/// Observers are notified only after the changes are committed to all
+/// providers; the events of a single operation are delivered in one batch.
+///
+/// Observers are owned by the stock, so they are required to be `Send` and
+/// `Sync` to keep the stock shareable between threads and the futures of
+/// [`super::AsyncStock`], which hold the stock across await points, `Send`.
+pub trait StockObserver: Send + Sync {
+    fn on_events(&mut self, events: &[StockEvent]);
+}
+
+impl<F> StockObserver for F
+where F: FnMut(&[StockEvent]) + Send + Sync
+{
+    fn on_events(&mut self, events: &[StockEvent]) { self(events) }
+}
+
+/// Observers registered with a stock.
+#[derive(Default)]
+pub(super) struct Observers(Vec<Box<dyn StockObserver>>);
+
+impl Debug for Observers {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result { write!(f, "Observers({})", self.0.len()) }
+}
+
+impl Observers {
+    pub(super) fn push(&mut self, observer: Box<dyn StockObserver>) { self.0.push(observer) }
+
+    pub(super) fn notify(&mut self, events: &[StockEvent]) {
+        if events.is_empty() {
+            return;
+        }
+        for observer in &mut self.0 {
+            observer.on_events(events);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use amplify::ByteArray;
+
+    use super::*;
+    use crate::containers::{Kit, ValidKit};
+    use crate::interface::resolver::DumbResolver;
+    use crate::interface::{ContractSuppl, TickerSuppl};
+    use crate::persistence::{MemIndex, MemStash, MemState, Stock};
+
+    type MemStock = Stock<MemStash, MemState, MemIndex>;
+
+    fn suppl_kit(contract_id: ContractId) -> ValidKit {
+        let suppl = ContractSuppl {
+            contract_id,
+            ticker: TickerSuppl::Absent,
+            media_kit: none!(),
+            global_state: none!(),
+            owned_state: none!(),
+            extensions: none!(),
+        };
+        Kit {
+            version: none!(),
+            ifaces: none!(),
+            schemata: none!(),
+            iimpls: none!(),
+            supplements: tiny_bset![suppl],
+            types: none!(),
+            scripts: none!(),
+            signatures: none!(),
+        }
+        .validate()
+        .unwrap()
+    }
+
+    #[test]
+    fn notify_observer() {
+        let first = ContractId::from_byte_array([1u8; 32]);
+        let second = ContractId::from_byte_array([2u8; 32]);
+
+        let received = Arc::new(Mutex::new(Vec::<Vec<StockEvent>>::new()));
+        let mut stock = MemStock::default();
+        let observer = received.clone();
+        stock
+            .subscribe(move |events: &[StockEvent]| observer.lock().unwrap().push(events.to_vec()));
+
+        stock.import_kit(suppl_kit(first)).unwrap();
+        // Already known data produce no notifications
+        stock.import_kit(suppl_kit(first)).unwrap();
+
+        let mut other = MemStock::default();
+        other.import_kit(suppl_kit(first)).unwrap();
+        other.import_kit(suppl_kit(second)).unwrap();
+        stock.merge(&other, &mut DumbResolver).unwrap();
+
+        assert_eq!(*received.lock().unwrap(), vec![
+            vec![StockEvent::SupplAdded { contract_id: first }],
+            vec![StockEvent::SupplAdded {
+                contract_id: second
+            }],
+        ]);
+    }
+}
@@ -19,21 +19,345 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::path::Path;
+//! File system persistence for the stock and in-memory providers.
+//!
+//! Stock is stored into a directory containing a manifest file and a data
+//! directory with stash, state and index files. On each store operation all
+//! data files are written and synced into a fresh data directory; after that
+//! the manifest listing the new directory and checksums of the files in it is
+//! atomically replaced. Thus, a crash at any moment leaves either the old or
+//! the new consistent set of files, and any damage to the data files is
+//! detected on load by checking them against the manifest.
+//!
+//! Each file starts with a header made of the `RGB\0` prefix, three magic
+//! bytes identifying the file type and a version of the data layout. Files
+//! using older layouts, including ones written before the header was
+//! introduced, are upgraded on load (see [`VersionedFile`]); the upgraded
+//! data are written with the current layout on the next store operation.
 
-use amplify::confinement::U32;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use amplify::confinement::{Confined, TinyOrdMap, TinyString, U64};
+use amplify::Bytes32;
+use commit_verify::{DigestExt, Sha256};
 use strict_encoding::{DeserializeError, SerializeError, StrictDeserialize, StrictSerialize};
 
+use crate::containers::{MAGIC_LEN, RGB_PREFIX};
+use crate::persistence::memory::{
+    MemIndexV0, MemIndexV1, MemIndexV2, MemStashV0, MemStashV1, MemStashV2, MemStateV0,
+};
 use crate::persistence::{
     IndexProvider, MemIndex, MemStash, MemState, StashProvider, StateProvider, Stock,
 };
+use crate::LIB_NAME_RGB_STD;
+
+/// Name of the file holding [`StockManifest`].
+pub const STOCK_MANIFEST_FILE: &str = "manifest.dat";
+const STOCK_MANIFEST_TMP: &str = "manifest.tmp";
+
+#[derive(Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum FsError {
+    #[from]
+    #[display(inner)]
+    Io(io::Error),
+
+    #[from]
+    #[display(inner)]
+    Serialize(SerializeError),
+
+    #[from]
+    #[display(inner)]
+    Deserialize(DeserializeError),
+
+    /// file name '{0}' is not valid for a stock data file.
+    InvalidFileName(String),
+
+    /// stock data contains too many files.
+    TooManyFiles,
+
+    /// stock data file '{0}' listed in the manifest is absent.
+    FileAbsent(String),
+
+    /// stock directory '{0}' has no manifest.
+    ManifestAbsent(String),
+
+    /// stock data file '{0}' doesn't match the checksum recorded in the
+    /// manifest. It means that the file was damaged or partially written, and
+    /// the stock must be restored from a backup.
+    ChecksumMismatch(String),
+
+    /// stock data directory contains file '{0}' which is not listed in the
+    /// manifest.
+    UnexpectedFile(String),
+
+    /// file '{0}' has invalid magic bytes and doesn't contain stock data of the
+    /// expected type.
+    InvalidMagic(String),
+
+    /// file '{file}' uses data layout version {version}, which is not supported
+    /// by this version of the library.
+    UnsupportedVersion { file: String, version: u16 },
+
+    /// file '{0}' is not encrypted.
+    #[cfg(feature = "fs-encrypt")]
+    NotEncrypted(String),
+
+    /// file '{file}' is encrypted using unknown key derivation method {kdf}.
+    #[cfg(feature = "fs-encrypt")]
+    UnknownKdf { file: String, kdf: u8 },
+
+    /// unable to derive encryption key: {0}
+    #[cfg(feature = "fs-encrypt")]
+    KeyDerivation(String),
+
+    /// unable to decrypt file '{0}': either the key is wrong or the file was
+    /// modified.
+    #[cfg(feature = "fs-encrypt")]
+    Decryption(String),
+}
 
 pub trait LoadFs: Sized {
-    fn load(path: impl AsRef<Path>) -> Result<Self, DeserializeError>;
+    fn load(path: impl AsRef<Path>) -> Result<Self, FsError>;
 }
 
 pub trait StoreFs {
-    fn store(&self, path: impl AsRef<Path>) -> Result<(), SerializeError>;
+    fn store(&self, path: impl AsRef<Path>) -> Result<(), FsError>;
+}
+
+/// Data persisted in a file with a versioned header.
+pub trait VersionedFile: StrictSerialize + StrictDeserialize {
+    /// Magic bytes identifying the file type.
+    const MAGIC: [u8; MAGIC_LEN];
+
+    /// Version of the data layout written by this version of the library.
+    const VERSION: u16;
+
+    /// Oldest data layout version which can be migrated to the current one.
+    /// Files using older layouts are not supported.
+    const MIN_VERSION: u16 = 0;
+
+    /// Upgrades data stored with one of the previous layout versions to the
+    /// current one.
+    ///
+    /// Version `0` denotes files written before the versioned header was
+    /// introduced. The method is never called with the current or a newer
+    /// version, nor with a version older than [`Self::MIN_VERSION`].
+    fn migrate(version: u16, data: Vec<u8>) -> Result<Self, DeserializeError>;
+
+    /// Reads the file, migrating its data if they use an older layout.
+    fn read_file(path: impl AsRef<Path>) -> Result<Self, FsError> {
+        let path = path.as_ref();
+        Self::from_file_data(path.display().to_string(), fs::read(path)?)
+    }
+
+    /// Decodes file content, migrating its data if they use an older layout.
+    ///
+    /// The `name` is used only for error reporting.
+    fn from_file_data(name: String, mut data: Vec<u8>) -> Result<Self, FsError> {
+        let header_len = RGB_PREFIX.len() + MAGIC_LEN + 2;
+        let version = if data.len() >= header_len && data[..RGB_PREFIX.len()] == RGB_PREFIX {
+            if data[RGB_PREFIX.len()..RGB_PREFIX.len() + MAGIC_LEN] != Self::MAGIC {
+                return Err(FsError::InvalidMagic(name));
+            }
+            let version = u16::from_le_bytes([data[header_len - 2], data[header_len - 1]]);
+            data.drain(..header_len);
+            version
+        } else {
+            0
+        };
+
+        if version > Self::VERSION || version < Self::MIN_VERSION {
+            return Err(FsError::UnsupportedVersion {
+                file: name,
+                version,
+            });
+        }
+        if version < Self::VERSION {
+            return Ok(Self::migrate(version, data)?);
+        }
+        Ok(Self::from_strict_serialized::<U64>(Confined::from_collection_unsafe(data))?)
+    }
+
+    /// Writes the file with the current data layout.
+    fn write_file(&self, path: impl AsRef<Path>) -> Result<(), FsError> {
+        let data = self.to_file_data()?;
+        let mut file = File::create(path)?;
+        file.write_all(&data)?;
+        file.flush()?;
+        Ok(())
+    }
+
+    /// Encodes file content, including the header, with the current data
+    /// layout.
+    fn to_file_data(&self) -> Result<Vec<u8>, FsError> {
+        let data = self.to_strict_serialized::<U64>()?;
+        let mut file = Vec::with_capacity(RGB_PREFIX.len() + MAGIC_LEN + 2 + data.len());
+        file.extend_from_slice(&RGB_PREFIX);
+        file.extend_from_slice(&Self::MAGIC);
+        file.extend_from_slice(&Self::VERSION.to_le_bytes());
+        file.extend_from_slice(data.as_slice());
+        Ok(file)
+    }
+}
+
+/// Manifest describing consistent set of stock data files.
+#[derive(Clone, Eq, PartialEq, Debug)]
+#[derive(StrictType, StrictEncode, StrictDecode)]
+#[strict_type(lib = LIB_NAME_RGB_STD)]
+pub struct StockManifest {
+    /// Sequence number of the stored stock data, increased on each store
+    /// operation.
+    pub generation: u64,
+    /// SHA256 checksums of the data files.
+    pub files: TinyOrdMap<TinyString, Bytes32>,
+}
+
+impl StrictSerialize for StockManifest {}
+impl StrictDeserialize for StockManifest {}
+
+impl VersionedFile for StockManifest {
+    const MAGIC: [u8; MAGIC_LEN] = *b"MNF";
+    const VERSION: u16 = 1;
+    const MIN_VERSION: u16 = 1;
+
+    fn migrate(_: u16, _: Vec<u8>) -> Result<Self, DeserializeError> {
+        unreachable!("manifests were always written with the current layout")
+    }
+}
+
+impl StockManifest {
+    /// Name of the directory containing data files of this manifest
+    /// generation.
+    pub fn data_dir(&self) -> String { Self::data_dir_name(self.generation) }
+
+    fn data_dir_name(generation: u64) -> String { format!("data-{generation:016x}") }
+
+    fn parse_data_dir_name(name: &str) -> Option<u64> {
+        let generation = name.strip_prefix("data-")?;
+        if generation.len() != 16 {
+            return None;
+        }
+        u64::from_str_radix(generation, 16).ok()
+    }
+
+    /// Reads the manifest from the stock directory, returning `None` if the
+    /// directory has no manifest.
+    pub fn read(path: impl AsRef<Path>) -> Result<Option<Self>, FsError> {
+        Self::read_with(path.as_ref(), &PlainManifest)
+    }
+
+    fn read_with(path: &Path, codec: &impl ManifestCodec) -> Result<Option<Self>, FsError> {
+        let file = path.join(STOCK_MANIFEST_FILE);
+        if !file.exists() {
+            return Ok(None);
+        }
+        let name = file.display().to_string();
+        let data = codec.open(&name, fs::read(&file)?)?;
+        Ok(Some(Self::from_file_data(name, data)?))
+    }
+
+    /// Atomically replaces the manifest in the stock directory.
+    fn write(&self, path: &Path, codec: &impl ManifestCodec) -> Result<(), FsError> {
+        let tmp = path.join(STOCK_MANIFEST_TMP);
+        let data = codec.seal(self.generation, self.to_file_data()?)?;
+        let mut file = File::create(&tmp)?;
+        file.write_all(&data)?;
+        file.sync_all()?;
+        drop(file);
+        fs::rename(tmp, path.join(STOCK_MANIFEST_FILE))?;
+        sync_dir(path)?;
+        Ok(())
+    }
+
+    /// Collects checksums of all files in the data directory.
+    fn with(generation: u64, dir: &Path) -> Result<Self, FsError> {
+        let mut files = TinyOrdMap::new();
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            if !entry.file_type()?.is_file() {
+                continue;
+            }
+            let name = file_name(&entry.file_name())?;
+            let path = entry.path();
+            File::open(&path)?.sync_all()?;
+            files
+                .insert(name, checksum(&path)?)
+                .map_err(|_| FsError::TooManyFiles)?;
+        }
+        Ok(StockManifest { generation, files })
+    }
+
+    /// Checks that the data directory contains exactly the files listed in
+    /// the manifest and that all of them match their checksums.
+    pub fn verify(&self, stock_dir: impl AsRef<Path>) -> Result<(), FsError> {
+        let dir = stock_dir.as_ref().join(self.data_dir());
+        for (name, expected) in self.files.iter() {
+            let path = dir.join(name.as_str());
+            if !path.is_file() {
+                return Err(FsError::FileAbsent(name.as_str().to_owned()));
+            }
+            if checksum(&path)? != *expected {
+                return Err(FsError::ChecksumMismatch(name.as_str().to_owned()));
+            }
+        }
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let name = file_name(&entry.file_name())?;
+            if !self.files.contains_key(&name) {
+                return Err(FsError::UnexpectedFile(name.as_str().to_owned()));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn file_name(name: &std::ffi::OsStr) -> Result<TinyString, FsError> {
+    let name = name
+        .to_str()
+        .ok_or_else(|| FsError::InvalidFileName(name.to_string_lossy().to_string()))?;
+    TinyString::try_from(name.to_owned()).map_err(|_| FsError::InvalidFileName(name.to_owned()))
+}
+
+fn checksum(path: &Path) -> Result<Bytes32, io::Error> {
+    let data = fs::read(path)?;
+    let mut hasher = Sha256::default();
+    hasher.input_raw(&data);
+    Ok(hasher.finish().into())
+}
+
+fn sync_dir(path: &Path) -> Result<(), io::Error> {
+    // Directories can't be opened for syncing on Windows, where rename
+    // operations are durable on their own.
+    #[cfg(unix)]
+    File::open(path)?.sync_all()?;
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+/// Format of the stock manifest file.
+pub(super) trait ManifestCodec {
+    /// Converts serialized manifest of the given generation into the content
+    /// of the manifest file.
+    fn seal(&self, generation: u64, data: Vec<u8>) -> Result<Vec<u8>, FsError>;
+
+    /// Checks the content of the manifest file and extracts the serialized
+    /// manifest from it.
+    ///
+    /// The `name` is used only for error reporting.
+    fn open(&self, name: &str, file: Vec<u8>) -> Result<Vec<u8>, FsError>;
+}
+
+/// Manifest file containing the serialized manifest as is.
+pub(super) struct PlainManifest;
+
+impl ManifestCodec for PlainManifest {
+    fn seal(&self, _: u64, data: Vec<u8>) -> Result<Vec<u8>, FsError> { Ok(data) }
+
+    fn open(&self, _: &str, file: Vec<u8>) -> Result<Vec<u8>, FsError> { Ok(file) }
 }
 
 impl<S: StashProvider, H: StateProvider, I: IndexProvider> LoadFs for Stock<S, H, I>
@@ -42,11 +366,18 @@ where
     H: LoadFs,
     I: LoadFs,
 {
-    fn load(path: impl AsRef<Path>) -> Result<Self, DeserializeError> {
-        let path = path.as_ref();
-        let stash = S::load(path)?;
-        let state = H::load(path)?;
-        let index = I::load(path)?;
+    /// Loads stock from the directory, verifying data files against the stock
+    /// manifest.
+    ///
+    /// Directories created before the manifest was introduced contain data
+    /// files right in the stock directory; they are loaded without
+    /// verification and get the manifest on the next store operation.
+    fn load(path: impl AsRef<Path>) -> Result<Self, FsError> {
+        let dir = stock_data_dir(path.as_ref())?;
+
+        let stash = S::load(&dir)?;
+        let state = H::load(&dir)?;
+        let index = I::load(&dir)?;
 
         Ok(Stock::with(stash, state, index))
     }
@@ -58,60 +389,408 @@ where
     H: StoreFs,
     I: StoreFs,
 {
-    fn store(&self, path: impl AsRef<Path>) -> Result<(), SerializeError> {
-        let path = path.as_ref();
-        self.as_stash_provider().store(path)?;
-        self.as_state_provider().store(path)?;
-        self.as_index_provider().store(path)?;
+    /// Stores stock into the directory such that a failure at any point leaves
+    /// the previously stored data intact.
+    fn store(&self, path: impl AsRef<Path>) -> Result<(), FsError> {
+        store_stock_data(path.as_ref(), &PlainManifest, |dir, _| {
+            self.as_stash_provider().store(dir)?;
+            self.as_state_provider().store(dir)?;
+            self.as_index_provider().store(dir)
+        })
+    }
+}
 
-        Ok(())
+/// Returns directory with the data files of the stock, verifying them against
+/// the stock manifest.
+///
+/// Directories created before the manifest was introduced contain data files
+/// right in the stock directory; they are returned without verification.
+fn stock_data_dir(path: &Path) -> Result<PathBuf, FsError> {
+    Ok(match verified_manifest(path, &PlainManifest)? {
+        Some(manifest) => path.join(manifest.data_dir()),
+        None => path.to_owned(),
+    })
+}
+
+/// Reads the stock manifest with `codec`, verifying data files against it.
+pub(super) fn verified_manifest(
+    path: &Path,
+    codec: &impl ManifestCodec,
+) -> Result<Option<StockManifest>, FsError> {
+    let manifest = StockManifest::read_with(path, codec)?;
+    if let Some(manifest) = &manifest {
+        manifest.verify(path)?;
+    }
+    Ok(manifest)
+}
+
+/// Writes a new generation of stock data files with `writer` and atomically
+/// switches the stock manifest, stored with `codec`, to it.
+///
+/// The generation number passed to `writer` is larger than the one of any
+/// data directory present in the stock directory; thus the previous manifest
+/// is not read and may use a different format.
+pub(super) fn store_stock_data(
+    path: &Path,
+    codec: &impl ManifestCodec,
+    writer: impl FnOnce(&Path, u64) -> Result<(), FsError>,
+) -> Result<(), FsError> {
+    fs::create_dir_all(path)?;
+
+    let mut prev = Vec::new();
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_dir() {
+            continue;
+        }
+        if let Some(generation) = entry
+            .file_name()
+            .to_str()
+            .and_then(StockManifest::parse_data_dir_name)
+        {
+            prev.push(generation);
+        }
+    }
+    let generation = prev.iter().max().map(|last| last + 1).unwrap_or_default();
+    let dir_name = StockManifest::data_dir_name(generation);
+
+    let tmp = path.join(format!("{dir_name}.tmp"));
+    if tmp.exists() {
+        fs::remove_dir_all(&tmp)?;
+    }
+    fs::create_dir(&tmp)?;
+    writer(&tmp, generation)?;
+    let manifest = StockManifest::with(generation, &tmp)?;
+    sync_dir(&tmp)?;
+
+    let dir = path.join(&dir_name);
+    if dir.exists() {
+        fs::remove_dir_all(&dir)?;
+    }
+    fs::rename(&tmp, &dir)?;
+    sync_dir(path)?;
+
+    // This is the commit point: before it the previous manifest and data
+    // are in force, after it - the new ones.
+    manifest.write(path, codec)?;
+
+    for prev in prev {
+        fs::remove_dir_all(path.join(StockManifest::data_dir_name(prev))).ok();
+    }
+    Ok(())
+}
+
+impl VersionedFile for MemStash {
+    const MAGIC: [u8; MAGIC_LEN] = *b"STS";
+    const VERSION: u16 = 3;
+
+    /// Version 2 has added information records to the secret seals; the
+    /// migrated seals get records with an unknown creation time. Version 3
+    /// keeps types as a map instead of a type system.
+    fn migrate(version: u16, data: Vec<u8>) -> Result<Self, DeserializeError> {
+        let v2: MemStashV2 = match version {
+            0 => migrate_headerless::<MemStashV1, MemStashV0>(data)?.into(),
+            1 => MemStashV1::from_strict_serialized::<U64>(Confined::from_collection_unsafe(data))?
+                .into(),
+            _ => MemStashV2::from_strict_serialized::<U64>(Confined::from_collection_unsafe(data))?,
+        };
+        Ok(v2.into())
+    }
+}
+
+impl VersionedFile for MemState {
+    const MAGIC: [u8; MAGIC_LEN] = *b"STT";
+    const VERSION: u16 = 1;
+
+    fn migrate(_: u16, data: Vec<u8>) -> Result<Self, DeserializeError> {
+        migrate_headerless::<Self, MemStateV0>(data)
+    }
+}
+
+impl VersionedFile for MemIndex {
+    const MAGIC: [u8; MAGIC_LEN] = *b"IDX";
+    const VERSION: u16 = 3;
+
+    /// Version 2 has added the spender index, and version 3 has added a flag
+    /// marking it as outdated. Spender index can't be filled in by the
+    /// migration from the versions preceding 2, so spender queries fail until
+    /// the stash is re-indexed with `Stock::rebuild_index`.
+    fn migrate(version: u16, data: Vec<u8>) -> Result<Self, DeserializeError> {
+        let (v2, spenders_indexed): (MemIndexV2, bool) = match version {
+            0 => (migrate_headerless::<MemIndexV1, MemIndexV0>(data)?.into(), false),
+            1 => {
+                let data = Confined::from_collection_unsafe(data);
+                (MemIndexV1::from_strict_serialized::<U64>(data)?.into(), false)
+            }
+            _ => {
+                let data = Confined::from_collection_unsafe(data);
+                (MemIndexV2::from_strict_serialized::<U64>(data)?, true)
+            }
+        };
+        Ok(v2.upgrade(spenders_indexed))
+    }
+}
+
+/// Decodes data of an in-memory provider stored without the versioned header.
+///
+/// Such files were written either with the current layout, or with the
+/// legacy layout `L` using smaller collection bounds, which is converted into
+/// the current one.
+fn migrate_headerless<T, L>(data: Vec<u8>) -> Result<T, DeserializeError>
+where
+    T: StrictDeserialize,
+    L: StrictDeserialize + Into<T>,
+{
+    let data = Confined::from_collection_unsafe(data);
+    match T::from_strict_serialized::<U64>(data.clone()) {
+        Ok(me) => Ok(me),
+        Err(err) => L::from_strict_serialized::<U64>(data)
+            .map(L::into)
+            .map_err(|_| err),
     }
 }
 
 impl LoadFs for MemStash {
-    fn load(path: impl AsRef<Path>) -> Result<Self, DeserializeError> {
+    fn load(path: impl AsRef<Path>) -> Result<Self, FsError> {
         let mut file = path.as_ref().to_owned();
         file.push("stash.dat");
-        Self::strict_deserialize_from_file::<U32>(file)
+        Self::read_file(file)
     }
 }
 
 impl StoreFs for MemStash {
-    fn store(&self, path: impl AsRef<Path>) -> Result<(), SerializeError> {
+    fn store(&self, path: impl AsRef<Path>) -> Result<(), FsError> {
         let mut file = path.as_ref().to_owned();
         file.push("stash.dat");
-        self.strict_serialize_to_file::<U32>(file)
+        self.write_file(file)
     }
 }
 
 impl LoadFs for MemState {
-    fn load(path: impl AsRef<Path>) -> Result<Self, DeserializeError> {
+    fn load(path: impl AsRef<Path>) -> Result<Self, FsError> {
         let mut file = path.as_ref().to_owned();
         file.push("state.dat");
-        Self::strict_deserialize_from_file::<U32>(file)
+        Self::read_file(file)
     }
 }
 
 impl StoreFs for MemState {
-    fn store(&self, path: impl AsRef<Path>) -> Result<(), SerializeError> {
+    fn store(&self, path: impl AsRef<Path>) -> Result<(), FsError> {
         let mut file = path.as_ref().to_owned();
         file.push("state.dat");
-        self.strict_serialize_to_file::<U32>(file)
+        self.write_file(file)
     }
 }
 
 impl LoadFs for MemIndex {
-    fn load(path: impl AsRef<Path>) -> Result<Self, DeserializeError> {
+    fn load(path: impl AsRef<Path>) -> Result<Self, FsError> {
         let mut file = path.as_ref().to_owned();
         file.push("index.dat");
-        Self::strict_deserialize_from_file::<U32>(file)
+        Self::read_file(file)
     }
 }
 
 impl StoreFs for MemIndex {
-    fn store(&self, path: impl AsRef<Path>) -> Result<(), SerializeError> {
+    fn store(&self, path: impl AsRef<Path>) -> Result<(), FsError> {
         let mut file = path.as_ref().to_owned();
         file.push("index.dat");
-        self.strict_serialize_to_file::<U32>(file)
+        self.write_file(file)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+
+    use amplify::Wrapper;
+    use rgb::{AssignmentType, OpId, Opout};
+
+    use super::*;
+    use crate::persistence::{IndexInconsistency, IndexReadProvider};
+
+    type MemStock = Stock<MemStash, MemState, MemIndex>;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("rgb-std-{name}-{}", std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        dir
+    }
+
+    fn file_data(data: &impl StrictSerialize, magic: [u8; MAGIC_LEN], version: u16) -> Vec<u8> {
+        let mut file = RGB_PREFIX.to_vec();
+        file.extend_from_slice(&magic);
+        file.extend_from_slice(&version.to_le_bytes());
+        file.extend_from_slice(data.to_strict_serialized::<U64>().unwrap().as_slice());
+        file
+    }
+
+    fn headerless_data(data: &impl StrictSerialize) -> Vec<u8> {
+        data.to_strict_serialized::<U64>()
+            .unwrap()
+            .as_slice()
+            .to_vec()
+    }
+
+    fn data_dirs(path: &Path) -> Vec<String> {
+        let mut dirs = fs::read_dir(path)
+            .unwrap()
+            .map(|entry| entry.unwrap())
+            .filter(|entry| entry.file_type().unwrap().is_dir())
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .collect::<Vec<_>>();
+        dirs.sort();
+        dirs
+    }
+
+    #[test]
+    fn store_generations() {
+        let dir = test_dir("fs-store-generations");
+        MemStock::default().store(&dir).unwrap();
+        MemStock::default().store(&dir).unwrap();
+
+        let manifest = StockManifest::read(&dir).unwrap().unwrap();
+        assert_eq!(manifest.generation, 1);
+        assert_eq!(data_dirs(&dir), vec![manifest.data_dir()]);
+        assert_eq!(manifest.files.len(), 3);
+        manifest.verify(&dir).unwrap();
+        assert!(MemStock::load(&dir).is_ok());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn failed_store() {
+        let dir = test_dir("fs-failed-store");
+        MemStock::default().store(&dir).unwrap();
+
+        let res = store_stock_data(&dir, &PlainManifest, |tmp, _| {
+            MemStash::default().store(tmp)?;
+            Err(FsError::TooManyFiles)
+        });
+        assert!(matches!(res, Err(FsError::TooManyFiles)));
+        let manifest = StockManifest::read(&dir).unwrap().unwrap();
+        assert_eq!(manifest.generation, 0);
+        assert!(MemStock::load(&dir).is_ok());
+
+        MemStock::default().store(&dir).unwrap();
+        let manifest = StockManifest::read(&dir).unwrap().unwrap();
+        assert_eq!(manifest.generation, 1);
+        assert_eq!(data_dirs(&dir), vec![manifest.data_dir()]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn damaged_data() {
+        let dir = test_dir("fs-damaged-data");
+        MemStock::default().store(&dir).unwrap();
+        let data_dir = dir.join(StockManifest::read(&dir).unwrap().unwrap().data_dir());
+
+        fs::write(data_dir.join("extra.dat"), b"data").unwrap();
+        assert!(
+            matches!(MemStock::load(&dir), Err(FsError::UnexpectedFile(name)) if name == "extra.dat")
+        );
+        fs::remove_file(data_dir.join("extra.dat")).unwrap();
+
+        let state = fs::read(data_dir.join("state.dat")).unwrap();
+        let mut damaged = state.clone();
+        *damaged.last_mut().unwrap() ^= 1;
+        fs::write(data_dir.join("state.dat"), damaged).unwrap();
+        assert!(
+            matches!(MemStock::load(&dir), Err(FsError::ChecksumMismatch(name)) if name == "state.dat")
+        );
+
+        fs::remove_file(data_dir.join("state.dat")).unwrap();
+        assert!(
+            matches!(MemStock::load(&dir), Err(FsError::FileAbsent(name)) if name == "state.dat")
+        );
+
+        fs::write(data_dir.join("state.dat"), state).unwrap();
+        assert!(MemStock::load(&dir).is_ok());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn legacy_dir() {
+        let dir = test_dir("fs-legacy-dir");
+        fs::create_dir_all(&dir).unwrap();
+        MemStash::default().store(&dir).unwrap();
+        MemState::default().store(&dir).unwrap();
+        MemIndex::default().store(&dir).unwrap();
+        assert!(MemStock::load(&dir).is_ok());
+
+        MemStock::default().store(&dir).unwrap();
+        assert!(StockManifest::read(&dir).unwrap().is_some());
+        assert!(MemStock::load(&dir).is_ok());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn headerless_files() {
+        let name = s!("test.dat");
+        MemStash::from_file_data(name.clone(), headerless_data(&MemStashV1::default())).unwrap();
+        MemStash::from_file_data(name.clone(), headerless_data(&MemStashV0::default())).unwrap();
+        MemState::from_file_data(name.clone(), headerless_data(&MemState::default())).unwrap();
+        MemState::from_file_data(name.clone(), headerless_data(&MemStateV0::default())).unwrap();
+        MemIndex::from_file_data(name.clone(), headerless_data(&MemIndexV1::default())).unwrap();
+        MemIndex::from_file_data(name.clone(), headerless_data(&MemIndexV0::default())).unwrap();
+
+        let manifest = StockManifest {
+            generation: 1,
+            files: none!(),
+        };
+        assert!(matches!(
+            StockManifest::from_file_data(name, headerless_data(&manifest)),
+            Err(FsError::UnsupportedVersion { version: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn versioned_files() {
+        let name = s!("test.dat");
+        let data = file_data(&MemStashV1::default(), MemStash::MAGIC, 1);
+        MemStash::from_file_data(name.clone(), data).unwrap();
+        let data = file_data(&MemStashV2::default(), MemStash::MAGIC, 2);
+        MemStash::from_file_data(name.clone(), data).unwrap();
+        let data = file_data(&MemIndexV1::default(), MemIndex::MAGIC, 1);
+        MemIndex::from_file_data(name.clone(), data).unwrap();
+        let data = file_data(&MemIndexV2::default(), MemIndex::MAGIC, 2);
+        MemIndex::from_file_data(name.clone(), data).unwrap();
+        let data = MemIndex::default().to_file_data().unwrap();
+        MemIndex::from_file_data(name.clone(), data).unwrap();
+
+        let data = file_data(&MemState::default(), MemStash::MAGIC, MemState::VERSION);
+        assert!(matches!(
+            MemState::from_file_data(name.clone(), data),
+            Err(FsError::InvalidMagic(_))
+        ));
+        let data = file_data(&MemState::default(), MemState::MAGIC, MemState::VERSION + 1);
+        assert!(matches!(
+            MemState::from_file_data(name, data),
+            Err(FsError::UnsupportedVersion { version, .. }) if version == MemState::VERSION + 1
+        ));
+    }
+
+    #[test]
+    fn migrated_spenders() {
+        let dir = test_dir("fs-migrated-spenders");
+        fs::create_dir_all(&dir).unwrap();
+        MemStash::default().store(&dir).unwrap();
+        MemState::default().store(&dir).unwrap();
+        let data = file_data(&MemIndexV1::default(), MemIndex::MAGIC, 1);
+        fs::write(dir.join("index.dat"), data).unwrap();
+
+        let opout =
+            Opout::new(OpId::from_inner([1u8; 32].into()), AssignmentType::from_inner(4000), 0);
+        let mut stock = MemStock::load(&dir).unwrap();
+        assert_eq!(
+            stock.as_index_provider().spenders(opout),
+            Err(IndexInconsistency::SpendersNotIndexed.into())
+        );
+
+        stock.rebuild_index().unwrap();
+        assert_eq!(stock.as_index_provider().spenders(opout), Ok(none!()));
+        stock.store(&dir).unwrap();
+        let stock = MemStock::load(&dir).unwrap();
+        assert_eq!(stock.as_index_provider().spenders(opout), Ok(none!()));
+        fs::remove_dir_all(dir).unwrap();
     }
 }
@@ -30,7 +30,7 @@ use rgb::{
 };
 
 use crate::containers::{BundledWitness, Consignment, ToWitnessId};
-use crate::SecretSeal;
+use crate::{SecretSeal, LIB_NAME_RGB_STD};
 
 #[derive(Clone, Eq, PartialEq, Debug, Display, Error, From)]
 #[display(inner)]
@@ -123,6 +123,26 @@ pub enum IndexInconsistency {
 
     /// absent information about witness for bundle {0}.
     BundleWitnessUnknown(BundleId),
+
+    /// spender index is not built; the stash must be re-indexed with
+    /// `Stock::rebuild_index`.
+    SpendersNotIndexed,
+}
+
+/// Operation spending an operation output, together with its bundle and the
+/// witness transaction.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+#[derive(StrictType, StrictDumb, StrictEncode, StrictDecode)]
+#[strict_type(lib = LIB_NAME_RGB_STD)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+pub struct Spender {
+    pub opid: OpId,
+    pub bundle_id: BundleId,
+    pub witness_id: XWitnessId,
 }
 
 #[derive(Debug)]
@@ -152,10 +172,7 @@ impl<P: IndexProvider> Index<P> {
     ) -> Result<(), IndexError<P>> {
         let contract_id = consignment.contract_id();
 
-        self.provider
-            .register_contract(contract_id)
-            .map_err(IndexError::WriteProvider)?;
-        self.index_genesis(contract_id, &consignment.genesis)?;
+        self.index_contract(contract_id, &consignment.genesis)?;
         for extension in &consignment.extensions {
             self.index_extension(contract_id, extension)?;
         }
@@ -173,6 +190,17 @@ impl<P: IndexProvider> Index<P> {
         Ok(())
     }
 
+    pub(super) fn index_contract(
+        &mut self,
+        contract_id: ContractId,
+        genesis: &Genesis,
+    ) -> Result<(), IndexError<P>> {
+        self.provider
+            .register_contract(contract_id)
+            .map_err(IndexError::WriteProvider)?;
+        self.index_genesis(contract_id, genesis)
+    }
+
     fn index_genesis(&mut self, id: ContractId, genesis: &Genesis) -> Result<(), IndexError<P>> {
         let opid = genesis.id();
         for (type_id, assign) in genesis.assignments.iter() {
@@ -198,7 +226,7 @@ impl<P: IndexProvider> Index<P> {
         Ok(())
     }
 
-    fn index_extension(
+    pub(super) fn index_extension(
         &mut self,
         id: ContractId,
         extension: &Extension,
@@ -240,6 +268,13 @@ impl<P: IndexProvider> Index<P> {
 
         for (opid, transition) in &bundle.known_transitions {
             self.provider.register_operation(*opid, bundle_id)?;
+            for input in &transition.inputs {
+                self.provider.register_spender(input.prev_out, Spender {
+                    opid: *opid,
+                    bundle_id,
+                    witness_id,
+                })?;
+            }
             for (type_id, assign) in transition.assignments.iter() {
                 match assign {
                     TypedAssigns::Declarative(vec) => {
@@ -285,6 +320,46 @@ impl<P: IndexProvider> Index<P> {
         Ok(())
     }
 
+    pub(super) fn clear(&mut self) -> Result<(), IndexError<P>> {
+        self.provider
+            .clear_index()
+            .map_err(IndexError::WriteProvider)
+    }
+
+    pub(super) fn remove_contract(
+        &mut self,
+        contract_id: ContractId,
+        opids: &BTreeSet<OpId>,
+    ) -> Result<(), IndexError<P>> {
+        self.provider
+            .remove_contract(contract_id, opids)
+            .map_err(IndexError::WriteProvider)
+    }
+
+    pub(super) fn begin_transaction(&mut self) -> Result<(), IndexError<P>> {
+        self.provider
+            .begin_transaction()
+            .map_err(IndexError::WriteProvider)
+    }
+
+    pub(super) fn prepare_transaction(&mut self) -> Result<(), IndexError<P>> {
+        self.provider
+            .prepare_transaction()
+            .map_err(IndexError::WriteProvider)
+    }
+
+    pub(super) fn commit_transaction(&mut self) -> Result<(), IndexError<P>> {
+        self.provider
+            .commit_transaction()
+            .map_err(IndexError::WriteProvider)
+    }
+
+    pub(super) fn rollback_transaction(&mut self) -> Result<(), IndexError<P>> {
+        self.provider
+            .rollback_transaction()
+            .map_err(IndexError::WriteProvider)
+    }
+
     pub(super) fn contracts_assigning(
         &self,
         outputs: BTreeSet<XOutputSeal>,
@@ -328,6 +403,20 @@ impl<P: IndexProvider> Index<P> {
     ) -> Result<(XWitnessId, ContractId), IndexError<P>> {
         Ok(self.provider.bundle_info(bundle_id)?)
     }
+
+    pub(super) fn spenders(&self, opout: Opout) -> Result<BTreeSet<Spender>, IndexError<P>> {
+        Ok(self.provider.spenders(opout)?)
+    }
+
+    pub(super) fn operations(
+        &self,
+    ) -> Result<impl Iterator<Item = (OpId, BundleId)> + '_, IndexError<P>> {
+        self.provider.operations().map_err(IndexError::ReadProvider)
+    }
+
+    pub(super) fn bundle_ids(&self) -> Result<impl Iterator<Item = BundleId> + '_, IndexError<P>> {
+        self.provider.bundle_ids().map_err(IndexError::ReadProvider)
+    }
 }
 
 pub trait IndexProvider: Debug + IndexReadProvider + IndexWriteProvider {}
@@ -362,11 +451,55 @@ pub trait IndexReadProvider {
         &self,
         bundle_id: BundleId,
     ) -> Result<(XWitnessId, ContractId), IndexReadError<Self::Error>>;
+
+    /// Returns all known operations spending the output. More than a single
+    /// spender means that the output was double-spent by conflicting witness
+    /// transactions.
+    ///
+    /// Must fail with [`IndexInconsistency::SpendersNotIndexed`] if the
+    /// spenders were not indexed, for instance when the index was migrated
+    /// from a layout which had no spender information and the stash was not
+    /// re-indexed since then. The default implementation is used by providers
+    /// which do not index spenders.
+    fn spenders(&self, opout: Opout) -> Result<BTreeSet<Spender>, IndexReadError<Self::Error>> {
+        let _ = opout;
+        Err(IndexInconsistency::SpendersNotIndexed.into())
+    }
+
+    /// Lists all indexed operations together with the bundles containing them.
+    fn operations(&self) -> Result<impl Iterator<Item = (OpId, BundleId)> + '_, Self::Error>;
+
+    /// Lists all bundles for which the index holds witness or contract
+    /// information.
+    fn bundle_ids(&self) -> Result<impl Iterator<Item = BundleId> + '_, Self::Error>;
 }
 
 pub trait IndexWriteProvider {
     type Error: Clone + Eq + Error;
 
+    /// Starts a transaction: all following writes must be either prepared
+    /// with [`Self::prepare_transaction`] and then committed with
+    /// [`Self::commit_transaction`], or reverted with
+    /// [`Self::rollback_transaction`].
+    ///
+    /// Providers which are not able to revert their writes may keep the
+    /// default no-op implementation of the transaction methods; the stock
+    /// operations are not atomic in such case.
+    fn begin_transaction(&mut self) -> Result<(), Self::Error> { Ok(()) }
+    /// Performs all the work required to commit the transaction which may
+    /// fail. A prepared transaction can still be reverted.
+    ///
+    /// The stock prepares all its providers before committing any of them, so
+    /// if any of them fails to prepare, the transactions of all the providers
+    /// are reverted.
+    fn prepare_transaction(&mut self) -> Result<(), Self::Error> { Ok(()) }
+    /// Completes a prepared transaction. Committed transactions are never
+    /// reverted.
+    fn commit_transaction(&mut self) -> Result<(), Self::Error> { Ok(()) }
+    /// Reverts all writes made since the transaction was started, restoring
+    /// the data to exactly the same state as they were before.
+    fn rollback_transaction(&mut self) -> Result<(), Self::Error> { Ok(()) }
+
     fn register_contract(&mut self, contract_id: ContractId) -> Result<bool, Self::Error>;
 
     fn register_bundle(
@@ -382,6 +515,21 @@ pub trait IndexWriteProvider {
         bundle_id: BundleId,
     ) -> Result<bool, IndexWriteError<Self::Error>>;
 
+    /// Registers an operation spending the output. Returns whether the spender
+    /// was not known before.
+    ///
+    /// Providers which do not index spenders may keep the default no-op
+    /// implementation; they must keep the default implementation of
+    /// [`IndexReadProvider::spenders`] as well.
+    fn register_spender(
+        &mut self,
+        opout: Opout,
+        spender: Spender,
+    ) -> Result<bool, IndexWriteError<Self::Error>> {
+        let _ = (opout, spender);
+        Ok(false)
+    }
+
     fn index_genesis_assignments<State: ExposedState>(
         &mut self,
         contract_id: ContractId,
@@ -398,4 +546,15 @@ pub trait IndexWriteProvider {
         type_id: AssignmentType,
         witness_id: XWitnessId,
     ) -> Result<(), IndexWriteError<Self::Error>>;
+
+    /// Removes all index data related to a contract. The `opids` must list all
+    /// known operations of the contract, including its genesis.
+    fn remove_contract(
+        &mut self,
+        contract_id: ContractId,
+        opids: &BTreeSet<OpId>,
+    ) -> Result<(), Self::Error>;
+
+    /// Removes all index data.
+    fn clear_index(&mut self) -> Result<(), Self::Error>;
 }
@@ -19,14 +19,18 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::borrow::Cow;
 use std::collections::BTreeSet;
 use std::convert::Infallible;
+use std::hash::Hash;
+use std::mem;
 
 use aluvm::library::{Lib, LibId};
 use amplify::confinement::{
-    self, Confined, LargeOrdMap, MediumBlob, MediumOrdMap, MediumOrdSet, SmallOrdMap, TinyOrdMap,
-    TinyOrdSet,
+    self, Confined, LargeOrdMap, LargeOrdSet, MediumBlob, MediumOrdMap, MediumOrdSet, SmallOrdMap,
+    TinyOrdMap, TinyOrdSet, U24,
 };
+use amplify::Wrapper;
 use bp::dbc::tapret::TapretCommitment;
 use commit_verify::{CommitId, Conceal};
 use rgb::{
@@ -35,13 +39,14 @@ use rgb::{
     SecretSeal, TransitionBundle, XChain, XOutputSeal, XWitnessId,
 };
 use strict_encoding::{StrictDeserialize, StrictSerialize, TypeName};
-use strict_types::TypeSystem;
+use strict_types::{SemId, Ty, TypeSystem};
 
+use super::shared_map::SharedMap;
 use super::{
     IndexInconsistency, IndexProvider, IndexReadError, IndexReadProvider, IndexWriteError,
-    IndexWriteProvider, SchemaIfaces, StashInconsistency, StashProvider, StashProviderError,
-    StashReadProvider, StashWriteProvider, StateProvider, StateReadProvider, StateUpdateError,
-    StateWriteProvider,
+    IndexWriteProvider, SchemaIfaces, SecretSealInfo, Spender, StashInconsistency, StashProvider,
+    StashProviderError, StashReadProvider, StashWriteProvider, StateProvider, StateReadProvider,
+    StateUpdateError, StateWriteProvider,
 };
 use crate::containers::{AnchorSet, ContentId, ContentSigs, SealWitness, SigBlob};
 use crate::interface::{ContractSuppl, Iface, IfaceId, IfaceImpl, IfaceRef};
@@ -58,18 +63,38 @@ use crate::LIB_NAME_RGB_STD;
 #[derive(StrictType, StrictEncode, StrictDecode)]
 #[strict_type(lib = LIB_NAME_RGB_STD)]
 pub struct MemStash {
-    schemata: TinyOrdMap<SchemaId, SchemaIfaces>,
-    ifaces: TinyOrdMap<IfaceId, Iface>,
-    geneses: TinyOrdMap<ContractId, Genesis>,
-    suppl: TinyOrdMap<ContractId, TinyOrdSet<ContractSuppl>>,
-    bundles: LargeOrdMap<BundleId, TransitionBundle>,
-    extensions: LargeOrdMap<OpId, Extension>,
-    witnesses: LargeOrdMap<XWitnessId, SealWitness>,
-    attachments: SmallOrdMap<AttachId, MediumBlob>,
-    secret_seals: MediumOrdSet<XChain<GraphSeal>>,
-    type_system: TypeSystem,
-    libs: SmallOrdMap<LibId, Lib>,
-    sigs: SmallOrdMap<ContentId, ContentSigs>,
+    schemata: SharedMap<SchemaId, SchemaIfaces>,
+    ifaces: SharedMap<IfaceId, Iface>,
+    geneses: SharedMap<ContractId, Genesis>,
+    suppl: SharedMap<ContractId, TinyOrdSet<ContractSuppl>>,
+    bundles: SharedMap<BundleId, TransitionBundle>,
+    extensions: SharedMap<OpId, Extension>,
+    witnesses: SharedMap<XWitnessId, SealWitness>,
+    attachments: SharedMap<AttachId, MediumBlob>,
+    secret_seals: SharedMap<XChain<GraphSeal>, SecretSealInfo>,
+    types: SharedMap<SemId, Ty<SemId>>,
+    libs: SharedMap<LibId, Lib>,
+    sigs: SharedMap<ContentId, ContentSigs>,
+    #[getter(skip)]
+    #[strict_type(skip)]
+    journal: Journal<StashUndo>,
+}
+
+/// Previous version of stash data changed by a transaction.
+#[derive(Clone, Debug)]
+enum StashUndo {
+    Schema(SchemaId, Option<SchemaIfaces>),
+    Iface(IfaceId, Option<Iface>),
+    Genesis(ContractId, Option<Genesis>),
+    Suppl(ContractId, Option<TinyOrdSet<ContractSuppl>>),
+    Bundle(BundleId, Option<TransitionBundle>),
+    Extension(OpId, Option<Extension>),
+    Witness(XWitnessId, Option<SealWitness>),
+    Attachment(AttachId, Option<MediumBlob>),
+    SecretSeal(XChain<GraphSeal>, Option<SecretSealInfo>),
+    Type(SemId, Option<Ty<SemId>>),
+    Lib(LibId, Option<Lib>),
+    Sigs(ContentId, Option<ContentSigs>),
 }
 
 impl StrictSerialize for MemStash {}
@@ -85,7 +110,15 @@ impl StashReadProvider for MemStash {
     // With in-memory data we have no connectivity or I/O errors
     type Error = Infallible;
 
-    fn type_system(&self) -> Result<&TypeSystem, Self::Error> { Ok(&self.type_system) }
+    fn type_system(&self) -> Result<Cow<TypeSystem>, Self::Error> {
+        // The number of types is checked when they are added
+        let types = self.types.iter().map(|(id, ty)| (*id, ty.clone()));
+        Ok(Cow::Owned(TypeSystem::from_inner(Confined::from_iter_unsafe(types))))
+    }
+
+    fn lib_ids(&self) -> Result<impl Iterator<Item = LibId>, Self::Error> {
+        Ok(self.libs.keys().copied())
+    }
 
     fn lib(&self, id: LibId) -> Result<&Lib, StashProviderError<Self::Error>> {
         self.libs
@@ -158,6 +191,10 @@ impl StashReadProvider for MemStash {
             .into_iter())
     }
 
+    fn suppl_contract_ids(&self) -> Result<impl Iterator<Item = ContractId>, Self::Error> {
+        Ok(self.suppl.keys().copied())
+    }
+
     fn genesis(
         &self,
         contract_id: ContractId,
@@ -203,6 +240,20 @@ impl StashReadProvider for MemStash {
             .ok_or(StashInconsistency::WitnessAbsent(witness_id).into())
     }
 
+    fn attachment_ids(&self) -> Result<impl Iterator<Item = AttachId>, Self::Error> {
+        Ok(self.attachments.keys().copied())
+    }
+
+    fn attachment(&self, id: AttachId) -> Result<&MediumBlob, StashProviderError<Self::Error>> {
+        self.attachments
+            .get(&id)
+            .ok_or(StashInconsistency::AttachmentAbsent(id).into())
+    }
+
+    fn sigs(&self) -> Result<impl Iterator<Item = (ContentId, &ContentSigs)>, Self::Error> {
+        Ok(self.sigs.iter().map(|(id, sigs)| (*id, sigs)))
+    }
+
     fn taprets(&self) -> Result<impl Iterator<Item = (XWitnessId, TapretCommitment)>, Self::Error> {
         Ok(self
             .witnesses
@@ -226,23 +277,63 @@ impl StashReadProvider for MemStash {
     ) -> Result<Option<XChain<GraphSeal>>, Self::Error> {
         Ok(self
             .secret_seals
-            .iter()
+            .keys()
             .find(|s| s.conceal() == secret)
             .copied())
     }
 
     fn secret_seals(&self) -> Result<impl Iterator<Item = XChain<GraphSeal>>, Self::Error> {
-        Ok(self.secret_seals.iter().copied())
+        Ok(self.secret_seals.keys().copied())
+    }
+
+    fn secret_seal_records(
+        &self,
+    ) -> Result<impl Iterator<Item = (XChain<GraphSeal>, SecretSealInfo)>, Self::Error> {
+        Ok(self
+            .secret_seals
+            .iter()
+            .map(|(seal, info)| (*seal, info.clone())))
     }
 }
 
 impl StashWriteProvider for MemStash {
     type Error = confinement::Error;
 
+    fn begin_transaction(&mut self) -> Result<(), Self::Error> {
+        self.journal.begin();
+        Ok(())
+    }
+
+    fn commit_transaction(&mut self) -> Result<(), Self::Error> {
+        self.journal.commit();
+        Ok(())
+    }
+
+    fn rollback_transaction(&mut self) -> Result<(), Self::Error> {
+        for undo in self.journal.rollback() {
+            match undo {
+                StashUndo::Schema(id, prev) => restore(&mut self.schemata, id, prev)?,
+                StashUndo::Iface(id, prev) => restore(&mut self.ifaces, id, prev)?,
+                StashUndo::Genesis(id, prev) => restore(&mut self.geneses, id, prev)?,
+                StashUndo::Suppl(id, prev) => restore(&mut self.suppl, id, prev)?,
+                StashUndo::Bundle(id, prev) => restore(&mut self.bundles, id, prev)?,
+                StashUndo::Extension(id, prev) => restore(&mut self.extensions, id, prev)?,
+                StashUndo::Witness(id, prev) => restore(&mut self.witnesses, id, prev)?,
+                StashUndo::Attachment(id, prev) => restore(&mut self.attachments, id, prev)?,
+                StashUndo::SecretSeal(seal, prev) => restore(&mut self.secret_seals, seal, prev)?,
+                StashUndo::Type(id, prev) => restore(&mut self.types, id, prev)?,
+                StashUndo::Lib(id, prev) => restore(&mut self.libs, id, prev)?,
+                StashUndo::Sigs(id, prev) => restore(&mut self.sigs, id, prev)?,
+            }
+        }
+        Ok(())
+    }
+
     fn replace_schema(&mut self, schema: Schema) -> Result<bool, confinement::Error> {
         let schema_id = schema.schema_id();
         if !self.schemata.contains_key(&schema_id) {
             self.schemata.insert(schema_id, SchemaIfaces::new(schema))?;
+            self.journal.record(|| StashUndo::Schema(schema_id, None));
             return Ok(true);
         }
         Ok(false)
@@ -252,53 +343,68 @@ impl StashWriteProvider for MemStash {
         let iface_id = iface.iface_id();
         if !self.ifaces.contains_key(&iface_id) {
             self.ifaces.insert(iface_id, iface)?;
+            self.journal.record(|| StashUndo::Iface(iface_id, None));
             return Ok(true);
         }
         Ok(false)
     }
 
     fn replace_iimpl(&mut self, iimpl: IfaceImpl) -> Result<bool, confinement::Error> {
-        let schema_ifaces = self
-            .schemata
-            .get_mut(&iimpl.schema_id)
-            .expect("unknown schema");
+        let schema_id = iimpl.schema_id;
+        let schema_ifaces = self.schemata.get_mut(&schema_id).expect("unknown schema");
+        self.journal
+            .record(|| StashUndo::Schema(schema_id, Some(schema_ifaces.clone())));
         let present = schema_ifaces.iimpls.contains_key(&iimpl.iface_id);
         schema_ifaces.iimpls.insert(iimpl.iface_id, iimpl)?;
         Ok(!present)
     }
 
     fn add_suppl(&mut self, suppl: ContractSuppl) -> Result<(), confinement::Error> {
-        match self.suppl.get_mut(&suppl.contract_id) {
+        let contract_id = suppl.contract_id;
+        match self.suppl.get_mut(&contract_id) {
             None => {
-                self.suppl
-                    .insert(suppl.contract_id, confined_bset![suppl])?;
+                self.suppl.insert(contract_id, confined_bset![suppl])?;
+                self.journal.record(|| StashUndo::Suppl(contract_id, None));
+            }
+            Some(suppls) => {
+                self.journal
+                    .record(|| StashUndo::Suppl(contract_id, Some(suppls.clone())));
+                suppls.push(suppl)?;
             }
-            Some(suppls) => suppls.push(suppl)?,
         }
         Ok(())
     }
 
     fn replace_genesis(&mut self, genesis: Genesis) -> Result<bool, confinement::Error> {
         let contract_id = genesis.contract_id();
-        let present = self.geneses.insert(contract_id, genesis)?.is_some();
+        let prev = self.geneses.insert(contract_id, genesis)?;
+        let present = prev.is_some();
+        self.journal
+            .record(|| StashUndo::Genesis(contract_id, prev));
         Ok(!present)
     }
 
     fn replace_extension(&mut self, extension: Extension) -> Result<bool, confinement::Error> {
         let opid = extension.id();
-        let present = self.extensions.insert(opid, extension)?.is_some();
+        let prev = self.extensions.insert(opid, extension)?;
+        let present = prev.is_some();
+        self.journal.record(|| StashUndo::Extension(opid, prev));
         Ok(!present)
     }
 
     fn replace_bundle(&mut self, bundle: TransitionBundle) -> Result<bool, confinement::Error> {
         let bundle_id = bundle.bundle_id();
-        let present = self.bundles.insert(bundle_id, bundle)?.is_some();
+        let prev = self.bundles.insert(bundle_id, bundle)?;
+        let present = prev.is_some();
+        self.journal.record(|| StashUndo::Bundle(bundle_id, prev));
         Ok(!present)
     }
 
     fn replace_witness(&mut self, witness: SealWitness) -> Result<bool, confinement::Error> {
         let witness_id = witness.witness_id();
-        let present = self.witnesses.insert(witness_id, witness)?.is_some();
+        let prev = self.witnesses.insert(witness_id, witness)?;
+        let present = prev.is_some();
+        self.journal.record(|| StashUndo::Witness(witness_id, prev));
         Ok(!present)
     }
 
@@ -307,16 +413,34 @@ impl StashWriteProvider for MemStash {
         id: AttachId,
         attach: MediumBlob,
     ) -> Result<bool, confinement::Error> {
-        let present = self.attachments.insert(id, attach)?.is_some();
+        let prev = self.attachments.insert(id, attach)?;
+        let present = prev.is_some();
+        self.journal.record(|| StashUndo::Attachment(id, prev));
         Ok(!present)
     }
 
     fn consume_types(&mut self, types: TypeSystem) -> Result<(), confinement::Error> {
-        self.type_system.extend(types)
+        let added = types
+            .iter()
+            .filter(|(id, _)| !self.types.contains_key(*id))
+            .collect::<Vec<_>>();
+        // Types must fit into a single type system
+        let len = self.types.len() + added.len();
+        if len > U24 {
+            return Err(confinement::Error::Oversize { len, max_len: U24 });
+        }
+        for (id, ty) in added {
+            self.types.insert(*id, ty.clone())?;
+            self.journal.record(|| StashUndo::Type(*id, None));
+        }
+        Ok(())
     }
 
     fn replace_lib(&mut self, lib: Lib) -> Result<bool, confinement::Error> {
-        let present = self.libs.insert(lib.id(), lib)?.is_some();
+        let lib_id = lib.id();
+        let prev = self.libs.insert(lib_id, lib)?;
+        let present = prev.is_some();
+        self.journal.record(|| StashUndo::Lib(lib_id, prev));
         Ok(!present)
     }
 
@@ -328,19 +452,75 @@ impl StashWriteProvider for MemStash {
         let sigs = sigs.into_iter();
         if sigs.len() > 0 {
             if let Some(prev_sigs) = self.sigs.get_mut(&content_id) {
+                self.journal
+                    .record(|| StashUndo::Sigs(content_id, Some(prev_sigs.clone())));
                 prev_sigs.extend(sigs)?;
             } else {
                 let sigs = Confined::try_from_iter(sigs)?;
                 self.sigs.insert(content_id, ContentSigs::from(sigs)).ok();
+                self.journal.record(|| StashUndo::Sigs(content_id, None));
             }
         }
         Ok(())
     }
 
     fn add_secret_seal(&mut self, seal: XChain<GraphSeal>) -> Result<bool, confinement::Error> {
-        let present = self.secret_seals.contains(&seal);
-        self.secret_seals.push(seal)?;
-        Ok(!present)
+        self.add_secret_seal_with(seal, SecretSealInfo::default())
+    }
+
+    fn add_secret_seal_with(
+        &mut self,
+        seal: XChain<GraphSeal>,
+        info: SecretSealInfo,
+    ) -> Result<bool, confinement::Error> {
+        if self.secret_seals.contains_key(&seal) {
+            return Ok(false);
+        }
+        self.secret_seals.insert(seal, info)?;
+        self.journal.record(|| StashUndo::SecretSeal(seal, None));
+        Ok(true)
+    }
+
+    fn remove_secret_seal(&mut self, seal: XChain<GraphSeal>) -> Result<bool, confinement::Error> {
+        let prev = self.secret_seals.remove(&seal)?;
+        let present = prev.is_some();
+        self.journal.record(|| StashUndo::SecretSeal(seal, prev));
+        Ok(present)
+    }
+
+    fn remove_genesis(&mut self, contract_id: ContractId) -> Result<bool, confinement::Error> {
+        let prev = self.geneses.remove(&contract_id)?;
+        let present = prev.is_some();
+        self.journal
+            .record(|| StashUndo::Genesis(contract_id, prev));
+        Ok(present)
+    }
+
+    fn remove_suppl(&mut self, contract_id: ContractId) -> Result<(), confinement::Error> {
+        let prev = self.suppl.remove(&contract_id)?;
+        self.journal.record(|| StashUndo::Suppl(contract_id, prev));
+        Ok(())
+    }
+
+    fn remove_extension(&mut self, opid: OpId) -> Result<bool, confinement::Error> {
+        let prev = self.extensions.remove(&opid)?;
+        let present = prev.is_some();
+        self.journal.record(|| StashUndo::Extension(opid, prev));
+        Ok(present)
+    }
+
+    fn remove_bundle(&mut self, bundle_id: BundleId) -> Result<bool, confinement::Error> {
+        let prev = self.bundles.remove(&bundle_id)?;
+        let present = prev.is_some();
+        self.journal.record(|| StashUndo::Bundle(bundle_id, prev));
+        Ok(present)
+    }
+
+    fn remove_witness(&mut self, witness_id: XWitnessId) -> Result<bool, confinement::Error> {
+        let prev = self.witnesses.remove(&witness_id)?;
+        let present = prev.is_some();
+        self.journal.record(|| StashUndo::Witness(witness_id, prev));
+        Ok(present)
     }
 }
 
@@ -357,7 +537,17 @@ impl From<confinement::Error> for StateUpdateError<confinement::Error> {
 #[derive(StrictType, StrictEncode, StrictDecode)]
 #[strict_type(lib = LIB_NAME_RGB_STD)]
 pub struct MemState {
-    history: TinyOrdMap<ContractId, ContractHistory>,
+    history: SharedMap<ContractId, ContractHistory>,
+    #[getter(skip)]
+    #[strict_type(skip)]
+    journal: Journal<StateUndo>,
+}
+
+/// Previous version of contract state changed by a transaction.
+#[derive(Clone, Debug)]
+enum StateUndo {
+    History(ContractId, Option<ContractHistory>),
+    All(SharedMap<ContractId, ContractHistory>),
 }
 
 impl StrictSerialize for MemState {}
@@ -365,6 +555,18 @@ impl StrictDeserialize for MemState {}
 
 impl MemState {
     pub fn new() -> Self { MemState::default() }
+
+    pub(super) fn replace_history(
+        &mut self,
+        contract_id: ContractId,
+        history: ContractHistory,
+    ) -> Result<bool, confinement::Error> {
+        let prev = self.history.insert(contract_id, history)?;
+        let added = prev.is_none();
+        self.journal
+            .record(|| StateUndo::History(contract_id, prev));
+        Ok(added)
+    }
 }
 
 impl StateProvider for MemState {}
@@ -383,6 +585,26 @@ impl StateReadProvider for MemState {
 impl StateWriteProvider for MemState {
     type Error = confinement::Error;
 
+    fn begin_transaction(&mut self) -> Result<(), Self::Error> {
+        self.journal.begin();
+        Ok(())
+    }
+
+    fn commit_transaction(&mut self) -> Result<(), Self::Error> {
+        self.journal.commit();
+        Ok(())
+    }
+
+    fn rollback_transaction(&mut self) -> Result<(), Self::Error> {
+        for undo in self.journal.rollback() {
+            match undo {
+                StateUndo::History(id, prev) => restore(&mut self.history, id, prev)?,
+                StateUndo::All(prev) => self.history = prev,
+            }
+        }
+        Ok(())
+    }
+
     fn create_or_update_state<R: ResolveHeight>(
         &mut self,
         contract_id: ContractId,
@@ -391,7 +613,9 @@ impl StateWriteProvider for MemState {
         let state = self.history.get(&contract_id);
         let updated =
             updater(state.cloned()).map_err(|e| StateUpdateError::Resolver(e.to_string()))?;
-        self.history.insert(contract_id, updated)?;
+        let prev = self.history.insert(contract_id, updated)?;
+        self.journal
+            .record(|| StateUndo::History(contract_id, prev));
         Ok(())
     }
 
@@ -404,9 +628,25 @@ impl StateWriteProvider for MemState {
             .history
             .get_mut(&contract_id)
             .ok_or(StateUpdateError::UnknownContract(contract_id))?;
+        self.journal
+            .record(|| StateUndo::History(contract_id, Some(state.clone())));
         updater(state).map_err(|e| StateUpdateError::Resolver(e.to_string()))?;
         Ok(())
     }
+
+    fn remove_state(&mut self, contract_id: ContractId) -> Result<bool, Self::Error> {
+        let prev = self.history.remove(&contract_id)?;
+        let present = prev.is_some();
+        self.journal
+            .record(|| StateUndo::History(contract_id, prev));
+        Ok(present)
+    }
+
+    fn clear_state(&mut self) -> Result<(), Self::Error> {
+        let prev = mem::take(&mut self.history);
+        self.journal.record(|| StateUndo::All(prev));
+        Ok(())
+    }
 }
 
 //////////
@@ -430,8 +670,8 @@ impl From<confinement::Error> for IndexWriteError<confinement::Error> {
     serde(crate = "serde_crate", rename_all = "camelCase")
 )]
 pub struct ContractIndex {
-    public_opouts: MediumOrdSet<Opout>,
-    outpoint_opouts: MediumOrdMap<XOutputSeal, MediumOrdSet<Opout>>,
+    public_opouts: LargeOrdSet<Opout>,
+    outpoint_opouts: LargeOrdMap<XOutputSeal, LargeOrdSet<Opout>>,
 }
 
 #[derive(Getters, Clone, Debug, Default)]
@@ -439,11 +679,31 @@ pub struct ContractIndex {
 #[derive(StrictType, StrictEncode, StrictDecode)]
 #[strict_type(lib = LIB_NAME_RGB_STD)]
 pub struct MemIndex {
-    op_bundle_index: MediumOrdMap<OpId, BundleId>,
-    bundle_contract_index: MediumOrdMap<BundleId, ContractId>,
-    bundle_witness_index: MediumOrdMap<BundleId, XWitnessId>,
-    contract_index: TinyOrdMap<ContractId, ContractIndex>,
-    terminal_index: MediumOrdMap<XChain<SecretSeal>, Opout>,
+    op_bundle_index: SharedMap<OpId, BundleId>,
+    bundle_contract_index: SharedMap<BundleId, ContractId>,
+    bundle_witness_index: SharedMap<BundleId, XWitnessId>,
+    contract_index: SharedMap<ContractId, ContractIndex>,
+    terminal_index: SharedMap<XChain<SecretSeal>, Opout>,
+    spent_index: SharedMap<Opout, TinyOrdSet<Spender>>,
+    /// Whether the spender index lacks data, since the index was migrated
+    /// from a layout which had no spender information.
+    spent_index_outdated: bool,
+    #[getter(skip)]
+    #[strict_type(skip)]
+    journal: Journal<IndexUndo>,
+}
+
+/// Previous version of index data changed by a transaction.
+#[derive(Clone, Debug)]
+enum IndexUndo {
+    OpBundle(OpId, Option<BundleId>),
+    BundleContract(BundleId, Option<ContractId>),
+    BundleWitness(BundleId, Option<XWitnessId>),
+    Contract(ContractId, Option<ContractIndex>),
+    Output(ContractId, XOutputSeal, Option<LargeOrdSet<Opout>>),
+    Terminal(XChain<SecretSeal>, Option<Opout>),
+    Spent(Opout, Option<TinyOrdSet<Spender>>),
+    All(Box<MemIndex>),
 }
 
 impl StrictSerialize for MemIndex {}
@@ -451,6 +711,43 @@ impl StrictDeserialize for MemIndex {}
 
 impl MemIndex {
     pub fn new() -> Self { MemIndex::default() }
+
+    pub(super) fn index_output(
+        &mut self,
+        contract_id: ContractId,
+        output: XOutputSeal,
+        opout: Opout,
+    ) -> Result<(), IndexWriteError<confinement::Error>> {
+        let index = self
+            .contract_index
+            .get_mut(&contract_id)
+            .ok_or(IndexInconsistency::ContractAbsent(contract_id))?;
+        match index.outpoint_opouts.get_mut(&output) {
+            Some(opouts) => {
+                self.journal
+                    .record(|| IndexUndo::Output(contract_id, output, Some(opouts.clone())));
+                opouts.push(opout)?;
+            }
+            None => {
+                index
+                    .outpoint_opouts
+                    .insert(output, confined_bset!(opout))?;
+                self.journal
+                    .record(|| IndexUndo::Output(contract_id, output, None));
+            }
+        }
+        Ok(())
+    }
+
+    pub(super) fn index_terminal(
+        &mut self,
+        seal: XChain<SecretSeal>,
+        opout: Opout,
+    ) -> Result<(), confinement::Error> {
+        let prev = self.terminal_index.insert(seal, opout)?;
+        self.journal.record(|| IndexUndo::Terminal(seal, prev));
+        Ok(())
+    }
 }
 
 impl IndexProvider for MemIndex {}
@@ -541,14 +838,84 @@ impl IndexReadProvider for MemIndex {
             .ok_or(IndexInconsistency::BundleContractUnknown(bundle_id))?;
         Ok((*witness_id, *contract_id))
     }
+
+    fn spenders(&self, opout: Opout) -> Result<BTreeSet<Spender>, IndexReadError<Self::Error>> {
+        if self.spent_index_outdated {
+            return Err(IndexInconsistency::SpendersNotIndexed.into());
+        }
+        Ok(self
+            .spent_index
+            .get(&opout)
+            .map(|spenders| spenders.to_inner())
+            .unwrap_or_default())
+    }
+
+    fn operations(&self) -> Result<impl Iterator<Item = (OpId, BundleId)> + '_, Self::Error> {
+        Ok(self
+            .op_bundle_index
+            .iter()
+            .map(|(opid, bundle_id)| (*opid, *bundle_id)))
+    }
+
+    fn bundle_ids(&self) -> Result<impl Iterator<Item = BundleId> + '_, Self::Error> {
+        Ok(self
+            .bundle_witness_index
+            .keys()
+            .chain(self.bundle_contract_index.keys())
+            .copied()
+            .collect::<BTreeSet<_>>()
+            .into_iter())
+    }
 }
 
 impl IndexWriteProvider for MemIndex {
     type Error = confinement::Error;
 
+    fn begin_transaction(&mut self) -> Result<(), Self::Error> {
+        self.journal.begin();
+        Ok(())
+    }
+
+    fn commit_transaction(&mut self) -> Result<(), Self::Error> {
+        self.journal.commit();
+        Ok(())
+    }
+
+    fn rollback_transaction(&mut self) -> Result<(), Self::Error> {
+        for undo in self.journal.rollback() {
+            match undo {
+                IndexUndo::OpBundle(id, prev) => restore(&mut self.op_bundle_index, id, prev)?,
+                IndexUndo::BundleContract(id, prev) => {
+                    restore(&mut self.bundle_contract_index, id, prev)?
+                }
+                IndexUndo::BundleWitness(id, prev) => {
+                    restore(&mut self.bundle_witness_index, id, prev)?
+                }
+                IndexUndo::Contract(id, prev) => restore(&mut self.contract_index, id, prev)?,
+                IndexUndo::Output(id, output, prev) => {
+                    // The contract index entry is always present here, since
+                    // its removal is journaled after the output changes.
+                    if let Some(index) = self.contract_index.get_mut(&id) {
+                        restore_confined(&mut index.outpoint_opouts, output, prev)?;
+                    }
+                }
+                IndexUndo::Terminal(seal, prev) => restore(&mut self.terminal_index, seal, prev)?,
+                IndexUndo::Spent(opout, prev) => restore(&mut self.spent_index, opout, prev)?,
+                IndexUndo::All(prev) => {
+                    let journal = mem::take(&mut self.journal);
+                    *self = *prev;
+                    self.journal = journal;
+                }
+            }
+        }
+        Ok(())
+    }
+
     fn register_contract(&mut self, contract_id: ContractId) -> Result<bool, Self::Error> {
         if !self.contract_index.contains_key(&contract_id) {
             self.contract_index.insert(contract_id, empty!())?;
+            self.journal
+                .record(|| IndexUndo::Contract(contract_id, None));
             Ok(true)
         } else {
             Ok(false)
@@ -585,16 +952,15 @@ impl IndexWriteProvider for MemIndex {
             }
             .into());
         }
-        let present1 = self
-            .bundle_witness_index
-            .insert(bundle_id, witness_id)?
-            .is_some();
-        let present2 = self
-            .bundle_contract_index
-            .insert(bundle_id, contract_id)?
-            .is_some();
-        debug_assert_eq!(present1, present2);
-        Ok(!present1)
+        let prev1 = self.bundle_witness_index.insert(bundle_id, witness_id)?;
+        let prev2 = self.bundle_contract_index.insert(bundle_id, contract_id)?;
+        let present = prev1.is_some();
+        debug_assert_eq!(present, prev2.is_some());
+        self.journal
+            .record(|| IndexUndo::BundleWitness(bundle_id, prev1));
+        self.journal
+            .record(|| IndexUndo::BundleContract(bundle_id, prev2));
+        Ok(!present)
     }
 
     fn register_operation(
@@ -614,10 +980,33 @@ impl IndexWriteProvider for MemIndex {
             }
             .into());
         }
-        let present = self.op_bundle_index.insert(opid, bundle_id)?.is_some();
+        let prev = self.op_bundle_index.insert(opid, bundle_id)?;
+        let present = prev.is_some();
+        self.journal.record(|| IndexUndo::OpBundle(opid, prev));
         Ok(!present)
     }
 
+    fn register_spender(
+        &mut self,
+        opout: Opout,
+        spender: Spender,
+    ) -> Result<bool, IndexWriteError<Self::Error>> {
+        match self.spent_index.get_mut(&opout) {
+            Some(spenders) => {
+                let present = spenders.contains(&spender);
+                self.journal
+                    .record(|| IndexUndo::Spent(opout, Some(spenders.clone())));
+                spenders.push(spender)?;
+                Ok(!present)
+            }
+            None => {
+                self.spent_index.insert(opout, confined_bset!(spender))?;
+                self.journal.record(|| IndexUndo::Spent(opout, None));
+                Ok(true)
+            }
+        }
+    }
+
     fn index_genesis_assignments<State: ExposedState>(
         &mut self,
         contract_id: ContractId,
@@ -625,10 +1014,9 @@ impl IndexWriteProvider for MemIndex {
         opid: OpId,
         type_id: AssignmentType,
     ) -> Result<(), IndexWriteError<Self::Error>> {
-        let index = self
-            .contract_index
-            .get_mut(&contract_id)
-            .ok_or(IndexInconsistency::ContractAbsent(contract_id))?;
+        if !self.contract_index.contains_key(&contract_id) {
+            return Err(IndexInconsistency::ContractAbsent(contract_id).into());
+        }
 
         for (no, a) in vec.iter().enumerate() {
             let opout = Opout::new(opid, type_id, no as u16);
@@ -636,19 +1024,10 @@ impl IndexWriteProvider for MemIndex {
                 let output = seal
                     .to_output_seal()
                     .expect("genesis seals always have outpoint");
-                match index.outpoint_opouts.get_mut(&output) {
-                    Some(opouts) => {
-                        opouts.push(opout)?;
-                    }
-                    None => {
-                        index
-                            .outpoint_opouts
-                            .insert(output, confined_bset!(opout))?;
-                    }
-                }
+                self.index_output(contract_id, output, opout)?;
             }
             if let Assign::Confidential { seal, .. } | Assign::ConfidentialSeal { seal, .. } = a {
-                self.terminal_index.insert(*seal, opout)?;
+                self.index_terminal(*seal, opout)?;
             }
         }
         Ok(())
@@ -662,10 +1041,9 @@ impl IndexWriteProvider for MemIndex {
         type_id: AssignmentType,
         witness_id: XWitnessId,
     ) -> Result<(), IndexWriteError<Self::Error>> {
-        let index = self
-            .contract_index
-            .get_mut(&contract_id)
-            .ok_or(IndexInconsistency::ContractAbsent(contract_id))?;
+        if !self.contract_index.contains_key(&contract_id) {
+            return Err(IndexInconsistency::ContractAbsent(contract_id).into());
+        }
 
         for (no, assign) in vec.iter().enumerate() {
             let opout = Opout::new(opid, type_id, no as u16);
@@ -677,23 +1055,534 @@ impl IndexWriteProvider for MemIndex {
                         seal, witness_id
                     )
                 });
-                match index.outpoint_opouts.get_mut(&output) {
-                    Some(opouts) => {
-                        opouts.push(opout)?;
-                    }
-                    None => {
-                        index
-                            .outpoint_opouts
-                            .insert(output, confined_bset!(opout))?;
-                    }
-                }
+                self.index_output(contract_id, output, opout)?;
             }
             if let Assign::Confidential { seal, .. } | Assign::ConfidentialSeal { seal, .. } =
                 assign
             {
-                self.terminal_index.insert(*seal, opout)?;
+                self.index_terminal(*seal, opout)?;
             }
         }
         Ok(())
     }
+
+    fn remove_contract(
+        &mut self,
+        contract_id: ContractId,
+        opids: &BTreeSet<OpId>,
+    ) -> Result<(), Self::Error> {
+        let prev = self.contract_index.remove(&contract_id)?;
+        self.journal
+            .record(|| IndexUndo::Contract(contract_id, prev));
+
+        let bundles = self
+            .bundle_contract_index
+            .iter()
+            .filter(|(_, id)| **id == contract_id)
+            .map(|(bundle_id, _)| *bundle_id)
+            .collect::<BTreeSet<_>>();
+        for bundle_id in &bundles {
+            let prev = self.bundle_contract_index.remove(bundle_id)?;
+            self.journal
+                .record(|| IndexUndo::BundleContract(*bundle_id, prev));
+            let prev = self.bundle_witness_index.remove(bundle_id)?;
+            self.journal
+                .record(|| IndexUndo::BundleWitness(*bundle_id, prev));
+        }
+
+        let ops = self
+            .op_bundle_index
+            .iter()
+            .filter(|(opid, bundle_id)| opids.contains(*opid) || bundles.contains(*bundle_id))
+            .map(|(opid, _)| *opid)
+            .collect::<Vec<_>>();
+        for opid in ops {
+            let prev = self.op_bundle_index.remove(&opid)?;
+            self.journal.record(|| IndexUndo::OpBundle(opid, prev));
+        }
+
+        let terminals = self
+            .terminal_index
+            .iter()
+            .filter(|(_, opout)| opids.contains(&opout.op))
+            .map(|(seal, _)| *seal)
+            .collect::<Vec<_>>();
+        for seal in terminals {
+            let prev = self.terminal_index.remove(&seal)?;
+            self.journal.record(|| IndexUndo::Terminal(seal, prev));
+        }
+
+        let spent = self
+            .spent_index
+            .keys()
+            .filter(|opout| opids.contains(&opout.op))
+            .copied()
+            .collect::<Vec<_>>();
+        for opout in spent {
+            let prev = self.spent_index.remove(&opout)?;
+            self.journal.record(|| IndexUndo::Spent(opout, prev));
+        }
+        Ok(())
+    }
+
+    fn clear_index(&mut self) -> Result<(), Self::Error> {
+        let journal = mem::take(&mut self.journal);
+        let prev = mem::take(self);
+        self.journal = journal;
+        self.journal.record(|| IndexUndo::All(Box::new(prev)));
+        Ok(())
+    }
+}
+
+//////////
+// JOURNAL
+//////////
+
+/// Undo journal of a provider transaction.
+///
+/// Keeps previous versions of the data changed since the start of the
+/// transaction, such that it can be reverted without copying all the data of
+/// the provider. The journal of a committed transaction is kept until the next
+/// transaction starts, so it still can be reverted if some other provider of
+/// the same stock fails to commit.
+#[derive(Clone, Debug)]
+pub(super) enum Journal<U> {
+    Idle,
+    Active(Vec<U>),
+    Committed(Vec<U>),
+}
+
+impl<U> Default for Journal<U> {
+    fn default() -> Self { Journal::Idle }
+}
+
+impl<U> Journal<U> {
+    pub fn begin(&mut self) { *self = Journal::Active(vec![]) }
+
+    pub fn is_active(&self) -> bool { matches!(self, Journal::Active(_)) }
+
+    pub fn commit(&mut self) {
+        if let Journal::Active(log) = self {
+            *self = Journal::Committed(mem::take(log));
+        }
+    }
+
+    /// Records previous version of the data which are changed, if there is
+    /// an active transaction.
+    pub fn record(&mut self, undo: impl FnOnce() -> U) {
+        if let Journal::Active(log) = self {
+            log.push(undo());
+        }
+    }
+
+    /// Takes all recorded changes, starting from the most recent ones.
+    pub fn rollback(&mut self) -> impl Iterator<Item = U> {
+        match mem::take(self) {
+            Journal::Idle => vec![],
+            Journal::Active(log) | Journal::Committed(log) => log,
+        }
+        .into_iter()
+        .rev()
+    }
+}
+
+/// Restores previous value of a map entry: re-inserts it if it was present
+/// before, or removes the entry otherwise.
+fn restore<K: Ord + Clone, V: Clone>(
+    map: &mut SharedMap<K, V>,
+    key: K,
+    prev: Option<V>,
+) -> Result<(), confinement::Error> {
+    match prev {
+        Some(val) => map.insert(key, val).map(|_| ()),
+        None => map.remove(&key).map(|_| ()),
+    }
+}
+
+/// Restores previous value of an entry in a confined map.
+fn restore_confined<K: Ord + Hash, V>(
+    map: &mut LargeOrdMap<K, V>,
+    key: K,
+    prev: Option<V>,
+) -> Result<(), confinement::Error> {
+    match prev {
+        Some(val) => map.insert(key, val).map(|_| ()),
+        None => map.remove(&key).map(|_| ()),
+    }
+}
+
+//////////
+// LEGACY
+//////////
+
+// Layouts of the in-memory providers used before their collections were
+// extended to the large confinement bounds. They are kept to read data files
+// stored by the previous versions of the library.
+
+#[derive(Clone, Debug, Default)]
+#[derive(StrictType, StrictEncode, StrictDecode)]
+#[strict_type(lib = LIB_NAME_RGB_STD)]
+pub(crate) struct MemStashV0 {
+    schemata: TinyOrdMap<SchemaId, SchemaIfaces>,
+    ifaces: TinyOrdMap<IfaceId, Iface>,
+    geneses: TinyOrdMap<ContractId, Genesis>,
+    suppl: TinyOrdMap<ContractId, TinyOrdSet<ContractSuppl>>,
+    bundles: LargeOrdMap<BundleId, TransitionBundle>,
+    extensions: LargeOrdMap<OpId, Extension>,
+    witnesses: LargeOrdMap<XWitnessId, SealWitness>,
+    attachments: SmallOrdMap<AttachId, MediumBlob>,
+    secret_seals: MediumOrdSet<XChain<GraphSeal>>,
+    type_system: TypeSystem,
+    libs: SmallOrdMap<LibId, Lib>,
+    sigs: SmallOrdMap<ContentId, ContentSigs>,
+}
+
+impl StrictSerialize for MemStashV0 {}
+impl StrictDeserialize for MemStashV0 {}
+
+impl From<MemStashV0> for MemStashV1 {
+    fn from(old: MemStashV0) -> Self {
+        // All collections are converted into ones with larger bounds, so the
+        // unchecked conversions can't fail.
+        MemStashV1 {
+            schemata: Confined::from_collection_unsafe(old.schemata.into_inner()),
+            ifaces: Confined::from_collection_unsafe(old.ifaces.into_inner()),
+            geneses: Confined::from_collection_unsafe(old.geneses.into_inner()),
+            suppl: Confined::from_collection_unsafe(old.suppl.into_inner()),
+            bundles: old.bundles,
+            extensions: old.extensions,
+            witnesses: old.witnesses,
+            attachments: Confined::from_collection_unsafe(old.attachments.into_inner()),
+            secret_seals: Confined::from_collection_unsafe(old.secret_seals.into_inner()),
+            type_system: old.type_system,
+            libs: Confined::from_collection_unsafe(old.libs.into_inner()),
+            sigs: Confined::from_collection_unsafe(old.sigs.into_inner()),
+        }
+    }
+}
+
+/// Stash layout used before secret seals got their information records.
+#[derive(Clone, Debug, Default)]
+#[derive(StrictType, StrictEncode, StrictDecode)]
+#[strict_type(lib = LIB_NAME_RGB_STD)]
+pub(crate) struct MemStashV1 {
+    schemata: LargeOrdMap<SchemaId, SchemaIfaces>,
+    ifaces: LargeOrdMap<IfaceId, Iface>,
+    geneses: LargeOrdMap<ContractId, Genesis>,
+    suppl: LargeOrdMap<ContractId, TinyOrdSet<ContractSuppl>>,
+    bundles: LargeOrdMap<BundleId, TransitionBundle>,
+    extensions: LargeOrdMap<OpId, Extension>,
+    witnesses: LargeOrdMap<XWitnessId, SealWitness>,
+    attachments: LargeOrdMap<AttachId, MediumBlob>,
+    secret_seals: LargeOrdSet<XChain<GraphSeal>>,
+    type_system: TypeSystem,
+    libs: LargeOrdMap<LibId, Lib>,
+    sigs: LargeOrdMap<ContentId, ContentSigs>,
+}
+
+impl StrictSerialize for MemStashV1 {}
+impl StrictDeserialize for MemStashV1 {}
+
+impl From<MemStashV1> for MemStashV2 {
+    fn from(old: MemStashV1) -> Self {
+        // Creation time of the old seals is unknown and they never expire.
+        MemStashV2 {
+            schemata: old.schemata,
+            ifaces: old.ifaces,
+            geneses: old.geneses,
+            suppl: old.suppl,
+            bundles: old.bundles,
+            extensions: old.extensions,
+            witnesses: old.witnesses,
+            attachments: old.attachments,
+            secret_seals: Confined::from_iter_unsafe(
+                old.secret_seals
+                    .into_iter()
+                    .map(|seal| (seal, SecretSealInfo::default())),
+            ),
+            type_system: old.type_system,
+            libs: old.libs,
+            sigs: old.sigs,
+        }
+    }
+}
+
+/// Stash layout used before types were kept in a shared map.
+#[derive(Clone, Debug, Default)]
+#[derive(StrictType, StrictEncode, StrictDecode)]
+#[strict_type(lib = LIB_NAME_RGB_STD)]
+pub(crate) struct MemStashV2 {
+    schemata: LargeOrdMap<SchemaId, SchemaIfaces>,
+    ifaces: LargeOrdMap<IfaceId, Iface>,
+    geneses: LargeOrdMap<ContractId, Genesis>,
+    suppl: LargeOrdMap<ContractId, TinyOrdSet<ContractSuppl>>,
+    bundles: LargeOrdMap<BundleId, TransitionBundle>,
+    extensions: LargeOrdMap<OpId, Extension>,
+    witnesses: LargeOrdMap<XWitnessId, SealWitness>,
+    attachments: LargeOrdMap<AttachId, MediumBlob>,
+    secret_seals: LargeOrdMap<XChain<GraphSeal>, SecretSealInfo>,
+    type_system: TypeSystem,
+    libs: LargeOrdMap<LibId, Lib>,
+    sigs: LargeOrdMap<ContentId, ContentSigs>,
+}
+
+impl StrictSerialize for MemStashV2 {}
+impl StrictDeserialize for MemStashV2 {}
+
+impl From<MemStashV2> for MemStash {
+    fn from(old: MemStashV2) -> Self {
+        MemStash {
+            schemata: old.schemata.into(),
+            ifaces: old.ifaces.into(),
+            geneses: old.geneses.into(),
+            suppl: old.suppl.into(),
+            bundles: old.bundles.into(),
+            extensions: old.extensions.into(),
+            witnesses: old.witnesses.into(),
+            attachments: old.attachments.into(),
+            secret_seals: old.secret_seals.into(),
+            types: old.type_system.into_inner().into_iter().collect(),
+            libs: old.libs.into(),
+            sigs: old.sigs.into(),
+            journal: default!(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+#[derive(StrictType, StrictEncode, StrictDecode)]
+#[strict_type(lib = LIB_NAME_RGB_STD)]
+pub(crate) struct MemStateV0 {
+    history: TinyOrdMap<ContractId, ContractHistory>,
+}
+
+impl StrictSerialize for MemStateV0 {}
+impl StrictDeserialize for MemStateV0 {}
+
+impl From<MemStateV0> for MemState {
+    fn from(old: MemStateV0) -> Self {
+        MemState {
+            history: old.history.into_iter().collect(),
+            journal: default!(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+#[derive(StrictType, StrictEncode, StrictDecode)]
+#[strict_type(lib = LIB_NAME_RGB_STD)]
+struct ContractIndexV0 {
+    public_opouts: MediumOrdSet<Opout>,
+    outpoint_opouts: MediumOrdMap<XOutputSeal, MediumOrdSet<Opout>>,
+}
+
+impl From<ContractIndexV0> for ContractIndex {
+    fn from(old: ContractIndexV0) -> Self {
+        ContractIndex {
+            public_opouts: Confined::from_collection_unsafe(old.public_opouts.into_inner()),
+            outpoint_opouts: Confined::from_collection_unsafe(
+                old.outpoint_opouts
+                    .into_inner()
+                    .into_iter()
+                    .map(|(output, opouts)| {
+                        (output, Confined::from_collection_unsafe(opouts.into_inner()))
+                    })
+                    .collect(),
+            ),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+#[derive(StrictType, StrictEncode, StrictDecode)]
+#[strict_type(lib = LIB_NAME_RGB_STD)]
+pub(crate) struct MemIndexV0 {
+    op_bundle_index: MediumOrdMap<OpId, BundleId>,
+    bundle_contract_index: MediumOrdMap<BundleId, ContractId>,
+    bundle_witness_index: MediumOrdMap<BundleId, XWitnessId>,
+    contract_index: TinyOrdMap<ContractId, ContractIndexV0>,
+    terminal_index: MediumOrdMap<XChain<SecretSeal>, Opout>,
+}
+
+impl StrictSerialize for MemIndexV0 {}
+impl StrictDeserialize for MemIndexV0 {}
+
+impl From<MemIndexV0> for MemIndexV1 {
+    fn from(old: MemIndexV0) -> Self {
+        MemIndexV1 {
+            op_bundle_index: Confined::from_collection_unsafe(old.op_bundle_index.into_inner()),
+            bundle_contract_index: Confined::from_collection_unsafe(
+                old.bundle_contract_index.into_inner(),
+            ),
+            bundle_witness_index: Confined::from_collection_unsafe(
+                old.bundle_witness_index.into_inner(),
+            ),
+            contract_index: Confined::from_collection_unsafe(
+                old.contract_index
+                    .into_inner()
+                    .into_iter()
+                    .map(|(contract_id, index)| (contract_id, index.into()))
+                    .collect(),
+            ),
+            terminal_index: Confined::from_collection_unsafe(old.terminal_index.into_inner()),
+        }
+    }
+}
+
+/// Index layout used before the spender index was introduced.
+#[derive(Clone, Debug, Default)]
+#[derive(StrictType, StrictEncode, StrictDecode)]
+#[strict_type(lib = LIB_NAME_RGB_STD)]
+pub(crate) struct MemIndexV1 {
+    op_bundle_index: LargeOrdMap<OpId, BundleId>,
+    bundle_contract_index: LargeOrdMap<BundleId, ContractId>,
+    bundle_witness_index: LargeOrdMap<BundleId, XWitnessId>,
+    contract_index: LargeOrdMap<ContractId, ContractIndex>,
+    terminal_index: LargeOrdMap<XChain<SecretSeal>, Opout>,
+}
+
+impl StrictSerialize for MemIndexV1 {}
+impl StrictDeserialize for MemIndexV1 {}
+
+impl From<MemIndexV1> for MemIndexV2 {
+    fn from(old: MemIndexV1) -> Self {
+        MemIndexV2 {
+            op_bundle_index: old.op_bundle_index,
+            bundle_contract_index: old.bundle_contract_index,
+            bundle_witness_index: old.bundle_witness_index,
+            contract_index: old.contract_index,
+            terminal_index: old.terminal_index,
+            spent_index: none!(),
+        }
+    }
+}
+
+/// Index layout used before the spender index got its completeness flag.
+#[derive(Clone, Debug, Default)]
+#[derive(StrictType, StrictEncode, StrictDecode)]
+#[strict_type(lib = LIB_NAME_RGB_STD)]
+pub(crate) struct MemIndexV2 {
+    op_bundle_index: LargeOrdMap<OpId, BundleId>,
+    bundle_contract_index: LargeOrdMap<BundleId, ContractId>,
+    bundle_witness_index: LargeOrdMap<BundleId, XWitnessId>,
+    contract_index: LargeOrdMap<ContractId, ContractIndex>,
+    terminal_index: LargeOrdMap<XChain<SecretSeal>, Opout>,
+    spent_index: LargeOrdMap<Opout, TinyOrdSet<Spender>>,
+}
+
+impl StrictSerialize for MemIndexV2 {}
+impl StrictDeserialize for MemIndexV2 {}
+
+impl MemIndexV2 {
+    /// Converts into the current layout. Spender information can't be
+    /// recovered without the stash, so the spender index of data migrated
+    /// from the layouts preceding it is marked as outdated until the stash is
+    /// re-indexed with `Stock::rebuild_index`.
+    pub(crate) fn upgrade(self, spenders_indexed: bool) -> MemIndex {
+        MemIndex {
+            op_bundle_index: self.op_bundle_index.into(),
+            bundle_contract_index: self.bundle_contract_index.into(),
+            bundle_witness_index: self.bundle_witness_index.into(),
+            contract_index: self.contract_index.into(),
+            terminal_index: self.terminal_index.into(),
+            spent_index: self.spent_index.into(),
+            spent_index_outdated: !spenders_indexed,
+            journal: default!(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use amplify::confinement::U64;
+    use amplify::ByteArray;
+    use bp::seals::txout::CloseMethod;
+    use bp::Txid;
+
+    use super::*;
+    use crate::interface::TickerSuppl;
+    use crate::stl::StandardTypes;
+
+    #[test]
+    fn types() {
+        let types = StandardTypes::new().type_system();
+        let mut stash = MemStash::default();
+
+        stash.begin_transaction().unwrap();
+        stash.consume_types(types.clone()).unwrap();
+        assert_eq!(*stash.type_system().unwrap(), types);
+        stash.rollback_transaction().unwrap();
+        assert!(stash.type_system().unwrap().is_empty());
+
+        stash.consume_types(types.clone()).unwrap();
+        let copy = stash.clone();
+        // Known types are neither copied nor recorded into the journal
+        stash.begin_transaction().unwrap();
+        stash.consume_types(types.clone()).unwrap();
+        assert!(matches!(&stash.journal, Journal::Active(log) if log.is_empty()));
+        stash.commit_transaction().unwrap();
+        assert_eq!(*stash.type_system().unwrap(), types);
+        assert_eq!(*copy.type_system().unwrap(), types);
+    }
+
+    #[test]
+    fn migrated_types() {
+        let types = StandardTypes::new().type_system();
+        let stash = MemStash::from(MemStashV2 {
+            type_system: types.clone(),
+            ..default!()
+        });
+        assert_eq!(*stash.type_system().unwrap(), types);
+
+        let data = stash.to_strict_serialized::<U64>().unwrap();
+        let stash = MemStash::from_strict_serialized::<U64>(data).unwrap();
+        assert_eq!(*stash.type_system().unwrap(), types);
+    }
+
+    #[test]
+    fn remove_secret_seal() {
+        let seal = XChain::Bitcoin(GraphSeal::with_blinding(
+            CloseMethod::TapretFirst,
+            Txid::coinbase(),
+            1u32,
+            1,
+        ));
+        let info = SecretSealInfo::new(1_700_000_000);
+        let mut stash = MemStash::default();
+        assert!(stash.add_secret_seal_with(seal, info.clone()).unwrap());
+
+        stash.begin_transaction().unwrap();
+        assert!(stash.remove_secret_seal(seal).unwrap());
+        assert!(!stash.remove_secret_seal(seal).unwrap());
+        stash.rollback_transaction().unwrap();
+        // Information about the seal is restored together with the seal
+        assert_eq!(stash.secret_seal_records().unwrap().collect::<Vec<_>>(), vec![(seal, info)]);
+
+        assert!(stash.remove_secret_seal(seal).unwrap());
+        assert!(stash.debug_secret_seals().is_empty());
+    }
+
+    #[test]
+    fn many_contracts() {
+        let mut stash = MemStash::default();
+        for no in 0..300u16 {
+            let mut id = [0u8; 32];
+            id[..2].copy_from_slice(&no.to_le_bytes());
+            stash
+                .add_suppl(ContractSuppl {
+                    contract_id: ContractId::from_byte_array(id),
+                    ticker: TickerSuppl::Absent,
+                    media_kit: none!(),
+                    global_state: none!(),
+                    owned_state: none!(),
+                    extensions: none!(),
+                })
+                .unwrap();
+        }
+        assert_eq!(stash.suppl_contract_ids().unwrap().count(), 300);
+
+        let data = stash.to_strict_serialized::<U64>().unwrap();
+        let stash = MemStash::from_strict_serialized::<U64>(data).unwrap();
+        assert_eq!(stash.suppl_contract_ids().unwrap().count(), 300);
+    }
 }
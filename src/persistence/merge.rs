@@ -0,0 +1,172 @@
+// RGB standard library for working with smart contracts on Bitcoin & Lightning
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2019-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2019-2024 LNP/BP Standards Association. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use rgb::{BundleId, ContractId, OpId, XWitnessId};
+
+/// Data which were not moved by [`super::Stock::merge`] since they conflict
+/// with the data already present in the stock.
+#[derive(Clone, Eq, PartialEq, Debug, Display)]
+#[display(doc_comments)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+pub enum MergeConflict {
+    /// genesis of contract {contract_id} can't be merged: {reason}
+    Genesis {
+        contract_id: ContractId,
+        reason: String,
+    },
+
+    /// state extension {opid} can't be merged: {reason}
+    Extension { opid: OpId, reason: String },
+
+    /// bundle {bundle_id} can't be merged: {reason}
+    Bundle { bundle_id: BundleId, reason: String },
+
+    /// witness {witness_id} can't be merged: {reason}
+    Witness {
+        witness_id: XWitnessId,
+        reason: String,
+    },
+
+    /// bundle {bundle_id} is anchored to witness {present} in the stock, while
+    /// the merged stock anchors it to witness {expected}.
+    DistinctBundleWitness {
+        bundle_id: BundleId,
+        present: XWitnessId,
+        expected: XWitnessId,
+    },
+}
+
+/// Report on data merged by [`super::Stock::merge`].
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+pub struct MergeReport {
+    pub conflicts: Vec<MergeConflict>,
+}
+
+impl MergeReport {
+    /// Detects whether all the data were merged without conflicts.
+    pub fn is_clean(&self) -> bool { self.conflicts.is_empty() }
+
+    pub(super) fn push(&mut self, conflict: MergeConflict) { self.conflicts.push(conflict) }
+
+    /// Reports bundles which are anchored to different witnesses in the stock
+    /// and in the merged stock, returning the witnesses of the merged stock
+    /// which must not be merged.
+    pub(super) fn check_bundle_witnesses(
+        &mut self,
+        present: &BTreeMap<BundleId, XWitnessId>,
+        merged: &BTreeMap<BundleId, XWitnessId>,
+    ) -> BTreeSet<XWitnessId> {
+        let mut skipped = BTreeSet::new();
+        for (bundle_id, expected) in merged {
+            if let Some(present) = present
+                .get(bundle_id)
+                .filter(|present| *present != expected)
+            {
+                self.push(MergeConflict::DistinctBundleWitness {
+                    bundle_id: *bundle_id,
+                    present: *present,
+                    expected: *expected,
+                });
+                skipped.insert(*expected);
+            }
+        }
+        skipped
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use amplify::ByteArray;
+    use bp::seals::txout::CloseMethod;
+    use bp::Txid;
+    use rgb::{GraphSeal, XChain};
+
+    use super::*;
+    use crate::interface::resolver::DumbResolver;
+    use crate::persistence::{MemIndex, MemStash, MemState, StashReadProvider, Stock};
+
+    type MemStock = Stock<MemStash, MemState, MemIndex>;
+
+    fn witness_id(no: u8) -> XWitnessId { XWitnessId::Bitcoin(Txid::from([no; 32])) }
+
+    #[test]
+    fn distinct_bundle_witnesses() {
+        let bundle1 = BundleId::from_byte_array([1u8; 32]);
+        let bundle2 = BundleId::from_byte_array([2u8; 32]);
+        let bundle3 = BundleId::from_byte_array([3u8; 32]);
+        let present = bmap! { bundle1 => witness_id(1), bundle2 => witness_id(2) };
+        let merged = bmap! {
+            bundle1 => witness_id(1),
+            bundle2 => witness_id(3),
+            bundle3 => witness_id(4),
+        };
+
+        let mut report = MergeReport::default();
+        assert!(report.check_bundle_witnesses(&present, &present).is_empty());
+        assert!(report.is_clean());
+
+        let skipped = report.check_bundle_witnesses(&present, &merged);
+        assert_eq!(skipped, bset! { witness_id(3) });
+        assert_eq!(report.conflicts, vec![MergeConflict::DistinctBundleWitness {
+            bundle_id: bundle2,
+            present: witness_id(2),
+            expected: witness_id(3),
+        }]);
+        assert!(!report.is_clean());
+    }
+
+    #[test]
+    fn clean_merge() {
+        let mut stock = MemStock::default();
+        let mut other = MemStock::default();
+        let report = stock.merge(&other, &mut DumbResolver).unwrap();
+        assert!(report.is_clean());
+
+        let seal = XChain::Bitcoin(GraphSeal::with_blinding(
+            CloseMethod::TapretFirst,
+            Txid::coinbase(),
+            1u32,
+            42,
+        ));
+        other.store_secret_seal(seal).unwrap();
+        let report = stock.merge(&other, &mut DumbResolver).unwrap();
+        assert!(report.is_clean());
+        assert_eq!(
+            stock
+                .as_stash_provider()
+                .secret_seals()
+                .unwrap()
+                .collect::<Vec<_>>(),
+            vec![seal]
+        );
+    }
+}
@@ -34,24 +34,59 @@ mod stock;
 mod stash;
 mod state;
 mod index;
+mod audit;
+mod audit_log;
+mod blinding;
+mod events;
+mod merge;
+mod selection;
+mod preview;
+mod multi;
+mod snapshot;
+mod shared_map;
+mod asynchronous;
 
 mod memory;
 #[cfg(feature = "fs")]
 pub mod fs;
+#[cfg(feature = "fs-encrypt")]
+pub mod encrypted;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
 
+pub use asynchronous::{
+    AsyncIndexProvider, AsyncIndexReadProvider, AsyncIndexWriteProvider, AsyncStashProvider,
+    AsyncStashReadProvider, AsyncStashWriteProvider, AsyncStateProvider, AsyncStateReadProvider,
+    AsyncStateWriteProvider, AsyncStock, AsyncStockError, IndexRecord, RecordingIndex,
+};
+pub use audit::{AuditIssue, AuditReport, AuditSeverity};
+pub use audit_log::{
+    AuditEntry, AuditEntryId, AuditLog, AuditLogError, AuditValidity, StockMutation,
+};
+pub use blinding::{BlindingError, SeedBlinder};
+pub use events::{StockEvent, StockObserver};
 pub use index::{
     Index, IndexError, IndexInconsistency, IndexProvider, IndexReadError, IndexReadProvider,
-    IndexWriteError, IndexWriteProvider,
+    IndexWriteError, IndexWriteProvider, Spender,
 };
 pub use memory::{MemIndex, MemStash, MemState};
+pub use merge::{MergeConflict, MergeReport};
+pub use multi::{MultiStock, ScopedStash, ScopedStashError, WalletScope, WalletSeals, WalletStock};
+pub use preview::{ChangePreview, ComposePreview, ContractPreview, PaymentPreview, SpentAllocation};
+pub use selection::{
+    FewestBlanks, FewestInputs, PrivacyPreserving, SelectionCandidate, SelectionStrategy,
+    SmallestSufficient,
+};
+pub use shared_map::SharedMap;
+pub use snapshot::{SharedStock, StockSnapshot};
 pub use stash::{
-    ProviderError as StashProviderError, SchemaIfaces, Stash, StashDataError, StashError,
-    StashInconsistency, StashProvider, StashReadProvider, StashWriteProvider,
+    ProviderError as StashProviderError, SchemaIfaces, SecretSealInfo, Stash, StashDataError,
+    StashError, StashInconsistency, StashProvider, StashReadProvider, StashWriteProvider,
 };
 pub use state::{
     PersistedState, StateProvider, StateReadProvider, StateUpdateError, StateWriteProvider,
 };
 pub use stock::{
     ComposeError, ConsignError, ContractIfaceError, FasciaError, InputError as StockInputError,
-    Stock, StockError, StockErrorAll, StockErrorMem,
+    MergeError as StockMergeError, Stock, StockError, StockErrorAll, StockErrorMem,
 };
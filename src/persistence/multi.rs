@@ -0,0 +1,818 @@
+// RGB standard library for working with smart contracts on Bitcoin & Lightning
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2019-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2019-2024 LNP/BP Standards Association. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Multiple wallets sharing a single stash.
+//!
+//! Contracts, schemata, interfaces and consensus history are kept once in a
+//! shared stash provider, while each wallet has its own contract state, index
+//! and secret seals. A [`Stock`] handle scoped to a single wallet is obtained
+//! with [`MultiStock::wallet`]; it sees the shared stash data, but only the
+//! secret seals of that wallet, and updates only the state and index of that
+//! wallet.
+//!
+//! Since the state and index of a wallet are updated only with the
+//! consignments accepted through its own handle, they may miss data added to
+//! the shared stash by other wallets. A wallet can be brought to the full view
+//! of the shared stash with [`Stock::rebuild_state`] and
+//! [`Stock::rebuild_index`].
+//!
+//! Since the shared stash data may be used by other wallets, they can't be
+//! removed through a wallet handle: [`Stock::forget_contract`] and
+//! [`Stock::archive_contract`] called on it fail with the
+//! [`ScopedStashError::SharedRemoval`] stash error. Observers and an audit log
+//! attached to a wallet handle are kept by [`MultiStock`] and are attached to
+//! all further handles of the same wallet.
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::mem;
+use std::ops::{Deref, DerefMut};
+
+use aluvm::library::{Lib, LibId};
+use amplify::confinement::{self, LargeOrdMap, MediumBlob};
+use bp::dbc::tapret::TapretCommitment;
+use commit_verify::Conceal;
+use rgb::{
+    Assign, AssignmentType, AttachId, BundleId, ContractHistory, ContractId, ExposedState,
+    Extension, Genesis, GenesisSeal, GraphSeal, Identity, OpId, Opout, Schema, SchemaId,
+    SecretSeal, TransitionBundle, XChain, XOutputSeal, XWitnessId,
+};
+use strict_encoding::{StrictDeserialize, StrictSerialize, TypeName};
+use strict_types::TypeSystem;
+
+use super::events::Observers;
+use super::memory::Journal;
+use super::{
+    AuditLog, IndexProvider, IndexReadError, IndexReadProvider, IndexWriteError,
+    IndexWriteProvider, MemIndex, MemState, SchemaIfaces, SecretSealInfo, Spender, StashProvider,
+    StashProviderError, StashReadProvider, StashWriteProvider, StateProvider, StateReadProvider,
+    StateUpdateError, StateWriteProvider, Stock,
+};
+use crate::containers::{ContentId, ContentSigs, SealWitness, SigBlob};
+use crate::interface::{ContractSuppl, Iface, IfaceId, IfaceImpl, IfaceRef};
+use crate::resolvers::ResolveHeight;
+use crate::LIB_NAME_RGB_STD;
+
+/// Secret seals of a single wallet.
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+#[derive(StrictType, StrictEncode, StrictDecode)]
+#[strict_type(lib = LIB_NAME_RGB_STD)]
+pub struct WalletSeals(LargeOrdMap<XChain<GraphSeal>, SecretSealInfo>);
+
+impl StrictSerialize for WalletSeals {}
+impl StrictDeserialize for WalletSeals {}
+
+impl WalletSeals {
+    pub fn new() -> Self { WalletSeals::default() }
+
+    pub fn len(&self) -> usize { self.0.len() }
+
+    pub fn is_empty(&self) -> bool { self.0.is_empty() }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&XChain<GraphSeal>, &SecretSealInfo)> {
+        self.0.iter()
+    }
+}
+
+/// Data of a single wallet kept by [`MultiStock`].
+#[derive(Clone, Debug, Default)]
+pub struct WalletScope<H: StateProvider = MemState, P: IndexProvider = MemIndex> {
+    pub state: H,
+    pub index: P,
+    pub seals: WalletSeals,
+    /// Audit log attached to the stock handles of the wallet.
+    pub audit_log: Option<AuditLog>,
+}
+
+impl<H: StateProvider, P: IndexProvider> WalletScope<H, P> {
+    pub fn with(state: H, index: P, seals: WalletSeals) -> Self {
+        WalletScope {
+            state,
+            index,
+            seals,
+            audit_log: None,
+        }
+    }
+}
+
+/// Errors of the stash provider scoped to a single wallet.
+#[derive(Clone, Eq, PartialEq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum ScopedStashError<E: Error> {
+    /// {0}
+    Stash(E),
+
+    /// wallet secret seals exceed the collection limit: {0}
+    Seals(confinement::Error),
+
+    /// data of the shared stash can't be removed through a wallet handle, since
+    /// they may be used by other wallets.
+    SharedRemoval,
+}
+
+/// Stash provider exposing the shared stash together with secret seals of a
+/// single wallet.
+#[derive(Debug)]
+pub struct ScopedStash<'a, S: StashProvider> {
+    stash: &'a mut S,
+    seals: &'a mut WalletSeals,
+    journal: Journal<(XChain<GraphSeal>, Option<SecretSealInfo>)>,
+}
+
+/// Stock handle scoped to a single wallet of [`MultiStock`].
+///
+/// Dereferences to [`Stock`]; the observers and the audit log of the handle are
+/// returned to [`MultiStock`] when the handle is dropped.
+#[derive(Debug)]
+pub struct WalletStock<'a, S: StashProvider, H: StateProvider, P: IndexProvider> {
+    stock: Stock<ScopedStash<'a, S>, &'a mut H, &'a mut P>,
+    observers: &'a mut Observers,
+    audit_log: &'a mut Option<AuditLog>,
+}
+
+impl<'a, S: StashProvider, H: StateProvider, P: IndexProvider> Deref for WalletStock<'a, S, H, P> {
+    type Target = Stock<ScopedStash<'a, S>, &'a mut H, &'a mut P>;
+
+    fn deref(&self) -> &Self::Target { &self.stock }
+}
+
+impl<'a, S: StashProvider, H: StateProvider, P: IndexProvider> DerefMut
+    for WalletStock<'a, S, H, P>
+{
+    fn deref_mut(&mut self) -> &mut Self::Target { &mut self.stock }
+}
+
+impl<'a, S: StashProvider, H: StateProvider, P: IndexProvider> Drop for WalletStock<'a, S, H, P> {
+    fn drop(&mut self) {
+        let (observers, audit_log) = self.stock.take_hooks();
+        *self.observers = observers;
+        *self.audit_log = audit_log;
+    }
+}
+
+/// Set of wallets sharing a single stash, each having its own state, index and
+/// secret seals.
+#[derive(Debug)]
+pub struct MultiStock<S: StashProvider, H: StateProvider = MemState, P: IndexProvider = MemIndex> {
+    stash: S,
+    wallets: BTreeMap<String, WalletScope<H, P>>,
+    observers: BTreeMap<String, Observers>,
+}
+
+impl<S: StashProvider, H: StateProvider, P: IndexProvider> MultiStock<S, H, P> {
+    pub fn new(stash: S) -> Self {
+        MultiStock {
+            stash,
+            wallets: none!(),
+            observers: none!(),
+        }
+    }
+
+    /// Constructs multi-wallet stock from the previously persisted data.
+    pub fn with(stash: S, wallets: BTreeMap<String, WalletScope<H, P>>) -> Self {
+        MultiStock {
+            stash,
+            wallets,
+            observers: none!(),
+        }
+    }
+
+    pub fn stash(&self) -> &S { &self.stash }
+
+    pub fn wallet_names(&self) -> impl Iterator<Item = &str> + '_ {
+        self.wallets.keys().map(String::as_str)
+    }
+
+    pub fn wallet_scope(&self, name: &str) -> Option<&WalletScope<H, P>> { self.wallets.get(name) }
+
+    /// Adds a new wallet, returning `false` if the wallet with the same name
+    /// already exists (in which case it is not modified).
+    pub fn add_wallet(&mut self, name: impl Into<String>, scope: WalletScope<H, P>) -> bool {
+        let name = name.into();
+        if self.wallets.contains_key(&name) {
+            return false;
+        }
+        self.wallets.insert(name, scope);
+        true
+    }
+
+    /// Removes the wallet, returning its data. The data added by the wallet to
+    /// the shared stash are kept.
+    pub fn remove_wallet(&mut self, name: &str) -> Option<WalletScope<H, P>> {
+        self.observers.remove(name);
+        self.wallets.remove(name)
+    }
+
+    /// Returns stock handle scoped to a single wallet.
+    ///
+    /// The handle has the observers registered with the previous handles of
+    /// the same wallet and the audit log of the wallet attached.
+    pub fn wallet(&mut self, name: &str) -> Option<WalletStock<'_, S, H, P>> {
+        let scope = self.wallets.get_mut(name)?;
+        let observers = self.observers.entry(name.to_owned()).or_default();
+        let stash = ScopedStash {
+            stash: &mut self.stash,
+            seals: &mut scope.seals,
+            journal: default!(),
+        };
+        let stock = Stock::with_hooks(
+            stash,
+            &mut scope.state,
+            &mut scope.index,
+            mem::take(observers),
+            scope.audit_log.take(),
+        );
+        Some(WalletStock {
+            stock,
+            observers,
+            audit_log: &mut scope.audit_log,
+        })
+    }
+
+    /// Returns the shared stash and the wallet data; the observers registered
+    /// with the wallet handles are dropped.
+    pub fn into_parts(self) -> (S, BTreeMap<String, WalletScope<H, P>>) {
+        (self.stash, self.wallets)
+    }
+}
+
+impl<'a, S: StashProvider> StashProvider for ScopedStash<'a, S> {}
+
+impl<'a, S: StashProvider> StashReadProvider for ScopedStash<'a, S> {
+    type Error = <S as StashReadProvider>::Error;
+
+    fn type_system(&self) -> Result<Cow<TypeSystem>, Self::Error> { self.stash.type_system() }
+
+    fn lib_ids(&self) -> Result<impl Iterator<Item = LibId>, Self::Error> { self.stash.lib_ids() }
+
+    fn lib(&self, id: LibId) -> Result<&Lib, StashProviderError<Self::Error>> { self.stash.lib(id) }
+
+    fn ifaces(&self) -> Result<impl Iterator<Item = (IfaceId, TypeName)>, Self::Error> {
+        self.stash.ifaces()
+    }
+
+    fn iface(&self, iface: impl Into<IfaceRef>) -> Result<&Iface, StashProviderError<Self::Error>> {
+        self.stash.iface(iface)
+    }
+
+    fn schemata(&self) -> Result<impl Iterator<Item = &SchemaIfaces>, Self::Error> {
+        self.stash.schemata()
+    }
+
+    fn schema(
+        &self,
+        schema_id: SchemaId,
+    ) -> Result<&SchemaIfaces, StashProviderError<Self::Error>> {
+        self.stash.schema(schema_id)
+    }
+
+    fn contract_ids(&self) -> Result<impl Iterator<Item = ContractId>, Self::Error> {
+        self.stash.contract_ids()
+    }
+
+    fn contract_ids_by_iface(
+        &self,
+        iface: impl Into<IfaceRef>,
+    ) -> Result<impl Iterator<Item = ContractId>, StashProviderError<Self::Error>> {
+        self.stash.contract_ids_by_iface(iface)
+    }
+
+    fn contract_supplements(
+        &self,
+        contract_id: ContractId,
+    ) -> Result<impl Iterator<Item = ContractSuppl>, Self::Error> {
+        self.stash.contract_supplements(contract_id)
+    }
+
+    fn suppl_contract_ids(&self) -> Result<impl Iterator<Item = ContractId>, Self::Error> {
+        self.stash.suppl_contract_ids()
+    }
+
+    fn genesis(
+        &self,
+        contract_id: ContractId,
+    ) -> Result<&Genesis, StashProviderError<Self::Error>> {
+        self.stash.genesis(contract_id)
+    }
+
+    fn witness_ids(&self) -> Result<impl Iterator<Item = XWitnessId>, Self::Error> {
+        self.stash.witness_ids()
+    }
+
+    fn bundle_ids(&self) -> Result<impl Iterator<Item = BundleId>, Self::Error> {
+        self.stash.bundle_ids()
+    }
+
+    fn bundle(
+        &self,
+        bundle_id: BundleId,
+    ) -> Result<&TransitionBundle, StashProviderError<Self::Error>> {
+        self.stash.bundle(bundle_id)
+    }
+
+    fn extension_ids(&self) -> Result<impl Iterator<Item = OpId>, Self::Error> {
+        self.stash.extension_ids()
+    }
+
+    fn extension(&self, op_id: OpId) -> Result<&Extension, StashProviderError<Self::Error>> {
+        self.stash.extension(op_id)
+    }
+
+    fn witness(
+        &self,
+        witness_id: XWitnessId,
+    ) -> Result<&SealWitness, StashProviderError<Self::Error>> {
+        self.stash.witness(witness_id)
+    }
+
+    fn attachment_ids(&self) -> Result<impl Iterator<Item = AttachId>, Self::Error> {
+        self.stash.attachment_ids()
+    }
+
+    fn attachment(&self, id: AttachId) -> Result<&MediumBlob, StashProviderError<Self::Error>> {
+        self.stash.attachment(id)
+    }
+
+    fn sigs(&self) -> Result<impl Iterator<Item = (ContentId, &ContentSigs)>, Self::Error> {
+        self.stash.sigs()
+    }
+
+    fn taprets(&self) -> Result<impl Iterator<Item = (XWitnessId, TapretCommitment)>, Self::Error> {
+        self.stash.taprets()
+    }
+
+    fn seal_secret(
+        &self,
+        secret: XChain<SecretSeal>,
+    ) -> Result<Option<XChain<GraphSeal>>, Self::Error> {
+        Ok(self.seals.0.keys().find(|s| s.conceal() == secret).copied())
+    }
+
+    fn secret_seals(&self) -> Result<impl Iterator<Item = XChain<GraphSeal>>, Self::Error> {
+        Ok(self.seals.0.keys().copied())
+    }
+
+    fn secret_seal_records(
+        &self,
+    ) -> Result<impl Iterator<Item = (XChain<GraphSeal>, SecretSealInfo)>, Self::Error> {
+        Ok(self
+            .seals
+            .0
+            .iter()
+            .map(|(seal, info)| (*seal, info.clone())))
+    }
+}
+
+impl<'a, S: StashProvider> StashWriteProvider for ScopedStash<'a, S> {
+    type Error = ScopedStashError<<S as StashWriteProvider>::Error>;
+
+    fn begin_transaction(&mut self) -> Result<(), Self::Error> {
+        self.stash
+            .begin_transaction()
+            .map_err(ScopedStashError::Stash)?;
+        self.journal.begin();
+        Ok(())
+    }
+
+    fn prepare_transaction(&mut self) -> Result<(), Self::Error> {
+        self.stash
+            .prepare_transaction()
+            .map_err(ScopedStashError::Stash)
+    }
+
+    fn commit_transaction(&mut self) -> Result<(), Self::Error> {
+        self.stash
+            .commit_transaction()
+            .map_err(ScopedStashError::Stash)?;
+        self.journal.commit();
+        Ok(())
+    }
+
+    fn rollback_transaction(&mut self) -> Result<(), Self::Error> {
+        for (seal, prev) in self.journal.rollback() {
+            // Restoring the previous content can't exceed the collection bounds
+            match prev {
+                Some(info) => self.seals.0.insert(seal, info).map(|_| ()),
+                None => self.seals.0.remove(&seal).map(|_| ()),
+            }
+            .expect("restoring wallet seals doesn't violate collection bounds");
+        }
+        self.stash
+            .rollback_transaction()
+            .map_err(ScopedStashError::Stash)
+    }
+
+    fn replace_schema(&mut self, schema: Schema) -> Result<bool, Self::Error> {
+        self.stash
+            .replace_schema(schema)
+            .map_err(ScopedStashError::Stash)
+    }
+
+    fn replace_iface(&mut self, iface: Iface) -> Result<bool, Self::Error> {
+        self.stash
+            .replace_iface(iface)
+            .map_err(ScopedStashError::Stash)
+    }
+
+    fn replace_iimpl(&mut self, iimpl: IfaceImpl) -> Result<bool, Self::Error> {
+        self.stash
+            .replace_iimpl(iimpl)
+            .map_err(ScopedStashError::Stash)
+    }
+
+    fn replace_genesis(&mut self, genesis: Genesis) -> Result<bool, Self::Error> {
+        self.stash
+            .replace_genesis(genesis)
+            .map_err(ScopedStashError::Stash)
+    }
+
+    fn replace_extension(&mut self, extension: Extension) -> Result<bool, Self::Error> {
+        self.stash
+            .replace_extension(extension)
+            .map_err(ScopedStashError::Stash)
+    }
+
+    fn replace_bundle(&mut self, bundle: TransitionBundle) -> Result<bool, Self::Error> {
+        self.stash
+            .replace_bundle(bundle)
+            .map_err(ScopedStashError::Stash)
+    }
+
+    fn replace_witness(&mut self, witness: SealWitness) -> Result<bool, Self::Error> {
+        self.stash
+            .replace_witness(witness)
+            .map_err(ScopedStashError::Stash)
+    }
+
+    fn replace_attachment(
+        &mut self,
+        id: AttachId,
+        attach: MediumBlob,
+    ) -> Result<bool, Self::Error> {
+        self.stash
+            .replace_attachment(id, attach)
+            .map_err(ScopedStashError::Stash)
+    }
+
+    fn replace_lib(&mut self, lib: Lib) -> Result<bool, Self::Error> {
+        self.stash.replace_lib(lib).map_err(ScopedStashError::Stash)
+    }
+
+    fn consume_types(&mut self, types: TypeSystem) -> Result<(), Self::Error> {
+        self.stash
+            .consume_types(types)
+            .map_err(ScopedStashError::Stash)
+    }
+
+    fn add_suppl(&mut self, suppl: ContractSuppl) -> Result<(), Self::Error> {
+        self.stash.add_suppl(suppl).map_err(ScopedStashError::Stash)
+    }
+
+    fn import_sigs<I>(&mut self, content_id: ContentId, sigs: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = (Identity, SigBlob)>,
+        I::IntoIter: ExactSizeIterator<Item = (Identity, SigBlob)>,
+    {
+        self.stash
+            .import_sigs(content_id, sigs)
+            .map_err(ScopedStashError::Stash)
+    }
+
+    fn add_secret_seal(&mut self, seal: XChain<GraphSeal>) -> Result<bool, Self::Error> {
+        self.add_secret_seal_with(seal, SecretSealInfo::default())
+    }
+
+    fn add_secret_seal_with(
+        &mut self,
+        seal: XChain<GraphSeal>,
+        info: SecretSealInfo,
+    ) -> Result<bool, Self::Error> {
+        if self.seals.0.contains_key(&seal) {
+            return Ok(false);
+        }
+        self.seals
+            .0
+            .insert(seal, info)
+            .map_err(ScopedStashError::Seals)?;
+        self.journal.record(|| (seal, None));
+        Ok(true)
+    }
+
+    fn remove_secret_seal(&mut self, seal: XChain<GraphSeal>) -> Result<bool, Self::Error> {
+        let prev = self
+            .seals
+            .0
+            .remove(&seal)
+            .map_err(ScopedStashError::Seals)?;
+        let present = prev.is_some();
+        self.journal.record(|| (seal, prev));
+        Ok(present)
+    }
+
+    fn remove_genesis(&mut self, _contract_id: ContractId) -> Result<bool, Self::Error> {
+        Err(ScopedStashError::SharedRemoval)
+    }
+
+    fn remove_suppl(&mut self, _contract_id: ContractId) -> Result<(), Self::Error> {
+        Err(ScopedStashError::SharedRemoval)
+    }
+
+    fn remove_extension(&mut self, _opid: OpId) -> Result<bool, Self::Error> {
+        Err(ScopedStashError::SharedRemoval)
+    }
+
+    fn remove_bundle(&mut self, _bundle_id: BundleId) -> Result<bool, Self::Error> {
+        Err(ScopedStashError::SharedRemoval)
+    }
+
+    fn remove_witness(&mut self, _witness_id: XWitnessId) -> Result<bool, Self::Error> {
+        Err(ScopedStashError::SharedRemoval)
+    }
+}
+
+// Providers borrowed from a wallet scope are used by the scoped stock handles.
+
+impl<T: StateProvider> StateProvider for &mut T {}
+
+impl<T: StateReadProvider> StateReadProvider for &mut T {
+    type Error = T::Error;
+
+    fn contract_state(
+        &self,
+        contract_id: ContractId,
+    ) -> Result<Option<&ContractHistory>, Self::Error> {
+        (**self).contract_state(contract_id)
+    }
+}
+
+impl<T: StateWriteProvider> StateWriteProvider for &mut T {
+    type Error = T::Error;
+
+    fn begin_transaction(&mut self) -> Result<(), Self::Error> { (**self).begin_transaction() }
+
+    fn prepare_transaction(&mut self) -> Result<(), Self::Error> { (**self).prepare_transaction() }
+
+    fn commit_transaction(&mut self) -> Result<(), Self::Error> { (**self).commit_transaction() }
+
+    fn rollback_transaction(&mut self) -> Result<(), Self::Error> {
+        (**self).rollback_transaction()
+    }
+
+    fn create_or_update_state<R: ResolveHeight>(
+        &mut self,
+        contract_id: ContractId,
+        updater: impl FnOnce(Option<ContractHistory>) -> Result<ContractHistory, R::Error>,
+    ) -> Result<(), StateUpdateError<Self::Error>> {
+        (**self).create_or_update_state::<R>(contract_id, updater)
+    }
+
+    fn update_state<R: ResolveHeight>(
+        &mut self,
+        contract_id: ContractId,
+        updater: impl FnMut(&mut ContractHistory) -> Result<(), R::Error>,
+    ) -> Result<(), StateUpdateError<Self::Error>> {
+        (**self).update_state::<R>(contract_id, updater)
+    }
+
+    fn remove_state(&mut self, contract_id: ContractId) -> Result<bool, Self::Error> {
+        (**self).remove_state(contract_id)
+    }
+
+    fn clear_state(&mut self) -> Result<(), Self::Error> { (**self).clear_state() }
+}
+
+impl<T: IndexProvider> IndexProvider for &mut T {}
+
+impl<T: IndexReadProvider> IndexReadProvider for &mut T {
+    type Error = T::Error;
+
+    fn contracts_assigning(
+        &self,
+        outputs: BTreeSet<XOutputSeal>,
+    ) -> Result<impl Iterator<Item = ContractId> + '_, Self::Error> {
+        (**self).contracts_assigning(outputs)
+    }
+
+    fn public_opouts(
+        &self,
+        contract_id: ContractId,
+    ) -> Result<BTreeSet<Opout>, IndexReadError<Self::Error>> {
+        (**self).public_opouts(contract_id)
+    }
+
+    fn opouts_by_outputs(
+        &self,
+        contract_id: ContractId,
+        outputs: impl IntoIterator<Item = impl Into<XOutputSeal>>,
+    ) -> Result<BTreeSet<Opout>, IndexReadError<Self::Error>> {
+        (**self).opouts_by_outputs(contract_id, outputs)
+    }
+
+    fn opouts_by_terminals(
+        &self,
+        terminals: impl IntoIterator<Item = XChain<SecretSeal>>,
+    ) -> Result<BTreeSet<Opout>, Self::Error> {
+        (**self).opouts_by_terminals(terminals)
+    }
+
+    fn bundle_id_for_op(&self, opid: OpId) -> Result<BundleId, IndexReadError<Self::Error>> {
+        (**self).bundle_id_for_op(opid)
+    }
+
+    fn bundle_info(
+        &self,
+        bundle_id: BundleId,
+    ) -> Result<(XWitnessId, ContractId), IndexReadError<Self::Error>> {
+        (**self).bundle_info(bundle_id)
+    }
+
+    fn spenders(&self, opout: Opout) -> Result<BTreeSet<Spender>, IndexReadError<Self::Error>> {
+        (**self).spenders(opout)
+    }
+
+    fn operations(&self) -> Result<impl Iterator<Item = (OpId, BundleId)> + '_, Self::Error> {
+        (**self).operations()
+    }
+
+    fn bundle_ids(&self) -> Result<impl Iterator<Item = BundleId> + '_, Self::Error> {
+        (**self).bundle_ids()
+    }
+}
+
+impl<T: IndexWriteProvider> IndexWriteProvider for &mut T {
+    type Error = T::Error;
+
+    fn begin_transaction(&mut self) -> Result<(), Self::Error> { (**self).begin_transaction() }
+
+    fn prepare_transaction(&mut self) -> Result<(), Self::Error> { (**self).prepare_transaction() }
+
+    fn commit_transaction(&mut self) -> Result<(), Self::Error> { (**self).commit_transaction() }
+
+    fn rollback_transaction(&mut self) -> Result<(), Self::Error> {
+        (**self).rollback_transaction()
+    }
+
+    fn register_contract(&mut self, contract_id: ContractId) -> Result<bool, Self::Error> {
+        (**self).register_contract(contract_id)
+    }
+
+    fn register_bundle(
+        &mut self,
+        bundle_id: BundleId,
+        witness_id: XWitnessId,
+        contract_id: ContractId,
+    ) -> Result<bool, IndexWriteError<Self::Error>> {
+        (**self).register_bundle(bundle_id, witness_id, contract_id)
+    }
+
+    fn register_operation(
+        &mut self,
+        opid: OpId,
+        bundle_id: BundleId,
+    ) -> Result<bool, IndexWriteError<Self::Error>> {
+        (**self).register_operation(opid, bundle_id)
+    }
+
+    fn register_spender(
+        &mut self,
+        opout: Opout,
+        spender: Spender,
+    ) -> Result<bool, IndexWriteError<Self::Error>> {
+        (**self).register_spender(opout, spender)
+    }
+
+    fn index_genesis_assignments<State: ExposedState>(
+        &mut self,
+        contract_id: ContractId,
+        vec: &[Assign<State, GenesisSeal>],
+        opid: OpId,
+        type_id: AssignmentType,
+    ) -> Result<(), IndexWriteError<Self::Error>> {
+        (**self).index_genesis_assignments(contract_id, vec, opid, type_id)
+    }
+
+    fn index_transition_assignments<State: ExposedState>(
+        &mut self,
+        contract_id: ContractId,
+        vec: &[Assign<State, GraphSeal>],
+        opid: OpId,
+        type_id: AssignmentType,
+        witness_id: XWitnessId,
+    ) -> Result<(), IndexWriteError<Self::Error>> {
+        (**self).index_transition_assignments(contract_id, vec, opid, type_id, witness_id)
+    }
+
+    fn remove_contract(
+        &mut self,
+        contract_id: ContractId,
+        opids: &BTreeSet<OpId>,
+    ) -> Result<(), Self::Error> {
+        (**self).remove_contract(contract_id, opids)
+    }
+
+    fn clear_index(&mut self) -> Result<(), Self::Error> { (**self).clear_index() }
+}
+
+#[cfg(test)]
+mod test {
+    use amplify::ByteArray;
+    use bp::seals::txout::CloseMethod;
+    use bp::Txid;
+
+    use super::*;
+    use crate::containers::Kit;
+    use crate::interface::TickerSuppl;
+    use crate::persistence::MemStash;
+
+    fn seal(blinding: u64) -> XChain<GraphSeal> {
+        XChain::Bitcoin(GraphSeal::with_blinding(
+            CloseMethod::TapretFirst,
+            Txid::coinbase(),
+            1u32,
+            blinding,
+        ))
+    }
+
+    #[test]
+    fn shared_stash() {
+        let contract_id = ContractId::from_byte_array([1u8; 32]);
+        let suppl = ContractSuppl {
+            contract_id,
+            ticker: TickerSuppl::Absent,
+            media_kit: none!(),
+            global_state: none!(),
+            owned_state: none!(),
+            extensions: none!(),
+        };
+        let kit = Kit {
+            version: none!(),
+            ifaces: none!(),
+            schemata: none!(),
+            iimpls: none!(),
+            supplements: tiny_bset![suppl],
+            types: none!(),
+            scripts: none!(),
+            signatures: none!(),
+        };
+
+        let mut stock: MultiStock<MemStash> = MultiStock::new(default!());
+        assert!(stock.add_wallet("alice", default!()));
+        assert!(stock.add_wallet("bob", default!()));
+        assert!(!stock.add_wallet("bob", default!()));
+
+        {
+            let mut alice = stock.wallet("alice").unwrap();
+            assert!(alice.store_secret_seal(seal(1)).unwrap());
+            alice.import_kit(kit.validate().unwrap()).unwrap();
+        }
+        {
+            let mut bob = stock.wallet("bob").unwrap();
+            assert!(bob.store_secret_seal(seal(2)).unwrap());
+            // Data added by other wallets are visible, but their seals are not
+            let stash = bob.as_stash_provider();
+            assert_eq!(stash.contract_supplements(contract_id).unwrap().count(), 1);
+            assert_eq!(stash.secret_seals().unwrap().collect::<Vec<_>>(), vec![seal(2)]);
+            assert_eq!(stash.seal_secret(seal(1).conceal()).unwrap(), None);
+        }
+
+        let seals = |name: &str| {
+            let scope = stock.wallet_scope(name).unwrap();
+            scope
+                .seals
+                .iter()
+                .map(|(seal, _)| *seal)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(seals("alice"), vec![seal(1)]);
+        assert_eq!(seals("bob"), vec![seal(2)]);
+        // Wallet seals are never added to the shared stash
+        assert_eq!(stock.stash().secret_seals().unwrap().count(), 0);
+        assert_eq!(
+            stock
+                .stash()
+                .contract_supplements(contract_id)
+                .unwrap()
+                .count(),
+            1
+        );
+    }
+}
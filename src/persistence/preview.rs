@@ -0,0 +1,332 @@
+// RGB standard library for working with smart contracts on Bitcoin & Lightning
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2019-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2019-2024 LNP/BP Standards Association. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use bp::Vout;
+use invoice::{Allocation, Amount};
+use rgb::{
+    Assign, AssignmentType, ContractId, ExposedState, GraphSeal, OpId, Opout, TypedAssigns,
+    XOutputSeal,
+};
+use strict_types::SemId;
+
+use super::PersistedState;
+use crate::containers::BuilderSeal;
+use crate::interface::{AmountChange, StateChange, VelocityHint};
+use crate::stl::StandardTypes;
+
+/// Allocation of the wallet which is spent by a composed state transition.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct SpentAllocation {
+    pub opout: Opout,
+    pub seal: XOutputSeal,
+    pub state: PersistedState,
+}
+
+/// State assigned by a composed state transition to an invoice beneficiary.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct PaymentPreview {
+    pub assignment_type: AssignmentType,
+    pub beneficiary: BuilderSeal<GraphSeal>,
+    pub state: PersistedState,
+}
+
+/// State assigned by a composed state transition back to the wallet, either
+/// as a change or as a state moved by a blank transition.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct ChangePreview {
+    pub assignment_type: AssignmentType,
+    pub vout: Vout,
+    /// Velocity hint with which the output was requested from the allocator.
+    pub velocity: VelocityHint,
+    pub state: PersistedState,
+}
+
+/// Effects of a composed state transition on a single contract.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct ContractPreview {
+    pub contract_id: ContractId,
+    pub opid: OpId,
+    pub spent: Vec<SpentAllocation>,
+    pub payments: Vec<PaymentPreview>,
+    pub change: Vec<ChangePreview>,
+    /// Assignment types which the interfaces implemented by the contract
+    /// declare as holding RGB21 token allocations.
+    pub fractions: BTreeSet<AssignmentType>,
+}
+
+impl ContractPreview {
+    /// Detects whether the transition is a blank one, i.e. it doesn't pay to
+    /// any of the invoice beneficiaries and just moves the contract state to
+    /// the new outputs.
+    pub fn is_blank(&self) -> bool { self.payments.is_empty() }
+
+    /// Computes changes of the wallet balance of the fungible state and of the
+    /// fractions of RGB21 tokens for each of the assignment types spent or
+    /// received by the transition.
+    ///
+    /// Fractions of all RGB21 tokens assigned with the same type are summed up.
+    pub fn balance_changes(&self) -> BTreeMap<AssignmentType, AmountChange> {
+        let mut changes = BTreeMap::<AssignmentType, AmountChange>::new();
+        for spent in &self.spent {
+            if let Some(amount) = self.balance_amount(spent.opout.ty, &spent.state) {
+                changes
+                    .entry(spent.opout.ty)
+                    .or_insert(AmountChange::Zero)
+                    .merge_spent(amount);
+            }
+        }
+        for change in &self.change {
+            if let Some(amount) = self.balance_amount(change.assignment_type, &change.state) {
+                changes
+                    .entry(change.assignment_type)
+                    .or_insert(AmountChange::Zero)
+                    .merge_received(amount);
+            }
+        }
+        changes
+    }
+
+    /// Returns amount of the state contributing to the wallet balance: the
+    /// value of fungible state or the token fraction of an RGB21 allocation.
+    fn balance_amount(
+        &self,
+        assignment_type: AssignmentType,
+        state: &PersistedState,
+    ) -> Option<Amount> {
+        match state {
+            PersistedState::Amount(amount, _, _) => Some(*amount),
+            PersistedState::Data(value, _) if self.fractions.contains(&assignment_type) => {
+                Allocation::from_data_state(value.clone())
+                    .map(|allocation| Amount::from(allocation.fraction().into_inner()))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Report on what a composed batch of state transitions will do once signed
+/// and published, produced by [`super::Stock::compose_preview`] and
+/// [`super::Stock::compose_many_preview`].
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct ComposePreview {
+    /// Effects of the main state transition of the batch.
+    pub main: ContractPreview,
+    /// Effects of the other state transitions of the batch, in the order they
+    /// are put into the batch.
+    pub others: Vec<ContractPreview>,
+}
+
+impl ComposePreview {
+    /// Iterates over effects of all state transitions of the batch.
+    pub fn contracts(&self) -> impl Iterator<Item = &ContractPreview> {
+        Some(&self.main).into_iter().chain(&self.others)
+    }
+
+    /// Iterates over contracts which get blank state transitions.
+    pub fn blank_contracts(&self) -> impl Iterator<Item = ContractId> + '_ {
+        self.contracts()
+            .filter(|preview| preview.is_blank())
+            .map(|preview| preview.contract_id)
+    }
+
+    /// Computes changes of the wallet balance of the fungible state and of the
+    /// RGB21 token fractions for each of the contracts and assignment types
+    /// affected by the batch (see [`ContractPreview::balance_changes`]).
+    pub fn balance_changes(&self) -> BTreeMap<ContractId, BTreeMap<AssignmentType, AmountChange>> {
+        // A batch contains a single state transition per contract
+        self.contracts()
+            .map(|preview| (preview.contract_id, preview.balance_changes()))
+            .collect()
+    }
+}
+
+/// Returns semantic id of the RGB21 allocation, with which interfaces declare
+/// assignments of token fractions.
+pub(super) fn allocation_sem_id() -> SemId { StandardTypes::new().get("RGBContract.Allocation") }
+
+/// Lists state with the seals it is assigned to by a composed state
+/// transition. Assignments with concealed state are skipped since they are
+/// never produced by the transition builder.
+pub(super) fn assigned_state(
+    assigns: &TypedAssigns<GraphSeal>,
+) -> Vec<(BuilderSeal<GraphSeal>, PersistedState)> {
+    fn revealed<State: ExposedState>(
+        vec: &[Assign<State, GraphSeal>],
+        f: impl Fn(&State) -> PersistedState,
+    ) -> Vec<(BuilderSeal<GraphSeal>, PersistedState)> {
+        vec.iter()
+            .filter_map(|assign| match assign {
+                Assign::Revealed { seal, state, .. } => {
+                    Some((BuilderSeal::Revealed(*seal), f(state)))
+                }
+                Assign::ConfidentialSeal { seal, state, .. } => {
+                    Some((BuilderSeal::Concealed(*seal), f(state)))
+                }
+                Assign::Confidential { .. } | Assign::ConfidentialState { .. } => None,
+            })
+            .collect()
+    }
+
+    match assigns {
+        TypedAssigns::Declarative(vec) => revealed(vec, |_| PersistedState::Void),
+        TypedAssigns::Fungible(vec) => revealed(vec, |state| {
+            PersistedState::Amount(state.value.into(), state.blinding, state.tag)
+        }),
+        TypedAssigns::Structured(vec) => {
+            revealed(vec, |state| PersistedState::Data(state.value.clone(), state.salt))
+        }
+        TypedAssigns::Attachment(vec) => {
+            revealed(vec, |state| PersistedState::Attachment(state.clone().into(), state.salt))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use amplify::{ByteArray, Wrapper};
+    use bp::seals::txout::CloseMethod;
+    use bp::Txid;
+    use rgb::{BlindingFactor, XChain};
+
+    use super::*;
+
+    fn seal(vout: u32) -> XChain<GraphSeal> {
+        XChain::Bitcoin(GraphSeal::with_blinding(
+            CloseMethod::TapretFirst,
+            Txid::coinbase(),
+            vout,
+            0,
+        ))
+    }
+
+    fn preview(contract_no: u8, fractions: &[u16]) -> ContractPreview {
+        ContractPreview {
+            contract_id: ContractId::from_byte_array([contract_no; 32]),
+            opid: OpId::from_inner([contract_no; 32].into()),
+            spent: vec![],
+            payments: vec![],
+            change: vec![],
+            fractions: fractions
+                .iter()
+                .map(|ty| AssignmentType::from_inner(*ty))
+                .collect(),
+        }
+    }
+
+    fn spend(preview: &mut ContractPreview, ty: u16, state: PersistedState) {
+        let no = preview.spent.len() as u16;
+        preview.spent.push(SpentAllocation {
+            opout: Opout::new(strict_dumb!(), AssignmentType::from_inner(ty), no),
+            seal: seal(no as u32).to_output_seal().expect("seal has txid"),
+            state,
+        });
+    }
+
+    fn receive(preview: &mut ContractPreview, ty: u16, state: PersistedState) {
+        preview.change.push(ChangePreview {
+            assignment_type: AssignmentType::from_inner(ty),
+            vout: Vout::from_u32(preview.change.len() as u32),
+            velocity: VelocityHint::Unspecified,
+            state,
+        });
+    }
+
+    fn pay(preview: &mut ContractPreview, ty: u16) {
+        preview.payments.push(PaymentPreview {
+            assignment_type: AssignmentType::from_inner(ty),
+            beneficiary: BuilderSeal::Revealed(seal(0)),
+            state: PersistedState::Void,
+        });
+    }
+
+    fn amount(value: u64) -> PersistedState {
+        PersistedState::Amount(Amount::from(value), BlindingFactor::random(), strict_dumb!())
+    }
+
+    fn fraction(token_index: u32, fraction: u64) -> PersistedState {
+        PersistedState::Data(Allocation::with(token_index, fraction).into(), 0)
+    }
+
+    #[test]
+    fn balance_changes() {
+        let mut main = preview(1, &[4001]);
+        spend(&mut main, 4000, amount(10));
+        spend(&mut main, 4000, amount(5));
+        spend(&mut main, 4001, fraction(1, 3));
+        spend(&mut main, 4001, fraction(2, 4));
+        // Data which are not declared as RGB21 allocations don't affect balance
+        spend(&mut main, 4002, fraction(1, 7));
+        spend(&mut main, 4003, PersistedState::Void);
+        receive(&mut main, 4000, amount(8));
+        receive(&mut main, 4001, fraction(2, 1));
+        receive(&mut main, 4004, amount(2));
+        receive(&mut main, 4005, amount(3));
+        spend(&mut main, 4005, amount(3));
+        pay(&mut main, 4000);
+
+        let ty = AssignmentType::from_inner;
+        let changes = bmap! {
+            ty(4000) => AmountChange::Dec(Amount::from(7u64)),
+            ty(4001) => AmountChange::Dec(Amount::from(6u64)),
+            ty(4004) => AmountChange::Inc(Amount::from(2u64)),
+            ty(4005) => AmountChange::Zero,
+        };
+        assert_eq!(main.balance_changes(), changes);
+
+        let mut blank = preview(2, &[]);
+        spend(&mut blank, 4000, amount(4));
+        receive(&mut blank, 4000, amount(4));
+        let batch = ComposePreview {
+            main: main.clone(),
+            others: vec![blank.clone()],
+        };
+        assert_eq!(batch.balance_changes(), bmap! {
+            main.contract_id => changes,
+            blank.contract_id => bmap! { ty(4000) => AmountChange::Zero },
+        });
+    }
+
+    #[test]
+    fn blank_contracts() {
+        let mut main = preview(1, &[]);
+        pay(&mut main, 4000);
+        let mut other = preview(3, &[]);
+        pay(&mut other, 4000);
+        let batch = ComposePreview {
+            main: main.clone(),
+            others: vec![preview(2, &[]), other, preview(4, &[])],
+        };
+        assert!(!main.is_blank());
+        assert_eq!(batch.contracts().count(), 4);
+        assert_eq!(batch.blank_contracts().collect::<Vec<_>>(), vec![
+            ContractId::from_byte_array([2u8; 32]),
+            ContractId::from_byte_array([4u8; 32]),
+        ]);
+
+        let batch = ComposePreview {
+            main: preview(1, &[]),
+            others: vec![],
+        };
+        assert_eq!(batch.blank_contracts().collect::<Vec<_>>(), vec![batch.main.contract_id]);
+    }
+}
@@ -0,0 +1,284 @@
+// RGB standard library for working with smart contracts on Bitcoin & Lightning
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2019-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2019-2024 LNP/BP Standards Association. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Selection of the outputs to spend for paying an invoice.
+//!
+//! [`super::Stock::select_outputs`] collects the amount of the invoiced
+//! contract state and the set of other contracts assigning state to each of
+//! the candidate outputs, and passes them to a [`SelectionStrategy`].
+
+use std::cmp::Reverse;
+use std::collections::BTreeSet;
+
+use invoice::Amount;
+use rand::seq::SliceRandom;
+use rgb::{ContractId, XOutputSeal};
+
+/// Candidate output for spending, holding some of the invoiced state.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SelectionCandidate {
+    pub output: XOutputSeal,
+    /// Amount of the invoiced fungible state, or the fraction of the invoiced
+    /// RGB21 token, assigned to the output.
+    pub amount: Amount,
+    /// Other contracts assigning state to the output, which will be moved by
+    /// blank state transitions once the output is spent.
+    pub other_contracts: BTreeSet<ContractId>,
+}
+
+/// Strategy selecting outputs which hold enough fungible state or token
+/// fractions to pay an invoice.
+pub trait SelectionStrategy {
+    /// Selects candidates with a total amount not less than `target`, or
+    /// returns `None` if there is no such selection.
+    fn select(&self, target: Amount, candidates: &[SelectionCandidate])
+        -> Option<Vec<XOutputSeal>>;
+}
+
+/// Accumulates candidates in the provided order until the target is reached.
+fn accumulate<'c>(
+    target: Amount,
+    candidates: impl IntoIterator<Item = &'c SelectionCandidate>,
+) -> Option<Vec<&'c SelectionCandidate>> {
+    let mut sum = Amount::ZERO;
+    let mut selected = vec![];
+    for candidate in candidates {
+        if sum >= target {
+            break;
+        }
+        sum += candidate.amount;
+        selected.push(candidate);
+    }
+    if sum < target {
+        return None;
+    }
+    Some(selected)
+}
+
+fn outputs(selected: Vec<&SelectionCandidate>) -> Vec<XOutputSeal> {
+    selected.into_iter().map(|c| c.output).collect()
+}
+
+/// Selects the single output with the smallest amount covering the target.
+/// If there is no such output, accumulates outputs starting from the largest
+/// one until the rest of the target can be covered by a single output, and
+/// picks the smallest of such outputs, minimizing the change.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct SmallestSufficient;
+
+impl SelectionStrategy for SmallestSufficient {
+    fn select(
+        &self,
+        target: Amount,
+        candidates: &[SelectionCandidate],
+    ) -> Option<Vec<XOutputSeal>> {
+        let mut sorted = candidates.iter().collect::<Vec<_>>();
+        sorted.sort_by_key(|c| Reverse(c.amount));
+        let mut sum = Amount::ZERO;
+        let mut selected = vec![];
+        for (pos, candidate) in sorted.iter().enumerate() {
+            let rest = target - sum;
+            // Outputs are sorted, so the last one covering the rest is the smallest
+            if let Some(last) = sorted[pos..].iter().rposition(|c| c.amount >= rest) {
+                selected.push(sorted[pos + last]);
+                return Some(outputs(selected));
+            }
+            sum += candidate.amount;
+            selected.push(*candidate);
+        }
+        None
+    }
+}
+
+/// Selects outputs starting from the largest one, such that the number of
+/// inputs is minimal.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct FewestInputs;
+
+impl SelectionStrategy for FewestInputs {
+    fn select(
+        &self,
+        target: Amount,
+        candidates: &[SelectionCandidate],
+    ) -> Option<Vec<XOutputSeal>> {
+        let mut sorted = candidates.iter().collect::<Vec<_>>();
+        sorted.sort_by_key(|c| Reverse(c.amount));
+        accumulate(target, sorted).map(outputs)
+    }
+}
+
+/// Selects outputs minimizing the number of other contracts which have to be
+/// moved by blank state transitions.
+///
+/// On each step picks the output adding the least number of other contracts
+/// to the already selected ones; the outputs adding the same number of
+/// contracts are picked starting from the largest one.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct FewestBlanks;
+
+impl SelectionStrategy for FewestBlanks {
+    fn select(
+        &self,
+        target: Amount,
+        candidates: &[SelectionCandidate],
+    ) -> Option<Vec<XOutputSeal>> {
+        let mut remaining = candidates.iter().collect::<Vec<_>>();
+        let mut contracts = BTreeSet::new();
+        let mut selected = vec![];
+        let mut sum = Amount::ZERO;
+        while sum < target {
+            let (pos, next) = remaining.iter().enumerate().min_by_key(|(_, c)| {
+                let added = c.other_contracts.difference(&contracts).count();
+                (added, Reverse(c.amount))
+            })?;
+            let next = *next;
+            remaining.remove(pos);
+            contracts.extend(next.other_contracts.iter().copied());
+            sum += next.amount;
+            selected.push(next);
+        }
+        Some(outputs(selected))
+    }
+}
+
+/// Selects outputs in a random order, preferring the ones which don't hold
+/// state of other contracts.
+///
+/// The selection doesn't reveal wallet heuristics to the payee, and avoids
+/// linking different contracts through the blank state transitions anchored
+/// to the same witness transaction.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct PrivacyPreserving;
+
+impl SelectionStrategy for PrivacyPreserving {
+    fn select(
+        &self,
+        target: Amount,
+        candidates: &[SelectionCandidate],
+    ) -> Option<Vec<XOutputSeal>> {
+        let mut shuffled = candidates.iter().collect::<Vec<_>>();
+        shuffled.shuffle(&mut rand::thread_rng());
+        // Stable sort keeps the random order within each of the groups
+        shuffled.sort_by_key(|c| !c.other_contracts.is_empty());
+        accumulate(target, shuffled).map(outputs)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use amplify::ByteArray;
+    use bp::seals::txout::CloseMethod;
+    use bp::Txid;
+    use rgb::{GraphSeal, XChain};
+
+    use super::*;
+
+    fn candidates(list: &[(u64, &[u8])]) -> Vec<SelectionCandidate> {
+        list.iter()
+            .enumerate()
+            .map(|(vout, (amount, other_contracts))| {
+                let seal = GraphSeal::with_blinding(
+                    CloseMethod::TapretFirst,
+                    Txid::coinbase(),
+                    vout as u32,
+                    0,
+                );
+                SelectionCandidate {
+                    output: XChain::Bitcoin(seal)
+                        .to_output_seal()
+                        .expect("seal with txid"),
+                    amount: Amount::from(*amount),
+                    other_contracts: other_contracts
+                        .iter()
+                        .map(|no| ContractId::from_byte_array([*no; 32]))
+                        .collect(),
+                }
+            })
+            .collect()
+    }
+
+    /// Runs the selection returning positions of the selected candidates.
+    fn select(
+        strategy: impl SelectionStrategy,
+        target: u64,
+        candidates: &[SelectionCandidate],
+    ) -> Option<Vec<usize>> {
+        let selected = strategy.select(Amount::from(target), candidates)?;
+        Some(
+            selected
+                .into_iter()
+                .map(|output| {
+                    candidates
+                        .iter()
+                        .position(|c| c.output == output)
+                        .expect("selected output is a candidate")
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn smallest_sufficient() {
+        let list = candidates(&[(5, &[]), (10, &[]), (3, &[]), (7, &[])]);
+        assert_eq!(select(SmallestSufficient, 7, &list), Some(vec![3]));
+        assert_eq!(select(SmallestSufficient, 6, &list), Some(vec![3]));
+        assert_eq!(select(SmallestSufficient, 10, &list), Some(vec![1]));
+        // 10 + 3 leaves less change than 10 + 7
+        assert_eq!(select(SmallestSufficient, 12, &list), Some(vec![1, 2]));
+        assert_eq!(select(SmallestSufficient, 25, &list), Some(vec![1, 3, 0, 2]));
+        assert_eq!(select(SmallestSufficient, 26, &list), None);
+        assert_eq!(select(SmallestSufficient, 1, &[]), None);
+    }
+
+    #[test]
+    fn fewest_inputs() {
+        let list = candidates(&[(5, &[]), (10, &[]), (3, &[]), (7, &[])]);
+        assert_eq!(select(FewestInputs, 7, &list), Some(vec![1]));
+        assert_eq!(select(FewestInputs, 12, &list), Some(vec![1, 3]));
+        assert_eq!(select(FewestInputs, 25, &list), Some(vec![1, 3, 0, 2]));
+        assert_eq!(select(FewestInputs, 26, &list), None);
+    }
+
+    #[test]
+    fn fewest_blanks() {
+        let list = candidates(&[(10, &[1, 2]), (4, &[]), (6, &[1]), (3, &[])]);
+        assert_eq!(select(FewestBlanks, 7, &list), Some(vec![1, 3]));
+        // The output adding a single contract is preferred over the larger one
+        assert_eq!(select(FewestBlanks, 12, &list), Some(vec![1, 3, 2]));
+        assert_eq!(select(FewestBlanks, 23, &list), Some(vec![1, 3, 2, 0]));
+        assert_eq!(select(FewestBlanks, 24, &list), None);
+    }
+
+    #[test]
+    fn privacy_preserving() {
+        let list = candidates(&[(10, &[1]), (4, &[]), (6, &[2]), (3, &[])]);
+        for _ in 0..16 {
+            let mut selected = select(PrivacyPreserving, 7, &list).unwrap();
+            selected.sort();
+            assert_eq!(selected, vec![1, 3]);
+
+            let selected = select(PrivacyPreserving, 8, &list).unwrap();
+            assert_eq!(selected.len(), 3);
+            assert!(selected[..2].contains(&1) && selected[..2].contains(&3));
+        }
+        assert_eq!(select(PrivacyPreserving, 24, &list), None);
+    }
+}
@@ -0,0 +1,155 @@
+// RGB standard library for working with smart contracts on Bitcoin & Lightning
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2019-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2019-2024 LNP/BP Standards Association. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Map with structural sharing used by the in-memory providers.
+
+use std::fmt::{self, Debug, Formatter};
+use std::hash::Hash;
+use std::io;
+use std::sync::Arc;
+
+use amplify::confinement::{self, LargeOrdMap};
+use im::OrdMap;
+use strict_encoding::{
+    DecodeError, StrictDecode, StrictDumb, StrictEncode, StrictType, TypeName, TypedRead,
+    TypedWrite,
+};
+
+/// Ordered map which copies share their data.
+///
+/// Cloning the map takes constant time: a clone shares the tree nodes and the
+/// values with the original map, and a change made to any of them copies only
+/// the nodes on the path to the changed entry and the changed value itself.
+/// The map is confined to the same number of items as [`LargeOrdMap`] and has
+/// the same strict encoding.
+pub struct SharedMap<K, V>(OrdMap<K, Arc<V>>);
+
+impl<K, V> Clone for SharedMap<K, V> {
+    fn clone(&self) -> Self { SharedMap(self.0.clone()) }
+}
+
+impl<K, V> Default for SharedMap<K, V> {
+    fn default() -> Self { SharedMap(OrdMap::new()) }
+}
+
+impl<K: Ord + Debug, V: Debug> Debug for SharedMap<K, V> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_map()
+            .entries(self.0.iter().map(|(key, value)| (key, value.as_ref())))
+            .finish()
+    }
+}
+
+impl<K: Ord + Clone, V: Clone> SharedMap<K, V> {
+    pub fn new() -> Self { SharedMap::default() }
+
+    pub fn len(&self) -> usize { self.0.len() }
+
+    pub fn is_empty(&self) -> bool { self.0.is_empty() }
+
+    pub fn contains_key(&self, key: &K) -> bool { self.0.contains_key(key) }
+
+    pub fn get(&self, key: &K) -> Option<&V> { self.0.get(key).map(Arc::as_ref) }
+
+    /// Returns mutable reference to the value, copying it first if it is
+    /// shared with other copies of the map.
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> { self.0.get_mut(key).map(Arc::make_mut) }
+
+    /// Inserts a value, returning the previous value for the same key.
+    ///
+    /// # Errors
+    ///
+    /// If the map already contains the maximal number of items allowed for
+    /// [`LargeOrdMap`] and the key is not present in it.
+    pub fn insert(&mut self, key: K, value: V) -> Result<Option<V>, confinement::Error> {
+        let len = self.0.len();
+        if len >= confinement::U32 && !self.0.contains_key(&key) {
+            return Err(confinement::Error::Oversize {
+                len: len + 1,
+                max_len: confinement::U32,
+            });
+        }
+        Ok(self.0.insert(key, Arc::new(value)).map(Self::unshare))
+    }
+
+    /// Removes a value, returning it if it was present.
+    ///
+    /// Never fails; the result type matches the one of [`LargeOrdMap`].
+    pub fn remove(&mut self, key: &K) -> Result<Option<V>, confinement::Error> {
+        Ok(self.0.remove(key).map(Self::unshare))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.0.iter().map(|(key, value)| (key, value.as_ref()))
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &K> { self.0.keys() }
+
+    pub fn values(&self) -> impl Iterator<Item = &V> { self.0.values().map(Arc::as_ref) }
+
+    fn unshare(value: Arc<V>) -> V { Arc::try_unwrap(value).unwrap_or_else(|arc| (*arc).clone()) }
+}
+
+impl<K: Ord + Clone, V: Clone> FromIterator<(K, V)> for SharedMap<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        SharedMap(
+            iter.into_iter()
+                .map(|(key, value)| (key, Arc::new(value)))
+                .collect(),
+        )
+    }
+}
+
+impl<K: Ord + Hash + Clone, V: Clone> From<LargeOrdMap<K, V>> for SharedMap<K, V> {
+    fn from(map: LargeOrdMap<K, V>) -> Self { map.into_iter().collect() }
+}
+
+impl<K: Ord + Hash + Clone, V: Clone> StrictDumb for SharedMap<K, V> {
+    fn strict_dumb() -> Self { SharedMap::default() }
+}
+
+impl<K: Ord + Hash + Clone, V: Clone> StrictType for SharedMap<K, V>
+where LargeOrdMap<K, V>: StrictType
+{
+    const STRICT_LIB_NAME: &'static str = <LargeOrdMap<K, V> as StrictType>::STRICT_LIB_NAME;
+
+    fn strict_name() -> Option<TypeName> { LargeOrdMap::<K, V>::strict_name() }
+}
+
+impl<K: Ord + Hash + Clone, V: Clone> StrictEncode for SharedMap<K, V>
+where LargeOrdMap<K, V>: StrictEncode
+{
+    fn strict_encode<W: TypedWrite>(&self, writer: W) -> io::Result<W> {
+        let map = LargeOrdMap::<K, V>::try_from_iter(
+            self.iter().map(|(key, value)| (key.clone(), value.clone())),
+        )
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        map.strict_encode(writer)
+    }
+}
+
+impl<K: Ord + Hash + Clone, V: Clone> StrictDecode for SharedMap<K, V>
+where LargeOrdMap<K, V>: StrictDecode
+{
+    fn strict_decode(reader: &mut impl TypedRead) -> Result<Self, DecodeError> {
+        LargeOrdMap::<K, V>::strict_decode(reader).map(SharedMap::from)
+    }
+}
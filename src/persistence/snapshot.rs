@@ -0,0 +1,597 @@
+// RGB standard library for working with smart contracts on Bitcoin & Lightning
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2019-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2019-2024 LNP/BP Standards Association. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Immutable snapshots of a stock.
+//!
+//! Providers wrapped into [`Arc`] are shared between a stock and its
+//! snapshots and copied on the first write made after a snapshot was taken
+//! (copy-on-write). Thus, taking a [`StockSnapshot`] is cheap, and readers may
+//! query it from other threads, seeing a consistent view of the data, while a
+//! writer continues to update the stock.
+//!
+//! Copy-on-write providers are designed for in-memory providers (see
+//! [`MemStash`], [`MemState`] and [`MemIndex`]), which keep their data in
+//! [`SharedMap`]s: a copy of such provider shares all map entries with the
+//! original, so the copy made on the first write takes time proportional to
+//! the number of the provider collections and not to the size of the data.
+//! The only exception is the stash type system, which is copied as a whole.
+//!
+//! [`SharedMap`]: super::SharedMap
+
+use std::borrow::Cow;
+use std::collections::BTreeSet;
+use std::ops::Deref;
+use std::sync::Arc;
+
+use aluvm::library::{Lib, LibId};
+use amplify::confinement::MediumBlob;
+use bp::dbc::tapret::TapretCommitment;
+use rgb::{
+    Assign, AssignmentType, AttachId, BundleId, ContractHistory, ContractId, ExposedState,
+    Extension, Genesis, GenesisSeal, GraphSeal, Identity, OpId, Opout, Schema, SchemaId,
+    SecretSeal, TransitionBundle, XChain, XOutputSeal, XWitnessId,
+};
+use strict_encoding::TypeName;
+use strict_types::TypeSystem;
+
+use super::{
+    IndexProvider, IndexReadError, IndexReadProvider, IndexWriteError, IndexWriteProvider,
+    MemIndex, MemStash, MemState, SchemaIfaces, SecretSealInfo, Spender, StashProvider,
+    StashProviderError, StashReadProvider, StashWriteProvider, StateProvider, StateReadProvider,
+    StateUpdateError, StateWriteProvider, Stock,
+};
+use crate::containers::{ContentId, ContentSigs, SealWitness, SigBlob};
+use crate::interface::{ContractSuppl, Iface, IfaceId, IfaceImpl, IfaceRef};
+use crate::resolvers::ResolveHeight;
+
+/// Stock which providers are shared with its snapshots.
+pub type SharedStock<S = MemStash, H = MemState, P = MemIndex> = Stock<Arc<S>, Arc<H>, Arc<P>>;
+
+/// Immutable view of a [`SharedStock`] at the moment the snapshot was taken.
+///
+/// The snapshot is not affected by the further changes of the stock and
+/// provides access to all read-only stock methods.
+#[derive(Debug)]
+pub struct StockSnapshot<S = MemStash, H = MemState, P = MemIndex>(SharedStock<S, H, P>)
+where
+    S: StashProvider + Clone,
+    H: StateProvider + Clone,
+    P: IndexProvider + Clone;
+
+impl<S, H, P> Clone for StockSnapshot<S, H, P>
+where
+    S: StashProvider + Clone,
+    H: StateProvider + Clone,
+    P: IndexProvider + Clone,
+{
+    fn clone(&self) -> Self { self.0.snapshot() }
+}
+
+impl<S, H, P> Deref for StockSnapshot<S, H, P>
+where
+    S: StashProvider + Clone,
+    H: StateProvider + Clone,
+    P: IndexProvider + Clone,
+{
+    type Target = SharedStock<S, H, P>;
+
+    fn deref(&self) -> &Self::Target { &self.0 }
+}
+
+impl<S, H, P> SharedStock<S, H, P>
+where
+    S: StashProvider + Clone,
+    H: StateProvider + Clone,
+    P: IndexProvider + Clone,
+{
+    /// Constructs stock which can be snapshotted.
+    pub fn shared(stash_provider: S, state_provider: H, index_provider: P) -> Self {
+        Stock::with(Arc::new(stash_provider), Arc::new(state_provider), Arc::new(index_provider))
+    }
+
+    /// Takes snapshot of the current stock data.
+    ///
+    /// The operation doesn't copy the data; the stock copies only the changed
+    /// map entries on the first change made after the snapshot. Observers
+    /// registered with the stock are not copied to the snapshot.
+    pub fn snapshot(&self) -> StockSnapshot<S, H, P> {
+        StockSnapshot(Stock::with(
+            self.as_stash_provider().clone(),
+            self.as_state_provider().clone(),
+            self.as_index_provider().clone(),
+        ))
+    }
+}
+
+impl<T: StashProvider + Clone> StashProvider for Arc<T> {}
+
+impl<T: StashReadProvider> StashReadProvider for Arc<T> {
+    type Error = T::Error;
+
+    fn type_system(&self) -> Result<Cow<TypeSystem>, Self::Error> { (**self).type_system() }
+
+    fn lib_ids(&self) -> Result<impl Iterator<Item = LibId>, Self::Error> { (**self).lib_ids() }
+
+    fn lib(&self, id: LibId) -> Result<&Lib, StashProviderError<Self::Error>> { (**self).lib(id) }
+
+    fn ifaces(&self) -> Result<impl Iterator<Item = (IfaceId, TypeName)>, Self::Error> {
+        (**self).ifaces()
+    }
+
+    fn iface(&self, iface: impl Into<IfaceRef>) -> Result<&Iface, StashProviderError<Self::Error>> {
+        (**self).iface(iface)
+    }
+
+    fn schemata(&self) -> Result<impl Iterator<Item = &SchemaIfaces>, Self::Error> {
+        (**self).schemata()
+    }
+
+    fn schema(
+        &self,
+        schema_id: SchemaId,
+    ) -> Result<&SchemaIfaces, StashProviderError<Self::Error>> {
+        (**self).schema(schema_id)
+    }
+
+    fn contract_ids(&self) -> Result<impl Iterator<Item = ContractId>, Self::Error> {
+        (**self).contract_ids()
+    }
+
+    fn contract_ids_by_iface(
+        &self,
+        iface: impl Into<IfaceRef>,
+    ) -> Result<impl Iterator<Item = ContractId>, StashProviderError<Self::Error>> {
+        (**self).contract_ids_by_iface(iface)
+    }
+
+    fn contract_supplements(
+        &self,
+        contract_id: ContractId,
+    ) -> Result<impl Iterator<Item = ContractSuppl>, Self::Error> {
+        (**self).contract_supplements(contract_id)
+    }
+
+    fn suppl_contract_ids(&self) -> Result<impl Iterator<Item = ContractId>, Self::Error> {
+        (**self).suppl_contract_ids()
+    }
+
+    fn genesis(
+        &self,
+        contract_id: ContractId,
+    ) -> Result<&Genesis, StashProviderError<Self::Error>> {
+        (**self).genesis(contract_id)
+    }
+
+    fn witness_ids(&self) -> Result<impl Iterator<Item = XWitnessId>, Self::Error> {
+        (**self).witness_ids()
+    }
+
+    fn bundle_ids(&self) -> Result<impl Iterator<Item = BundleId>, Self::Error> {
+        (**self).bundle_ids()
+    }
+
+    fn bundle(
+        &self,
+        bundle_id: BundleId,
+    ) -> Result<&TransitionBundle, StashProviderError<Self::Error>> {
+        (**self).bundle(bundle_id)
+    }
+
+    fn extension_ids(&self) -> Result<impl Iterator<Item = OpId>, Self::Error> {
+        (**self).extension_ids()
+    }
+
+    fn extension(&self, op_id: OpId) -> Result<&Extension, StashProviderError<Self::Error>> {
+        (**self).extension(op_id)
+    }
+
+    fn witness(
+        &self,
+        witness_id: XWitnessId,
+    ) -> Result<&SealWitness, StashProviderError<Self::Error>> {
+        (**self).witness(witness_id)
+    }
+
+    fn attachment_ids(&self) -> Result<impl Iterator<Item = AttachId>, Self::Error> {
+        (**self).attachment_ids()
+    }
+
+    fn attachment(&self, id: AttachId) -> Result<&MediumBlob, StashProviderError<Self::Error>> {
+        (**self).attachment(id)
+    }
+
+    fn sigs(&self) -> Result<impl Iterator<Item = (ContentId, &ContentSigs)>, Self::Error> {
+        (**self).sigs()
+    }
+
+    fn taprets(&self) -> Result<impl Iterator<Item = (XWitnessId, TapretCommitment)>, Self::Error> {
+        (**self).taprets()
+    }
+
+    fn seal_secret(
+        &self,
+        secret: XChain<SecretSeal>,
+    ) -> Result<Option<XChain<GraphSeal>>, Self::Error> {
+        (**self).seal_secret(secret)
+    }
+
+    fn secret_seals(&self) -> Result<impl Iterator<Item = XChain<GraphSeal>>, Self::Error> {
+        (**self).secret_seals()
+    }
+
+    fn secret_seal_records(
+        &self,
+    ) -> Result<impl Iterator<Item = (XChain<GraphSeal>, SecretSealInfo)>, Self::Error> {
+        (**self).secret_seal_records()
+    }
+}
+
+impl<T: StashWriteProvider + Clone> StashWriteProvider for Arc<T> {
+    type Error = T::Error;
+
+    fn begin_transaction(&mut self) -> Result<(), Self::Error> {
+        Arc::make_mut(self).begin_transaction()
+    }
+
+    fn prepare_transaction(&mut self) -> Result<(), Self::Error> {
+        Arc::make_mut(self).prepare_transaction()
+    }
+
+    fn commit_transaction(&mut self) -> Result<(), Self::Error> {
+        Arc::make_mut(self).commit_transaction()
+    }
+
+    fn rollback_transaction(&mut self) -> Result<(), Self::Error> {
+        Arc::make_mut(self).rollback_transaction()
+    }
+
+    fn replace_schema(&mut self, schema: Schema) -> Result<bool, Self::Error> {
+        Arc::make_mut(self).replace_schema(schema)
+    }
+
+    fn replace_iface(&mut self, iface: Iface) -> Result<bool, Self::Error> {
+        Arc::make_mut(self).replace_iface(iface)
+    }
+
+    fn replace_iimpl(&mut self, iimpl: IfaceImpl) -> Result<bool, Self::Error> {
+        Arc::make_mut(self).replace_iimpl(iimpl)
+    }
+
+    fn replace_genesis(&mut self, genesis: Genesis) -> Result<bool, Self::Error> {
+        Arc::make_mut(self).replace_genesis(genesis)
+    }
+
+    fn replace_extension(&mut self, extension: Extension) -> Result<bool, Self::Error> {
+        Arc::make_mut(self).replace_extension(extension)
+    }
+
+    fn replace_bundle(&mut self, bundle: TransitionBundle) -> Result<bool, Self::Error> {
+        Arc::make_mut(self).replace_bundle(bundle)
+    }
+
+    fn replace_witness(&mut self, witness: SealWitness) -> Result<bool, Self::Error> {
+        Arc::make_mut(self).replace_witness(witness)
+    }
+
+    fn replace_attachment(
+        &mut self,
+        id: AttachId,
+        attach: MediumBlob,
+    ) -> Result<bool, Self::Error> {
+        Arc::make_mut(self).replace_attachment(id, attach)
+    }
+
+    fn replace_lib(&mut self, lib: Lib) -> Result<bool, Self::Error> {
+        Arc::make_mut(self).replace_lib(lib)
+    }
+
+    fn consume_types(&mut self, types: TypeSystem) -> Result<(), Self::Error> {
+        Arc::make_mut(self).consume_types(types)
+    }
+
+    fn add_suppl(&mut self, suppl: ContractSuppl) -> Result<(), Self::Error> {
+        Arc::make_mut(self).add_suppl(suppl)
+    }
+
+    fn import_sigs<I>(&mut self, content_id: ContentId, sigs: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = (Identity, SigBlob)>,
+        I::IntoIter: ExactSizeIterator<Item = (Identity, SigBlob)>,
+    {
+        Arc::make_mut(self).import_sigs(content_id, sigs)
+    }
+
+    fn add_secret_seal(&mut self, seal: XChain<GraphSeal>) -> Result<bool, Self::Error> {
+        Arc::make_mut(self).add_secret_seal(seal)
+    }
+
+    fn add_secret_seal_with(
+        &mut self,
+        seal: XChain<GraphSeal>,
+        info: SecretSealInfo,
+    ) -> Result<bool, Self::Error> {
+        Arc::make_mut(self).add_secret_seal_with(seal, info)
+    }
+
+    fn remove_secret_seal(&mut self, seal: XChain<GraphSeal>) -> Result<bool, Self::Error> {
+        Arc::make_mut(self).remove_secret_seal(seal)
+    }
+
+    fn remove_genesis(&mut self, contract_id: ContractId) -> Result<bool, Self::Error> {
+        Arc::make_mut(self).remove_genesis(contract_id)
+    }
+
+    fn remove_suppl(&mut self, contract_id: ContractId) -> Result<(), Self::Error> {
+        Arc::make_mut(self).remove_suppl(contract_id)
+    }
+
+    fn remove_extension(&mut self, opid: OpId) -> Result<bool, Self::Error> {
+        Arc::make_mut(self).remove_extension(opid)
+    }
+
+    fn remove_bundle(&mut self, bundle_id: BundleId) -> Result<bool, Self::Error> {
+        Arc::make_mut(self).remove_bundle(bundle_id)
+    }
+
+    fn remove_witness(&mut self, witness_id: XWitnessId) -> Result<bool, Self::Error> {
+        Arc::make_mut(self).remove_witness(witness_id)
+    }
+}
+
+impl<T: StateProvider + Clone> StateProvider for Arc<T> {}
+
+impl<T: StateReadProvider> StateReadProvider for Arc<T> {
+    type Error = T::Error;
+
+    fn contract_state(
+        &self,
+        contract_id: ContractId,
+    ) -> Result<Option<&ContractHistory>, Self::Error> {
+        (**self).contract_state(contract_id)
+    }
+}
+
+impl<T: StateWriteProvider + Clone> StateWriteProvider for Arc<T> {
+    type Error = T::Error;
+
+    fn begin_transaction(&mut self) -> Result<(), Self::Error> {
+        Arc::make_mut(self).begin_transaction()
+    }
+
+    fn prepare_transaction(&mut self) -> Result<(), Self::Error> {
+        Arc::make_mut(self).prepare_transaction()
+    }
+
+    fn commit_transaction(&mut self) -> Result<(), Self::Error> {
+        Arc::make_mut(self).commit_transaction()
+    }
+
+    fn rollback_transaction(&mut self) -> Result<(), Self::Error> {
+        Arc::make_mut(self).rollback_transaction()
+    }
+
+    fn create_or_update_state<R: ResolveHeight>(
+        &mut self,
+        contract_id: ContractId,
+        updater: impl FnOnce(Option<ContractHistory>) -> Result<ContractHistory, R::Error>,
+    ) -> Result<(), StateUpdateError<Self::Error>> {
+        Arc::make_mut(self).create_or_update_state::<R>(contract_id, updater)
+    }
+
+    fn update_state<R: ResolveHeight>(
+        &mut self,
+        contract_id: ContractId,
+        updater: impl FnMut(&mut ContractHistory) -> Result<(), R::Error>,
+    ) -> Result<(), StateUpdateError<Self::Error>> {
+        Arc::make_mut(self).update_state::<R>(contract_id, updater)
+    }
+
+    fn remove_state(&mut self, contract_id: ContractId) -> Result<bool, Self::Error> {
+        Arc::make_mut(self).remove_state(contract_id)
+    }
+
+    fn clear_state(&mut self) -> Result<(), Self::Error> { Arc::make_mut(self).clear_state() }
+}
+
+impl<T: IndexProvider + Clone> IndexProvider for Arc<T> {}
+
+impl<T: IndexReadProvider> IndexReadProvider for Arc<T> {
+    type Error = T::Error;
+
+    fn contracts_assigning(
+        &self,
+        outputs: BTreeSet<XOutputSeal>,
+    ) -> Result<impl Iterator<Item = ContractId> + '_, Self::Error> {
+        (**self).contracts_assigning(outputs)
+    }
+
+    fn public_opouts(
+        &self,
+        contract_id: ContractId,
+    ) -> Result<BTreeSet<Opout>, IndexReadError<Self::Error>> {
+        (**self).public_opouts(contract_id)
+    }
+
+    fn opouts_by_outputs(
+        &self,
+        contract_id: ContractId,
+        outputs: impl IntoIterator<Item = impl Into<XOutputSeal>>,
+    ) -> Result<BTreeSet<Opout>, IndexReadError<Self::Error>> {
+        (**self).opouts_by_outputs(contract_id, outputs)
+    }
+
+    fn opouts_by_terminals(
+        &self,
+        terminals: impl IntoIterator<Item = XChain<SecretSeal>>,
+    ) -> Result<BTreeSet<Opout>, Self::Error> {
+        (**self).opouts_by_terminals(terminals)
+    }
+
+    fn bundle_id_for_op(&self, opid: OpId) -> Result<BundleId, IndexReadError<Self::Error>> {
+        (**self).bundle_id_for_op(opid)
+    }
+
+    fn bundle_info(
+        &self,
+        bundle_id: BundleId,
+    ) -> Result<(XWitnessId, ContractId), IndexReadError<Self::Error>> {
+        (**self).bundle_info(bundle_id)
+    }
+
+    fn spenders(&self, opout: Opout) -> Result<BTreeSet<Spender>, IndexReadError<Self::Error>> {
+        (**self).spenders(opout)
+    }
+
+    fn operations(&self) -> Result<impl Iterator<Item = (OpId, BundleId)> + '_, Self::Error> {
+        (**self).operations()
+    }
+
+    fn bundle_ids(&self) -> Result<impl Iterator<Item = BundleId> + '_, Self::Error> {
+        (**self).bundle_ids()
+    }
+}
+
+impl<T: IndexWriteProvider + Clone> IndexWriteProvider for Arc<T> {
+    type Error = T::Error;
+
+    fn begin_transaction(&mut self) -> Result<(), Self::Error> {
+        Arc::make_mut(self).begin_transaction()
+    }
+
+    fn prepare_transaction(&mut self) -> Result<(), Self::Error> {
+        Arc::make_mut(self).prepare_transaction()
+    }
+
+    fn commit_transaction(&mut self) -> Result<(), Self::Error> {
+        Arc::make_mut(self).commit_transaction()
+    }
+
+    fn rollback_transaction(&mut self) -> Result<(), Self::Error> {
+        Arc::make_mut(self).rollback_transaction()
+    }
+
+    fn register_contract(&mut self, contract_id: ContractId) -> Result<bool, Self::Error> {
+        Arc::make_mut(self).register_contract(contract_id)
+    }
+
+    fn register_bundle(
+        &mut self,
+        bundle_id: BundleId,
+        witness_id: XWitnessId,
+        contract_id: ContractId,
+    ) -> Result<bool, IndexWriteError<Self::Error>> {
+        Arc::make_mut(self).register_bundle(bundle_id, witness_id, contract_id)
+    }
+
+    fn register_operation(
+        &mut self,
+        opid: OpId,
+        bundle_id: BundleId,
+    ) -> Result<bool, IndexWriteError<Self::Error>> {
+        Arc::make_mut(self).register_operation(opid, bundle_id)
+    }
+
+    fn register_spender(
+        &mut self,
+        opout: Opout,
+        spender: Spender,
+    ) -> Result<bool, IndexWriteError<Self::Error>> {
+        Arc::make_mut(self).register_spender(opout, spender)
+    }
+
+    fn index_genesis_assignments<State: ExposedState>(
+        &mut self,
+        contract_id: ContractId,
+        vec: &[Assign<State, GenesisSeal>],
+        opid: OpId,
+        type_id: AssignmentType,
+    ) -> Result<(), IndexWriteError<Self::Error>> {
+        Arc::make_mut(self).index_genesis_assignments(contract_id, vec, opid, type_id)
+    }
+
+    fn index_transition_assignments<State: ExposedState>(
+        &mut self,
+        contract_id: ContractId,
+        vec: &[Assign<State, GraphSeal>],
+        opid: OpId,
+        type_id: AssignmentType,
+        witness_id: XWitnessId,
+    ) -> Result<(), IndexWriteError<Self::Error>> {
+        Arc::make_mut(self).index_transition_assignments(
+            contract_id,
+            vec,
+            opid,
+            type_id,
+            witness_id,
+        )
+    }
+
+    fn remove_contract(
+        &mut self,
+        contract_id: ContractId,
+        opids: &BTreeSet<OpId>,
+    ) -> Result<(), Self::Error> {
+        Arc::make_mut(self).remove_contract(contract_id, opids)
+    }
+
+    fn clear_index(&mut self) -> Result<(), Self::Error> { Arc::make_mut(self).clear_index() }
+}
+
+#[cfg(test)]
+mod test {
+    use bp::seals::txout::CloseMethod;
+    use bp::Txid;
+
+    use super::*;
+
+    fn seal(blinding: u64) -> XChain<GraphSeal> {
+        XChain::Bitcoin(GraphSeal::with_blinding(
+            CloseMethod::TapretFirst,
+            Txid::coinbase(),
+            1u32,
+            blinding,
+        ))
+    }
+
+    fn secret_seals(stock: &SharedStock) -> Vec<XChain<GraphSeal>> {
+        stock.as_stash_provider().secret_seals().unwrap().collect()
+    }
+
+    #[test]
+    fn isolation() {
+        let mut stock: SharedStock = SharedStock::shared(default!(), default!(), default!());
+        stock.store_secret_seal(seal(1)).unwrap();
+        let snapshot = stock.snapshot();
+        let copy = snapshot.clone();
+
+        stock.store_secret_seal(seal(2)).unwrap();
+        let mut expected = vec![seal(1), seal(2)];
+        expected.sort();
+        assert_eq!(secret_seals(&stock), expected);
+        assert_eq!(secret_seals(&snapshot), vec![seal(1)]);
+        assert_eq!(secret_seals(&copy), vec![seal(1)]);
+
+        let later = stock.snapshot();
+        stock.store_secret_seal(seal(3)).unwrap();
+        assert_eq!(secret_seals(&later), expected);
+        assert_eq!(secret_seals(&snapshot), vec![seal(1)]);
+    }
+}
@@ -0,0 +1,1756 @@
+// RGB standard library for working with smart contracts on Bitcoin & Lightning
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2019-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2019-2024 LNP/BP Standards Association. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Persistence providers keeping stash, contract state and index in a single
+//! SQLite database file.
+//!
+//! Each write operation performed by the providers results in an update of a
+//! single database row, so there is no need to re-serialize the whole stash
+//! after each accepted consignment. Index lookups are performed directly with
+//! SQL queries. Since provider APIs return references to the stored data,
+//! stash and contract state are cached in memory: when the database is opened
+//! the providers read schemata, interfaces, libraries, types, signatures,
+//! supplements and secret seals, and only the identifiers of geneses, bundles,
+//! extensions, witnesses, attachments and contract states. The latter are read
+//! from the database when they are accessed for the first time.
+//!
+//! The database layout is versioned: each change of the layout is a migration,
+//! applied when a database created by a previous version of the library is
+//! opened.
+//!
+//! The providers are not `Clone`: copies would share the database but not the
+//! cached data, so they can't be used with [`super::SharedStock`] snapshots.
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet};
+use std::mem;
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+
+use aluvm::library::{Lib, LibId};
+use amplify::confinement::{self, Confined, MediumBlob};
+use amplify::Wrapper;
+use bp::dbc::tapret::TapretCommitment;
+use commit_verify::{CommitId, Conceal};
+use rgb::{
+    Assign, AssignmentType, AttachId, BundleId, ContractHistory, ContractId, ExposedState,
+    Extension, Genesis, GenesisSeal, GraphSeal, Identity, OpId, Operation, Opout, Schema, SchemaId,
+    SecretSeal, TransitionBundle, XChain, XOutputSeal, XWitnessId,
+};
+use rusqlite::{params, Connection, OptionalExtension};
+use strict_encoding::{StreamReader, StreamWriter, StrictDecode, StrictEncode, TypeName};
+use strict_types::{SemId, Ty, TypeSystem};
+
+use super::memory::Journal;
+use super::{
+    IndexInconsistency, IndexProvider, IndexReadError, IndexReadProvider, IndexWriteError,
+    IndexWriteProvider, SchemaIfaces, SecretSealInfo, Spender, StashInconsistency, StashProvider,
+    StashProviderError, StashReadProvider, StashWriteProvider, StateProvider, StateReadProvider,
+    StateUpdateError, StateWriteProvider, Stock,
+};
+use crate::containers::{AnchorSet, ContentId, ContentSigs, SealWitness, SigBlob};
+use crate::interface::{ContractSuppl, Iface, IfaceId, IfaceImpl, IfaceRef};
+use crate::resolvers::ResolveHeight;
+
+const SQL_MAX_BLOB_LEN: usize = usize::MAX;
+
+/// Changes of the database layout, in the order of their introduction.
+///
+/// Databases keep the number of the applied migrations in `user_version`
+/// pragma, so when a database is opened only the migrations which were not
+/// applied to it yet are performed.
+const SQL_MIGRATIONS: [&str; 3] = [
+    // Initial layout
+    "
+CREATE TABLE schemata (id BLOB PRIMARY KEY, data BLOB NOT NULL);
+CREATE TABLE ifaces (id BLOB PRIMARY KEY, data BLOB NOT NULL);
+CREATE TABLE geneses (id BLOB PRIMARY KEY, data BLOB NOT NULL);
+CREATE TABLE suppl (
+    contract_id BLOB NOT NULL,
+    data BLOB NOT NULL,
+    PRIMARY KEY (contract_id, data)
+);
+CREATE TABLE bundles (id BLOB PRIMARY KEY, data BLOB NOT NULL);
+CREATE TABLE extensions (id BLOB PRIMARY KEY, data BLOB NOT NULL);
+CREATE TABLE witnesses (id BLOB PRIMARY KEY, data BLOB NOT NULL);
+CREATE TABLE attachments (id BLOB PRIMARY KEY, data BLOB NOT NULL);
+CREATE TABLE secret_seals (seal BLOB PRIMARY KEY);
+CREATE TABLE types (id BLOB PRIMARY KEY, data BLOB NOT NULL);
+CREATE TABLE libs (id BLOB PRIMARY KEY, data BLOB NOT NULL);
+CREATE TABLE sigs (id BLOB PRIMARY KEY, data BLOB NOT NULL);
+
+CREATE TABLE history (contract_id BLOB PRIMARY KEY, data BLOB NOT NULL);
+
+CREATE TABLE idx_contracts (contract_id BLOB PRIMARY KEY);
+CREATE TABLE idx_bundles (
+    bundle_id BLOB PRIMARY KEY,
+    witness_id BLOB NOT NULL,
+    contract_id BLOB NOT NULL
+);
+CREATE TABLE idx_ops (opid BLOB PRIMARY KEY, bundle_id BLOB NOT NULL);
+CREATE TABLE idx_outputs (
+    contract_id BLOB NOT NULL,
+    output BLOB NOT NULL,
+    opout BLOB NOT NULL,
+    PRIMARY KEY (contract_id, output, opout)
+);
+CREATE INDEX idx_outputs_by_output ON idx_outputs (output);
+CREATE TABLE idx_public (
+    contract_id BLOB NOT NULL,
+    opout BLOB NOT NULL,
+    PRIMARY KEY (contract_id, opout)
+);
+CREATE TABLE idx_terminals (seal BLOB PRIMARY KEY, opout BLOB NOT NULL);
+",
+    // Spender index. It is filled only by re-indexing the stash, so the
+    // migration schedules it.
+    "
+CREATE TABLE idx_spent (
+    opout BLOB NOT NULL,
+    spender BLOB NOT NULL,
+    PRIMARY KEY (opout, spender)
+);
+CREATE TABLE maintenance (task TEXT PRIMARY KEY);
+INSERT INTO maintenance (task) VALUES ('reindex');
+",
+    // Information records of secret seals. Seals added before have no records
+    // and get the default ones when loaded.
+    "
+CREATE TABLE secret_seal_info (id BLOB PRIMARY KEY, data BLOB NOT NULL);
+",
+];
+
+#[derive(Clone, Eq, PartialEq, Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum SqlError {
+    /// database error: {0}
+    Database(String),
+
+    /// unable to encode data for storing in the database: {0}
+    Encoding(String),
+
+    /// database contains invalid data: {0}
+    Decoding(String),
+
+    /// unable to upgrade database layout: {0}
+    Migration(String),
+
+    /// database layout version {0} is newer than the one supported by the
+    /// library ({1}).
+    UnsupportedVersion(u32, usize),
+
+    #[from]
+    #[display(inner)]
+    Confinement(confinement::Error),
+}
+
+impl From<rusqlite::Error> for SqlError {
+    fn from(err: rusqlite::Error) -> Self { SqlError::Database(err.to_string()) }
+}
+
+impl From<SqlError> for StateUpdateError<SqlError> {
+    fn from(err: SqlError) -> Self { StateUpdateError::Connectivity(err) }
+}
+
+impl From<SqlError> for IndexReadError<SqlError> {
+    fn from(err: SqlError) -> Self { IndexReadError::Connectivity(err) }
+}
+
+impl From<SqlError> for IndexWriteError<SqlError> {
+    fn from(err: SqlError) -> Self { IndexWriteError::Connectivity(err) }
+}
+
+fn encode<T: StrictEncode>(val: &T) -> Result<Vec<u8>, SqlError> {
+    let mut data = Vec::new();
+    let writer = StreamWriter::new::<SQL_MAX_BLOB_LEN>(&mut data);
+    val.strict_write(writer)
+        .map_err(|e| SqlError::Encoding(e.to_string()))?;
+    Ok(data)
+}
+
+fn decode<T: StrictDecode>(data: &[u8]) -> Result<T, SqlError> {
+    let reader = StreamReader::new::<SQL_MAX_BLOB_LEN>(data);
+    T::strict_read(reader).map_err(|e| SqlError::Decoding(e.to_string()))
+}
+
+/// Connection to a SQLite database file shared by the stash, state and index
+/// providers.
+///
+/// Providers sharing the connection also share a single database transaction.
+/// It is started by the first provider beginning a transaction and committed
+/// to the database once all of them have prepared it, so the changes made by
+/// the stock to all its providers become durable at once.
+#[derive(Clone, Debug)]
+pub struct SqlDb(Arc<Mutex<SqlConn>>);
+
+#[derive(Debug)]
+struct SqlConn {
+    conn: Connection,
+    /// Number of providers taking part in the transaction.
+    open: usize,
+    /// Number of providers which have prepared the transaction.
+    prepared: usize,
+}
+
+impl SqlDb {
+    /// Opens database file, creating it and all the required tables if they
+    /// are not present.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, SqlError> {
+        let conn = Connection::open(path)?;
+        conn.pragma_update_and_check(None, "journal_mode", "WAL", |_| Ok(()))?;
+        Self::init(conn)
+    }
+
+    /// Creates a new database which is kept in memory.
+    pub fn open_in_memory() -> Result<Self, SqlError> { Self::init(Connection::open_in_memory()?) }
+
+    fn init(mut conn: Connection) -> Result<Self, SqlError> {
+        Self::migrate(&mut conn)?;
+        Ok(Self(Arc::new(Mutex::new(SqlConn {
+            conn,
+            open: 0,
+            prepared: 0,
+        }))))
+    }
+
+    /// Applies all migrations which were not applied to the database yet, in
+    /// a single transaction.
+    fn migrate(conn: &mut Connection) -> Result<(), SqlError> {
+        let tx = conn.transaction()?;
+        let version: u32 = tx.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        if version as usize > SQL_MIGRATIONS.len() {
+            return Err(SqlError::UnsupportedVersion(version, SQL_MIGRATIONS.len()));
+        }
+        if version as usize == SQL_MIGRATIONS.len() {
+            return Ok(());
+        }
+        for (no, migration) in SQL_MIGRATIONS.iter().enumerate().skip(version as usize) {
+            tx.execute_batch(migration)
+                .map_err(|err| SqlError::Migration(format!("migration #{}: {err}", no + 1)))?;
+        }
+        if version == 0 {
+            // A new database doesn't have any data to re-index
+            tx.execute("DELETE FROM maintenance", [])?;
+        }
+        tx.pragma_update(None, "user_version", SQL_MIGRATIONS.len() as u32)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Constructs stock which keeps all its data in this database.
+    ///
+    /// If a migration of the database layout requires re-indexing of the
+    /// stash, the index is rebuilt. Until then, spender queries of the index
+    /// fail with [`IndexInconsistency::SpendersNotIndexed`].
+    pub fn open_stock(&self) -> Result<Stock<SqlStash, SqlState, SqlIndex>, SqlError> {
+        let stash = SqlStash::load(self.clone())?;
+        let state = SqlState::load(self.clone())?;
+        let index = SqlIndex::with(self.clone());
+        let mut stock = Stock::with(stash, state, index);
+
+        if self.is_reindex_pending()? {
+            // Clearing the index also removes the pending task
+            stock
+                .rebuild_index()
+                .map_err(|err| SqlError::Migration(err.to_string()))?;
+        }
+        Ok(stock)
+    }
+
+    /// Checks whether a migration has scheduled re-indexing of the stash which
+    /// was not performed yet.
+    fn is_reindex_pending(&self) -> Result<bool, SqlError> {
+        let count: u32 = self.with(|conn| {
+            conn.query_row("SELECT COUNT(*) FROM maintenance WHERE task = 'reindex'", [], |row| {
+                row.get(0)
+            })
+        })?;
+        Ok(count > 0)
+    }
+
+    fn with<T>(&self, f: impl FnOnce(&Connection) -> rusqlite::Result<T>) -> Result<T, SqlError> {
+        f(&self.lock().conn).map_err(SqlError::from)
+    }
+
+    fn lock(&self) -> MutexGuard<SqlConn> {
+        self.0.lock().expect("SQLite connection mutex is poisoned")
+    }
+
+    fn execute(&self, sql: &str, params: impl rusqlite::Params) -> Result<usize, SqlError> {
+        self.with(|conn| conn.execute(sql, params))
+    }
+
+    fn rows(&self, sql: &str, params: impl rusqlite::Params) -> Result<Vec<Vec<u8>>, SqlError> {
+        self.with(|conn| {
+            let mut stmt = conn.prepare(sql)?;
+            let rows = stmt.query_map(params, |row| row.get::<_, Vec<u8>>(0))?;
+            rows.collect()
+        })
+    }
+
+    fn pairs(
+        &self,
+        sql: &str,
+        params: impl rusqlite::Params,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, SqlError> {
+        self.with(|conn| {
+            let mut stmt = conn.prepare(sql)?;
+            let rows = stmt.query_map(params, |row| Ok((row.get(0)?, row.get(1)?)))?;
+            rows.collect()
+        })
+    }
+
+    fn row(&self, sql: &str, params: impl rusqlite::Params) -> Result<Option<Vec<u8>>, SqlError> {
+        self.with(|conn| conn.query_row(sql, params, |row| row.get(0)).optional())
+    }
+
+    fn load_map<K: StrictDecode + Ord, V: StrictDecode>(
+        &self,
+        table: &str,
+    ) -> Result<BTreeMap<K, V>, SqlError> {
+        self.pairs(&format!("SELECT id, data FROM {table}"), [])?
+            .into_iter()
+            .map(|(k, v)| Ok((decode(&k)?, decode(&v)?)))
+            .collect()
+    }
+
+    /// Reads identifiers of all table rows, leaving the data to be loaded when
+    /// they are accessed for the first time.
+    fn load_ids<K: StrictDecode + Ord, V>(
+        &self,
+        table: &str,
+        key: &str,
+    ) -> Result<BTreeMap<K, OnceLock<V>>, SqlError> {
+        self.rows(&format!("SELECT {key} FROM {table}"), [])?
+            .into_iter()
+            .map(|k| Ok((decode(&k)?, OnceLock::new())))
+            .collect()
+    }
+
+    /// Returns cached data of a table row, reading them from the database if
+    /// they were not accessed before.
+    fn load_cached<'c, V: StrictDecode>(
+        &self,
+        table: &str,
+        key: &str,
+        id: &impl StrictEncode,
+        cached: &'c OnceLock<V>,
+    ) -> Result<&'c V, SqlError> {
+        if let Some(val) = cached.get() {
+            return Ok(val);
+        }
+        let data = self
+            .row(&format!("SELECT data FROM {table} WHERE {key} = ?1"), [encode(id)?])?
+            .ok_or_else(|| SqlError::Decoding(format!("row is absent from {table} table")))?;
+        let val = decode(&data)?;
+        Ok(cached.get_or_init(|| val))
+    }
+
+    fn delete(&self, table: &str, id: &impl StrictEncode) -> Result<bool, SqlError> {
+        let count = self.execute(&format!("DELETE FROM {table} WHERE id = ?1"), [encode(id)?])?;
+        Ok(count > 0)
+    }
+
+    /// Joins the shared transaction, starting it if no other provider takes
+    /// part in it.
+    fn begin(&self) -> Result<(), SqlError> {
+        let mut db = self.lock();
+        if db.open == 0 {
+            db.conn.execute_batch("BEGIN")?;
+        }
+        db.open += 1;
+        Ok(())
+    }
+
+    /// Prepares the shared transaction on behalf of a provider. Once the last
+    /// provider taking part in the transaction prepares it, the transaction is
+    /// committed to the database; if this fails, the transaction is kept and
+    /// can be reverted by the providers.
+    fn prepare(&self) -> Result<(), SqlError> {
+        let mut db = self.lock();
+        if db.prepared + 1 >= db.open && !db.conn.is_autocommit() {
+            db.conn.execute_batch("COMMIT")?;
+        }
+        db.prepared += 1;
+        Ok(())
+    }
+
+    /// Leaves the shared transaction. If the transaction was not prepared, it
+    /// is committed when the last provider leaves it.
+    fn commit(&self) -> Result<(), SqlError> {
+        let mut db = self.lock();
+        db.open = db.open.saturating_sub(1);
+        if db.open == 0 {
+            db.prepared = 0;
+            if !db.conn.is_autocommit() {
+                db.conn.execute_batch("COMMIT")?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reverts the shared transaction for all providers taking part in it and
+    /// leaves it.
+    fn rollback(&self) -> Result<(), SqlError> {
+        let mut db = self.lock();
+        db.open = db.open.saturating_sub(1);
+        if db.open == 0 {
+            db.prepared = 0;
+        }
+        if !db.conn.is_autocommit() {
+            db.conn.execute_batch("ROLLBACK")?;
+        }
+        Ok(())
+    }
+
+    fn upsert(
+        &self,
+        table: &str,
+        id: &impl StrictEncode,
+        data: &impl StrictEncode,
+    ) -> Result<(), SqlError> {
+        self.execute(
+            &format!("INSERT OR REPLACE INTO {table} (id, data) VALUES (?1, ?2)"),
+            params![encode(id)?, encode(data)?],
+        )?;
+        Ok(())
+    }
+}
+
+//////////
+// STASH
+//////////
+
+/// Stash kept in SQLite database.
+#[derive(Debug)]
+pub struct SqlStash {
+    db: SqlDb,
+    schemata: BTreeMap<SchemaId, SchemaIfaces>,
+    ifaces: BTreeMap<IfaceId, Iface>,
+    geneses: BTreeMap<ContractId, OnceLock<Genesis>>,
+    suppl: BTreeMap<ContractId, BTreeSet<ContractSuppl>>,
+    bundles: BTreeMap<BundleId, OnceLock<TransitionBundle>>,
+    extensions: BTreeMap<OpId, OnceLock<Extension>>,
+    witnesses: BTreeMap<XWitnessId, OnceLock<SealWitness>>,
+    attachments: BTreeMap<AttachId, OnceLock<MediumBlob>>,
+    secret_seals: BTreeMap<XChain<GraphSeal>, SecretSealInfo>,
+    type_system: TypeSystem,
+    libs: BTreeMap<LibId, Lib>,
+    sigs: BTreeMap<ContentId, ContentSigs>,
+    journal: Journal<StashUndo>,
+}
+
+/// Previous version of cached stash data changed by a transaction.
+///
+/// Entries which were not loaded from the database are kept not loaded, and
+/// after the rollback they are read from the restored database rows.
+#[derive(Clone, Debug)]
+enum StashUndo {
+    Schema(SchemaId, Option<SchemaIfaces>),
+    Iface(IfaceId, Option<Iface>),
+    Genesis(ContractId, Option<OnceLock<Genesis>>),
+    Suppl(ContractId, Option<BTreeSet<ContractSuppl>>),
+    Bundle(BundleId, Option<OnceLock<TransitionBundle>>),
+    Extension(OpId, Option<OnceLock<Extension>>),
+    Witness(XWitnessId, Option<OnceLock<SealWitness>>),
+    Attachment(AttachId, Option<OnceLock<MediumBlob>>),
+    SecretSeal(XChain<GraphSeal>, Option<SecretSealInfo>),
+    /// Types added by the transaction.
+    Types(Vec<SemId>),
+    Lib(LibId, Option<Lib>),
+    Sigs(ContentId, Option<ContentSigs>),
+}
+
+/// Restores previous value of a cache entry.
+fn restore<K: Ord, V>(cache: &mut BTreeMap<K, V>, key: K, prev: Option<V>) {
+    match prev {
+        Some(val) => cache.insert(key, val),
+        None => cache.remove(&key),
+    };
+}
+
+impl SqlStash {
+    /// Loads stash data from the database.
+    ///
+    /// Geneses, bundles, extensions, witnesses and attachments are read later,
+    /// when accessed.
+    pub fn load(db: SqlDb) -> Result<Self, SqlError> {
+        let mut suppl = BTreeMap::<ContractId, BTreeSet<ContractSuppl>>::new();
+        for data in db.rows("SELECT data FROM suppl", [])? {
+            let item: ContractSuppl = decode(&data)?;
+            suppl.entry(item.contract_id).or_default().insert(item);
+        }
+        // Seals stored before the information records were introduced don't
+        // have them
+        let mut info: BTreeMap<XChain<GraphSeal>, SecretSealInfo> =
+            db.load_map("secret_seal_info")?;
+        let secret_seals = db
+            .rows("SELECT seal FROM secret_seals", [])?
+            .into_iter()
+            .map(|data| {
+                let seal = decode(&data)?;
+                Ok((seal, info.remove(&seal).unwrap_or_default()))
+            })
+            .collect::<Result<_, SqlError>>()?;
+        let types = db.load_map::<SemId, Ty<SemId>>("types")?;
+        let type_system = TypeSystem::from_inner(Confined::try_from(types)?);
+
+        Ok(SqlStash {
+            schemata: db.load_map("schemata")?,
+            ifaces: db.load_map("ifaces")?,
+            geneses: db.load_ids("geneses", "id")?,
+            suppl,
+            bundles: db.load_ids("bundles", "id")?,
+            extensions: db.load_ids("extensions", "id")?,
+            witnesses: db.load_ids("witnesses", "id")?,
+            attachments: db.load_ids("attachments", "id")?,
+            secret_seals,
+            type_system,
+            libs: db.load_map("libs")?,
+            sigs: db.load_map("sigs")?,
+            journal: default!(),
+            db,
+        })
+    }
+}
+
+impl SqlStash {
+    fn cached<'c, K: StrictEncode + Ord, V: StrictDecode>(
+        &'c self,
+        table: &str,
+        cache: &'c BTreeMap<K, OnceLock<V>>,
+        id: K,
+        absent: StashInconsistency,
+    ) -> Result<&'c V, StashProviderError<SqlError>> {
+        let cached = cache.get(&id).ok_or(absent)?;
+        self.db
+            .load_cached(table, "id", &id, cached)
+            .map_err(StashProviderError::Connectivity)
+    }
+}
+
+impl StashProvider for SqlStash {}
+
+impl StashReadProvider for SqlStash {
+    type Error = SqlError;
+
+    fn type_system(&self) -> Result<Cow<TypeSystem>, Self::Error> {
+        Ok(Cow::Borrowed(&self.type_system))
+    }
+
+    fn lib_ids(&self) -> Result<impl Iterator<Item = LibId>, Self::Error> {
+        Ok(self.libs.keys().copied())
+    }
+
+    fn lib(&self, id: LibId) -> Result<&Lib, StashProviderError<Self::Error>> {
+        self.libs
+            .get(&id)
+            .ok_or_else(|| StashInconsistency::LibAbsent(id).into())
+    }
+
+    fn ifaces(&self) -> Result<impl Iterator<Item = (IfaceId, TypeName)>, Self::Error> {
+        Ok(self
+            .ifaces
+            .iter()
+            .map(|(id, iface)| (*id, iface.name.clone())))
+    }
+
+    fn iface(&self, iface: impl Into<IfaceRef>) -> Result<&Iface, StashProviderError<Self::Error>> {
+        let iref = iface.into();
+        match iref {
+            IfaceRef::Name(ref name) => self.ifaces.values().find(|iface| &iface.name == name),
+            IfaceRef::Id(ref id) => self.ifaces.get(id),
+        }
+        .ok_or_else(|| StashInconsistency::IfaceAbsent(iref).into())
+    }
+
+    fn schemata(&self) -> Result<impl Iterator<Item = &SchemaIfaces>, Self::Error> {
+        Ok(self.schemata.values())
+    }
+
+    fn schema(
+        &self,
+        schema_id: SchemaId,
+    ) -> Result<&SchemaIfaces, StashProviderError<Self::Error>> {
+        self.schemata
+            .get(&schema_id)
+            .ok_or_else(|| StashInconsistency::SchemaAbsent(schema_id).into())
+    }
+
+    fn contract_ids(&self) -> Result<impl Iterator<Item = ContractId>, Self::Error> {
+        Ok(self.geneses.keys().copied())
+    }
+
+    fn contract_ids_by_iface(
+        &self,
+        iface: impl Into<IfaceRef>,
+    ) -> Result<impl Iterator<Item = ContractId>, StashProviderError<Self::Error>> {
+        let iface = self.iface(iface)?;
+        let iface_id = iface.iface_id();
+        let schemata = self
+            .schemata
+            .iter()
+            .filter(|(_, iface)| iface.iimpls.contains_key(&iface_id))
+            .map(|(schema_id, _)| schema_id)
+            .collect::<BTreeSet<_>>();
+        let mut contract_ids = vec![];
+        for (contract_id, cached) in &self.geneses {
+            let genesis = self
+                .db
+                .load_cached("geneses", "id", contract_id, cached)
+                .map_err(StashProviderError::Connectivity)?;
+            if schemata.contains(&genesis.schema_id) {
+                contract_ids.push(*contract_id);
+            }
+        }
+        Ok(contract_ids.into_iter())
+    }
+
+    fn contract_supplements(
+        &self,
+        contract_id: ContractId,
+    ) -> Result<impl Iterator<Item = ContractSuppl>, Self::Error> {
+        Ok(self
+            .suppl
+            .get(&contract_id)
+            .cloned()
+            .unwrap_or_default()
+            .into_iter())
+    }
+
+    fn suppl_contract_ids(&self) -> Result<impl Iterator<Item = ContractId>, Self::Error> {
+        Ok(self.suppl.keys().copied())
+    }
+
+    fn genesis(
+        &self,
+        contract_id: ContractId,
+    ) -> Result<&Genesis, StashProviderError<Self::Error>> {
+        self.cached(
+            "geneses",
+            &self.geneses,
+            contract_id,
+            StashInconsistency::ContractAbsent(contract_id),
+        )
+    }
+
+    fn witness_ids(&self) -> Result<impl Iterator<Item = XWitnessId>, Self::Error> {
+        Ok(self.witnesses.keys().copied())
+    }
+
+    fn bundle_ids(&self) -> Result<impl Iterator<Item = BundleId>, Self::Error> {
+        Ok(self.bundles.keys().copied())
+    }
+
+    fn bundle(
+        &self,
+        bundle_id: BundleId,
+    ) -> Result<&TransitionBundle, StashProviderError<Self::Error>> {
+        self.cached(
+            "bundles",
+            &self.bundles,
+            bundle_id,
+            StashInconsistency::BundleAbsent(bundle_id),
+        )
+    }
+
+    fn extension_ids(&self) -> Result<impl Iterator<Item = OpId>, Self::Error> {
+        Ok(self.extensions.keys().copied())
+    }
+
+    fn extension(&self, op_id: OpId) -> Result<&Extension, StashProviderError<Self::Error>> {
+        self.cached(
+            "extensions",
+            &self.extensions,
+            op_id,
+            StashInconsistency::OperationAbsent(op_id),
+        )
+    }
+
+    fn witness(
+        &self,
+        witness_id: XWitnessId,
+    ) -> Result<&SealWitness, StashProviderError<Self::Error>> {
+        self.cached(
+            "witnesses",
+            &self.witnesses,
+            witness_id,
+            StashInconsistency::WitnessAbsent(witness_id),
+        )
+    }
+
+    fn attachment_ids(&self) -> Result<impl Iterator<Item = AttachId>, Self::Error> {
+        Ok(self.attachments.keys().copied())
+    }
+
+    fn attachment(&self, id: AttachId) -> Result<&MediumBlob, StashProviderError<Self::Error>> {
+        self.cached("attachments", &self.attachments, id, StashInconsistency::AttachmentAbsent(id))
+    }
+
+    fn sigs(&self) -> Result<impl Iterator<Item = (ContentId, &ContentSigs)>, Self::Error> {
+        Ok(self.sigs.iter().map(|(id, sigs)| (*id, sigs)))
+    }
+
+    fn taprets(&self) -> Result<impl Iterator<Item = (XWitnessId, TapretCommitment)>, Self::Error> {
+        let mut taprets = vec![];
+        for (witness_id, cached) in &self.witnesses {
+            let witness = self.db.load_cached("witnesses", "id", witness_id, cached)?;
+            if let AnchorSet::Tapret(anchor) |
+            AnchorSet::Double {
+                tapret: anchor,
+                opret: _,
+            } = &witness.anchors
+            {
+                taprets.push((*witness_id, TapretCommitment {
+                    mpc: anchor.mpc_proof.commit_id(),
+                    nonce: anchor.dbc_proof.path_proof.nonce(),
+                }));
+            }
+        }
+        Ok(taprets.into_iter())
+    }
+
+    fn seal_secret(
+        &self,
+        secret: XChain<SecretSeal>,
+    ) -> Result<Option<XChain<GraphSeal>>, Self::Error> {
+        Ok(self
+            .secret_seals
+            .keys()
+            .find(|s| s.conceal() == secret)
+            .copied())
+    }
+
+    fn secret_seals(&self) -> Result<impl Iterator<Item = XChain<GraphSeal>>, Self::Error> {
+        Ok(self.secret_seals.keys().copied())
+    }
+
+    fn secret_seal_records(
+        &self,
+    ) -> Result<impl Iterator<Item = (XChain<GraphSeal>, SecretSealInfo)>, Self::Error> {
+        Ok(self
+            .secret_seals
+            .iter()
+            .map(|(seal, info)| (*seal, info.clone())))
+    }
+}
+
+impl StashWriteProvider for SqlStash {
+    type Error = SqlError;
+
+    // Database changes are reverted with the shared transaction, and the cache
+    // with the undo journal
+    fn begin_transaction(&mut self) -> Result<(), Self::Error> {
+        self.db.begin()?;
+        self.journal.begin();
+        Ok(())
+    }
+
+    fn prepare_transaction(&mut self) -> Result<(), Self::Error> { self.db.prepare() }
+
+    fn commit_transaction(&mut self) -> Result<(), Self::Error> {
+        self.journal.commit();
+        self.db.commit()
+    }
+
+    fn rollback_transaction(&mut self) -> Result<(), Self::Error> {
+        let active = self.journal.is_active();
+        for undo in self.journal.rollback() {
+            match undo {
+                StashUndo::Schema(id, prev) => restore(&mut self.schemata, id, prev),
+                StashUndo::Iface(id, prev) => restore(&mut self.ifaces, id, prev),
+                StashUndo::Genesis(id, prev) => restore(&mut self.geneses, id, prev),
+                StashUndo::Suppl(id, prev) => restore(&mut self.suppl, id, prev),
+                StashUndo::Bundle(id, prev) => restore(&mut self.bundles, id, prev),
+                StashUndo::Extension(id, prev) => restore(&mut self.extensions, id, prev),
+                StashUndo::Witness(id, prev) => restore(&mut self.witnesses, id, prev),
+                StashUndo::Attachment(id, prev) => restore(&mut self.attachments, id, prev),
+                StashUndo::SecretSeal(seal, prev) => restore(&mut self.secret_seals, seal, prev),
+                StashUndo::Types(ids) => {
+                    let types = self
+                        .type_system
+                        .iter()
+                        .filter(|(id, _)| !ids.contains(*id))
+                        .map(|(id, ty)| (*id, ty.clone()));
+                    self.type_system = TypeSystem::from_inner(Confined::try_from_iter(types)?);
+                }
+                StashUndo::Lib(id, prev) => restore(&mut self.libs, id, prev),
+                StashUndo::Sigs(id, prev) => restore(&mut self.sigs, id, prev),
+            }
+        }
+        if active {
+            self.db.rollback()?;
+        }
+        Ok(())
+    }
+
+    fn replace_schema(&mut self, schema: Schema) -> Result<bool, SqlError> {
+        let schema_id = schema.schema_id();
+        if !self.schemata.contains_key(&schema_id) {
+            let schema_ifaces = SchemaIfaces::new(schema);
+            self.db.upsert("schemata", &schema_id, &schema_ifaces)?;
+            self.schemata.insert(schema_id, schema_ifaces);
+            self.journal.record(|| StashUndo::Schema(schema_id, None));
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    fn replace_iface(&mut self, iface: Iface) -> Result<bool, SqlError> {
+        let iface_id = iface.iface_id();
+        if !self.ifaces.contains_key(&iface_id) {
+            self.db.upsert("ifaces", &iface_id, &iface)?;
+            self.ifaces.insert(iface_id, iface);
+            self.journal.record(|| StashUndo::Iface(iface_id, None));
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    fn replace_iimpl(&mut self, iimpl: IfaceImpl) -> Result<bool, SqlError> {
+        let schema_id = iimpl.schema_id;
+        let mut schema_ifaces = self
+            .schemata
+            .get(&schema_id)
+            .expect("unknown schema")
+            .clone();
+        let present = schema_ifaces.iimpls.contains_key(&iimpl.iface_id);
+        schema_ifaces.iimpls.insert(iimpl.iface_id, iimpl)?;
+        self.db.upsert("schemata", &schema_id, &schema_ifaces)?;
+        let prev = self.schemata.insert(schema_id, schema_ifaces);
+        self.journal.record(|| StashUndo::Schema(schema_id, prev));
+        Ok(!present)
+    }
+
+    fn add_suppl(&mut self, suppl: ContractSuppl) -> Result<(), SqlError> {
+        self.db.execute(
+            "INSERT OR REPLACE INTO suppl (contract_id, data) VALUES (?1, ?2)",
+            params![encode(&suppl.contract_id)?, encode(&suppl)?],
+        )?;
+        let contract_id = suppl.contract_id;
+        self.journal
+            .record(|| StashUndo::Suppl(contract_id, self.suppl.get(&contract_id).cloned()));
+        self.suppl.entry(contract_id).or_default().insert(suppl);
+        Ok(())
+    }
+
+    fn replace_genesis(&mut self, genesis: Genesis) -> Result<bool, SqlError> {
+        let contract_id = genesis.contract_id();
+        self.db.upsert("geneses", &contract_id, &genesis)?;
+        let prev = self.geneses.insert(contract_id, OnceLock::from(genesis));
+        let present = prev.is_some();
+        self.journal
+            .record(|| StashUndo::Genesis(contract_id, prev));
+        Ok(!present)
+    }
+
+    fn replace_extension(&mut self, extension: Extension) -> Result<bool, SqlError> {
+        let opid = extension.id();
+        self.db.upsert("extensions", &opid, &extension)?;
+        let prev = self.extensions.insert(opid, OnceLock::from(extension));
+        let present = prev.is_some();
+        self.journal.record(|| StashUndo::Extension(opid, prev));
+        Ok(!present)
+    }
+
+    fn replace_bundle(&mut self, bundle: TransitionBundle) -> Result<bool, SqlError> {
+        let bundle_id = bundle.bundle_id();
+        self.db.upsert("bundles", &bundle_id, &bundle)?;
+        let prev = self.bundles.insert(bundle_id, OnceLock::from(bundle));
+        let present = prev.is_some();
+        self.journal.record(|| StashUndo::Bundle(bundle_id, prev));
+        Ok(!present)
+    }
+
+    fn replace_witness(&mut self, witness: SealWitness) -> Result<bool, SqlError> {
+        let witness_id = witness.witness_id();
+        self.db.upsert("witnesses", &witness_id, &witness)?;
+        let prev = self.witnesses.insert(witness_id, OnceLock::from(witness));
+        let present = prev.is_some();
+        self.journal.record(|| StashUndo::Witness(witness_id, prev));
+        Ok(!present)
+    }
+
+    fn replace_attachment(&mut self, id: AttachId, attach: MediumBlob) -> Result<bool, SqlError> {
+        self.db.upsert("attachments", &id, &attach)?;
+        let prev = self.attachments.insert(id, OnceLock::from(attach));
+        let present = prev.is_some();
+        self.journal.record(|| StashUndo::Attachment(id, prev));
+        Ok(!present)
+    }
+
+    // Each type is kept in its own row, so only the types which are not known
+    // yet are written
+    fn consume_types(&mut self, types: TypeSystem) -> Result<(), SqlError> {
+        let mut added = BTreeMap::new();
+        for (sem_id, ty) in types.iter() {
+            if !self.type_system.contains_key(sem_id) {
+                added.insert(*sem_id, ty.clone());
+            }
+        }
+        if added.is_empty() {
+            return Ok(());
+        }
+        for (sem_id, ty) in &added {
+            self.db.upsert("types", sem_id, ty)?;
+        }
+        let ids = added.keys().copied().collect::<Vec<_>>();
+        self.type_system
+            .extend(TypeSystem::from_inner(Confined::try_from(added)?))?;
+        self.journal.record(|| StashUndo::Types(ids));
+        Ok(())
+    }
+
+    fn replace_lib(&mut self, lib: Lib) -> Result<bool, SqlError> {
+        let lib_id = lib.id();
+        self.db.upsert("libs", &lib_id, &lib)?;
+        let prev = self.libs.insert(lib_id, lib);
+        let present = prev.is_some();
+        self.journal.record(|| StashUndo::Lib(lib_id, prev));
+        Ok(!present)
+    }
+
+    fn import_sigs<I>(&mut self, content_id: ContentId, sigs: I) -> Result<(), SqlError>
+    where
+        I: IntoIterator<Item = (Identity, SigBlob)>,
+        I::IntoIter: ExactSizeIterator<Item = (Identity, SigBlob)>,
+    {
+        let sigs = sigs.into_iter();
+        if sigs.len() > 0 {
+            let content_sigs = if let Some(prev_sigs) = self.sigs.get(&content_id) {
+                let mut prev_sigs = prev_sigs.clone();
+                prev_sigs.extend(sigs)?;
+                prev_sigs
+            } else {
+                ContentSigs::from(Confined::try_from_iter(sigs)?)
+            };
+            self.db.upsert("sigs", &content_id, &content_sigs)?;
+            let prev = self.sigs.insert(content_id, content_sigs);
+            self.journal.record(|| StashUndo::Sigs(content_id, prev));
+        }
+        Ok(())
+    }
+
+    fn add_secret_seal(&mut self, seal: XChain<GraphSeal>) -> Result<bool, SqlError> {
+        self.add_secret_seal_with(seal, SecretSealInfo::default())
+    }
+
+    fn add_secret_seal_with(
+        &mut self,
+        seal: XChain<GraphSeal>,
+        info: SecretSealInfo,
+    ) -> Result<bool, SqlError> {
+        if self.secret_seals.contains_key(&seal) {
+            return Ok(false);
+        }
+        self.db.execute(
+            "INSERT OR REPLACE INTO secret_seals (seal) VALUES (?1)",
+            params![encode(&seal)?],
+        )?;
+        self.db.upsert("secret_seal_info", &seal, &info)?;
+        self.secret_seals.insert(seal, info);
+        self.journal.record(|| StashUndo::SecretSeal(seal, None));
+        Ok(true)
+    }
+
+    fn remove_secret_seal(&mut self, seal: XChain<GraphSeal>) -> Result<bool, SqlError> {
+        self.db
+            .execute("DELETE FROM secret_seals WHERE seal = ?1", [encode(&seal)?])?;
+        self.db.delete("secret_seal_info", &seal)?;
+        let prev = self.secret_seals.remove(&seal);
+        let present = prev.is_some();
+        self.journal.record(|| StashUndo::SecretSeal(seal, prev));
+        Ok(present)
+    }
+
+    fn remove_genesis(&mut self, contract_id: ContractId) -> Result<bool, SqlError> {
+        self.db.delete("geneses", &contract_id)?;
+        let prev = self.geneses.remove(&contract_id);
+        let present = prev.is_some();
+        self.journal
+            .record(|| StashUndo::Genesis(contract_id, prev));
+        Ok(present)
+    }
+
+    fn remove_suppl(&mut self, contract_id: ContractId) -> Result<(), SqlError> {
+        self.db
+            .execute("DELETE FROM suppl WHERE contract_id = ?1", [encode(&contract_id)?])?;
+        let prev = self.suppl.remove(&contract_id);
+        self.journal.record(|| StashUndo::Suppl(contract_id, prev));
+        Ok(())
+    }
+
+    fn remove_extension(&mut self, opid: OpId) -> Result<bool, SqlError> {
+        self.db.delete("extensions", &opid)?;
+        let prev = self.extensions.remove(&opid);
+        let present = prev.is_some();
+        self.journal.record(|| StashUndo::Extension(opid, prev));
+        Ok(present)
+    }
+
+    fn remove_bundle(&mut self, bundle_id: BundleId) -> Result<bool, SqlError> {
+        self.db.delete("bundles", &bundle_id)?;
+        let prev = self.bundles.remove(&bundle_id);
+        let present = prev.is_some();
+        self.journal.record(|| StashUndo::Bundle(bundle_id, prev));
+        Ok(present)
+    }
+
+    fn remove_witness(&mut self, witness_id: XWitnessId) -> Result<bool, SqlError> {
+        self.db.delete("witnesses", &witness_id)?;
+        let prev = self.witnesses.remove(&witness_id);
+        let present = prev.is_some();
+        self.journal.record(|| StashUndo::Witness(witness_id, prev));
+        Ok(present)
+    }
+}
+
+//////////
+// STATE
+//////////
+
+/// Contract state kept in SQLite database, one row per contract.
+#[derive(Debug)]
+pub struct SqlState {
+    db: SqlDb,
+    history: BTreeMap<ContractId, OnceLock<ContractHistory>>,
+    journal: Journal<StateUndo>,
+}
+
+/// Previous version of cached contract state changed by a transaction.
+#[derive(Clone, Debug)]
+enum StateUndo {
+    History(ContractId, Option<OnceLock<ContractHistory>>),
+    All(BTreeMap<ContractId, OnceLock<ContractHistory>>),
+}
+
+impl SqlState {
+    /// Loads identifiers of the contracts having state in the database; the
+    /// state of each contract is read when it is accessed for the first time.
+    pub fn load(db: SqlDb) -> Result<Self, SqlError> {
+        Ok(SqlState {
+            history: db.load_ids("history", "contract_id")?,
+            db,
+            journal: default!(),
+        })
+    }
+
+    fn save(&self, contract_id: ContractId, history: &ContractHistory) -> Result<(), SqlError> {
+        self.db.execute(
+            "INSERT OR REPLACE INTO history (contract_id, data) VALUES (?1, ?2)",
+            params![encode(&contract_id)?, encode(history)?],
+        )?;
+        Ok(())
+    }
+}
+
+impl StateProvider for SqlState {}
+
+impl StateReadProvider for SqlState {
+    type Error = SqlError;
+
+    fn contract_state(
+        &self,
+        contract_id: ContractId,
+    ) -> Result<Option<&ContractHistory>, Self::Error> {
+        self.history
+            .get(&contract_id)
+            .map(|cached| {
+                self.db
+                    .load_cached("history", "contract_id", &contract_id, cached)
+            })
+            .transpose()
+    }
+}
+
+impl StateWriteProvider for SqlState {
+    type Error = SqlError;
+
+    // Database changes are reverted with the shared transaction, and the cache
+    // with the undo journal
+    fn begin_transaction(&mut self) -> Result<(), Self::Error> {
+        self.db.begin()?;
+        self.journal.begin();
+        Ok(())
+    }
+
+    fn prepare_transaction(&mut self) -> Result<(), Self::Error> { self.db.prepare() }
+
+    fn commit_transaction(&mut self) -> Result<(), Self::Error> {
+        self.journal.commit();
+        self.db.commit()
+    }
+
+    fn rollback_transaction(&mut self) -> Result<(), Self::Error> {
+        let active = self.journal.is_active();
+        for undo in self.journal.rollback() {
+            match undo {
+                StateUndo::History(id, prev) => restore(&mut self.history, id, prev),
+                StateUndo::All(prev) => self.history = prev,
+            }
+        }
+        if active {
+            self.db.rollback()?;
+        }
+        Ok(())
+    }
+
+    fn create_or_update_state<R: ResolveHeight>(
+        &mut self,
+        contract_id: ContractId,
+        updater: impl FnOnce(Option<ContractHistory>) -> Result<ContractHistory, R::Error>,
+    ) -> Result<(), StateUpdateError<Self::Error>> {
+        let state = self.contract_state(contract_id)?;
+        let updated =
+            updater(state.cloned()).map_err(|e| StateUpdateError::Resolver(e.to_string()))?;
+        self.save(contract_id, &updated)?;
+        let prev = self.history.insert(contract_id, OnceLock::from(updated));
+        self.journal
+            .record(|| StateUndo::History(contract_id, prev));
+        Ok(())
+    }
+
+    fn update_state<R: ResolveHeight>(
+        &mut self,
+        contract_id: ContractId,
+        mut updater: impl FnMut(&mut ContractHistory) -> Result<(), R::Error>,
+    ) -> Result<(), StateUpdateError<Self::Error>> {
+        let mut state = self
+            .contract_state(contract_id)?
+            .ok_or(StateUpdateError::UnknownContract(contract_id))?
+            .clone();
+        updater(&mut state).map_err(|e| StateUpdateError::Resolver(e.to_string()))?;
+        self.save(contract_id, &state)?;
+        let prev = self.history.insert(contract_id, OnceLock::from(state));
+        self.journal
+            .record(|| StateUndo::History(contract_id, prev));
+        Ok(())
+    }
+
+    fn remove_state(&mut self, contract_id: ContractId) -> Result<bool, Self::Error> {
+        self.db
+            .execute("DELETE FROM history WHERE contract_id = ?1", [encode(&contract_id)?])?;
+        let prev = self.history.remove(&contract_id);
+        let present = prev.is_some();
+        self.journal
+            .record(|| StateUndo::History(contract_id, prev));
+        Ok(present)
+    }
+
+    fn clear_state(&mut self) -> Result<(), Self::Error> {
+        self.db.execute("DELETE FROM history", [])?;
+        let prev = mem::take(&mut self.history);
+        self.journal.record(|| StateUndo::All(prev));
+        Ok(())
+    }
+}
+
+//////////
+// INDEX
+//////////
+
+/// Index over stash data kept in SQLite database and queried directly with
+/// SQL.
+#[derive(Debug)]
+pub struct SqlIndex {
+    db: SqlDb,
+}
+
+impl SqlIndex {
+    pub fn with(db: SqlDb) -> Self { SqlIndex { db } }
+
+    fn is_contract_known(&self, contract_id: ContractId) -> Result<bool, SqlError> {
+        Ok(self
+            .db
+            .row("SELECT contract_id FROM idx_contracts WHERE contract_id = ?1", [encode(
+                &contract_id,
+            )?])?
+            .is_some())
+    }
+
+    fn index_output(
+        &self,
+        contract_id: ContractId,
+        output: XOutputSeal,
+        opout: Opout,
+    ) -> Result<(), SqlError> {
+        self.db.execute(
+            "INSERT OR IGNORE INTO idx_outputs (contract_id, output, opout) VALUES (?1, ?2, ?3)",
+            params![encode(&contract_id)?, encode(&output)?, encode(&opout)?],
+        )?;
+        Ok(())
+    }
+
+    fn index_terminal(&self, seal: XChain<SecretSeal>, opout: Opout) -> Result<(), SqlError> {
+        self.db.execute(
+            "INSERT OR REPLACE INTO idx_terminals (seal, opout) VALUES (?1, ?2)",
+            params![encode(&seal)?, encode(&opout)?],
+        )?;
+        Ok(())
+    }
+}
+
+impl IndexProvider for SqlIndex {}
+
+impl IndexReadProvider for SqlIndex {
+    type Error = SqlError;
+
+    fn contracts_assigning(
+        &self,
+        outputs: BTreeSet<XOutputSeal>,
+    ) -> Result<impl Iterator<Item = ContractId> + '_, Self::Error> {
+        let mut contracts = BTreeSet::new();
+        for output in outputs {
+            for data in self.db.rows(
+                "SELECT DISTINCT contract_id FROM idx_outputs WHERE output = ?1",
+                [encode(&output)?],
+            )? {
+                contracts.insert(decode::<ContractId>(&data)?);
+            }
+        }
+        Ok(contracts.into_iter())
+    }
+
+    fn public_opouts(
+        &self,
+        contract_id: ContractId,
+    ) -> Result<BTreeSet<Opout>, IndexReadError<Self::Error>> {
+        if !self.is_contract_known(contract_id)? {
+            return Err(IndexInconsistency::ContractAbsent(contract_id).into());
+        }
+        let opouts = self
+            .db
+            .rows("SELECT opout FROM idx_public WHERE contract_id = ?1", [encode(&contract_id)?])?
+            .into_iter()
+            .map(|data| decode(&data))
+            .collect::<Result<_, _>>()?;
+        Ok(opouts)
+    }
+
+    fn opouts_by_outputs(
+        &self,
+        contract_id: ContractId,
+        outputs: impl IntoIterator<Item = impl Into<XOutputSeal>>,
+    ) -> Result<BTreeSet<Opout>, IndexReadError<Self::Error>> {
+        if !self.is_contract_known(contract_id)? {
+            return Err(IndexInconsistency::ContractAbsent(contract_id).into());
+        }
+        let contract = encode(&contract_id)?;
+        let mut opouts = BTreeSet::new();
+        for output in outputs.into_iter().map(|o| o.into()) {
+            let rows = self.db.rows(
+                "SELECT opout FROM idx_outputs WHERE contract_id = ?1 AND output = ?2",
+                params![contract, encode(&output)?],
+            )?;
+            if rows.is_empty() {
+                return Err(IndexInconsistency::OutpointUnknown(output, contract_id).into());
+            }
+            for data in rows {
+                opouts.insert(decode(&data)?);
+            }
+        }
+        Ok(opouts)
+    }
+
+    fn opouts_by_terminals(
+        &self,
+        terminals: impl IntoIterator<Item = XChain<SecretSeal>>,
+    ) -> Result<BTreeSet<Opout>, Self::Error> {
+        let mut opouts = BTreeSet::new();
+        for seal in terminals {
+            if let Some(data) = self
+                .db
+                .row("SELECT opout FROM idx_terminals WHERE seal = ?1", [encode(&seal)?])?
+            {
+                opouts.insert(decode(&data)?);
+            }
+        }
+        Ok(opouts)
+    }
+
+    fn bundle_id_for_op(&self, opid: OpId) -> Result<BundleId, IndexReadError<Self::Error>> {
+        let data = self
+            .db
+            .row("SELECT bundle_id FROM idx_ops WHERE opid = ?1", [encode(&opid)?])?
+            .ok_or(IndexInconsistency::BundleAbsent(opid))?;
+        Ok(decode(&data)?)
+    }
+
+    fn bundle_info(
+        &self,
+        bundle_id: BundleId,
+    ) -> Result<(XWitnessId, ContractId), IndexReadError<Self::Error>> {
+        let (witness_id, contract_id) = self
+            .db
+            .pairs(
+                "SELECT witness_id, contract_id FROM idx_bundles WHERE bundle_id = ?1",
+                [encode(&bundle_id)?],
+            )?
+            .pop()
+            .ok_or(IndexInconsistency::BundleWitnessUnknown(bundle_id))?;
+        Ok((decode(&witness_id)?, decode(&contract_id)?))
+    }
+
+    fn spenders(&self, opout: Opout) -> Result<BTreeSet<Spender>, IndexReadError<Self::Error>> {
+        if self.db.is_reindex_pending()? {
+            return Err(IndexInconsistency::SpendersNotIndexed.into());
+        }
+        let spenders = self
+            .db
+            .rows("SELECT spender FROM idx_spent WHERE opout = ?1", [encode(&opout)?])?
+            .into_iter()
+            .map(|data| decode(&data))
+            .collect::<Result<_, _>>()?;
+        Ok(spenders)
+    }
+
+    fn operations(&self) -> Result<impl Iterator<Item = (OpId, BundleId)> + '_, Self::Error> {
+        let ops = self
+            .db
+            .pairs("SELECT opid, bundle_id FROM idx_ops", [])?
+            .into_iter()
+            .map(|(opid, bundle_id)| Ok((decode(&opid)?, decode(&bundle_id)?)))
+            .collect::<Result<Vec<_>, SqlError>>()?;
+        Ok(ops.into_iter())
+    }
+
+    fn bundle_ids(&self) -> Result<impl Iterator<Item = BundleId> + '_, Self::Error> {
+        let bundles = self
+            .db
+            .rows("SELECT bundle_id FROM idx_bundles", [])?
+            .into_iter()
+            .map(|data| decode(&data))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(bundles.into_iter())
+    }
+}
+
+impl IndexWriteProvider for SqlIndex {
+    type Error = SqlError;
+
+    // Index has no cache, so it is reverted with the shared transaction only
+    fn begin_transaction(&mut self) -> Result<(), Self::Error> { self.db.begin() }
+
+    fn prepare_transaction(&mut self) -> Result<(), Self::Error> { self.db.prepare() }
+
+    fn commit_transaction(&mut self) -> Result<(), Self::Error> { self.db.commit() }
+
+    fn rollback_transaction(&mut self) -> Result<(), Self::Error> { self.db.rollback() }
+
+    fn register_contract(&mut self, contract_id: ContractId) -> Result<bool, Self::Error> {
+        let count = self.db.execute(
+            "INSERT OR IGNORE INTO idx_contracts (contract_id) VALUES (?1)",
+            [encode(&contract_id)?],
+        )?;
+        Ok(count > 0)
+    }
+
+    fn register_bundle(
+        &mut self,
+        bundle_id: BundleId,
+        witness_id: XWitnessId,
+        contract_id: ContractId,
+    ) -> Result<bool, IndexWriteError<Self::Error>> {
+        let present = self.db.pairs(
+            "SELECT witness_id, contract_id FROM idx_bundles WHERE bundle_id = ?1",
+            [encode(&bundle_id)?],
+        )?;
+        if let Some((alt_witness, alt_contract)) = present.first() {
+            let alt_witness: XWitnessId = decode(alt_witness)?;
+            let alt_contract: ContractId = decode(alt_contract)?;
+            if alt_witness != witness_id {
+                return Err(IndexInconsistency::DistinctBundleWitness {
+                    bundle_id,
+                    present: alt_witness,
+                    expected: witness_id,
+                }
+                .into());
+            }
+            if alt_contract != contract_id {
+                return Err(IndexInconsistency::DistinctBundleContract {
+                    bundle_id,
+                    present: alt_contract,
+                    expected: contract_id,
+                }
+                .into());
+            }
+            return Ok(false);
+        }
+        self.db.execute(
+            "INSERT INTO idx_bundles (bundle_id, witness_id, contract_id) VALUES (?1, ?2, ?3)",
+            params![encode(&bundle_id)?, encode(&witness_id)?, encode(&contract_id)?],
+        )?;
+        Ok(true)
+    }
+
+    fn register_operation(
+        &mut self,
+        opid: OpId,
+        bundle_id: BundleId,
+    ) -> Result<bool, IndexWriteError<Self::Error>> {
+        if let Some(alt) = self
+            .db
+            .row("SELECT bundle_id FROM idx_ops WHERE opid = ?1", [encode(&opid)?])?
+        {
+            let alt: BundleId = decode(&alt)?;
+            if alt != bundle_id {
+                return Err(IndexInconsistency::DistinctBundleOp {
+                    opid,
+                    present: alt,
+                    expected: bundle_id,
+                }
+                .into());
+            }
+            return Ok(false);
+        }
+        self.db.execute(
+            "INSERT INTO idx_ops (opid, bundle_id) VALUES (?1, ?2)",
+            params![encode(&opid)?, encode(&bundle_id)?],
+        )?;
+        Ok(true)
+    }
+
+    fn register_spender(
+        &mut self,
+        opout: Opout,
+        spender: Spender,
+    ) -> Result<bool, IndexWriteError<Self::Error>> {
+        let count = self.db.execute(
+            "INSERT OR IGNORE INTO idx_spent (opout, spender) VALUES (?1, ?2)",
+            params![encode(&opout)?, encode(&spender)?],
+        )?;
+        Ok(count > 0)
+    }
+
+    fn index_genesis_assignments<State: ExposedState>(
+        &mut self,
+        contract_id: ContractId,
+        vec: &[Assign<State, GenesisSeal>],
+        opid: OpId,
+        type_id: AssignmentType,
+    ) -> Result<(), IndexWriteError<Self::Error>> {
+        if !self.is_contract_known(contract_id)? {
+            return Err(IndexInconsistency::ContractAbsent(contract_id).into());
+        }
+
+        for (no, a) in vec.iter().enumerate() {
+            let opout = Opout::new(opid, type_id, no as u16);
+            if let Assign::ConfidentialState { seal, .. } | Assign::Revealed { seal, .. } = a {
+                let output = seal
+                    .to_output_seal()
+                    .expect("genesis seals always have outpoint");
+                self.index_output(contract_id, output, opout)?;
+            }
+            if let Assign::Confidential { seal, .. } | Assign::ConfidentialSeal { seal, .. } = a {
+                self.index_terminal(*seal, opout)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn index_transition_assignments<State: ExposedState>(
+        &mut self,
+        contract_id: ContractId,
+        vec: &[Assign<State, GraphSeal>],
+        opid: OpId,
+        type_id: AssignmentType,
+        witness_id: XWitnessId,
+    ) -> Result<(), IndexWriteError<Self::Error>> {
+        if !self.is_contract_known(contract_id)? {
+            return Err(IndexInconsistency::ContractAbsent(contract_id).into());
+        }
+
+        for (no, assign) in vec.iter().enumerate() {
+            let opout = Opout::new(opid, type_id, no as u16);
+            if let Assign::ConfidentialState { seal, .. } | Assign::Revealed { seal, .. } = assign {
+                let output = seal.try_to_output_seal(witness_id).unwrap_or_else(|_| {
+                    panic!(
+                        "chain mismatch between assignment vout seal ({}) and witness transaction \
+                         ({})",
+                        seal, witness_id
+                    )
+                });
+                self.index_output(contract_id, output, opout)?;
+            }
+            if let Assign::Confidential { seal, .. } | Assign::ConfidentialSeal { seal, .. } =
+                assign
+            {
+                self.index_terminal(*seal, opout)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn remove_contract(
+        &mut self,
+        contract_id: ContractId,
+        opids: &BTreeSet<OpId>,
+    ) -> Result<(), Self::Error> {
+        let contract = encode(&contract_id)?;
+        let bundles = self
+            .db
+            .rows("SELECT bundle_id FROM idx_bundles WHERE contract_id = ?1", [&contract])?;
+        for bundle in bundles {
+            self.db
+                .execute("DELETE FROM idx_ops WHERE bundle_id = ?1", [bundle])?;
+        }
+        for opid in opids {
+            self.db
+                .execute("DELETE FROM idx_ops WHERE opid = ?1", [encode(opid)?])?;
+        }
+        for (seal, opout) in self.db.pairs("SELECT seal, opout FROM idx_terminals", [])? {
+            let opout: Opout = decode(&opout)?;
+            if opids.contains(&opout.op) {
+                self.db
+                    .execute("DELETE FROM idx_terminals WHERE seal = ?1", [seal])?;
+            }
+        }
+        for data in self.db.rows("SELECT DISTINCT opout FROM idx_spent", [])? {
+            let opout: Opout = decode(&data)?;
+            if opids.contains(&opout.op) {
+                self.db
+                    .execute("DELETE FROM idx_spent WHERE opout = ?1", [data])?;
+            }
+        }
+        for table in ["idx_bundles", "idx_outputs", "idx_public", "idx_contracts"] {
+            self.db
+                .execute(&format!("DELETE FROM {table} WHERE contract_id = ?1"), [&contract])?;
+        }
+        Ok(())
+    }
+
+    fn clear_index(&mut self) -> Result<(), Self::Error> {
+        self.db.with(|conn| {
+            conn.execute_batch(
+                "DELETE FROM idx_contracts; DELETE FROM idx_bundles; DELETE FROM idx_ops;
+                 DELETE FROM idx_outputs; DELETE FROM idx_public; DELETE FROM idx_terminals;
+                 DELETE FROM idx_spent; DELETE FROM maintenance WHERE task = 'reindex';",
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use amplify::ByteArray;
+    use bp::seals::txout::CloseMethod;
+    use bp::Txid;
+
+    use super::*;
+    use crate::interface::TickerSuppl;
+    use crate::persistence::{ConsignError, StockError};
+    use crate::stl::StandardTypes;
+
+    fn seal(blinding: u64) -> XChain<GraphSeal> {
+        XChain::Bitcoin(GraphSeal::with_blinding(
+            CloseMethod::TapretFirst,
+            Txid::coinbase(),
+            1u32,
+            blinding,
+        ))
+    }
+
+    #[test]
+    fn round_trip() {
+        let db = SqlDb::open_in_memory().unwrap();
+        let contract_id = ContractId::from_byte_array([1u8; 32]);
+        let info = SecretSealInfo::new(1_700_000_000);
+
+        let mut stash = SqlStash::load(db.clone()).unwrap();
+        assert!(stash.add_secret_seal_with(seal(1), info.clone()).unwrap());
+        assert!(!stash.add_secret_seal_with(seal(1), info.clone()).unwrap());
+        let mut index = SqlIndex::with(db.clone());
+        assert!(index.register_contract(contract_id).unwrap());
+        assert!(!index.register_contract(contract_id).unwrap());
+
+        let stash = SqlStash::load(db.clone()).unwrap();
+        assert_eq!(stash.secret_seal_records().unwrap().collect::<Vec<_>>(), vec![(seal(1), info)]);
+        assert!(SqlIndex::with(db).is_contract_known(contract_id).unwrap());
+    }
+
+    #[test]
+    fn types() {
+        let db = SqlDb::open_in_memory().unwrap();
+        let types = StandardTypes::new().type_system();
+        let mut stash = SqlStash::load(db.clone()).unwrap();
+
+        stash.begin_transaction().unwrap();
+        stash.consume_types(types.clone()).unwrap();
+        stash.rollback_transaction().unwrap();
+        assert!(stash.type_system().unwrap().is_empty());
+        assert!(SqlStash::load(db.clone())
+            .unwrap()
+            .type_system()
+            .unwrap()
+            .is_empty());
+
+        stash.consume_types(types.clone()).unwrap();
+        stash.consume_types(types.clone()).unwrap();
+        let count: usize = db
+            .with(|conn| conn.query_row("SELECT COUNT(*) FROM types", [], |row| row.get(0)))
+            .unwrap();
+        assert_eq!(count, types.len());
+        assert_eq!(*SqlStash::load(db).unwrap().type_system().unwrap(), types);
+    }
+
+    #[test]
+    fn transaction_rollback() {
+        let db = SqlDb::open_in_memory().unwrap();
+        let contract_id = ContractId::from_byte_array([1u8; 32]);
+        let mut stash = SqlStash::load(db.clone()).unwrap();
+        let mut index = SqlIndex::with(db.clone());
+
+        stash.begin_transaction().unwrap();
+        index.begin_transaction().unwrap();
+        stash.add_secret_seal(seal(1)).unwrap();
+        index.register_contract(contract_id).unwrap();
+        index.prepare_transaction().unwrap();
+        stash.prepare_transaction().unwrap();
+        index.commit_transaction().unwrap();
+        stash.commit_transaction().unwrap();
+
+        stash.begin_transaction().unwrap();
+        index.begin_transaction().unwrap();
+        stash.add_secret_seal(seal(2)).unwrap();
+        stash.remove_secret_seal(seal(1)).unwrap();
+        index
+            .register_contract(ContractId::from_byte_array([2u8; 32]))
+            .unwrap();
+        index.rollback_transaction().unwrap();
+        stash.rollback_transaction().unwrap();
+
+        // Transaction is still reverted when only some providers prepared it
+        stash.begin_transaction().unwrap();
+        index.begin_transaction().unwrap();
+        stash.add_secret_seal(seal(3)).unwrap();
+        index.prepare_transaction().unwrap();
+        index.rollback_transaction().unwrap();
+        stash.rollback_transaction().unwrap();
+
+        assert_eq!(stash.secret_seals().unwrap().collect::<Vec<_>>(), vec![seal(1)]);
+        let reloaded = SqlStash::load(db.clone()).unwrap();
+        assert_eq!(reloaded.secret_seals().unwrap().collect::<Vec<_>>(), vec![seal(1)]);
+        assert!(index.is_contract_known(contract_id).unwrap());
+        assert!(!index
+            .is_contract_known(ContractId::from_byte_array([2u8; 32]))
+            .unwrap());
+    }
+
+    #[test]
+    fn unsupported_version() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.pragma_update(None, "user_version", 10u32).unwrap();
+        assert_eq!(
+            SqlDb::init(conn).unwrap_err(),
+            SqlError::UnsupportedVersion(10, SQL_MIGRATIONS.len())
+        );
+    }
+
+    #[test]
+    fn spenders_after_migration() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(SQL_MIGRATIONS[0]).unwrap();
+        conn.pragma_update(None, "user_version", 1u32).unwrap();
+        let db = SqlDb::init(conn).unwrap();
+
+        let opout =
+            Opout::new(OpId::from_inner([1u8; 32].into()), AssignmentType::from_inner(4000), 0);
+        assert!(db.is_reindex_pending().unwrap());
+        assert_eq!(
+            SqlIndex::with(db.clone()).spenders(opout),
+            Err(IndexInconsistency::SpendersNotIndexed.into())
+        );
+
+        db.open_stock().unwrap();
+        assert!(!db.is_reindex_pending().unwrap());
+        assert_eq!(SqlIndex::with(db).spenders(opout), Ok(none!()));
+    }
+
+    #[test]
+    fn remove_secret_seal() {
+        let db = SqlDb::open_in_memory().unwrap();
+        let info = SecretSealInfo::new(1_700_000_000);
+        let mut stash = SqlStash::load(db.clone()).unwrap();
+        assert!(stash.add_secret_seal_with(seal(1), info.clone()).unwrap());
+        assert!(stash.add_secret_seal_with(seal(2), info.clone()).unwrap());
+
+        stash.begin_transaction().unwrap();
+        assert!(stash.remove_secret_seal(seal(1)).unwrap());
+        stash.rollback_transaction().unwrap();
+        let records = |db: &SqlDb| {
+            SqlStash::load(db.clone())
+                .unwrap()
+                .secret_seal_records()
+                .unwrap()
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(records(&db), vec![(seal(1), info.clone()), (seal(2), info.clone())]);
+
+        assert!(stash.remove_secret_seal(seal(1)).unwrap());
+        assert!(!stash.remove_secret_seal(seal(1)).unwrap());
+        assert_eq!(records(&db), vec![(seal(2), info)]);
+    }
+
+    #[test]
+    fn archive_many_supplements() {
+        let db = SqlDb::open_in_memory().unwrap();
+        let schema: Schema = strict_dumb!();
+        let mut genesis: Genesis = strict_dumb!();
+        genesis.schema_id = schema.schema_id();
+        let contract_id = genesis.contract_id();
+
+        let mut stash = SqlStash::load(db.clone()).unwrap();
+        stash.replace_schema(schema).unwrap();
+        stash.replace_genesis(genesis).unwrap();
+        // Unlike consignments, the database doesn't limit the number of supplements
+        for no in 0..=u8::MAX as u16 {
+            stash
+                .add_suppl(ContractSuppl {
+                    contract_id,
+                    ticker: TickerSuppl::Absent,
+                    media_kit: Confined::try_from(no.to_string()).unwrap(),
+                    global_state: none!(),
+                    owned_state: none!(),
+                    extensions: none!(),
+                })
+                .unwrap();
+        }
+        SqlIndex::with(db.clone())
+            .register_contract(contract_id)
+            .unwrap();
+
+        let mut stock = db.open_stock().unwrap();
+        assert!(matches!(
+            stock.archive_contract(contract_id),
+            Err(StockError::InvalidInput(ConsignError::TooManySupplements))
+        ));
+        // The contract is kept if it can't be archived
+        assert_eq!(stock.contract_ids().unwrap().collect::<Vec<_>>(), vec![contract_id]);
+        assert_eq!(
+            SqlStash::load(db)
+                .unwrap()
+                .contract_supplements(contract_id)
+                .unwrap()
+                .count(),
+            256
+        );
+    }
+}
@@ -19,12 +19,13 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::borrow::Cow;
 use std::collections::{BTreeMap, BTreeSet};
 use std::error::Error;
 use std::fmt::Debug;
 
 use aluvm::library::{Lib, LibId};
-use amplify::confinement::{Confined, MediumBlob, TinyOrdMap};
+use amplify::confinement::{Confined, MediumBlob, SmallString, TinyOrdMap};
 use bp::dbc::anchor::MergeError;
 use bp::dbc::tapret::TapretCommitment;
 use commit_verify::mpc;
@@ -38,7 +39,9 @@ use strict_types::typesys::UnknownType;
 use strict_types::TypeSystem;
 
 use crate::accessors::{MergeReveal, MergeRevealError};
-use crate::containers::{BundledWitness, Consignment, ContentId, Kit, SealWitness, SigBlob};
+use crate::containers::{
+    Backup, BundledWitness, Consignment, ContentId, ContentSigs, Kit, SealWitness, SigBlob,
+};
 use crate::interface::{
     ContractBuilder, ContractSuppl, Iface, IfaceId, IfaceImpl, IfaceRef, TransitionBuilder,
 };
@@ -112,9 +115,15 @@ pub enum StashInconsistency {
     /// bundle {0} is absent.
     BundleAbsent(BundleId),
 
+    /// attachment {0} is absent.
+    AttachmentAbsent(AttachId),
+
     /// none of known anchors contain information on bundle {0} under contract
     /// {1}.
     BundleMissedInAnchors(BundleId, ContractId),
+
+    /// type system of the stash is incomplete: {0}
+    TypeAbsent(UnknownType),
 }
 
 #[derive(Clone, PartialEq, Eq, Debug, Display, Error, From)]
@@ -141,6 +150,13 @@ pub enum StashDataError {
 
     /// schema {0} doesn't implement interface {1}.
     NoIfaceImpl(SchemaId, IfaceId),
+
+    /// stash data exceed the size limits of a backup.
+    BackupOverflow,
+
+    /// some data covered by the base backup were removed from the stash; since
+    /// incremental backups can't record removals, a full backup must be made.
+    BackupRemoval,
 }
 
 #[derive(Clone, Eq, PartialEq, Debug)]
@@ -165,6 +181,49 @@ impl SchemaIfaces {
     }
 }
 
+/// Information about a secret seal given out to a payer, for instance as an
+/// invoice beneficiary.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Default)]
+#[derive(StrictType, StrictDumb, StrictEncode, StrictDecode)]
+#[strict_type(lib = LIB_NAME_RGB_STD)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+pub struct SecretSealInfo {
+    /// Unix timestamp of the seal creation. Zero for the seals stored before
+    /// the information was tracked.
+    pub created: i64,
+    /// Unix timestamp after which no state is expected to be assigned to the
+    /// seal.
+    pub expiry: Option<i64>,
+    /// Reference to the invoice in which the seal was given out.
+    pub invoice: Option<SmallString>,
+}
+
+impl SecretSealInfo {
+    pub fn new(created: i64) -> Self {
+        SecretSealInfo {
+            created,
+            expiry: None,
+            invoice: None,
+        }
+    }
+
+    pub fn with(created: i64, expiry: Option<i64>, invoice: Option<SmallString>) -> Self {
+        SecretSealInfo {
+            created,
+            expiry,
+            invoice,
+        }
+    }
+
+    /// Detects whether the seal has expired by the moment given as a unix
+    /// timestamp.
+    pub fn is_expired(&self, now: i64) -> bool { self.expiry.map(|e| e < now).unwrap_or_default() }
+}
+
 #[derive(Debug)]
 pub struct Stash<P: StashProvider> {
     provider: P,
@@ -191,6 +250,12 @@ impl<P: StashProvider> Stash<P> {
     ) -> Result<impl Iterator<Item = (IfaceId, TypeName)> + '_, StashError<P>> {
         self.provider.ifaces().map_err(StashError::ReadProvider)
     }
+    pub(super) fn lib_ids(&self) -> Result<impl Iterator<Item = LibId> + '_, StashError<P>> {
+        self.provider.lib_ids().map_err(StashError::ReadProvider)
+    }
+    pub(super) fn lib(&self, id: LibId) -> Result<&Lib, StashError<P>> {
+        Ok(self.provider.lib(id)?)
+    }
     pub(super) fn iface(&self, iface: impl Into<IfaceRef>) -> Result<&Iface, StashError<P>> {
         Ok(self.provider.iface(iface)?)
     }
@@ -219,6 +284,38 @@ impl<P: StashProvider> Stash<P> {
     pub(super) fn witness(&self, witness_id: XWitnessId) -> Result<&SealWitness, StashError<P>> {
         Ok(self.provider.witness(witness_id)?)
     }
+    pub(super) fn extension(&self, opid: OpId) -> Result<&Extension, StashError<P>> {
+        Ok(self.provider.extension(opid)?)
+    }
+    pub(super) fn attachment(&self, id: AttachId) -> Result<&MediumBlob, StashError<P>> {
+        Ok(self.provider.attachment(id)?)
+    }
+
+    pub(super) fn witness_ids(
+        &self,
+    ) -> Result<impl Iterator<Item = XWitnessId> + '_, StashError<P>> {
+        self.provider.witness_ids().map_err(StashError::ReadProvider)
+    }
+    pub(super) fn bundle_ids(&self) -> Result<impl Iterator<Item = BundleId> + '_, StashError<P>> {
+        self.provider.bundle_ids().map_err(StashError::ReadProvider)
+    }
+    pub(super) fn extension_ids(&self) -> Result<impl Iterator<Item = OpId> + '_, StashError<P>> {
+        self.provider
+            .extension_ids()
+            .map_err(StashError::ReadProvider)
+    }
+    pub(super) fn attachment_ids(
+        &self,
+    ) -> Result<impl Iterator<Item = AttachId> + '_, StashError<P>> {
+        self.provider
+            .attachment_ids()
+            .map_err(StashError::ReadProvider)
+    }
+    pub(super) fn sigs(
+        &self,
+    ) -> Result<impl Iterator<Item = (ContentId, &ContentSigs)> + '_, StashError<P>> {
+        self.provider.sigs().map_err(StashError::ReadProvider)
+    }
 
     pub(super) fn contract_ids_by_iface(
         &self,
@@ -235,6 +332,28 @@ impl<P: StashProvider> Stash<P> {
             .map_err(StashError::ReadProvider)
     }
 
+    pub(super) fn suppl_contract_ids(
+        &self,
+    ) -> Result<impl Iterator<Item = ContractId> + '_, StashError<P>> {
+        self.provider
+            .suppl_contract_ids()
+            .map_err(StashError::ReadProvider)
+    }
+    pub(super) fn secret_seals(
+        &self,
+    ) -> Result<impl Iterator<Item = XChain<GraphSeal>> + '_, StashError<P>> {
+        self.provider
+            .secret_seals()
+            .map_err(StashError::ReadProvider)
+    }
+    pub(super) fn secret_seal_records(
+        &self,
+    ) -> Result<impl Iterator<Item = (XChain<GraphSeal>, SecretSealInfo)> + '_, StashError<P>> {
+        self.provider
+            .secret_seal_records()
+            .map_err(StashError::ReadProvider)
+    }
+
     pub(super) fn extract<'a>(
         &self,
         schema: &Schema,
@@ -418,6 +537,113 @@ impl<P: StashProvider> Stash<P> {
         Ok(())
     }
 
+    /// Imports all data from the backup, merging them with the data already
+    /// present in the stash.
+    pub(super) fn consume_backup(&mut self, backup: Backup) -> Result<(), StashError<P>> {
+        if let Some(types) = backup.types {
+            self.consume_types(types)?;
+        }
+        for (_, lib) in backup.libs {
+            self.consume_lib(lib)?;
+        }
+        for (_, schema) in backup.schemata {
+            self.consume_schema(schema)?;
+        }
+        for (_, iface) in backup.ifaces {
+            self.consume_iface(iface)?;
+        }
+        for (_, iimpl) in backup.iimpls {
+            self.consume_iimpl(iimpl)?;
+        }
+        for (_, suppl) in backup.supplements {
+            self.consume_suppl(suppl)?;
+        }
+
+        for (_, genesis) in backup.geneses {
+            self.consume_genesis(genesis)?;
+        }
+        for (_, extension) in backup.extensions {
+            self.consume_extension(extension)?;
+        }
+        for (_, witness) in backup.witnesses {
+            self.consume_witness(witness)?;
+        }
+        for (_, bundle) in backup.bundles {
+            self.consume_bundle(bundle)?;
+        }
+        for (id, attach) in backup.attachments {
+            self.consume_attachment(id, attach)?;
+        }
+
+        for (content_id, sigs) in backup.signatures {
+            // Do not bother if we can't import all the sigs
+            self.consume_sigs(content_id, sigs).ok();
+        }
+        for (seal, info) in backup.secret_seals {
+            self.store_secret_seal(seal, info)?;
+        }
+        Ok(())
+    }
+
+    /// Removes genesis, supplements, extensions and bundles of the contract.
+    /// Witnesses are removed only if they are listed in `witnesses`.
+    pub(super) fn remove_contract(
+        &mut self,
+        contract_id: ContractId,
+        bundles: impl IntoIterator<Item = BundleId>,
+        extensions: impl IntoIterator<Item = OpId>,
+        witnesses: impl IntoIterator<Item = XWitnessId>,
+    ) -> Result<(), StashError<P>> {
+        for bundle_id in bundles {
+            self.provider
+                .remove_bundle(bundle_id)
+                .map_err(StashError::WriteProvider)?;
+        }
+        for opid in extensions {
+            self.provider
+                .remove_extension(opid)
+                .map_err(StashError::WriteProvider)?;
+        }
+        for witness_id in witnesses {
+            self.provider
+                .remove_witness(witness_id)
+                .map_err(StashError::WriteProvider)?;
+        }
+        self.provider
+            .remove_suppl(contract_id)
+            .map_err(StashError::WriteProvider)?;
+        self.provider
+            .remove_genesis(contract_id)
+            .map_err(StashError::WriteProvider)?;
+        Ok(())
+    }
+
+    pub(super) fn begin_transaction(&mut self) -> Result<(), StashError<P>> {
+        self.provider
+            .begin_transaction()
+            .map_err(StashError::WriteProvider)
+    }
+
+    pub(super) fn prepare_transaction(&mut self) -> Result<(), StashError<P>> {
+        self.provider
+            .prepare_transaction()
+            .map_err(StashError::WriteProvider)
+    }
+
+    pub(super) fn commit_transaction(&mut self) -> Result<(), StashError<P>> {
+        self.provider
+            .commit_transaction()
+            .map_err(StashError::WriteProvider)
+    }
+
+    pub(super) fn rollback_transaction(&mut self) -> Result<(), StashError<P>> {
+        self.provider
+            .rollback_transaction()
+            .map_err(StashError::WriteProvider)
+    }
+
+    /// Reveals consignment terminals using the secret seals known to the
+    /// stash. Seals removed with `Stock::prune_secret_seals` are not revealed.
     pub(super) fn resolve_secrets<const TRANSFER: bool>(
         &self,
         mut consignment: Consignment<TRANSFER>,
@@ -440,23 +666,9 @@ impl<P: StashProvider> Stash<P> {
     ) -> Result<(), StashError<P>> {
         let contract_id = consignment.contract_id();
 
-        let genesis = match self.genesis(contract_id) {
-            Ok(g) => g.clone().merge_reveal(consignment.genesis)?,
-            Err(_) => consignment.genesis,
-        };
-        self.provider
-            .replace_genesis(genesis)
-            .map_err(StashError::WriteProvider)?;
-
+        self.consume_genesis(consignment.genesis)?;
         for extension in consignment.extensions {
-            let opid = extension.id();
-            let extension = match self.provider.extension(opid) {
-                Ok(e) => e.clone().merge_reveal(extension)?,
-                Err(_) => extension,
-            };
-            self.provider
-                .replace_extension(extension)
-                .map_err(StashError::WriteProvider)?;
+            self.consume_extension(extension)?;
         }
 
         for bw in consignment.bundles {
@@ -518,6 +730,85 @@ impl<P: StashProvider> Stash<P> {
         Ok(())
     }
 
+    pub(super) fn consume_types(&mut self, types: TypeSystem) -> Result<(), StashError<P>> {
+        self.provider
+            .consume_types(types)
+            .map_err(StashError::WriteProvider)
+    }
+
+    pub(super) fn consume_lib(&mut self, lib: Lib) -> Result<bool, StashError<P>> {
+        self.provider
+            .replace_lib(lib)
+            .map_err(StashError::WriteProvider)
+    }
+
+    pub(super) fn consume_schema(&mut self, schema: Schema) -> Result<bool, StashError<P>> {
+        self.provider
+            .replace_schema(schema)
+            .map_err(StashError::WriteProvider)
+    }
+
+    pub(super) fn consume_iface(&mut self, iface: Iface) -> Result<bool, StashError<P>> {
+        self.provider
+            .replace_iface(iface)
+            .map_err(StashError::WriteProvider)
+    }
+
+    pub(super) fn consume_iimpl(&mut self, iimpl: IfaceImpl) -> Result<bool, StashError<P>> {
+        self.provider
+            .replace_iimpl(iimpl)
+            .map_err(StashError::WriteProvider)
+    }
+
+    pub(super) fn consume_suppl(&mut self, suppl: ContractSuppl) -> Result<(), StashError<P>> {
+        self.provider
+            .add_suppl(suppl)
+            .map_err(StashError::WriteProvider)
+    }
+
+    pub(super) fn consume_attachment(
+        &mut self,
+        id: AttachId,
+        attach: MediumBlob,
+    ) -> Result<bool, StashError<P>> {
+        self.provider
+            .replace_attachment(id, attach)
+            .map_err(StashError::WriteProvider)
+    }
+
+    pub(super) fn consume_sigs(
+        &mut self,
+        content_id: ContentId,
+        sigs: ContentSigs,
+    ) -> Result<(), StashError<P>> {
+        self.provider
+            .import_sigs(content_id, sigs.into_iter())
+            .map_err(StashError::WriteProvider)
+    }
+
+    pub(super) fn consume_genesis(&mut self, genesis: Genesis) -> Result<bool, StashError<P>> {
+        let genesis = match self.genesis(genesis.contract_id()) {
+            Ok(g) => g.clone().merge_reveal(genesis)?,
+            Err(_) => genesis,
+        };
+        self.provider
+            .replace_genesis(genesis)
+            .map_err(StashError::WriteProvider)
+    }
+
+    pub(super) fn consume_extension(
+        &mut self,
+        extension: Extension,
+    ) -> Result<bool, StashError<P>> {
+        let extension = match self.provider.extension(extension.id()) {
+            Ok(e) => e.clone().merge_reveal(extension)?,
+            Err(_) => extension,
+        };
+        self.provider
+            .replace_extension(extension)
+            .map_err(StashError::WriteProvider)
+    }
+
     pub(crate) fn consume_witness(&mut self, witness: SealWitness) -> Result<bool, StashError<P>> {
         let witness = match self.provider.witness(witness.witness_id()).cloned() {
             Ok(mut w) => {
@@ -549,9 +840,19 @@ impl<P: StashProvider> Stash<P> {
     pub(crate) fn store_secret_seal(
         &mut self,
         seal: XChain<GraphSeal>,
+        info: SecretSealInfo,
+    ) -> Result<bool, StashError<P>> {
+        self.provider
+            .add_secret_seal_with(seal, info)
+            .map_err(StashError::WriteProvider)
+    }
+
+    pub(super) fn remove_secret_seal(
+        &mut self,
+        seal: XChain<GraphSeal>,
     ) -> Result<bool, StashError<P>> {
         self.provider
-            .add_secret_seal(seal)
+            .remove_secret_seal(seal)
             .map_err(StashError::WriteProvider)
     }
 }
@@ -562,7 +863,9 @@ pub trait StashReadProvider {
     /// Error type which must indicate problems on data retrieval.
     type Error: Clone + Eq + Error;
 
-    fn type_system(&self) -> Result<&TypeSystem, Self::Error>;
+    /// Returns all types known to the stash.
+    fn type_system(&self) -> Result<Cow<TypeSystem>, Self::Error>;
+    fn lib_ids(&self) -> Result<impl Iterator<Item = LibId>, Self::Error>;
     fn lib(&self, id: LibId) -> Result<&Lib, ProviderError<Self::Error>>;
 
     fn ifaces(&self) -> Result<impl Iterator<Item = (IfaceId, TypeName)>, Self::Error>;
@@ -587,6 +890,7 @@ pub trait StashReadProvider {
         contract_id: ContractId,
     ) -> Result<impl Iterator<Item = ContractSuppl>, Self::Error>;
 
+    fn suppl_contract_ids(&self) -> Result<impl Iterator<Item = ContractId>, Self::Error>;
     fn genesis(&self, contract_id: ContractId) -> Result<&Genesis, ProviderError<Self::Error>>;
     fn witness_ids(&self) -> Result<impl Iterator<Item = XWitnessId>, Self::Error>;
     fn bundle_ids(&self) -> Result<impl Iterator<Item = BundleId>, Self::Error>;
@@ -594,6 +898,9 @@ pub trait StashReadProvider {
     fn extension_ids(&self) -> Result<impl Iterator<Item = OpId>, Self::Error>;
     fn extension(&self, op_id: OpId) -> Result<&Extension, ProviderError<Self::Error>>;
     fn witness(&self, witness_id: XWitnessId) -> Result<&SealWitness, ProviderError<Self::Error>>;
+    fn attachment_ids(&self) -> Result<impl Iterator<Item = AttachId>, Self::Error>;
+    fn attachment(&self, id: AttachId) -> Result<&MediumBlob, ProviderError<Self::Error>>;
+    fn sigs(&self) -> Result<impl Iterator<Item = (ContentId, &ContentSigs)>, Self::Error>;
 
     fn taprets(&self) -> Result<impl Iterator<Item = (XWitnessId, TapretCommitment)>, Self::Error>;
     fn seal_secret(
@@ -601,11 +908,45 @@ pub trait StashReadProvider {
         secret: XChain<SecretSeal>,
     ) -> Result<Option<XChain<GraphSeal>>, Self::Error>;
     fn secret_seals(&self) -> Result<impl Iterator<Item = XChain<GraphSeal>>, Self::Error>;
+    /// Returns secret seals together with information about them.
+    ///
+    /// Providers which don't keep the information may use the default
+    /// implementation, returning [`SecretSealInfo::default`] for all seals.
+    fn secret_seal_records(
+        &self,
+    ) -> Result<impl Iterator<Item = (XChain<GraphSeal>, SecretSealInfo)>, Self::Error> {
+        Ok(self
+            .secret_seals()?
+            .map(|seal| (seal, SecretSealInfo::default())))
+    }
 }
 
 pub trait StashWriteProvider {
     type Error: Clone + Eq + Error;
 
+    /// Starts a transaction: all following writes must be either prepared
+    /// with [`Self::prepare_transaction`] and then committed with
+    /// [`Self::commit_transaction`], or reverted with
+    /// [`Self::rollback_transaction`].
+    ///
+    /// Providers which are not able to revert their writes may keep the
+    /// default no-op implementation of the transaction methods; the stock
+    /// operations are not atomic in such case.
+    fn begin_transaction(&mut self) -> Result<(), Self::Error> { Ok(()) }
+    /// Performs all the work required to commit the transaction which may
+    /// fail. A prepared transaction can still be reverted.
+    ///
+    /// The stock prepares all its providers before committing any of them, so
+    /// if any of them fails to prepare, the transactions of all the providers
+    /// are reverted.
+    fn prepare_transaction(&mut self) -> Result<(), Self::Error> { Ok(()) }
+    /// Completes a prepared transaction. Committed transactions are never
+    /// reverted.
+    fn commit_transaction(&mut self) -> Result<(), Self::Error> { Ok(()) }
+    /// Reverts all writes made since the transaction was started, restoring
+    /// the data to exactly the same state as they were before.
+    fn rollback_transaction(&mut self) -> Result<(), Self::Error> { Ok(()) }
+
     fn replace_schema(&mut self, schema: Schema) -> Result<bool, Self::Error>;
     fn replace_iface(&mut self, iface: Iface) -> Result<bool, Self::Error>;
     fn replace_iimpl(&mut self, iimpl: IfaceImpl) -> Result<bool, Self::Error>;
@@ -625,4 +966,24 @@ pub trait StashWriteProvider {
         I::IntoIter: ExactSizeIterator<Item = (Identity, SigBlob)>;
 
     fn add_secret_seal(&mut self, seal: XChain<GraphSeal>) -> Result<bool, Self::Error>;
+    /// Adds secret seal together with information about it. Returns `false`
+    /// and keeps the existing information if the seal is already known.
+    ///
+    /// Providers which don't keep the information may use the default
+    /// implementation, which ignores it.
+    fn add_secret_seal_with(
+        &mut self,
+        seal: XChain<GraphSeal>,
+        info: SecretSealInfo,
+    ) -> Result<bool, Self::Error> {
+        let _ = info;
+        self.add_secret_seal(seal)
+    }
+    fn remove_secret_seal(&mut self, seal: XChain<GraphSeal>) -> Result<bool, Self::Error>;
+
+    fn remove_genesis(&mut self, contract_id: ContractId) -> Result<bool, Self::Error>;
+    fn remove_suppl(&mut self, contract_id: ContractId) -> Result<(), Self::Error>;
+    fn remove_extension(&mut self, opid: OpId) -> Result<bool, Self::Error>;
+    fn remove_bundle(&mut self, bundle_id: BundleId) -> Result<bool, Self::Error>;
+    fn remove_witness(&mut self, witness_id: XWitnessId) -> Result<bool, Self::Error>;
 }
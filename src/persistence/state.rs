@@ -76,6 +76,29 @@ pub trait StateReadProvider {
 pub trait StateWriteProvider {
     type Error: Clone + Eq + Error;
 
+    /// Starts a transaction: all following writes must be either prepared
+    /// with [`Self::prepare_transaction`] and then committed with
+    /// [`Self::commit_transaction`], or reverted with
+    /// [`Self::rollback_transaction`].
+    ///
+    /// Providers which are not able to revert their writes may keep the
+    /// default no-op implementation of the transaction methods; the stock
+    /// operations are not atomic in such case.
+    fn begin_transaction(&mut self) -> Result<(), Self::Error> { Ok(()) }
+    /// Performs all the work required to commit the transaction which may
+    /// fail. A prepared transaction can still be reverted.
+    ///
+    /// The stock prepares all its providers before committing any of them, so
+    /// if any of them fails to prepare, the transactions of all the providers
+    /// are reverted.
+    fn prepare_transaction(&mut self) -> Result<(), Self::Error> { Ok(()) }
+    /// Completes a prepared transaction. Committed transactions are never
+    /// reverted.
+    fn commit_transaction(&mut self) -> Result<(), Self::Error> { Ok(()) }
+    /// Reverts all writes made since the transaction was started, restoring
+    /// the data to exactly the same state as they were before.
+    fn rollback_transaction(&mut self) -> Result<(), Self::Error> { Ok(()) }
+
     fn create_or_update_state<R: ResolveHeight>(
         &mut self,
         contract_id: ContractId,
@@ -87,4 +110,10 @@ pub trait StateWriteProvider {
         contract_id: ContractId,
         updater: impl FnMut(&mut ContractHistory) -> Result<(), R::Error>,
     ) -> Result<(), StateUpdateError<Self::Error>>;
+
+    /// Removes state of a single contract.
+    fn remove_state(&mut self, contract_id: ContractId) -> Result<bool, Self::Error>;
+
+    /// Removes state of all contracts.
+    fn clear_state(&mut self) -> Result<(), Self::Error>;
 }
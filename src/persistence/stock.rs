@@ -19,44 +19,60 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::cell::{Cell, RefCell};
 use std::cmp::Ordering;
 use std::collections::btree_map::Entry;
 use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::convert::Infallible;
 use std::error::Error;
 use std::fmt::Debug;
+use std::ops::Sub;
+use std::{mem, slice};
 
-use amplify::confinement::{Confined, U24};
+use amplify::confinement::{self, Confined, U24};
+use amplify::Wrapper;
 use bp::seals::txout::CloseMethod;
-use bp::Vout;
+use bp::{Outpoint, Vout};
 use chrono::Utc;
 use commit_verify::Conceal;
-use invoice::{Amount, Beneficiary, InvoiceState, NonFungible, RgbInvoice};
+use invoice::{
+    Allocation, Amount, Beneficiary, ChainNet, InvoiceState, NonFungible, OwnedFraction,
+    RgbInvoice, RgbInvoiceBuilder, TokenIndex, XChainNet,
+};
 use rgb::{
     validation, AssignmentType, BlindingFactor, BundleId, ContractHistory, ContractId,
-    ContractState, DbcProof, EAnchor, GraphSeal, OpId, Operation, Opout, SchemaId, SecretSeal,
-    Transition, WitnessAnchor, XChain, XOutpoint, XOutputSeal, XWitnessId,
+    ContractState, DbcProof, EAnchor, GraphSeal, Layer1, OpId, Operation, Opout, SchemaId,
+    SecretSeal, Transition, TransitionBundle, WitnessAnchor, XChain, XOutpoint, XOutputSeal,
+    XWitnessId,
 };
-use strict_encoding::{FieldName, TypeName};
+use strict_encoding::{FieldName, StrictEncode, TypeName};
+use strict_types::SemId;
 
+use super::events::Observers;
+use super::preview::{allocation_sem_id, assigned_state};
 use super::{
-    Index, IndexError, IndexInconsistency, IndexProvider, IndexReadProvider, IndexWriteProvider,
-    MemIndex, MemStash, MemState, PersistedState, SchemaIfaces, Stash, StashDataError, StashError,
-    StashInconsistency, StashProvider, StashReadProvider, StashWriteProvider, StateProvider,
-    StateReadProvider, StateUpdateError, StateWriteProvider,
+    AuditIssue, AuditLog, AuditLogError, AuditReport, AuditValidity, BlindingError, ChangePreview,
+    ComposePreview, ContractPreview, Index, IndexError, IndexInconsistency, IndexProvider,
+    IndexReadProvider, IndexWriteProvider, MemIndex, MemStash, MemState, MergeConflict,
+    MergeReport, PaymentPreview, PersistedState, SchemaIfaces, SecretSealInfo, SeedBlinder,
+    SelectionCandidate, SelectionStrategy, Spender, SpentAllocation, Stash, StashDataError,
+    StashError, StashInconsistency, StashProvider, StashReadProvider, StashWriteProvider,
+    StateProvider, StateReadProvider, StateUpdateError, StateWriteProvider, StockEvent,
+    StockMutation, StockObserver,
 };
 use crate::accessors::{MergeRevealError, RevealError};
 use crate::containers::{
-    AnchorSet, AnchoredBundles, Batch, BuilderSeal, BundledWitness, Consignment, ContainerVer,
-    Contract, Fascia, PubWitness, SealWitness, Terminal, TerminalSeal, Transfer, TransitionInfo,
-    TransitionInfoError, ValidConsignment, ValidContract, ValidKit, ValidTransfer,
+    backup_digest, AnchorSet, AnchoredBundles, Backup, BackupError, BackupItem, BackupManifest,
+    Batch, BuilderSeal, BundledWitness, Consignment, ContainerVer, Contract, Fascia, PubWitness,
+    SealWitness, Terminal, TerminalSeal, Transfer, TransitionInfo, TransitionInfoError,
+    ValidConsignment, ValidContract, ValidKit, ValidTransfer,
 };
 use crate::interface::resolver::DumbResolver;
 use crate::interface::{
-    BuilderError, ContractBuilder, ContractIface, Iface, IfaceId, IfaceRef, TransitionBuilder,
-    VelocityHint,
+    AttachedState, BuilderError, ContractBuilder, ContractIface, ContractSuppl, Iface, IfaceId,
+    IfaceRef, OwnedIface, TransitionBuilder, VelocityHint,
 };
-use crate::resolvers::ResolveHeight;
+use crate::resolvers::{ResolveHeight, WitnessStatus};
 
 pub type ContractAssignments = HashMap<XOutputSeal, HashMap<Opout, PersistedState>>;
 
@@ -103,6 +119,9 @@ pub enum StockError<
 
     #[from]
     StashData(StashDataError),
+
+    /// the change can't be recorded in the attached audit log.
+    AuditLog(AuditLogError),
 }
 
 impl<S: StashProvider, H: StateProvider, P: IndexProvider, E: Error> From<StashError<S>>
@@ -152,6 +171,12 @@ pub enum ConsignError {
     /// too many transitions.
     TooManyBundles,
 
+    /// unable to construct consignment: too many state extensions.
+    TooManyExtensions,
+
+    /// unable to construct consignment: too many contract supplements.
+    TooManySupplements,
+
     /// public state at operation output {0} is concealed.
     ConcealedPublicState(Opout),
 
@@ -198,12 +223,27 @@ pub enum ComposeError {
     /// expired invoice.
     InvoiceExpired,
 
+    /// no invoices to pay were provided.
+    NoInvoices,
+
+    /// invoices to be paid in the same batch are issued for different layer 1
+    /// networks.
+    InconsistentLayer1,
+
+    /// invoices for contract {0} to be paid in the same batch use different
+    /// interfaces or operations.
+    InconsistentInvoices(ContractId),
+
     /// the invoice contains no contract information.
     NoContract,
 
     /// the invoice contains no interface information.
     NoIface,
 
+    /// contract {0} doesn't implement any interface, so its blank state
+    /// transition can't be constructed.
+    NoBlankIface(ContractId),
+
     /// the invoice requirements can't be fulfilled using available assets or
     /// smart contract state.
     InsufficientState,
@@ -223,6 +263,10 @@ pub enum ComposeError {
     #[from]
     #[display(inner)]
     Builder(BuilderError),
+
+    #[from]
+    #[display(inner)]
+    Blinding(BlindingError),
 }
 
 impl<S: StashProvider, H: StateProvider, P: IndexProvider> From<ComposeError>
@@ -264,6 +308,31 @@ impl<S: StashProvider, H: StateProvider, P: IndexProvider> From<ContractIfaceErr
     fn from(err: ContractIfaceError) -> Self { Self::InvalidInput(err) }
 }
 
+#[derive(Clone, PartialEq, Eq, Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum MergeError {
+    /// unable to read data from the merged stock: {0}
+    Source(String),
+}
+
+impl<S: StashProvider, H: StateProvider, P: IndexProvider> From<MergeError>
+    for StockError<S, H, P, MergeError>
+{
+    fn from(err: MergeError) -> Self { Self::InvalidInput(err) }
+}
+
+impl<S: StashProvider, H: StateProvider, P: IndexProvider> From<BackupError>
+    for StockError<S, H, P, BackupError>
+{
+    fn from(err: BackupError) -> Self { Self::InvalidInput(err) }
+}
+
+impl<S: StashProvider, H: StateProvider, P: IndexProvider> From<AuditLogError>
+    for StockError<S, H, P, AuditLogError>
+{
+    fn from(err: AuditLogError) -> Self { Self::InvalidInput(err) }
+}
+
 #[derive(Clone, PartialEq, Eq, Debug, Display, Error, From)]
 #[display(inner)]
 pub enum InputError {
@@ -275,6 +344,12 @@ pub enum InputError {
     Fascia(FasciaError),
     #[from]
     ContractIface(ContractIfaceError),
+    #[from]
+    Merge(MergeError),
+    #[from]
+    Backup(BackupError),
+    #[from]
+    AuditLog(AuditLogError),
 }
 
 macro_rules! stock_err_conv {
@@ -296,6 +371,7 @@ macro_rules! stock_err_conv {
                     StockError::StashInconsistency(e) => StockError::StashInconsistency(e),
                     StockError::StateInconsistency(e) => StockError::StateInconsistency(e),
                     StockError::IndexInconsistency(e) => StockError::IndexInconsistency(e),
+                    StockError::AuditLog(e) => StockError::AuditLog(e),
                 }
             }
         }
@@ -317,16 +393,31 @@ impl From<Infallible> for FasciaError {
 impl From<Infallible> for ContractIfaceError {
     fn from(_: Infallible) -> Self { unreachable!() }
 }
+impl From<Infallible> for MergeError {
+    fn from(_: Infallible) -> Self { unreachable!() }
+}
+impl From<Infallible> for BackupError {
+    fn from(_: Infallible) -> Self { unreachable!() }
+}
+impl From<Infallible> for AuditLogError {
+    fn from(_: Infallible) -> Self { unreachable!() }
+}
 
 stock_err_conv!(Infallible, ComposeError);
 stock_err_conv!(Infallible, ConsignError);
 stock_err_conv!(Infallible, FasciaError);
 stock_err_conv!(Infallible, ContractIfaceError);
+stock_err_conv!(Infallible, MergeError);
+stock_err_conv!(Infallible, BackupError);
+stock_err_conv!(Infallible, AuditLogError);
 stock_err_conv!(Infallible, InputError);
 stock_err_conv!(ComposeError, InputError);
 stock_err_conv!(ConsignError, InputError);
 stock_err_conv!(FasciaError, InputError);
 stock_err_conv!(ContractIfaceError, InputError);
+stock_err_conv!(MergeError, InputError);
+stock_err_conv!(BackupError, InputError);
+stock_err_conv!(AuditLogError, InputError);
 
 pub type StockErrorMem<E = Infallible> = StockError<MemStash, MemState, MemIndex, E>;
 pub type StockErrorAll<S = MemStash, H = MemState, P = MemIndex> = StockError<S, H, P, InputError>;
@@ -340,6 +431,8 @@ pub struct Stock<
     stash: Stash<S>,
     state: H,
     index: Index<P>,
+    observers: Observers,
+    audit_log: Option<AuditLog>,
 }
 
 impl<S: StashProvider, H: StateProvider, P: IndexProvider> Default for Stock<S, H, P>
@@ -353,6 +446,8 @@ where
             stash: default!(),
             state: default!(),
             index: default!(),
+            observers: default!(),
+            audit_log: None,
         }
     }
 }
@@ -363,7 +458,86 @@ impl<S: StashProvider, H: StateProvider, P: IndexProvider> Stock<S, H, P> {
             stash: Stash::new(stash_provider),
             state: state_provider,
             index: Index::new(index_provider),
+            observers: default!(),
+            audit_log: None,
+        }
+    }
+
+    /// Constructs stock handle of a wallet, taking the observers and the audit
+    /// log kept by [`super::MultiStock`] between the handles.
+    pub(super) fn with_hooks(
+        stash_provider: S,
+        state_provider: H,
+        index_provider: P,
+        observers: Observers,
+        audit_log: Option<AuditLog>,
+    ) -> Self {
+        Stock {
+            stash: Stash::new(stash_provider),
+            state: state_provider,
+            index: Index::new(index_provider),
+            observers,
+            audit_log,
+        }
+    }
+
+    /// Takes the observers and the audit log out of the stock handle of a
+    /// wallet, returning them back to [`super::MultiStock`].
+    pub(super) fn take_hooks(&mut self) -> (Observers, Option<AuditLog>) {
+        (mem::take(&mut self.observers), self.audit_log.take())
+    }
+
+    /// Registers observer which will be notified about all further changes
+    /// made by [`Self::import_kit`], [`Self::import_contract`],
+    /// [`Self::accept_transfer`], [`Self::consume_fascia`] and
+    /// [`Self::merge`].
+    pub fn subscribe(&mut self, observer: impl StockObserver + 'static) {
+        self.observers.push(Box::new(observer));
+    }
+
+    /// Attaches audit log, to which all further changes to the stock are
+    /// appended, returning the previously attached log.
+    ///
+    /// The log must be persisted by the caller after each change (see
+    /// [`Self::audit_log`]).
+    pub fn attach_audit_log(&mut self, log: AuditLog) -> Option<AuditLog> {
+        self.audit_log.replace(log)
+    }
+
+    /// Detaches audit log, such that further changes to the stock are not
+    /// logged.
+    pub fn detach_audit_log(&mut self) -> Option<AuditLog> { self.audit_log.take() }
+
+    pub fn audit_log(&self) -> Option<&AuditLog> { self.audit_log.as_ref() }
+
+    /// Prepares record of a change for the attached audit log, failing if the
+    /// change can't be logged. Must be called before the change is committed;
+    /// returns `None` if no audit log is attached.
+    fn audit_mutation<E: Error>(
+        &self,
+        mutation: impl FnOnce() -> Result<StockMutation, confinement::Error>,
+    ) -> Result<Option<StockMutation>, StockError<S, H, P, E>> {
+        let Some(log) = &self.audit_log else {
+            return Ok(None);
+        };
+        if log.is_full() {
+            return Err(StockError::AuditLog(AuditLogError::TooManyEntries));
+        }
+        mutation()
+            .map(Some)
+            .map_err(|_| StockError::AuditLog(AuditLogError::EntryOverflow))
+    }
+
+    fn log_mutation<E: Error>(
+        &mut self,
+        validity: AuditValidity,
+        mutation: Option<StockMutation>,
+    ) -> Result<(), StockError<S, H, P, E>> {
+        if let (Some(log), Some(mutation)) = (&mut self.audit_log, mutation) {
+            log.append(Utc::now().timestamp(), validity, mutation)
+                .map_err(StockError::AuditLog)?;
         }
+        Ok(())
     }
 
     #[doc(hidden)]
@@ -417,6 +591,64 @@ impl<S: StashProvider, H: StateProvider, P: IndexProvider> Stock<S, H, P> {
         Ok(self.index.contracts_assigning(outputs)?)
     }
 
+    /// Returns state transitions which have spent a given operation output.
+    ///
+    /// More than a single spender means that the output was double-spent by
+    /// different witnesses, only one of which may be mined.
+    pub fn spenders(&self, opout: Opout) -> Result<BTreeSet<Spender>, StockError<S, H, P>> {
+        Ok(self.index.spenders(opout)?)
+    }
+
+    /// Returns operation outputs of a contract assigned to the provided
+    /// witness outputs which were not spent by any known state transition.
+    pub fn unspent_opouts(
+        &self,
+        contract_id: ContractId,
+        outputs: impl IntoIterator<Item = impl Into<XOutputSeal>>,
+    ) -> Result<BTreeSet<Opout>, StockError<S, H, P>> {
+        let mut unspent = BTreeSet::new();
+        for opout in self.index.opouts_by_outputs(contract_id, outputs)? {
+            if self.index.spenders(opout)?.is_empty() {
+                unspent.insert(opout);
+            }
+        }
+        Ok(unspent)
+    }
+
+    /// Traces the spending of a given operation output forward through the
+    /// contract history, returning for each of the spent outputs the set of its
+    /// spenders. Outputs which are not present in the returned map are
+    /// unspent.
+    pub fn spend_graph(
+        &self,
+        opout: Opout,
+    ) -> Result<BTreeMap<Opout, BTreeSet<Spender>>, StockError<S, H, P>> {
+        let mut graph = BTreeMap::new();
+        let mut queue = vec![opout];
+        while let Some(opout) = queue.pop() {
+            if graph.contains_key(&opout) {
+                continue;
+            }
+            let spenders = self.index.spenders(opout)?;
+            if spenders.is_empty() {
+                continue;
+            }
+            for spender in &spenders {
+                let bundle = self.stash.bundle(spender.bundle_id)?;
+                let Some(transition) = bundle.known_transitions.get(&spender.opid) else {
+                    continue;
+                };
+                for (ty, assigns) in transition.assignments.iter() {
+                    for no in 0..assigns.len_u16() {
+                        queue.push(Opout::new(spender.opid, *ty, no));
+                    }
+                }
+            }
+            graph.insert(opout, spenders);
+        }
+        Ok(graph)
+    }
+
     fn contract_raw(
         &self,
         contract_id: ContractId,
@@ -741,6 +973,53 @@ impl<S: StashProvider, H: StateProvider, P: IndexProvider> Stock<S, H, P> {
         )
     }
 
+    /// Composes a batch of state transitions updating state for the provided
+    /// set of previous outputs, satisfying requirements of the invoice, paying
+    /// the change back and including the necessary blank state transitions.
+    ///
+    /// Unlike [`Self::compose`], all seal and Pedersen commitment blinding
+    /// factors are derived from the wallet seed, such that the change and
+    /// blank outputs can be recovered with [`Self::recover_seals`].
+    #[allow(clippy::result_large_err)]
+    pub fn compose_seeded(
+        &self,
+        invoice: &RgbInvoice,
+        prev_outputs: impl IntoIterator<Item = impl Into<XOutputSeal>>,
+        method: CloseMethod,
+        beneficiary_vout: Option<impl Into<Vout>>,
+        allocator: impl Fn(ContractId, AssignmentType, VelocityHint) -> Option<Vout>,
+        blinder: &SeedBlinder,
+    ) -> Result<Batch, StockError<S, H, P, ComposeError>> {
+        // Blinder callbacks can't fail, so the first blinding error is kept and
+        // reported once the batch is composed
+        let failure = Cell::new(None);
+        let batch = self.compose_deterministic(
+            invoice,
+            prev_outputs,
+            method,
+            beneficiary_vout,
+            allocator,
+            |id, ty| {
+                blinder
+                    .next_pedersen_blinding(id, ty)
+                    .unwrap_or_else(|err| {
+                        failure.set(failure.get().or(Some(err)));
+                        BlindingFactor::random()
+                    })
+            },
+            |id, ty| {
+                blinder.next_seal_blinding(id, ty).unwrap_or_else(|err| {
+                    failure.set(failure.get().or(Some(err)));
+                    rand::random()
+                })
+            },
+        );
+        if let Some(err) = failure.get() {
+            return Err(ComposeError::from(err).into());
+        }
+        batch
+    }
+
     /// Composes a batch of state transitions updating state for the provided
     /// set of previous outputs, satisfying requirements of the invoice, paying
     /// the change back and including the necessary blank state transitions.
@@ -755,7 +1034,97 @@ impl<S: StashProvider, H: StateProvider, P: IndexProvider> Stock<S, H, P> {
         pedersen_blinder: impl Fn(ContractId, AssignmentType) -> BlindingFactor,
         seal_blinder: impl Fn(ContractId, AssignmentType) -> u64,
     ) -> Result<Batch, StockError<S, H, P, ComposeError>> {
-        let layer1 = invoice.layer1();
+        let beneficiary_vout: Option<Vout> = beneficiary_vout.map(|vout| vout.into());
+        self.compose_many_deterministic(
+            slice::from_ref(invoice),
+            prev_outputs,
+            method,
+            |_| beneficiary_vout,
+            allocator,
+            pedersen_blinder,
+            seal_blinder,
+        )
+    }
+
+    /// Composes a batch of state transitions paying multiple invoices, which
+    /// may be issued for different contracts, within a single witness
+    /// transaction.
+    ///
+    /// Invoices are grouped by contract, and for each of the contracts a single
+    /// state transition is created, spending all the contract state assigned to
+    /// the provided set of previous outputs, paying all the invoices for the
+    /// contract and the change back. The transition for the contract of the
+    /// first invoice is put into [`Batch::main`]; the transitions for the other
+    /// invoiced contracts precede the blank state transitions in
+    /// [`Batch::blanks`].
+    ///
+    /// The `beneficiary_vout` is called with the index of the invoice in
+    /// `invoices` to get the output paying to the beneficiary of an invoice
+    /// with a witness output beneficiary.
+    #[allow(clippy::result_large_err)]
+    pub fn compose_many(
+        &self,
+        invoices: &[RgbInvoice],
+        prev_outputs: impl IntoIterator<Item = impl Into<XOutputSeal>>,
+        method: CloseMethod,
+        beneficiary_vout: impl Fn(usize) -> Option<Vout>,
+        allocator: impl Fn(ContractId, AssignmentType, VelocityHint) -> Option<Vout>,
+    ) -> Result<Batch, StockError<S, H, P, ComposeError>> {
+        self.compose_many_deterministic(
+            invoices,
+            prev_outputs,
+            method,
+            beneficiary_vout,
+            allocator,
+            |_, _| BlindingFactor::random(),
+            |_, _| rand::random(),
+        )
+    }
+
+    /// Composes a batch of state transitions paying multiple invoices within a
+    /// single witness transaction (see [`Self::compose_many`]), using the
+    /// provided blinding factors.
+    #[allow(clippy::too_many_arguments, clippy::result_large_err)]
+    pub fn compose_many_deterministic(
+        &self,
+        invoices: &[RgbInvoice],
+        prev_outputs: impl IntoIterator<Item = impl Into<XOutputSeal>>,
+        method: CloseMethod,
+        beneficiary_vout: impl Fn(usize) -> Option<Vout>,
+        allocator: impl Fn(ContractId, AssignmentType, VelocityHint) -> Option<Vout>,
+        pedersen_blinder: impl Fn(ContractId, AssignmentType) -> BlindingFactor,
+        seal_blinder: impl Fn(ContractId, AssignmentType) -> u64,
+    ) -> Result<Batch, StockError<S, H, P, ComposeError>> {
+        self.compose_tracked(
+            invoices,
+            prev_outputs,
+            method,
+            beneficiary_vout,
+            allocator,
+            pedersen_blinder,
+            seal_blinder,
+            |_, _| (),
+        )
+    }
+
+    /// Composes a batch of state transitions paying multiple invoices (see
+    /// [`Self::compose_many_deterministic`]), reporting to `track_change` each
+    /// of the seals which the wallet assigns to itself as a change or with a
+    /// blank transition, together with the velocity hint it was allocated
+    /// with.
+    #[allow(clippy::too_many_arguments, clippy::result_large_err)]
+    fn compose_tracked(
+        &self,
+        invoices: &[RgbInvoice],
+        prev_outputs: impl IntoIterator<Item = impl Into<XOutputSeal>>,
+        method: CloseMethod,
+        beneficiary_vout: impl Fn(usize) -> Option<Vout>,
+        allocator: impl Fn(ContractId, AssignmentType, VelocityHint) -> Option<Vout>,
+        pedersen_blinder: impl Fn(ContractId, AssignmentType) -> BlindingFactor,
+        seal_blinder: impl Fn(ContractId, AssignmentType) -> u64,
+        track_change: impl Fn(XChain<GraphSeal>, VelocityHint),
+    ) -> Result<Batch, StockError<S, H, P, ComposeError>> {
+        let layer1 = invoices.first().ok_or(ComposeError::NoInvoices)?.layer1();
         let prev_outputs = prev_outputs
             .into_iter()
             .map(|o| o.into())
@@ -776,121 +1145,204 @@ impl<S: StashProvider, H: StateProvider, P: IndexProvider> Stock<S, H, P> {
                     .ok_or(ComposeError::NoBlankOrChange(velocity, assignment_type))?;
                 let seal =
                     GraphSeal::with_blinded_vout(method, vout, seal_blinder(id, assignment_type));
-                Ok(BuilderSeal::Revealed(XChain::with(layer1, seal)))
+                let seal = XChain::with(layer1, seal);
+                track_change(seal, velocity);
+                Ok(BuilderSeal::Revealed(seal))
             };
 
         // 1. Prepare the data
-        if let Some(expiry) = invoice.expiry {
-            if expiry < Utc::now().timestamp() {
-                return Err(ComposeError::InvoiceExpired.into());
+        let groups = invoice_groups(invoices, layer1, Utc::now().timestamp())?;
+
+        // 2. Prepare transitions
+        let mut transitions = Vec::with_capacity(groups.len());
+        for (contract_id, list) in &groups {
+            let contract_id = *contract_id;
+            let first = &invoices[list[0]];
+            let iface = first.iface.as_ref().ok_or(ComposeError::NoIface)?;
+            let mut main_builder =
+                self.transition_builder(contract_id, iface.clone(), first.operation.clone())?;
+
+            // Beneficiaries together with the state requested by them
+            let mut requests = Vec::with_capacity(list.len());
+            for no in list {
+                let invoice = &invoices[*no];
+                if invoice.iface.as_ref() != Some(iface) || invoice.operation != first.operation {
+                    return Err(ComposeError::InconsistentInvoices(contract_id).into());
+                }
+                let assignment_name = invoice
+                    .assignment
+                    .as_ref()
+                    .or_else(|| main_builder.default_assignment().ok())
+                    .ok_or(BuilderError::NoDefaultAssignment)?
+                    .clone();
+                let assignment_id = main_builder
+                    .assignments_type(&assignment_name)
+                    .ok_or(BuilderError::InvalidStateField(assignment_name.clone()))?;
+
+                let layer1 = invoice.beneficiary.chain_network().layer1();
+                let beneficiary = match (invoice.beneficiary.into_inner(), beneficiary_vout(*no)) {
+                    (Beneficiary::BlindedSeal(seal), _) => {
+                        BuilderSeal::Concealed(XChain::with(layer1, seal))
+                    }
+                    (Beneficiary::WitnessVout(_), Some(vout)) => {
+                        BuilderSeal::Revealed(XChain::with(
+                            layer1,
+                            GraphSeal::with_blinded_vout(
+                                method,
+                                vout,
+                                seal_blinder(contract_id, assignment_id),
+                            ),
+                        ))
+                    }
+                    (Beneficiary::WitnessVout(_), None) => {
+                        return Err(ComposeError::NoBeneficiaryOutput.into());
+                    }
+                };
+                requests.push((assignment_id, beneficiary, invoice.owned_state.clone()));
             }
-        }
-        let contract_id = invoice.contract.ok_or(ComposeError::NoContract)?;
-        let iface = invoice.iface.as_ref().ok_or(ComposeError::NoIface)?;
-        let mut main_builder =
-            self.transition_builder(contract_id, iface.clone(), invoice.operation.clone())?;
-        let assignment_name = invoice
-            .assignment
-            .as_ref()
-            .or_else(|| main_builder.default_assignment().ok())
-            .ok_or(BuilderError::NoDefaultAssignment)?
-            .clone();
-        let assignment_id = main_builder
-            .assignments_type(&assignment_name)
-            .ok_or(BuilderError::InvalidStateField(assignment_name.clone()))?;
-
-        let layer1 = invoice.beneficiary.chain_network().layer1();
-        let beneficiary = match (invoice.beneficiary.into_inner(), beneficiary_vout) {
-            (Beneficiary::BlindedSeal(seal), _) => {
-                BuilderSeal::Concealed(XChain::with(layer1, seal))
-            }
-            (Beneficiary::WitnessVout(_), Some(vout)) => BuilderSeal::Revealed(XChain::with(
-                layer1,
-                GraphSeal::with_blinded_vout(
-                    method,
-                    vout,
-                    seal_blinder(contract_id, assignment_id),
-                ),
-            )),
-            (Beneficiary::WitnessVout(_), None) => {
-                return Err(ComposeError::NoBeneficiaryOutput.into());
+            let requested = requests
+                .iter()
+                .map(|(assignment_id, _, _)| *assignment_id)
+                .collect::<BTreeSet<_>>();
+            // State of the non-invoiced types is re-blinded with the blinding
+            // factor of the (first) invoiced type
+            let invoiced_type = requests[0].0;
+
+            let mut main_inputs = Vec::<XOutputSeal>::new();
+            let mut sum_inputs = BTreeMap::<AssignmentType, Amount>::new();
+            // Non-fungible state of the invoiced types, part of which is paid back
+            // as a change
+            let mut state_inputs = BTreeMap::<AssignmentType, Vec<PersistedState>>::new();
+            for (output, list) in
+                self.contract_assignments_for(contract_id, prev_outputs.iter().copied())?
+            {
+                main_inputs.push(output);
+                for (opout, mut state) in list {
+                    main_builder = main_builder.add_input(opout, state.clone())?;
+                    if !requested.contains(&opout.ty) {
+                        let seal = output_for_assignment(contract_id, opout.ty)?;
+                        state.update_blinding(pedersen_blinder(contract_id, invoiced_type));
+                        main_builder = main_builder.add_owned_state_raw(opout.ty, seal, state)?;
+                    } else if let PersistedState::Amount(value, _, _) = state {
+                        *sum_inputs.entry(opout.ty).or_insert(Amount::ZERO) += value;
+                    } else {
+                        state_inputs.entry(opout.ty).or_default().push(state);
+                    }
+                }
             }
-        };
 
-        // 2. Prepare transition
-        let mut main_inputs = Vec::<XOutputSeal>::new();
-        let mut sum_inputs = Amount::ZERO;
-        let mut data_inputs = vec![];
-        for (output, list) in
-            self.contract_assignments_for(contract_id, prev_outputs.iter().copied())?
-        {
-            main_inputs.push(output);
-            for (opout, mut state) in list {
-                main_builder = main_builder.add_input(opout, state.clone())?;
-                if opout.ty != assignment_id {
-                    let seal = output_for_assignment(contract_id, opout.ty)?;
-                    state.update_blinding(pedersen_blinder(contract_id, assignment_id));
-                    main_builder = main_builder.add_owned_state_raw(opout.ty, seal, state)?;
-                } else if let PersistedState::Amount(value, _, _) = state {
-                    sum_inputs += value;
-                } else if let PersistedState::Data(value, _) = state {
-                    data_inputs.push(value);
+            // Sum up the state requested by the beneficiaries, taking the requested
+            // rights and attachments out of the inputs
+            let mut sum_outputs = BTreeMap::<AssignmentType, Amount>::new();
+            let mut fractions = BTreeMap::<(AssignmentType, TokenIndex), OwnedFraction>::new();
+            let mut payments = Vec::with_capacity(requests.len());
+            for (assignment_id, beneficiary, owned_state) in requests {
+                let payment = match owned_state {
+                    InvoiceState::Amount(amt) => {
+                        *sum_outputs.entry(assignment_id).or_insert(Amount::ZERO) += amt;
+                        Payment::Amount(amt)
+                    }
+                    InvoiceState::Data(NonFungible::RGB21(allocation)) => {
+                        *fractions
+                            .entry((assignment_id, allocation.token_index()))
+                            .or_default() += allocation.fraction();
+                        Payment::Allocation(allocation)
+                    }
+                    InvoiceState::Void => {
+                        take_state(&mut state_inputs, assignment_id, |state| {
+                            matches!(state, PersistedState::Void).then_some(())
+                        })?;
+                        Payment::Rights
+                    }
+                    InvoiceState::Attach(attach_id) => {
+                        let attachment =
+                            take_state(&mut state_inputs, assignment_id, |state| match state {
+                                PersistedState::Attachment(attach, _) if attach.id == attach_id => {
+                                    Some(attach.clone())
+                                }
+                                _ => None,
+                            })?;
+                        Payment::Attachment(attachment)
+                    }
+                };
+                payments.push((assignment_id, beneficiary, payment));
+            }
+
+            // Add change
+            for (assignment_id, sum_inputs) in sum_inputs {
+                let sum_outputs = sum_outputs.remove(&assignment_id).unwrap_or(Amount::ZERO);
+                if let Some(change) = change(sum_inputs, sum_outputs)? {
+                    let seal = output_for_assignment(contract_id, assignment_id)?;
+                    main_builder = main_builder.add_fungible_state_raw(
+                        assignment_id,
+                        seal,
+                        change,
+                        pedersen_blinder(contract_id, assignment_id),
+                    )?;
                 }
             }
-        }
-        // Add change
-        let main_transition = match invoice.owned_state.clone() {
-            InvoiceState::Amount(amt) => {
-                match sum_inputs.cmp(&amt) {
-                    Ordering::Greater => {
-                        let seal = output_for_assignment(contract_id, assignment_id)?;
-                        main_builder = main_builder.add_fungible_state_raw(
-                            assignment_id,
-                            seal,
-                            sum_inputs - amt,
-                            pedersen_blinder(contract_id, assignment_id),
-                        )?;
-                    }
-                    Ordering::Less => return Err(ComposeError::InsufficientState.into()),
-                    Ordering::Equal => {}
+            if !sum_outputs.is_empty() {
+                return Err(ComposeError::InsufficientState.into());
+            }
+            // Token fractions owned by the inputs are summed up, and the part not
+            // paid to the beneficiaries is returned as a change
+            for ((assignment_id, token_index), requested) in fractions {
+                let owned = take_fractions(&mut state_inputs, assignment_id, token_index);
+                if let Some(change) = change(owned, requested)? {
+                    let seal = output_for_assignment(contract_id, assignment_id)?;
+                    main_builder = main_builder.add_data_raw(
+                        assignment_id,
+                        seal,
+                        Allocation::with(token_index, change),
+                        seal_blinder(contract_id, assignment_id),
+                    )?;
+                }
+            }
+            for (assignment_id, list) in state_inputs {
+                for state in list {
+                    let seal = output_for_assignment(contract_id, assignment_id)?;
+                    main_builder = main_builder.add_owned_state_raw(assignment_id, seal, state)?;
                 }
-                main_builder
-                    .add_fungible_state_raw(
+            }
+
+            // Pay to the beneficiaries
+            for (assignment_id, beneficiary, payment) in payments {
+                main_builder = match payment {
+                    Payment::Amount(amt) => main_builder.add_fungible_state_raw(
                         assignment_id,
                         beneficiary,
                         amt,
                         pedersen_blinder(contract_id, assignment_id),
-                    )?
-                    .complete_transition()?
+                    )?,
+                    Payment::Allocation(allocation) => main_builder.add_data_raw(
+                        assignment_id,
+                        beneficiary,
+                        allocation,
+                        seal_blinder(contract_id, assignment_id),
+                    )?,
+                    Payment::Rights => main_builder.add_rights_raw(assignment_id, beneficiary)?,
+                    Payment::Attachment(attachment) => main_builder.add_attachment_raw(
+                        assignment_id,
+                        beneficiary,
+                        attachment,
+                        seal_blinder(contract_id, assignment_id),
+                    )?,
+                };
             }
-            InvoiceState::Data(data) => match data {
-                NonFungible::RGB21(allocation) => {
-                    if !data_inputs.into_iter().any(|x| x == allocation.into()) {
-                        return Err(ComposeError::InsufficientState.into());
-                    }
 
-                    main_builder
-                        .add_data_raw(
-                            assignment_id,
-                            beneficiary,
-                            allocation,
-                            seal_blinder(contract_id, assignment_id),
-                        )?
-                        .complete_transition()?
-                }
-            },
-            _ => {
-                todo!("only TypedState::Amount and TypedState::Allocation are currently supported")
-            }
-        };
+            let transition = main_builder.complete_transition()?;
+            let info = TransitionInfo::new(transition, main_inputs)
+                .map_err(|_| ComposeError::TooManyInputs)?;
+            transitions.push(info);
+        }
 
         // 3. Prepare other transitions
         // Enumerate state
         let mut spent_state =
             HashMap::<ContractId, HashMap<XOutputSeal, HashMap<Opout, PersistedState>>>::new();
         for id in self.contracts_assigning(prev_outputs.iter().copied())? {
-            // Skip current contract
-            if id == contract_id {
+            // Skip invoiced contracts
+            if groups.iter().any(|(contract_id, _)| *contract_id == id) {
                 continue;
             }
             let state = self.contract_assignments_for(id, prev_outputs.iter().copied())?;
@@ -901,9 +1353,17 @@ impl<S: StashProvider, H: StateProvider, P: IndexProvider> Stock<S, H, P> {
         }
 
         // Construct blank transitions
-        let mut blanks = Confined::<Vec<_>, 0, { U24 - 1 }>::with_capacity(spent_state.len());
+        let mut transitions = transitions.into_iter();
+        let main = transitions.next().expect("at least one invoice is present");
+        let mut blanks = Confined::<Vec<_>, 0, { U24 - 1 }>::with_capacity(
+            transitions.len() + spent_state.len(),
+        );
+        for info in transitions {
+            blanks.push(info).map_err(|_| ComposeError::TooManyBlanks)?;
+        }
+        let iface = invoices[0].iface.as_ref().ok_or(ComposeError::NoIface)?;
         for (id, list) in spent_state {
-            let mut blank_builder = self.blank_builder(id, iface.clone())?;
+            let mut blank_builder = self.blank_builder(id, self.blank_iface(id, iface)?)?;
             let mut outputs = Vec::with_capacity(list.len());
             for (output, assigns) in list {
                 outputs.push(output);
@@ -921,94 +1381,1625 @@ impl<S: StashProvider, H: StateProvider, P: IndexProvider> Stock<S, H, P> {
             blanks.push(info).map_err(|_| ComposeError::TooManyBlanks)?;
         }
 
-        let main = TransitionInfo::new(main_transition, main_inputs)
-            .map_err(|_| ComposeError::TooManyInputs)?;
         Ok(Batch { main, blanks })
     }
 
-    pub fn import_kit(&mut self, kit: ValidKit) -> Result<validation::Status, StockError<S, H, P>> {
-        let (kit, status) = kit.split();
-        self.stash.consume_kit(kit)?;
-        Ok(status)
+    /// Selects interface for constructing a blank state transition of the
+    /// contract: the preferred interface if the contract implements it, or
+    /// any other interface implemented by the contract.
+    fn blank_iface(
+        &self,
+        contract_id: ContractId,
+        preferred: &IfaceRef,
+    ) -> Result<IfaceRef, StockError<S, H, P, ComposeError>> {
+        let schema_id = self.stash.genesis(contract_id)?.schema_id;
+        let iimpls = &self.stash.schema(schema_id)?.iimpls;
+        if let Ok(iface) = self.stash.iface(preferred.clone()) {
+            if iimpls.contains_key(&iface.iface_id()) {
+                return Ok(preferred.clone());
+            }
+        }
+        let iface_id = iimpls
+            .keys()
+            .next()
+            .ok_or(ComposeError::NoBlankIface(contract_id))?;
+        Ok(IfaceRef::Id(*iface_id))
     }
 
-    pub fn import_contract<R: ResolveHeight>(
-        &mut self,
-        contract: ValidContract,
-        resolver: &mut R,
-    ) -> Result<validation::Status, StockError<S, H, P>> {
-        self.consume_consignment(contract, resolver)
+    /// Composes a batch of state transitions paying the invoice in the same way
+    /// as [`Self::compose`], and reports what the batch will do once signed:
+    /// which allocations are spent, which state goes to the beneficiary, which
+    /// change is returned to which output and with which velocity hint, and
+    /// which contracts get blank state transitions.
+    ///
+    /// The stock is not modified, and the batch may be discarded if the user
+    /// declines the payment.
+    #[allow(clippy::result_large_err)]
+    pub fn compose_preview(
+        &self,
+        invoice: &RgbInvoice,
+        prev_outputs: impl IntoIterator<Item = impl Into<XOutputSeal>>,
+        method: CloseMethod,
+        beneficiary_vout: Option<impl Into<Vout>>,
+        allocator: impl Fn(ContractId, AssignmentType, VelocityHint) -> Option<Vout>,
+    ) -> Result<(Batch, ComposePreview), StockError<S, H, P, ComposeError>> {
+        let beneficiary_vout: Option<Vout> = beneficiary_vout.map(|vout| vout.into());
+        self.compose_many_preview(
+            slice::from_ref(invoice),
+            prev_outputs,
+            method,
+            |_| beneficiary_vout,
+            allocator,
+        )
     }
 
-    pub fn accept_transfer<R: ResolveHeight>(
-        &mut self,
-        contract: ValidTransfer,
-        resolver: &mut R,
-    ) -> Result<validation::Status, StockError<S, H, P>> {
-        self.consume_consignment(contract, resolver)
+    /// Composes a batch of state transitions paying multiple invoices in the
+    /// same way as [`Self::compose_many`], and reports what the batch will do
+    /// once signed (see [`Self::compose_preview`]).
+    ///
+    /// The state transition for the contract of the first invoice is reported
+    /// as [`ComposePreview::main`].
+    #[allow(clippy::result_large_err)]
+    pub fn compose_many_preview(
+        &self,
+        invoices: &[RgbInvoice],
+        prev_outputs: impl IntoIterator<Item = impl Into<XOutputSeal>>,
+        method: CloseMethod,
+        beneficiary_vout: impl Fn(usize) -> Option<Vout>,
+        allocator: impl Fn(ContractId, AssignmentType, VelocityHint) -> Option<Vout>,
+    ) -> Result<(Batch, ComposePreview), StockError<S, H, P, ComposeError>> {
+        // Seals assigned back to the wallet together with the velocity they
+        // were allocated with; all other seals belong to the beneficiaries
+        let changes = RefCell::new(BTreeMap::new());
+        let batch = self.compose_tracked(
+            invoices,
+            prev_outputs,
+            method,
+            beneficiary_vout,
+            allocator,
+            |_, _| BlindingFactor::random(),
+            |_, _| rand::random(),
+            |seal, velocity| {
+                changes.borrow_mut().insert(seal, velocity);
+            },
+        )?;
+        let changes = changes.into_inner();
+
+        let allocation = allocation_sem_id();
+        let main = self.transition_preview(&batch.main, &changes, allocation)?;
+        let others = batch
+            .blanks
+            .iter()
+            .map(|info| self.transition_preview(info, &changes, allocation))
+            .collect::<Result<_, _>>()?;
+        Ok((batch, ComposePreview { main, others }))
     }
 
-    fn consume_consignment<R: ResolveHeight, const TRANSFER: bool>(
-        &mut self,
-        consignment: ValidConsignment<TRANSFER>,
-        resolver: &mut R,
-    ) -> Result<validation::Status, StockError<S, H, P>> {
-        let contract_id = consignment.contract_id();
-        let (mut consignment, status) = consignment.split();
-
-        consignment = self.stash.resolve_secrets(consignment)?;
-        self.state
-            .create_or_update_state::<R>(contract_id, |history| {
-                consignment.update_history(history, resolver)
-            })?;
-        self.index.index_consignment(&consignment)?;
-        self.stash.consume_consignment(consignment)?;
+    #[allow(clippy::result_large_err)]
+    fn transition_preview(
+        &self,
+        info: &TransitionInfo,
+        changes: &BTreeMap<XChain<GraphSeal>, VelocityHint>,
+        allocation: SemId,
+    ) -> Result<ContractPreview, StockError<S, H, P>> {
+        let contract_id = info.transition.contract_id;
+
+        let mut opouts = BTreeSet::new();
+        for input in &info.transition.inputs {
+            opouts.insert(input.prev_out);
+        }
+        let mut spent = Vec::with_capacity(opouts.len());
+        for (seal, assigns) in
+            self.contract_assignments_for(contract_id, info.inputs.iter().copied())?
+        {
+            for (opout, state) in assigns {
+                if opouts.contains(&opout) {
+                    spent.push(SpentAllocation { opout, seal, state });
+                }
+            }
+        }
+        spent.sort_by_key(|allocation| allocation.opout);
+
+        let mut payments = vec![];
+        let mut change = vec![];
+        for (assignment_type, assigns) in info.transition.assignments.iter() {
+            let assignment_type = *assignment_type;
+            for (seal, state) in assigned_state(assigns) {
+                let velocity = match seal {
+                    BuilderSeal::Revealed(seal) => changes.get(&seal).map(|v| (seal, *v)),
+                    BuilderSeal::Concealed(_) => None,
+                };
+                match velocity {
+                    Some((XChain::Bitcoin(seal) | XChain::Liquid(seal), velocity)) => {
+                        change.push(ChangePreview {
+                            assignment_type,
+                            vout: seal.vout,
+                            velocity,
+                            state,
+                        })
+                    }
+                    _ => payments.push(PaymentPreview {
+                        assignment_type,
+                        beneficiary: seal,
+                        state,
+                    }),
+                }
+            }
+        }
+
+        Ok(ContractPreview {
+            contract_id,
+            opid: info.id,
+            spent,
+            payments,
+            change,
+            fractions: self.fraction_types(contract_id, allocation)?,
+        })
+    }
+
+    /// Detects assignment types holding RGB21 token allocations, which are
+    /// declared by any of the interfaces implemented by the contract.
+    #[allow(clippy::result_large_err)]
+    fn fraction_types(
+        &self,
+        contract_id: ContractId,
+        allocation: SemId,
+    ) -> Result<BTreeSet<AssignmentType>, StockError<S, H, P>> {
+        let schema_id = self.stash.genesis(contract_id)?.schema_id;
+        let mut fractions = BTreeSet::new();
+        for (iface_id, iimpl) in &self.stash.schema(schema_id)?.iimpls {
+            let iface = self.stash.iface(*iface_id)?;
+            for (name, assign) in &iface.assignments {
+                if assign.owned_state == OwnedIface::Data(allocation) {
+                    fractions.extend(iimpl.assignments_type(name));
+                }
+            }
+        }
+        Ok(fractions)
+    }
+
+    /// Selects outputs from the `candidates` which should be spent for paying
+    /// the invoice with [`Self::compose`].
+    ///
+    /// For fungible state and fractions of RGB21 tokens the outputs are
+    /// selected by the `strategy`. For other state a single output holding the
+    /// requested state is selected, preferring the one which assigns state of
+    /// the least number of other contracts.
+    #[allow(clippy::result_large_err)]
+    pub fn select_outputs(
+        &self,
+        invoice: &RgbInvoice,
+        candidates: impl IntoIterator<Item = impl Into<XOutputSeal>>,
+        strategy: &impl SelectionStrategy,
+    ) -> Result<BTreeSet<XOutputSeal>, StockError<S, H, P, ComposeError>> {
+        let contract_id = invoice.contract.ok_or(ComposeError::NoContract)?;
+        let iface = invoice.iface.as_ref().ok_or(ComposeError::NoIface)?;
+        let builder =
+            self.transition_builder(contract_id, iface.clone(), invoice.operation.clone())?;
+        let assignment_name = invoice
+            .assignment
+            .as_ref()
+            .or_else(|| builder.default_assignment().ok())
+            .ok_or(BuilderError::NoDefaultAssignment)?
+            .clone();
+        let assignment_id = builder
+            .assignments_type(&assignment_name)
+            .ok_or(BuilderError::InvalidStateField(assignment_name))?;
 
+        let target = match invoice.owned_state.clone() {
+            InvoiceState::Amount(amt) => Some(amt),
+            InvoiceState::Data(NonFungible::RGB21(allocation)) => {
+                Some(Amount::from(allocation.fraction().into_inner()))
+            }
+            _ => None,
+        };
+
+        let candidates = candidates
+            .into_iter()
+            .map(|o| o.into())
+            .collect::<BTreeSet<XOutputSeal>>();
+        let mut selectable = vec![];
+        for (output, assignments) in
+            self.contract_assignments_for(contract_id, candidates.iter().copied())?
+        {
+            let mut amount = Amount::ZERO;
+            let mut holds = false;
+            for (opout, state) in assignments {
+                if opout.ty != assignment_id {
+                    continue;
+                }
+                match (&invoice.owned_state, state) {
+                    (InvoiceState::Amount(_), PersistedState::Amount(value, _, _)) => {
+                        amount += value;
+                        holds = true;
+                    }
+                    (
+                        InvoiceState::Data(NonFungible::RGB21(requested)),
+                        PersistedState::Data(value, _),
+                    ) => {
+                        if let Some(allocation) = Allocation::from_data_state(value)
+                            .filter(|a| a.token_index() == requested.token_index())
+                        {
+                            amount += Amount::from(allocation.fraction().into_inner());
+                            holds = true;
+                        }
+                    }
+                    (InvoiceState::Void, PersistedState::Void) => holds = true,
+                    (InvoiceState::Attach(attach_id), PersistedState::Attachment(attach, _)) => {
+                        holds |= attach.id == *attach_id;
+                    }
+                    _ => {}
+                }
+            }
+            if !holds {
+                continue;
+            }
+            let other_contracts = self
+                .contracts_assigning([output])?
+                .filter(|id| *id != contract_id)
+                .collect();
+            selectable.push(SelectionCandidate {
+                output,
+                amount,
+                other_contracts,
+            });
+        }
+
+        let selected = match target {
+            Some(target) => strategy.select(target, &selectable),
+            None => selectable
+                .iter()
+                .min_by_key(|c| c.other_contracts.len())
+                .map(|c| vec![c.output]),
+        };
+        let selected = selected.ok_or(ComposeError::InsufficientState)?;
+        Ok(selected.into_iter().collect())
+    }
+
+    pub fn import_kit(&mut self, kit: ValidKit) -> Result<validation::Status, StockError<S, H, P>> {
+        let (kit, status) = kit.split();
+        let mut events = vec![];
+        for suppl in &kit.supplements {
+            self.suppl_event(suppl, &mut events)?;
+        }
+        let mutation = self.audit_mutation(|| {
+            Ok(StockMutation::KitImported {
+                kit_id: kit.kit_id(),
+                ifaces: Confined::try_from_iter(kit.ifaces.iter().map(Iface::iface_id))?,
+                schemata: Confined::try_from_iter(kit.schemata.iter().map(|s| s.schema_id()))?,
+            })
+        })?;
+        self.stash.consume_kit(kit)?;
+        self.log_mutation(AuditValidity::from(&status), mutation)?;
+        self.observers.notify(&events);
         Ok(status)
     }
 
-    /// Imports fascia into the stash, index and inventory.
-    ///
-    /// Part of the transfer workflow. Called once PSBT is completed and an RGB
-    /// fascia containing anchor and all state transitions is exported from
-    /// it.
+    pub fn import_contract<R: ResolveHeight>(
+        &mut self,
+        contract: ValidContract,
+        resolver: &mut R,
+    ) -> Result<validation::Status, StockError<S, H, P>> {
+        self.consume_consignment(contract, resolver)
+    }
+
+    pub fn accept_transfer<R: ResolveHeight>(
+        &mut self,
+        contract: ValidTransfer,
+        resolver: &mut R,
+    ) -> Result<validation::Status, StockError<S, H, P>> {
+        self.consume_consignment(contract, resolver)
+    }
+
+    fn consume_consignment<R: ResolveHeight, const TRANSFER: bool>(
+        &mut self,
+        consignment: ValidConsignment<TRANSFER>,
+        resolver: &mut R,
+    ) -> Result<validation::Status, StockError<S, H, P>> {
+        let contract_id = consignment.contract_id();
+        let (mut consignment, status) = consignment.split();
+
+        let mutation = self.audit_mutation(|| {
+            let consignment_id = consignment.consignment_id();
+            let witness_ids =
+                Confined::try_from_iter(consignment.bundles.iter().map(|bw| bw.witness_id()))?;
+            Ok(if TRANSFER {
+                StockMutation::TransferAccepted {
+                    contract_id,
+                    consignment_id,
+                    witness_ids,
+                }
+            } else {
+                StockMutation::ContractImported {
+                    contract_id,
+                    consignment_id,
+                    witness_ids,
+                }
+            })
+        })?;
+
+        consignment = self.stash.resolve_secrets(consignment)?;
+
+        let mut events = vec![];
+        if !is_present(self.stash.genesis(contract_id))? {
+            events.push(StockEvent::ContractAdded { contract_id });
+        }
+        let mut transitions = vec![];
+        for bw in &consignment.bundles {
+            let witness_id = bw.witness_id();
+            self.witness_event(witness_id, &mut events)?;
+            for bundle in bw.bundles() {
+                self.bundle_event(contract_id, bundle, witness_id, &mut transitions, &mut events)?;
+            }
+        }
+        for suppl in &consignment.supplements {
+            self.suppl_event(suppl, &mut events)?;
+        }
+        let allocations = self.allocations(contract_id)?;
+
+        let status = self.transaction(|stock| {
+            stock
+                .state
+                .create_or_update_state::<R>(contract_id, |history| {
+                    consignment.update_history(history, resolver)
+                })?;
+            stock.index.index_consignment(&consignment)?;
+            stock.stash.consume_consignment(consignment)?;
+            Ok(status)
+        })?;
+
+        self.log_mutation(AuditValidity::from(&status), mutation)?;
+        self.allocation_events(contract_id, &allocations, &transitions, &mut events)?;
+        self.observers.notify(&events);
+        Ok(status)
+    }
+
+    /// Imports fascia into the stash, index and inventory.
+    ///
+    /// Part of the transfer workflow. Called once PSBT is completed and an RGB
+    /// fascia containing anchor and all state transitions is exported from
+    /// it.
+    ///
+    /// Must be called before the consignment is created, when witness
+    /// transaction is not yet mined.
+    pub fn consume_fascia(
+        &mut self,
+        fascia: Fascia,
+    ) -> Result<(), StockError<S, H, P, FasciaError>> {
+        let witness_id = fascia.witness_id;
+        let anchor = fascia.anchor.clone();
+        let bundles = fascia.into_bundles().into_iter().collect::<Vec<_>>();
+        let mutation = self.audit_mutation(|| {
+            Ok(StockMutation::FasciaConsumed {
+                witness_id,
+                bundles: Confined::try_from_iter(
+                    bundles
+                        .iter()
+                        .map(|(contract_id, bundle)| (*contract_id, bundle.bundle_id())),
+                )?,
+            })
+        })?;
+
+        let mut events = vec![];
+        let mut transitions = BTreeMap::<ContractId, Vec<Transition>>::new();
+        let mut allocations = BTreeMap::new();
+        self.witness_event(witness_id, &mut events)?;
+        for (contract_id, bundle) in &bundles {
+            let list = transitions.entry(*contract_id).or_default();
+            self.bundle_event(*contract_id, bundle, witness_id, list, &mut events)?;
+            if let Entry::Vacant(entry) = allocations.entry(*contract_id) {
+                entry.insert(self.allocations(*contract_id)?);
+            }
+        }
+
+        self.transaction(|stock| {
+            stock
+                .stash
+                .consume_witness(SealWitness::new(witness_id, anchor))?;
+
+            for (contract_id, bundle) in bundles {
+                let ids1 = bundle
+                    .known_transitions
+                    .keys()
+                    .copied()
+                    .collect::<BTreeSet<_>>();
+                let ids2 = bundle.input_map.values().copied().collect::<BTreeSet<_>>();
+                if !ids1.is_subset(&ids2) {
+                    return Err(FasciaError::InvalidBundle(contract_id, bundle.bundle_id()).into());
+                }
+
+                stock.index.index_bundle(contract_id, &bundle, witness_id)?;
+
+                stock
+                    .state
+                    .update_state::<DumbResolver>(contract_id, |history| {
+                        for transition in bundle.known_transitions.values() {
+                            let witness_anchor = WitnessAnchor::from_mempool(witness_id);
+                            history.add_transition(transition, witness_anchor);
+                        }
+                        Ok(())
+                    })?;
+
+                stock.stash.consume_bundle(bundle)?;
+            }
+            Ok(())
+        })?;
+
+        self.log_mutation(AuditValidity::NotApplicable, mutation)?;
+        for (contract_id, allocations) in allocations {
+            let transitions = &transitions[&contract_id];
+            self.allocation_events(contract_id, &allocations, transitions, &mut events)?;
+        }
+        self.observers.notify(&events);
+        Ok(())
+    }
+
+    fn witness_event(
+        &self,
+        witness_id: XWitnessId,
+        events: &mut Vec<StockEvent>,
+    ) -> Result<(), StashError<S>> {
+        if !events.contains(&StockEvent::WitnessAdded { witness_id }) &&
+            !is_present(self.stash.witness(witness_id))?
+        {
+            events.push(StockEvent::WitnessAdded { witness_id });
+        }
+        Ok(())
+    }
+
+    /// Reports bundle unknown to the stash, collecting its transitions.
+    fn bundle_event(
+        &self,
+        contract_id: ContractId,
+        bundle: &TransitionBundle,
+        witness_id: XWitnessId,
+        transitions: &mut Vec<Transition>,
+        events: &mut Vec<StockEvent>,
+    ) -> Result<(), StashError<S>> {
+        let bundle_id = bundle.bundle_id();
+        if is_present(self.stash.bundle(bundle_id))? {
+            return Ok(());
+        }
+        events.push(StockEvent::BundleAdded {
+            contract_id,
+            bundle_id,
+            witness_id,
+        });
+        transitions.extend(bundle.known_transitions.values().cloned());
+        Ok(())
+    }
+
+    fn suppl_event(
+        &self,
+        suppl: &ContractSuppl,
+        events: &mut Vec<StockEvent>,
+    ) -> Result<(), StockError<S, H, P>> {
+        let contract_id = suppl.contract_id;
+        if !self
+            .stash
+            .contract_supplements(contract_id)?
+            .any(|known| &known == suppl)
+        {
+            events.push(StockEvent::SupplAdded { contract_id });
+        }
+        Ok(())
+    }
+
+    /// Collects seals of all allocations known to the contract state.
+    fn allocations(
+        &self,
+        contract_id: ContractId,
+    ) -> Result<BTreeMap<Opout, XOutputSeal>, StockError<S, H, P>> {
+        let Some(history) = self
+            .state
+            .contract_state(contract_id)
+            .map_err(StockError::StateRead)?
+        else {
+            return Ok(none!());
+        };
+        Ok(history
+            .fungibles()
+            .map(|a| (a.opout, a.seal))
+            .chain(history.data().map(|a| (a.opout, a.seal)))
+            .chain(history.rights().map(|a| (a.opout, a.seal)))
+            .chain(history.attach().map(|a| (a.opout, a.seal)))
+            .collect())
+    }
+
+    /// Reports allocations spent by the new transitions and the allocations
+    /// which have appeared in the contract state since it had the `before`
+    /// allocations.
+    fn allocation_events(
+        &self,
+        contract_id: ContractId,
+        before: &BTreeMap<Opout, XOutputSeal>,
+        transitions: &[Transition],
+        events: &mut Vec<StockEvent>,
+    ) -> Result<(), StockError<S, H, P>> {
+        let after = self.allocations(contract_id)?;
+        for transition in transitions {
+            let spender = transition.id();
+            for input in &transition.inputs {
+                let opout = input.prev_out;
+                if let Some(seal) = before.get(&opout).or_else(|| after.get(&opout)) {
+                    events.push(StockEvent::AllocationSpent {
+                        contract_id,
+                        opout,
+                        seal: *seal,
+                        spender,
+                    });
+                }
+            }
+        }
+        for (opout, seal) in after {
+            if !before.contains_key(&opout) {
+                events.push(StockEvent::AllocationReceived {
+                    contract_id,
+                    opout,
+                    seal,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs `f` as a single transaction over the stash, contract state and
+    /// index, such that if it fails all changes it has made to any of the
+    /// providers are reverted.
+    fn transaction<T, E: Error>(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> Result<T, StockError<S, H, P, E>>,
+    ) -> Result<T, StockError<S, H, P, E>> {
+        self.stash.begin_transaction()?;
+        if let Err(err) = self.state.begin_transaction() {
+            self.stash.rollback_transaction().ok();
+            return Err(StockError::StateWrite(err));
+        }
+        if let Err(err) = self.index.begin_transaction() {
+            self.state.rollback_transaction().ok();
+            self.stash.rollback_transaction().ok();
+            return Err(err.into());
+        }
+
+        // All providers are prepared before any of them is committed, so a failure
+        // to prepare one of them reverts the changes made to all of them.
+        let res = f(self).and_then(|res| {
+            self.index.prepare_transaction()?;
+            self.state
+                .prepare_transaction()
+                .map_err(StockError::StateWrite)?;
+            self.stash.prepare_transaction()?;
+            Ok(res)
+        });
+        if res.is_err() {
+            // Rollback failures are not reported since they would hide the
+            // original error.
+            self.index.rollback_transaction().ok();
+            self.state.rollback_transaction().ok();
+            self.stash.rollback_transaction().ok();
+            return res;
+        }
+
+        // Prepared transactions are not reverted, so all providers are committed
+        // even if some of them fail.
+        let index = self.index.commit_transaction();
+        let state = self.state.commit_transaction();
+        let stash = self.stash.commit_transaction();
+        index?;
+        state.map_err(StockError::StateWrite)?;
+        stash?;
+        res
+    }
+
+    /// Removes contract from the stock, including its genesis, bundles,
+    /// extensions, supplements, contract state and index data.
+    ///
+    /// Witnesses are removed only if they do not anchor bundles of other
+    /// contracts. Schemata, interfaces, libraries, types, attachments and
+    /// signatures are kept, since they may be used by other contracts.
+    pub fn forget_contract(&mut self, contract_id: ContractId) -> Result<(), StockError<S, H, P>> {
+        self.drop_contract(contract_id, false)
+    }
+
+    fn drop_contract(
+        &mut self,
+        contract_id: ContractId,
+        archived: bool,
+    ) -> Result<(), StockError<S, H, P>> {
+        let genesis_id = self.stash.genesis(contract_id)?.id();
+        let (mut anchored, mut unanchored) = self.stash_bundles()?;
+        let bundles = anchored.remove(&contract_id).unwrap_or_default();
+        let unanchored = unanchored.remove(&contract_id).unwrap_or_default();
+        let extensions = self
+            .stash_extensions()?
+            .remove(&contract_id)
+            .unwrap_or_default();
+
+        let mut opids = bset![genesis_id];
+        opids.extend(extensions.iter().copied());
+        for bundle_id in bundles.keys().chain(&unanchored) {
+            let bundle = self.stash.bundle(*bundle_id)?;
+            opids.extend(bundle.input_map.values().copied());
+            opids.extend(bundle.known_transitions.keys().copied());
+        }
+
+        let remaining = self
+            .stash
+            .bundle_ids()?
+            .filter(|bundle_id| !bundles.contains_key(bundle_id) && !unanchored.contains(bundle_id))
+            .collect::<BTreeSet<_>>();
+        let mut witnesses = BTreeSet::new();
+        for witness_id in bundles.values().collect::<BTreeSet<_>>() {
+            let witness = self.stash.witness(*witness_id)?;
+            if !witness
+                .anchors
+                .known_bundle_ids()
+                .any(|bundle_id| remaining.contains(&bundle_id))
+            {
+                witnesses.insert(*witness_id);
+            }
+        }
+
+        let mutation = self.audit_mutation(|| {
+            let witness_ids = Confined::try_from_iter(witnesses.iter().copied())?;
+            Ok(if archived {
+                StockMutation::ContractArchived {
+                    contract_id,
+                    witness_ids,
+                }
+            } else {
+                StockMutation::ContractRemoved {
+                    contract_id,
+                    witness_ids,
+                }
+            })
+        })?;
+        self.transaction(|stock| {
+            stock.stash.remove_contract(
+                contract_id,
+                bundles.into_keys().chain(unanchored),
+                extensions,
+                witnesses,
+            )?;
+            stock
+                .state
+                .remove_state(contract_id)
+                .map_err(StockError::StateWrite)?;
+            stock.index.remove_contract(contract_id, &opids)?;
+            Ok(())
+        })?;
+        self.log_mutation(AuditValidity::NotApplicable, mutation)
+    }
+
+    /// Exports all data known about the contract as a contract consignment and
+    /// removes the contract from the stock (see [`Self::forget_contract`]).
+    ///
+    /// Unlike [`Self::export_contract`], the returned consignment includes the
+    /// whole known contract history, and not just its public part. Bundles
+    /// which are not anchored by any known witness can't be consigned and are
+    /// dropped.
+    pub fn archive_contract(
+        &mut self,
+        contract_id: ContractId,
+    ) -> Result<Contract, StockError<S, H, P, ConsignError>> {
+        let mut contract = self.export_contract(contract_id)?;
+
+        let mut bundles = BTreeMap::<XWitnessId, BundledWitness>::new();
+        let known = self
+            .stash_bundles()?
+            .0
+            .remove(&contract_id)
+            .unwrap_or_default();
+        let bundled_witnesses = contract.bundles.iter().cloned().map(Ok).chain(
+            known
+                .into_keys()
+                .map(|bundle_id| self.bundled_witness(bundle_id)),
+        );
+        for bw in bundled_witnesses {
+            let bw = bw?;
+            let witness_id = bw.witness_id();
+            match bundles.get_mut(&witness_id) {
+                Some(prev) => {
+                    *prev = prev.clone().merge_reveal(bw)?;
+                }
+                None => {
+                    bundles.insert(witness_id, bw);
+                }
+            }
+        }
+        contract.bundles = Confined::try_from_iter(bundles.into_values())
+            .map_err(|_| ConsignError::TooManyBundles)?;
+
+        let mut extensions = Vec::new();
+        for opid in self
+            .stash_extensions()?
+            .remove(&contract_id)
+            .unwrap_or_default()
+        {
+            extensions.push(self.stash.extension(opid)?.clone());
+        }
+        contract.extensions =
+            Confined::try_from_iter(extensions).map_err(|_| ConsignError::TooManyExtensions)?;
+        contract.supplements =
+            Confined::try_from_iter(self.stash.contract_supplements(contract_id)?)
+                .map_err(|_| ConsignError::TooManySupplements)?;
+
+        self.drop_contract(contract_id, true)?;
+        Ok(contract)
+    }
+
+    /// Merges into the stock all data known to the `other` stock: type system,
+    /// libraries, schemata, interfaces and their implementations, supplements,
+    /// geneses, extensions, witnesses, bundles, attachments, signatures and
+    /// secret seals.
+    ///
+    /// Data known to both stocks are merged by revealing their concealed
+    /// parts. Data which can't be merged, including bundles anchored to
+    /// different witnesses, are left intact and reported as conflicts; all
+    /// other data are moved in a single transaction, in which the index and
+    /// the contract state are also rebuilt using the resolver.
+    pub fn merge<S2: StashProvider, H2: StateProvider, P2: IndexProvider, R: ResolveHeight>(
+        &mut self,
+        other: &Stock<S2, H2, P2>,
+        resolver: &mut R,
+    ) -> Result<MergeReport, StockError<S, H, P, MergeError>> {
+        let source = |err: StashError<S2>| MergeError::Source(err.to_string());
+        let src = &other.stash;
+
+        let mut report = MergeReport::default();
+        let present = self.bundle_witnesses()?;
+        let merged = other
+            .bundle_witnesses()
+            .map_err(|err| MergeError::Source(err.to_string()))?;
+        // Witnesses which are not merged, together with all bundles they anchor
+        let mut skipped = report.check_bundle_witnesses(&present, &merged);
+
+        let mut events = vec![];
+        let mut transitions = BTreeMap::<ContractId, Vec<Transition>>::new();
+        let mut allocations = BTreeMap::new();
+        let contract_ids = src.contract_ids().map_err(source)?.collect::<BTreeSet<_>>();
+        for contract_id in contract_ids.iter().copied() {
+            if !is_present(self.stash.genesis(contract_id))? {
+                events.push(StockEvent::ContractAdded { contract_id });
+            }
+        }
+        let mutation = self.audit_mutation(|| {
+            Ok(StockMutation::StockMerged {
+                contract_ids: Confined::try_from_iter(contract_ids)?,
+            })
+        })?;
+        for witness_id in src.witness_ids().map_err(source)? {
+            if !skipped.contains(&witness_id) {
+                self.witness_event(witness_id, &mut events)?;
+            }
+        }
+        for (bundle_id, witness_id) in &merged {
+            if skipped.contains(witness_id) {
+                continue;
+            }
+            let bundle = src.bundle(*bundle_id).map_err(source)?;
+            let Some(contract_id) = bundle
+                .known_transitions
+                .values()
+                .next()
+                .map(|transition| transition.contract_id)
+            else {
+                continue;
+            };
+            let list = transitions.entry(contract_id).or_default();
+            self.bundle_event(contract_id, bundle, *witness_id, list, &mut events)?;
+            if let Entry::Vacant(entry) = allocations.entry(contract_id) {
+                entry.insert(self.allocations(contract_id)?);
+            }
+        }
+        for contract_id in src.suppl_contract_ids().map_err(source)? {
+            for suppl in src.contract_supplements(contract_id).map_err(source)? {
+                self.suppl_event(&suppl, &mut events)?;
+            }
+        }
+
+        // Witnesses are resolved beforehand, so the index and the contract state
+        // are rebuilt in the same transaction in which the data are merged
+        let mut witness_ids = self.stash.witness_ids()?.collect::<BTreeSet<_>>();
+        witness_ids.extend(src.witness_ids().map_err(source)?);
+        let witnesses = self.resolve_witness_ids(witness_ids, resolver)?;
+
+        self.transaction::<_, MergeError>(|stock| {
+            let types = other
+                .as_stash_provider()
+                .type_system()
+                .map_err(|err| MergeError::Source(err.to_string()))?;
+            stock.stash.consume_types(types.into_owned())?;
+            for lib_id in src.lib_ids().map_err(source)? {
+                stock
+                    .stash
+                    .consume_lib(src.lib(lib_id).map_err(source)?.clone())?;
+            }
+
+            for schema_ifaces in src.schemata().map_err(source)? {
+                stock.stash.consume_schema(schema_ifaces.schema.clone())?;
+            }
+            for (iface_id, _) in src.ifaces().map_err(source)? {
+                stock
+                    .stash
+                    .consume_iface(src.iface(iface_id).map_err(source)?.clone())?;
+            }
+            for schema_ifaces in src.schemata().map_err(source)? {
+                for iimpl in schema_ifaces.iimpls.values() {
+                    stock.stash.consume_iimpl(iimpl.clone())?;
+                }
+            }
+            for contract_id in src.suppl_contract_ids().map_err(source)? {
+                for suppl in src.contract_supplements(contract_id).map_err(source)? {
+                    stock.stash.consume_suppl(suppl)?;
+                }
+            }
+
+            for contract_id in src.contract_ids().map_err(source)? {
+                let genesis = src.genesis(contract_id).map_err(source)?.clone();
+                if let Some(reason) = merge_conflict(stock.stash.consume_genesis(genesis))? {
+                    report.push(MergeConflict::Genesis {
+                        contract_id,
+                        reason,
+                    });
+                }
+            }
+            for opid in src.extension_ids().map_err(source)? {
+                let extension = src.extension(opid).map_err(source)?.clone();
+                if let Some(reason) = merge_conflict(stock.stash.consume_extension(extension))? {
+                    report.push(MergeConflict::Extension { opid, reason });
+                }
+            }
+            for witness_id in src.witness_ids().map_err(source)? {
+                if skipped.contains(&witness_id) {
+                    continue;
+                }
+                let witness = src.witness(witness_id).map_err(source)?.clone();
+                if let Some(reason) = merge_conflict(stock.stash.consume_witness(witness))? {
+                    report.push(MergeConflict::Witness { witness_id, reason });
+                    skipped.insert(witness_id);
+                }
+            }
+            for bundle_id in src.bundle_ids().map_err(source)? {
+                if merged
+                    .get(&bundle_id)
+                    .is_some_and(|witness_id| skipped.contains(witness_id))
+                {
+                    continue;
+                }
+                let bundle = src.bundle(bundle_id).map_err(source)?.clone();
+                if let Some(reason) = merge_conflict(stock.stash.consume_bundle(bundle))? {
+                    report.push(MergeConflict::Bundle { bundle_id, reason });
+                }
+            }
+
+            for id in src.attachment_ids().map_err(source)? {
+                let attach = src.attachment(id).map_err(source)?.clone();
+                stock.stash.consume_attachment(id, attach)?;
+            }
+            for (content_id, sigs) in src.sigs().map_err(source)? {
+                // Do not bother if we can't import all the sigs
+                stock.stash.consume_sigs(content_id, sigs.clone()).ok();
+            }
+            let known = stock.stash.secret_seals()?.collect::<BTreeSet<_>>();
+            for (seal, info) in src.secret_seal_records().map_err(source)? {
+                if !known.contains(&seal) {
+                    stock.stash.store_secret_seal(seal, info)?;
+                }
+            }
+
+            stock.reindex()?;
+            stock.replay_state(&witnesses)?;
+            Ok(())
+        })?;
+
+        self.log_mutation(AuditValidity::NotApplicable, mutation)?;
+
+        for (contract_id, allocations) in allocations {
+            let transitions = &transitions[&contract_id];
+            self.allocation_events(contract_id, &allocations, transitions, &mut events)?;
+        }
+        self.observers.notify(&events);
+        Ok(report)
+    }
+
+    /// Creates full backup of the stock data which can't be re-computed: the
+    /// stash data and secret seals.
+    pub fn backup(&self) -> Result<Backup, StockError<S, H, P>> { self.collect_backup(None) }
+
+    /// Creates incremental backup containing only the data which were added
+    /// or updated since the last backup covered by the manifest.
+    ///
+    /// Incremental backups can't record removals, so the method fails if some
+    /// of the data covered by the manifest were removed from the stock since
+    /// then (see [`Self::forget_contract`] and [`Self::prune_secret_seals`]);
+    /// a full backup must be made instead.
+    pub fn backup_since(&self, base: &BackupManifest) -> Result<Backup, StockError<S, H, P>> {
+        self.collect_backup(Some(base))
+    }
+
+    fn collect_backup(&self, base: Option<&BackupManifest>) -> Result<Backup, StockError<S, H, P>> {
+        /// Selects data changed since the base backup, tracking all the data
+        /// present in the stock.
+        struct Diff<'a> {
+            base: Option<&'a BackupManifest>,
+            present: BTreeSet<BackupItem>,
+        }
+        impl Diff<'_> {
+            fn changed(&mut self, item: BackupItem, data: &impl StrictEncode) -> bool {
+                let changed = self
+                    .base
+                    .map_or(true, |base| !base.contains(&item, backup_digest(data)));
+                self.present.insert(item);
+                changed
+            }
+            fn add<K: Ord, V: StrictEncode + Clone>(
+                &mut self,
+                map: &mut BTreeMap<K, V>,
+                item: BackupItem,
+                id: K,
+                data: &V,
+            ) {
+                if self.changed(item, data) {
+                    map.insert(id, data.clone());
+                }
+            }
+        }
+        let mut diff = Diff {
+            base,
+            present: none!(),
+        };
+
+        let types = self
+            .stash
+            .as_provider()
+            .type_system()
+            .map_err(StockError::StashRead)?;
+        let types = diff
+            .changed(BackupItem::Types, &*types)
+            .then(|| types.into_owned());
+
+        let mut libs = BTreeMap::new();
+        for id in self.stash.lib_ids()? {
+            diff.add(&mut libs, BackupItem::Lib(id), id, self.stash.lib(id)?);
+        }
+        let mut schemata = BTreeMap::new();
+        let mut iimpls = BTreeMap::new();
+        for schema_ifaces in self.stash.schemata()? {
+            let schema_id = schema_ifaces.schema.schema_id();
+            let schema = &schema_ifaces.schema;
+            diff.add(&mut schemata, BackupItem::Schema(schema_id), schema_id, schema);
+            for iimpl in schema_ifaces.iimpls.values() {
+                let id = iimpl.impl_id();
+                diff.add(&mut iimpls, BackupItem::IfaceImpl(id), id, iimpl);
+            }
+        }
+        let mut ifaces = BTreeMap::new();
+        for (id, _) in self.stash.ifaces()? {
+            diff.add(&mut ifaces, BackupItem::Iface(id), id, self.stash.iface(id)?);
+        }
+        let mut supplements = BTreeMap::new();
+        for contract_id in self.stash.suppl_contract_ids()? {
+            for suppl in self.stash.contract_supplements(contract_id)? {
+                let id = suppl.suppl_id();
+                diff.add(&mut supplements, BackupItem::Suppl(id), id, &suppl);
+            }
+        }
+
+        let mut geneses = BTreeMap::new();
+        for id in self.stash.contract_ids()? {
+            diff.add(&mut geneses, BackupItem::Genesis(id), id, self.stash.genesis(id)?);
+        }
+        let mut extensions = BTreeMap::new();
+        for id in self.stash.extension_ids()? {
+            diff.add(&mut extensions, BackupItem::Extension(id), id, self.stash.extension(id)?);
+        }
+        let mut bundles = BTreeMap::new();
+        for id in self.stash.bundle_ids()? {
+            diff.add(&mut bundles, BackupItem::Bundle(id), id, self.stash.bundle(id)?);
+        }
+        let mut witnesses = BTreeMap::new();
+        for id in self.stash.witness_ids()? {
+            diff.add(&mut witnesses, BackupItem::Witness(id), id, self.stash.witness(id)?);
+        }
+        let mut attachments = BTreeMap::new();
+        for id in self.stash.attachment_ids()? {
+            diff.add(&mut attachments, BackupItem::Attachment(id), id, self.stash.attachment(id)?);
+        }
+        let mut signatures = BTreeMap::new();
+        for (id, sigs) in self.stash.sigs()? {
+            diff.add(&mut signatures, BackupItem::Sigs(id.clone()), id, sigs);
+        }
+        let mut secret_seals = BTreeMap::new();
+        for (seal, info) in self.stash.secret_seal_records()? {
+            diff.add(&mut secret_seals, BackupItem::SecretSeal(seal), seal, &info);
+        }
+
+        // Incremental backups can't remove data restored from the base backups
+        if let Some(base) = base {
+            if base.digests.keys().any(|item| !diff.present.contains(item)) {
+                return Err(StashDataError::BackupRemoval.into());
+            }
+        }
+
+        let overflow = |_: confinement::Error| StashDataError::BackupOverflow;
+        Ok(Backup {
+            version: ContainerVer::V2,
+            base: base.map(|base| base.backup_id),
+            types,
+            libs: Confined::try_from(libs).map_err(overflow)?,
+            schemata: Confined::try_from(schemata).map_err(overflow)?,
+            ifaces: Confined::try_from(ifaces).map_err(overflow)?,
+            iimpls: Confined::try_from(iimpls).map_err(overflow)?,
+            supplements: Confined::try_from(supplements).map_err(overflow)?,
+            geneses: Confined::try_from(geneses).map_err(overflow)?,
+            extensions: Confined::try_from(extensions).map_err(overflow)?,
+            bundles: Confined::try_from(bundles).map_err(overflow)?,
+            witnesses: Confined::try_from(witnesses).map_err(overflow)?,
+            attachments: Confined::try_from(attachments).map_err(overflow)?,
+            signatures: Confined::try_from(signatures).map_err(overflow)?,
+            secret_seals: Confined::try_from(secret_seals).map_err(overflow)?,
+        })
+    }
+
+    /// Restores data from a chain of backups, which starts with a full backup
+    /// followed by incremental backups each made on top of the previous one,
+    /// and rebuilds contract state and index in the same transaction.
+    ///
+    /// The restored data are merged with the data already present in the
+    /// stock. Returns manifest of the backup chain, which can be used to make
+    /// further incremental backups.
+    pub fn restore<R: ResolveHeight>(
+        &mut self,
+        backups: impl IntoIterator<Item = Backup>,
+        resolver: &mut R,
+    ) -> Result<BackupManifest, StockError<S, H, P, BackupError>> {
+        let backups = backups.into_iter().collect::<Vec<_>>();
+        let manifest = BackupManifest::with(&backups)?;
+        let mutation = self.audit_mutation(|| {
+            Ok(StockMutation::BackupRestored {
+                backup_ids: Confined::try_from_iter(backups.iter().map(Backup::backup_id))?,
+            })
+        })?;
+        let mut witness_ids = self.stash.witness_ids()?.collect::<BTreeSet<_>>();
+        for backup in &backups {
+            witness_ids.extend(backup.witnesses.keys().copied());
+        }
+        let witnesses = self.resolve_witness_ids(witness_ids, resolver)?;
+        self.transaction::<_, BackupError>(|stock| {
+            for backup in backups {
+                stock.stash.consume_backup(backup)?;
+            }
+            stock.reindex()?;
+            stock.replay_state(&witnesses)?;
+            Ok(())
+        })?;
+        self.log_mutation(AuditValidity::NotApplicable, mutation)?;
+        Ok(manifest)
+    }
+
+    /// Adds data from the backup to the stash and puts the provided histories
+    /// into the contract state, re-creating the index in the same transaction.
+    ///
+    /// Unlike [`Self::restore`], contract state is not re-computed, so the
+    /// histories must match the data in the stash.
+    pub(super) fn load(
+        &mut self,
+        backup: Backup,
+        histories: BTreeMap<ContractId, ContractHistory>,
+    ) -> Result<(), StockError<S, H, P>> {
+        self.transaction::<_, Infallible>(|stock| {
+            stock.stash.consume_backup(backup)?;
+            for (contract_id, history) in histories {
+                stock
+                    .state
+                    .create_or_update_state::<DumbResolver>(contract_id, |_| Ok(history))?;
+            }
+            stock.reindex()?;
+            Ok(())
+        })
+    }
+
+    /// Drops contract state and index data and re-computes them from the
+    /// stash in a single transaction.
+    ///
+    /// Useful for recovering from loss or corruption of the state or index
+    /// storage. Returns bundles which were skipped since they are not anchored
+    /// by any witness known to the stash (see [`Self::rebuild_index`]).
+    pub fn rebuild<R: ResolveHeight>(
+        &mut self,
+        resolver: &mut R,
+    ) -> Result<BTreeMap<ContractId, BTreeSet<BundleId>>, StockError<S, H, P>> {
+        let witnesses = self.resolve_witnesses(resolver)?;
+        let mutation = self.audit_mutation(|| {
+            Ok(StockMutation::StockRebuilt {
+                index: true,
+                state: true,
+            })
+        })?;
+        let unanchored = self.transaction(|stock| {
+            let unanchored = stock.reindex()?;
+            stock.replay_state(&witnesses)?;
+            Ok(unanchored)
+        })?;
+        self.log_mutation(AuditValidity::NotApplicable, mutation)?;
+        Ok(unanchored)
+    }
+
+    /// Drops the index and re-creates it in a single transaction by replaying
+    /// all geneses, extensions and bundles known to the stash.
+    ///
+    /// Bundles which are not anchored by any witness known to the stash can't
+    /// be indexed; they are skipped and returned grouped by contract.
+    pub fn rebuild_index(
+        &mut self,
+    ) -> Result<BTreeMap<ContractId, BTreeSet<BundleId>>, StockError<S, H, P>> {
+        let mutation = self.audit_mutation(|| {
+            Ok(StockMutation::StockRebuilt {
+                index: true,
+                state: false,
+            })
+        })?;
+        let unanchored = self.transaction(|stock| stock.reindex())?;
+        self.log_mutation(AuditValidity::NotApplicable, mutation)?;
+        Ok(unanchored)
+    }
+
+    /// Re-creates the index without starting a transaction, returning bundles
+    /// which are not anchored by any known witness.
+    fn reindex(&mut self) -> Result<BTreeMap<ContractId, BTreeSet<BundleId>>, StockError<S, H, P>> {
+        let (bundles, unanchored) = self.stash_bundles()?;
+        let extensions = self.stash_extensions()?;
+        self.index.clear()?;
+
+        for contract_id in self.stash.contract_ids()?.collect::<Vec<_>>() {
+            let genesis = self.stash.genesis(contract_id)?;
+            self.index.index_contract(contract_id, genesis)?;
+            for opid in extensions.get(&contract_id).into_iter().flatten() {
+                let extension = self.stash.extension(*opid)?;
+                self.index.index_extension(contract_id, extension)?;
+            }
+            for (bundle_id, witness_id) in bundles.get(&contract_id).into_iter().flatten() {
+                let bundle = self.stash.bundle(*bundle_id)?;
+                self.index.index_bundle(contract_id, bundle, *witness_id)?;
+            }
+        }
+        Ok(unanchored)
+    }
+
+    /// Drops the state of all contracts and re-computes it by replaying all
+    /// geneses, extensions and bundles known to the stash, using the resolver
+    /// to order the operations according to their witnesses.
+    pub fn rebuild_state<R: ResolveHeight>(
+        &mut self,
+        resolver: &mut R,
+    ) -> Result<(), StockError<S, H, P>> {
+        self.replay_witnesses(resolver, |_| {
+            Ok(StockMutation::StockRebuilt {
+                index: false,
+                state: true,
+            })
+        })?;
+        Ok(())
+    }
+
+    /// Re-resolves all witnesses known to the stash and updates state of all
+    /// contracts according to their new status.
+    ///
+    /// Must be called after blockchain re-orgs or transaction replacements.
+    /// Operations from witnesses which were archived, and all operations
+    /// spending their outputs, are removed from the contract state (they are
+    /// still kept in the stash and may be restored with the next update if the
+    /// witness gets mined again).
+    ///
+    /// Returns status of each known witness.
+    pub fn update_witnesses<R: ResolveHeight>(
+        &mut self,
+        resolver: &mut R,
+    ) -> Result<BTreeMap<XWitnessId, WitnessStatus>, StockError<S, H, P>> {
+        self.replay_witnesses(resolver, |witnesses| {
+            Ok(StockMutation::WitnessesUpdated {
+                witness_ids: Confined::try_from_iter(witnesses.keys().copied())?,
+            })
+        })
+    }
+
+    /// Resolves all witnesses known to the stash and replays the state of all
+    /// contracts with them in a single transaction, logging the mutation
+    /// constructed from the witness statuses.
+    fn replay_witnesses<R: ResolveHeight>(
+        &mut self,
+        resolver: &mut R,
+        mutation: impl FnOnce(
+            &BTreeMap<XWitnessId, WitnessStatus>,
+        ) -> Result<StockMutation, confinement::Error>,
+    ) -> Result<BTreeMap<XWitnessId, WitnessStatus>, StockError<S, H, P>> {
+        let witnesses = self.resolve_witnesses(resolver)?;
+        let mutation = self.audit_mutation(|| mutation(&witnesses))?;
+        self.transaction(|stock| stock.replay_state(&witnesses))?;
+        self.log_mutation(AuditValidity::NotApplicable, mutation)?;
+        Ok(witnesses)
+    }
+
+    fn resolve_witnesses<R: ResolveHeight>(
+        &self,
+        resolver: &mut R,
+    ) -> Result<BTreeMap<XWitnessId, WitnessStatus>, StockError<S, H, P>> {
+        self.resolve_witness_ids(self.stash.witness_ids()?, resolver)
+    }
+
+    fn resolve_witness_ids<R: ResolveHeight>(
+        &self,
+        witness_ids: impl IntoIterator<Item = XWitnessId>,
+        resolver: &mut R,
+    ) -> Result<BTreeMap<XWitnessId, WitnessStatus>, StockError<S, H, P>> {
+        let mut witnesses = BTreeMap::new();
+        for witness_id in witness_ids {
+            let status = resolver
+                .resolve_witness(witness_id)
+                .map_err(|e| StockError::Resolver(e.to_string()))?;
+            witnesses.insert(witness_id, status);
+        }
+        Ok(witnesses)
+    }
+
+    /// Replaces state of all contracts with the one computed from the stash
+    /// data using the provided witness statuses.
+    fn replay_state(
+        &mut self,
+        witnesses: &BTreeMap<XWitnessId, WitnessStatus>,
+    ) -> Result<(), StockError<S, H, P>> {
+        let (bundles, _) = self.stash_bundles()?;
+        let extensions = self.stash_extensions()?;
+
+        let mut histories = BTreeMap::new();
+        for contract_id in self.stash.contract_ids()?.collect::<Vec<_>>() {
+            let history = self.replay_history(
+                contract_id,
+                bundles.get(&contract_id).cloned().unwrap_or_default(),
+                extensions.get(&contract_id).cloned().unwrap_or_default(),
+                witnesses,
+            )?;
+            histories.insert(contract_id, history);
+        }
+
+        self.state.clear_state().map_err(StockError::StateWrite)?;
+        for (contract_id, history) in histories {
+            self.state
+                .create_or_update_state::<DumbResolver>(contract_id, |_| Ok(history))?;
+        }
+        Ok(())
+    }
+
+    /// Verifies the attached audit log: checks its hash chain and replays it
+    /// against the stash, ensuring that the data added by the logged changes
+    /// are present in the stash, and the removed contracts and secret seals
+    /// are absent.
+    ///
+    /// Succeeds if no audit log is attached.
+    pub fn verify_audit_log(&self) -> Result<(), StockError<S, H, P, AuditLogError>> {
+        let Some(log) = &self.audit_log else {
+            return Ok(());
+        };
+        log.verify_chain()?;
+        let replay = log.replay();
+
+        for iface_id in replay.ifaces {
+            if !is_present(self.stash.iface(iface_id))? {
+                return Err(AuditLogError::IfaceAbsent(iface_id).into());
+            }
+        }
+        for schema_id in replay.schemata {
+            if !is_present(self.stash.schema(schema_id))? {
+                return Err(AuditLogError::SchemaAbsent(schema_id).into());
+            }
+        }
+        for (contract_id, present) in replay.contracts {
+            match (present, is_present(self.stash.genesis(contract_id))?) {
+                (true, false) => return Err(AuditLogError::ContractAbsent(contract_id).into()),
+                (false, true) => return Err(AuditLogError::ContractPresent(contract_id).into()),
+                _ => {}
+            }
+        }
+        for witness_id in replay.witnesses {
+            if !is_present(self.stash.witness(witness_id))? {
+                return Err(AuditLogError::WitnessAbsent(witness_id).into());
+            }
+        }
+        for bundle_id in replay.bundles.into_keys() {
+            if !is_present(self.stash.bundle(bundle_id))? {
+                return Err(AuditLogError::BundleAbsent(bundle_id).into());
+            }
+        }
+        let known = self
+            .stash
+            .secret_seals()?
+            .map(|seal| seal.conceal())
+            .collect::<BTreeSet<_>>();
+        for (seal, present) in replay.seals {
+            match (present, known.contains(&seal)) {
+                (true, false) => return Err(AuditLogError::SealAbsent(seal).into()),
+                (false, true) => return Err(AuditLogError::SealPresent(seal).into()),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks consistency of the stash, contract state and index data and
+    /// reports all detected problems.
     ///
-    /// Must be called before the consignment is created, when witness
-    /// transaction is not yet mined.
-    pub fn consume_fascia(
-        &mut self,
-        fascia: Fascia,
-    ) -> Result<(), StockError<S, H, P, FasciaError>> {
-        let witness_id = fascia.witness_id;
+    /// Unlike other stock operations, which fail on the first inconsistency
+    /// found, the audit collects all issues into a report. The method returns
+    /// an error only if some of the providers are not able to read the data.
+    pub fn audit(&self) -> Result<AuditReport, StockError<S, H, P>> {
+        let mut report = AuditReport::default();
+
+        // Schemata and interface implementations
+        for schema_ifaces in self.stash.schemata()? {
+            let schema_id = schema_ifaces.schema.schema_id();
+            for lib_id in schema_ifaces.schema.libs() {
+                match self.stash.lib(lib_id) {
+                    Ok(_) => {}
+                    Err(StashError::Inconsistency(_)) => {
+                        report.push(AuditIssue::LibAbsent { schema_id, lib_id })
+                    }
+                    Err(err) => return Err(err.into()),
+                }
+            }
+            for (iface_id, iimpl) in &schema_ifaces.iimpls {
+                if iimpl.schema_id != schema_id {
+                    report.push(AuditIssue::IimplSchemaMismatch {
+                        schema_id,
+                        iface_id: *iface_id,
+                        iimpl_schema_id: iimpl.schema_id,
+                    });
+                }
+                match self.stash.iface(*iface_id) {
+                    Ok(_) => {}
+                    Err(StashError::Inconsistency(_)) => {
+                        report.push(AuditIssue::IimplIfaceAbsent {
+                            schema_id,
+                            iface_id: *iface_id,
+                        })
+                    }
+                    Err(err) => return Err(err.into()),
+                }
+            }
+        }
+
+        // Contracts
+        let contract_ids = self.stash.contract_ids()?.collect::<BTreeSet<_>>();
+        for contract_id in &contract_ids {
+            let contract_id = *contract_id;
+            let schema_id = self.stash.genesis(contract_id)?.schema_id;
+            match self.stash.schema(schema_id) {
+                Ok(_) => {}
+                Err(StashError::Inconsistency(_)) => {
+                    report.push(AuditIssue::ContractSchemaAbsent {
+                        contract_id,
+                        schema_id,
+                    })
+                }
+                Err(err) => return Err(err.into()),
+            }
+            if self
+                .state
+                .contract_state(contract_id)
+                .map_err(StockError::StateRead)?
+                .is_none()
+            {
+                report.push(AuditIssue::ContractWithoutState(contract_id));
+            }
+            match self.index.public_opouts(contract_id) {
+                Ok(_) => {}
+                Err(IndexError::Inconsistency(IndexInconsistency::ContractAbsent(_))) => {
+                    report.push(AuditIssue::ContractNotIndexed(contract_id))
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+        for contract_id in self.stash.suppl_contract_ids()? {
+            if !contract_ids.contains(&contract_id) {
+                report.push(AuditIssue::SupplUnknownContract(contract_id));
+            }
+        }
+
+        // Bundles and witnesses
+        let mut anchored = BTreeSet::new();
+        let witness_ids = self.stash.witness_ids()?.collect::<BTreeSet<_>>();
+        for witness_id in &witness_ids {
+            let witness = self.stash.witness(*witness_id)?;
+            anchored.extend(witness.anchors.known_bundle_ids());
+        }
+        let bundle_ids = self.stash.bundle_ids()?.collect::<BTreeSet<_>>();
+        for bundle_id in &bundle_ids {
+            if !anchored.contains(bundle_id) {
+                report.push(AuditIssue::BundleWithoutWitness(*bundle_id));
+            }
+        }
+
+        // Index
+        for (opid, bundle_id) in self.index.operations()? {
+            if !bundle_ids.contains(&bundle_id) {
+                report.push(AuditIssue::DanglingOperation { opid, bundle_id });
+            }
+        }
+        for bundle_id in self.index.bundle_ids()? {
+            if !bundle_ids.contains(&bundle_id) {
+                report.push(AuditIssue::DanglingBundle(bundle_id));
+                continue;
+            }
+            let (witness_id, contract_id) = match self.index.bundle_info(bundle_id) {
+                Ok(info) => info,
+                Err(IndexError::Inconsistency(_)) => {
+                    report.push(AuditIssue::IncompleteBundleInfo(bundle_id));
+                    continue;
+                }
+                Err(err) => return Err(err.into()),
+            };
+            if !witness_ids.contains(&witness_id) {
+                report.push(AuditIssue::DanglingWitness {
+                    bundle_id,
+                    witness_id,
+                });
+            }
+            if !contract_ids.contains(&contract_id) {
+                report.push(AuditIssue::DanglingContract {
+                    bundle_id,
+                    contract_id,
+                });
+            }
+        }
+
+        // Secret seals
+        for seal in self.stash.secret_seals()? {
+            if self.index.opouts_by_terminals([seal.conceal()])?.is_empty() {
+                report.push(AuditIssue::OrphanSecretSeal(seal));
+            }
+        }
+
+        Ok(report)
+    }
 
-        self.stash
-            .consume_witness(SealWitness::new(fascia.witness_id, fascia.anchor.clone()))?;
+    /// Collects witnesses anchoring each of the bundles known to the stash.
+    fn bundle_witnesses(&self) -> Result<BTreeMap<BundleId, XWitnessId>, StockError<S, H, P>> {
+        let mut bundle_witness = BTreeMap::new();
+        for witness_id in self.stash.witness_ids()? {
+            let witness = self.stash.witness(witness_id)?;
+            for bundle_id in witness.anchors.known_bundle_ids() {
+                bundle_witness.insert(bundle_id, witness_id);
+            }
+        }
+        Ok(bundle_witness)
+    }
 
-        for (contract_id, bundle) in fascia.into_bundles() {
-            let ids1 = bundle
+    /// Collects bundles known to the stash per contract, together with their
+    /// witnesses. Bundles which are not anchored by any known witness are
+    /// returned separately.
+    #[allow(clippy::type_complexity)]
+    fn stash_bundles(
+        &self,
+    ) -> Result<
+        (
+            BTreeMap<ContractId, BTreeMap<BundleId, XWitnessId>>,
+            BTreeMap<ContractId, BTreeSet<BundleId>>,
+        ),
+        StockError<S, H, P>,
+    > {
+        let bundle_witness = self.bundle_witnesses()?;
+
+        let mut contracts = BTreeMap::<ContractId, BTreeMap<BundleId, XWitnessId>>::new();
+        let mut unanchored = BTreeMap::<ContractId, BTreeSet<BundleId>>::new();
+        for bundle_id in self.stash.bundle_ids()? {
+            let bundle = self.stash.bundle(bundle_id)?;
+            // Bundles without known transitions do not affect contract state and
+            // can't be indexed
+            let Some(contract_id) = bundle
                 .known_transitions
-                .keys()
-                .copied()
-                .collect::<BTreeSet<_>>();
-            let ids2 = bundle.input_map.values().copied().collect::<BTreeSet<_>>();
-            if !ids1.is_subset(&ids2) {
-                return Err(FasciaError::InvalidBundle(contract_id, bundle.bundle_id()).into());
+                .values()
+                .next()
+                .map(|transition| transition.contract_id)
+            else {
+                continue;
+            };
+            match bundle_witness.get(&bundle_id) {
+                Some(witness_id) => {
+                    contracts
+                        .entry(contract_id)
+                        .or_default()
+                        .insert(bundle_id, *witness_id);
+                }
+                None => {
+                    unanchored.entry(contract_id).or_default().insert(bundle_id);
+                }
             }
+        }
+        Ok((contracts, unanchored))
+    }
 
-            self.index.index_bundle(contract_id, &bundle, witness_id)?;
+    /// Collects ids of extensions known to the stash per contract.
+    fn stash_extensions(&self) -> Result<BTreeMap<ContractId, Vec<OpId>>, StockError<S, H, P>> {
+        let mut contracts = BTreeMap::<ContractId, Vec<OpId>>::new();
+        for opid in self.stash.extension_ids()? {
+            let extension = self.stash.extension(opid)?;
+            contracts
+                .entry(extension.contract_id)
+                .or_default()
+                .push(opid);
+        }
+        Ok(contracts)
+    }
 
-            self.state
-                .update_state::<DumbResolver>(contract_id, |history| {
-                    for transition in bundle.known_transitions.values() {
-                        let witness_anchor = WitnessAnchor::from_mempool(witness_id);
-                        history.add_transition(transition, witness_anchor);
+    /// Re-computes contract history from genesis, the provided bundles and
+    /// extensions.
+    ///
+    /// Bundles are applied in the order given by [`replay_order`], so bundles
+    /// with archived witnesses and bundles spending their outputs are skipped.
+    fn replay_history(
+        &self,
+        contract_id: ContractId,
+        bundles: BTreeMap<BundleId, XWitnessId>,
+        extensions: Vec<OpId>,
+        witnesses: &BTreeMap<XWitnessId, WitnessStatus>,
+    ) -> Result<ContractHistory, StockError<S, H, P>> {
+        let genesis = self.stash.genesis(contract_id)?;
+        let mut history = ContractHistory::with(genesis.schema_id, contract_id, genesis);
+
+        let mut known = Vec::with_capacity(bundles.len());
+        let mut replayed = Vec::with_capacity(bundles.len());
+        for (bundle_id, witness_id) in bundles {
+            let bundle = self.stash.bundle(bundle_id)?;
+            let witness_anchor = match witnesses
+                .get(&witness_id)
+                .ok_or(StashInconsistency::WitnessAbsent(witness_id))?
+            {
+                WitnessStatus::Resolved(witness_anchor) => Some(*witness_anchor),
+                WitnessStatus::Archived => None,
+            };
+            let spent = bundle
+                .known_transitions
+                .values()
+                .flat_map(|transition| &transition.inputs)
+                .map(|input| input.prev_out.op)
+                .collect();
+            replayed.push(ReplayedBundle {
+                witness_anchor,
+                opids: bundle.known_transitions.keys().copied().collect(),
+                spent,
+            });
+            known.push(bundle);
+        }
+
+        let extension_ids = extensions.iter().copied().collect::<BTreeSet<_>>();
+        let mut ordered_extensions = BTreeMap::<OpId, WitnessAnchor>::new();
+        for (pos, witness_anchor) in replay_order(&replayed) {
+            for transition in known[pos].known_transitions.values() {
+                history.add_transition(transition, witness_anchor);
+                for input in &transition.inputs {
+                    let id = input.prev_out.op;
+                    if !extension_ids.contains(&id) {
+                        continue;
                     }
-                    Ok(())
-                })?;
+                    ordered_extensions
+                        .entry(id)
+                        .and_modify(|ord| {
+                            if *ord > witness_anchor {
+                                *ord = witness_anchor
+                            }
+                        })
+                        .or_insert(witness_anchor);
+                }
+            }
+        }
 
-            self.stash.consume_bundle(bundle)?;
+        for opid in extensions {
+            if let Some(witness_anchor) = ordered_extensions.get(&opid) {
+                history.add_extension(self.stash.extension(opid)?, *witness_anchor);
+            }
         }
-        Ok(())
+
+        Ok(history)
     }
 
     fn transition(&self, opid: OpId) -> Result<&Transition, StockError<S, H, P, ConsignError>> {
@@ -1057,10 +3048,887 @@ impl<S: StashProvider, H: StateProvider, P: IndexProvider> Stock<S, H, P> {
         })
     }
 
+    /// Stores secret seal which doesn't expire and is not linked to an
+    /// invoice.
     pub fn store_secret_seal(
         &mut self,
         seal: XChain<GraphSeal>,
     ) -> Result<bool, StockError<S, H, P>> {
-        Ok(self.stash.store_secret_seal(seal)?)
+        let info = SecretSealInfo::new(Utc::now().timestamp());
+        self.store_secret_seal_with(seal, info)
+    }
+
+    /// Stores secret seal together with information about its expiry and the
+    /// invoice it was given out in.
+    ///
+    /// Returns `false` and keeps the existing information if the seal is
+    /// already known.
+    pub fn store_secret_seal_with(
+        &mut self,
+        seal: XChain<GraphSeal>,
+        info: SecretSealInfo,
+    ) -> Result<bool, StockError<S, H, P>> {
+        self.store_seal(seal, info)
+    }
+
+    fn store_seal<E: Error>(
+        &mut self,
+        seal: XChain<GraphSeal>,
+        info: SecretSealInfo,
+    ) -> Result<bool, StockError<S, H, P, E>> {
+        let mutation = self.seals_mutation(&bset![seal])?;
+        let stored = self.stash.store_secret_seal(seal, info)?;
+        if stored {
+            self.log_mutation(AuditValidity::NotApplicable, mutation)?;
+        }
+        Ok(stored)
+    }
+
+    /// Prepares audit log record of the secret seals added to the stash.
+    fn seals_mutation<E: Error>(
+        &self,
+        seals: &BTreeSet<XChain<GraphSeal>>,
+    ) -> Result<Option<StockMutation>, StockError<S, H, P, E>> {
+        if seals.is_empty() {
+            return Ok(None);
+        }
+        self.audit_mutation(|| {
+            Ok(StockMutation::SecretSealsStored {
+                seals: Confined::try_from_iter(seals.iter().map(|seal| seal.conceal()))?,
+            })
+        })
+    }
+
+    /// Detects whether some state was assigned to the secret seal by any of
+    /// the accepted transfers.
+    fn is_seal_received(&self, seal: XChain<GraphSeal>) -> Result<bool, StockError<S, H, P>> {
+        Ok(!self.index.opouts_by_terminals([seal.conceal()])?.is_empty())
+    }
+
+    fn filter_secret_seals(
+        &self,
+        f: impl Fn(bool, &SecretSealInfo) -> bool,
+    ) -> Result<BTreeMap<XChain<GraphSeal>, SecretSealInfo>, StockError<S, H, P>> {
+        let mut seals = BTreeMap::new();
+        for (seal, info) in self.stash.secret_seal_records()? {
+            if f(self.is_seal_received(seal)?, &info) {
+                seals.insert(seal, info);
+            }
+        }
+        Ok(seals)
+    }
+
+    /// Returns secret seals which haven't received any state yet and are not
+    /// expired.
+    pub fn pending_seals(
+        &self,
+    ) -> Result<BTreeMap<XChain<GraphSeal>, SecretSealInfo>, StockError<S, H, P>> {
+        let now = Utc::now().timestamp();
+        self.filter_secret_seals(|received, info| !received && !info.is_expired(now))
+    }
+
+    /// Returns secret seals to which some state was assigned by the accepted
+    /// transfers.
+    pub fn received_seals(
+        &self,
+    ) -> Result<BTreeMap<XChain<GraphSeal>, SecretSealInfo>, StockError<S, H, P>> {
+        self.filter_secret_seals(|received, _| received)
+    }
+
+    /// Returns secret seals which have expired without receiving any state.
+    pub fn expired_seals(
+        &self,
+    ) -> Result<BTreeMap<XChain<GraphSeal>, SecretSealInfo>, StockError<S, H, P>> {
+        let now = Utc::now().timestamp();
+        self.filter_secret_seals(|received, info| !received && info.is_expired(now))
+    }
+
+    /// Removes secret seals which have expired without receiving any state,
+    /// returning the removed seals.
+    ///
+    /// Transfers assigning state to the removed seals are still accepted, but
+    /// the seals are not revealed by them anymore, and the assigned state is
+    /// not owned by the wallet. If such a transfer is expected, the seal must
+    /// be stored once again before accepting it.
+    pub fn prune_secret_seals(
+        &mut self,
+    ) -> Result<BTreeSet<XChain<GraphSeal>>, StockError<S, H, P>> {
+        let expired = self.expired_seals()?;
+        if expired.is_empty() {
+            return Ok(none!());
+        }
+        let mutation = self.audit_mutation(|| {
+            Ok(StockMutation::SecretSealsPruned {
+                seals: Confined::try_from_iter(expired.keys().map(|seal| seal.conceal()))?,
+            })
+        })?;
+        let pruned = self.transaction(|stock| {
+            for seal in expired.keys() {
+                stock.stash.remove_secret_seal(*seal)?;
+            }
+            Ok(expired.into_keys().collect::<BTreeSet<_>>())
+        })?;
+        self.log_mutation(AuditValidity::NotApplicable, mutation)?;
+        Ok(pruned)
+    }
+
+    /// Derives a new seal from the wallet seed for receiving state of the
+    /// given type, stores it and returns its concealed form, which should be
+    /// used as an invoice beneficiary.
+    pub fn store_seeded_seal(
+        &mut self,
+        blinder: &SeedBlinder,
+        contract_id: ContractId,
+        assignment_type: AssignmentType,
+        method: CloseMethod,
+        outpoint: impl Into<XOutpoint>,
+        info: SecretSealInfo,
+    ) -> Result<XChain<SecretSeal>, StockError<S, H, P, BlindingError>> {
+        let seal = blinder
+            .next_seal(contract_id, assignment_type, method, outpoint.into())
+            .map_err(StockError::InvalidInput)?;
+        self.store_seal(seal, info)?;
+        Ok(seal.conceal())
+    }
+
+    /// Derives a new seal from the wallet seed for receiving state of the
+    /// given type and stores it (see [`Self::store_seeded_seal`]), returning
+    /// builder of an invoice for the contract paying to the concealed seal.
+    ///
+    /// If `info` specifies seal expiry, it is used as the invoice expiry.
+    #[allow(clippy::too_many_arguments)]
+    pub fn seeded_invoice(
+        &mut self,
+        blinder: &SeedBlinder,
+        contract_id: ContractId,
+        assignment_type: AssignmentType,
+        method: CloseMethod,
+        outpoint: Outpoint,
+        chain_net: ChainNet,
+        info: SecretSealInfo,
+    ) -> Result<RgbInvoiceBuilder, StockError<S, H, P, BlindingError>> {
+        let expiry = info.expiry;
+        let blinding = blinder
+            .next_seal_blinding(contract_id, assignment_type)
+            .map_err(StockError::InvalidInput)?;
+        let seal = GraphSeal::with_blinding(method, outpoint.txid, outpoint.vout, blinding);
+        self.store_seal(XChain::with(chain_net.layer1(), seal), info)?;
+        let beneficiary = XChainNet::with(chain_net, Beneficiary::BlindedSeal(seal.conceal()));
+        let builder = RgbInvoiceBuilder::with(contract_id, beneficiary);
+        Ok(match expiry {
+            Some(expiry) => builder.set_expiry_timestamp(expiry),
+            None => builder,
+        })
+    }
+
+    /// Re-derives seals from the wallet seed for the provided candidate
+    /// outpoints, and finds the ones which were used as concealed seals in the
+    /// known consignments.
+    ///
+    /// For each contract and each of its assignment types the derivation
+    /// counter is increased until `gap_limit` consecutive counter values have
+    /// no matches. Counters consumed by Pedersen blinding factors of the same
+    /// assignment type contribute to the gap, so the limit must account for
+    /// them.
+    ///
+    /// Found seals which are not known yet are added to the stash secret seals,
+    /// keeping information about the known ones, and the blinder counters are
+    /// advanced past them. The state assigned to the recovered seals
+    /// becomes known after the consignments are accepted once again.
+    pub fn recover_seals(
+        &mut self,
+        blinder: &SeedBlinder,
+        outpoints: impl IntoIterator<Item = impl Into<XOutpoint>>,
+        gap_limit: u32,
+    ) -> Result<BTreeSet<XChain<GraphSeal>>, StockError<S, H, P>> {
+        let outpoints = outpoints
+            .into_iter()
+            .map(|o| o.into())
+            .collect::<BTreeSet<XOutpoint>>();
+        let methods = [CloseMethod::OpretFirst, CloseMethod::TapretFirst];
+
+        let mut found = BTreeSet::new();
+        let contract_ids = self.stash.contract_ids()?.collect::<BTreeSet<_>>();
+        for contract_id in contract_ids {
+            let schema_id = self.stash.genesis(contract_id)?.schema_id;
+            let schema = &self.stash.schema(schema_id)?.schema;
+            for assignment_type in schema.owned_types.keys().copied() {
+                let mut counter = 0u32;
+                let mut gap = 0u32;
+                while gap < gap_limit {
+                    let mut matched = false;
+                    for outpoint in &outpoints {
+                        for method in methods {
+                            let seal = blinder.seal(
+                                contract_id,
+                                assignment_type,
+                                counter,
+                                method,
+                                *outpoint,
+                            );
+                            if !self.index.opouts_by_terminals([seal.conceal()])?.is_empty() {
+                                found.insert(seal);
+                                matched = true;
+                            }
+                        }
+                    }
+                    // No seals can be derived past the last counter value
+                    let Some(next) = counter.checked_add(1) else {
+                        if matched {
+                            blinder.advance(contract_id, assignment_type, u32::MAX);
+                        }
+                        break;
+                    };
+                    counter = next;
+                    if matched {
+                        blinder.advance(contract_id, assignment_type, counter);
+                        gap = 0;
+                    } else {
+                        gap += 1;
+                    }
+                }
+            }
+        }
+
+        let known = self.stash.secret_seals()?.collect::<BTreeSet<_>>();
+        let unknown = found.difference(&known).copied().collect::<BTreeSet<_>>();
+        let mutation = self.seals_mutation(&unknown)?;
+        let info = SecretSealInfo::new(Utc::now().timestamp());
+        self.transaction(|stock| {
+            for seal in unknown {
+                stock.stash.store_secret_seal(seal, info.clone())?;
+            }
+            Ok(())
+        })?;
+        self.log_mutation(AuditValidity::NotApplicable, mutation)?;
+        Ok(found)
+    }
+}
+
+/// Bundle to be replayed into contract history.
+struct ReplayedBundle<A> {
+    /// Anchor of the bundle witness, or `None` if the witness is archived.
+    witness_anchor: Option<A>,
+    /// Ids of the bundle transitions.
+    opids: BTreeSet<OpId>,
+    /// Ids of the operations spent by the bundle transitions.
+    spent: BTreeSet<OpId>,
+}
+
+/// Orders bundles for replaying contract history, returning positions of the
+/// bundles to apply together with their witness anchors.
+///
+/// Bundles are applied in the order of their witnesses, making sure that each
+/// bundle is applied only after all bundles it spends from. Bundles with
+/// archived witnesses and bundles spending their outputs are skipped.
+fn replay_order<A: Ord + Copy>(bundles: &[ReplayedBundle<A>]) -> Vec<(usize, A)> {
+    let mut pending = Vec::with_capacity(bundles.len());
+    let mut unapplied = BTreeSet::new();
+    let mut invalid = BTreeSet::new();
+    for (pos, bundle) in bundles.iter().enumerate() {
+        match bundle.witness_anchor {
+            Some(witness_anchor) => {
+                unapplied.extend(bundle.opids.iter().copied());
+                pending.push((witness_anchor, pos));
+            }
+            None => invalid.extend(bundle.opids.iter().copied()),
+        }
+    }
+    pending.sort_by_key(|(witness_anchor, _)| *witness_anchor);
+
+    // Transitions spending outputs of invalid operations are invalid as well
+    while let Some(i) = pending
+        .iter()
+        .position(|(_, pos)| bundles[*pos].spent.iter().any(|id| invalid.contains(id)))
+    {
+        let (_, pos) = pending.remove(i);
+        for opid in &bundles[pos].opids {
+            unapplied.remove(opid);
+            invalid.insert(*opid);
+        }
+    }
+
+    let mut order = Vec::with_capacity(pending.len());
+    while !pending.is_empty() {
+        let i = pending
+            .iter()
+            .position(|(_, pos)| bundles[*pos].spent.iter().all(|id| !unapplied.contains(id)))
+            .unwrap_or_default();
+        let (witness_anchor, pos) = pending.remove(i);
+        for opid in &bundles[pos].opids {
+            unapplied.remove(opid);
+        }
+        order.push((pos, witness_anchor));
+    }
+    order
+}
+
+/// Checks the invoices paid by a single batch and groups them by contract,
+/// such that each group is paid by a single state transition. Groups and the
+/// invoices within them keep the order of the invoices.
+fn invoice_groups(
+    invoices: &[RgbInvoice],
+    layer1: Layer1,
+    now: i64,
+) -> Result<Vec<(ContractId, Vec<usize>)>, ComposeError> {
+    let mut groups = Vec::<(ContractId, Vec<usize>)>::new();
+    for (no, invoice) in invoices.iter().enumerate() {
+        if let Some(expiry) = invoice.expiry {
+            if expiry < now {
+                return Err(ComposeError::InvoiceExpired);
+            }
+        }
+        if invoice.layer1() != layer1 {
+            return Err(ComposeError::InconsistentLayer1);
+        }
+        let contract_id = invoice.contract.ok_or(ComposeError::NoContract)?;
+        match groups.iter_mut().find(|(id, _)| *id == contract_id) {
+            Some((_, list)) => list.push(no),
+            None => groups.push((contract_id, vec![no])),
+        }
+    }
+    Ok(groups)
+}
+
+/// State paid to a beneficiary of an invoice.
+enum Payment {
+    Amount(Amount),
+    Allocation(Allocation),
+    Rights,
+    Attachment(AttachedState),
+}
+
+/// Takes from the inputs non-fungible state of the given type matching the
+/// invoice requirements, returning the data extracted by the selector.
+fn take_state<T>(
+    inputs: &mut BTreeMap<AssignmentType, Vec<PersistedState>>,
+    assignment_type: AssignmentType,
+    select: impl Fn(&PersistedState) -> Option<T>,
+) -> Result<T, ComposeError> {
+    let list = inputs
+        .get_mut(&assignment_type)
+        .ok_or(ComposeError::InsufficientState)?;
+    let (pos, taken) = list
+        .iter()
+        .enumerate()
+        .find_map(|(pos, state)| select(state).map(|taken| (pos, taken)))
+        .ok_or(ComposeError::InsufficientState)?;
+    list.remove(pos);
+    Ok(taken)
+}
+
+/// Takes from the inputs all allocations of the RGB21 token with the given
+/// type, returning the sum of their fractions.
+fn take_fractions(
+    inputs: &mut BTreeMap<AssignmentType, Vec<PersistedState>>,
+    assignment_type: AssignmentType,
+    token_index: TokenIndex,
+) -> OwnedFraction {
+    let mut owned = OwnedFraction::default();
+    if let Some(list) = inputs.get_mut(&assignment_type) {
+        list.retain(|state| {
+            let PersistedState::Data(value, _) = state else {
+                return true;
+            };
+            match Allocation::from_data_state(value.clone()) {
+                Some(allocation) if allocation.token_index() == token_index => {
+                    owned += allocation.fraction();
+                    false
+                }
+                _ => true,
+            }
+        });
+    }
+    owned
+}
+
+/// Computes the change returned from the inputs after paying the requested
+/// state, which is `None` if the inputs are spent completely.
+fn change<T: Ord + Sub<Output = T>>(inputs: T, requested: T) -> Result<Option<T>, ComposeError> {
+    match inputs.cmp(&requested) {
+        Ordering::Greater => Ok(Some(inputs - requested)),
+        Ordering::Less => Err(ComposeError::InsufficientState),
+        Ordering::Equal => Ok(None),
+    }
+}
+
+/// Separates data which can't be merged from provider failures.
+fn merge_conflict<P: StashProvider, T>(
+    res: Result<T, StashError<P>>,
+) -> Result<Option<String>, StashError<P>> {
+    match res {
+        Ok(_) => Ok(None),
+        Err(StashError::Data(err)) => Ok(Some(err.to_string())),
+        Err(err) => Err(err),
+    }
+}
+
+/// Separates data absent from the stash, which are reported as inconsistency,
+/// from provider failures.
+fn is_present<P: StashProvider, T>(res: Result<T, StashError<P>>) -> Result<bool, StashError<P>> {
+    match res {
+        Ok(_) => Ok(true),
+        Err(StashError::Inconsistency(_)) => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use amplify::{ByteArray, Wrapper};
+    use bp::Txid;
+    use rgb::Genesis;
+
+    use super::*;
+    use crate::containers::Kit;
+    use crate::interface::TickerSuppl;
+    use crate::persistence::FewestInputs;
+    use crate::stl::MediaType;
+
+    type MemStock = Stock<MemStash, MemState, MemIndex>;
+
+    fn beneficiary(chain_net: ChainNet) -> XChainNet<Beneficiary> {
+        let seal = GraphSeal::with_blinding(CloseMethod::TapretFirst, Txid::coinbase(), 1u32, 42);
+        XChainNet::with(chain_net, Beneficiary::BlindedSeal(seal.conceal()))
+    }
+
+    fn compose_many(
+        invoices: &[RgbInvoice],
+    ) -> Result<Batch, StockError<MemStash, MemState, MemIndex, ComposeError>> {
+        MemStock::default().compose_many(
+            invoices,
+            Vec::<XOutputSeal>::new(),
+            CloseMethod::TapretFirst,
+            |_| None,
+            |_, _, _| None,
+        )
+    }
+
+    fn suppl_kit(contract_id: ContractId) -> ValidKit {
+        let suppl = ContractSuppl {
+            contract_id,
+            ticker: TickerSuppl::Absent,
+            media_kit: none!(),
+            global_state: none!(),
+            owned_state: none!(),
+            extensions: none!(),
+        };
+        let kit = Kit {
+            version: none!(),
+            ifaces: none!(),
+            schemata: none!(),
+            iimpls: none!(),
+            supplements: tiny_bset![suppl],
+            types: none!(),
+            scripts: none!(),
+            signatures: none!(),
+        };
+        kit.validate().unwrap()
+    }
+
+    /// Constructs bundle with a single transition of the contract.
+    fn bundle(contract_id: ContractId) -> TransitionBundle {
+        let mut transition: Transition = strict_dumb!();
+        transition.contract_id = contract_id;
+        let mut bundle: TransitionBundle = strict_dumb!();
+        bundle.known_transitions =
+            Confined::from_collection_unsafe(bmap! { transition.id() => transition });
+        bundle
+    }
+
+    #[test]
+    fn compose_many_invoices() {
+        let contract_id = ContractId::from_byte_array([1u8; 32]);
+        let invoice = |chain_net| RgbInvoiceBuilder::with(contract_id, beneficiary(chain_net));
+
+        assert!(matches!(
+            compose_many(&[]),
+            Err(StockError::InvalidInput(ComposeError::NoInvoices))
+        ));
+        assert!(matches!(
+            compose_many(&[
+                invoice(ChainNet::BitcoinRegtest).finish(),
+                invoice(ChainNet::LiquidTestnet).finish(),
+            ]),
+            Err(StockError::InvalidInput(ComposeError::InconsistentLayer1))
+        ));
+        assert!(matches!(
+            compose_many(&[
+                invoice(ChainNet::BitcoinRegtest).finish(),
+                RgbInvoiceBuilder::new(beneficiary(ChainNet::BitcoinRegtest)).finish(),
+            ]),
+            Err(StockError::InvalidInput(ComposeError::NoContract))
+        ));
+        assert!(matches!(
+            compose_many(&[
+                invoice(ChainNet::BitcoinRegtest).finish(),
+                invoice(ChainNet::BitcoinRegtest)
+                    .set_expiry_timestamp(1)
+                    .finish(),
+            ]),
+            Err(StockError::InvalidInput(ComposeError::InvoiceExpired))
+        ));
+    }
+
+    #[test]
+    fn invoice_batch_groups() {
+        let first = ContractId::from_byte_array([1u8; 32]);
+        let second = ContractId::from_byte_array([2u8; 32]);
+        let invoice = |contract_id| {
+            RgbInvoiceBuilder::with(contract_id, beneficiary(ChainNet::BitcoinRegtest))
+                .set_amount_raw(10u64)
+                .set_expiry_timestamp(100)
+                .finish()
+        };
+
+        // Two invoices for the same contract are paid by a single transition
+        let invoices = [invoice(first), invoice(first)];
+        let groups = invoice_groups(&invoices, Layer1::Bitcoin, 100).unwrap();
+        assert_eq!(groups, vec![(first, vec![0, 1])]);
+
+        let invoices = [invoice(second), invoice(first), invoice(second)];
+        assert_eq!(invoice_groups(&invoices, Layer1::Bitcoin, 0).unwrap(), vec![
+            (second, vec![0, 2]),
+            (first, vec![1])
+        ]);
+        assert!(matches!(
+            invoice_groups(&invoices, Layer1::Bitcoin, 101),
+            Err(ComposeError::InvoiceExpired)
+        ));
+        assert!(matches!(
+            invoice_groups(&invoices, Layer1::Liquid, 0),
+            Err(ComposeError::InconsistentLayer1)
+        ));
+        assert!(invoice_groups(&[], Layer1::Bitcoin, 0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn select_outputs_invoice() {
+        let stock = MemStock::default();
+        let contract_id = ContractId::from_byte_array([1u8; 32]);
+        let select = |invoice: RgbInvoiceBuilder| {
+            stock.select_outputs(&invoice.finish(), Vec::<XOutputSeal>::new(), &FewestInputs)
+        };
+
+        assert!(matches!(
+            select(RgbInvoiceBuilder::new(beneficiary(ChainNet::BitcoinRegtest))),
+            Err(StockError::InvalidInput(ComposeError::NoContract))
+        ));
+        assert!(matches!(
+            select(RgbInvoiceBuilder::with(contract_id, beneficiary(ChainNet::BitcoinRegtest))),
+            Err(StockError::InvalidInput(ComposeError::NoIface))
+        ));
+        assert!(matches!(
+            select(
+                RgbInvoiceBuilder::with(contract_id, beneficiary(ChainNet::BitcoinRegtest))
+                    .set_interface("RGB20")
+            ),
+            Err(StockError::StashInconsistency(_))
+        ));
+    }
+
+    #[test]
+    fn take_state_order() {
+        let assignment_type = AssignmentType::from_inner(4000);
+        let attach = AttachedState {
+            id: strict_dumb!(),
+            media_type: MediaType::with("text/plain"),
+        };
+        let mut inputs = bmap! {
+            assignment_type => vec![
+                PersistedState::Attachment(attach.clone(), 1),
+                PersistedState::Void,
+                PersistedState::Attachment(attach.clone(), 2),
+                PersistedState::Void,
+            ]
+        };
+        let void = |state: &PersistedState| matches!(state, PersistedState::Void).then_some(());
+        let attachment = |state: &PersistedState| match state {
+            PersistedState::Attachment(attach, salt) => Some((attach.clone(), *salt)),
+            _ => None,
+        };
+
+        take_state(&mut inputs, assignment_type, void).unwrap();
+        assert_eq!(
+            take_state(&mut inputs, assignment_type, attachment).unwrap(),
+            (attach.clone(), 1)
+        );
+        // The state which is not taken is returned as a change in the original order
+        assert_eq!(inputs[&assignment_type], vec![
+            PersistedState::Attachment(attach, 2),
+            PersistedState::Void
+        ]);
+
+        assert!(matches!(
+            take_state(&mut inputs, AssignmentType::from_inner(4001), void),
+            Err(ComposeError::InsufficientState)
+        ));
+        take_state(&mut inputs, assignment_type, void).unwrap();
+        assert!(matches!(
+            take_state(&mut inputs, assignment_type, void),
+            Err(ComposeError::InsufficientState)
+        ));
+    }
+
+    #[test]
+    fn fraction_change() {
+        let assignment_type = AssignmentType::from_inner(4000);
+        let fraction = |token_index: u32, fraction: u64| {
+            PersistedState::Data(Allocation::with(token_index, fraction).into(), 0)
+        };
+        let mut inputs = bmap! {
+            assignment_type => vec![
+                fraction(1, 3),
+                fraction(2, 5),
+                PersistedState::Void,
+                fraction(1, 4),
+            ]
+        };
+
+        let owned = take_fractions(&mut inputs, assignment_type, TokenIndex::from(1));
+        assert_eq!(owned, OwnedFraction::from(7));
+        // Allocations of other tokens are kept
+        assert_eq!(inputs[&assignment_type], vec![fraction(2, 5), PersistedState::Void]);
+        assert_eq!(change(owned, OwnedFraction::from(7)).unwrap(), None);
+        assert_eq!(change(owned, OwnedFraction::from(2)).unwrap(), Some(OwnedFraction::from(5)));
+        assert!(matches!(
+            change(owned, OwnedFraction::from(8)),
+            Err(ComposeError::InsufficientState)
+        ));
+
+        let owned = take_fractions(&mut inputs, assignment_type, TokenIndex::from(3));
+        assert_eq!(owned, OwnedFraction::default());
+        assert!(matches!(
+            change(owned, OwnedFraction::from(1)),
+            Err(ComposeError::InsufficientState)
+        ));
+        let owned = take_fractions(&mut inputs, AssignmentType::from_inner(4001), 2.into());
+        assert_eq!(owned, OwnedFraction::default());
+        assert_eq!(inputs[&assignment_type].len(), 2);
+    }
+
+    #[test]
+    fn amount_change() {
+        let amount = Amount::from;
+        assert_eq!(change(amount(10u64), amount(10u64)).unwrap(), None);
+        assert_eq!(change(amount(10u64), amount(4u64)).unwrap(), Some(amount(6u64)));
+        assert_eq!(change(amount(10u64), Amount::ZERO).unwrap(), Some(amount(10u64)));
+        assert!(matches!(
+            change(amount(10u64), amount(11u64)),
+            Err(ComposeError::InsufficientState)
+        ));
+    }
+
+    #[test]
+    fn rebuild_index_atomic() {
+        let seal = GraphSeal::with_blinding(CloseMethod::TapretFirst, Txid::coinbase(), 1u32, 42);
+        let mut index = MemIndex::default();
+        index
+            .index_terminal(XChain::Bitcoin(seal.conceal()), strict_dumb!())
+            .unwrap();
+        let mut stock = MemStock::with(MemStash::default(), MemState::default(), index);
+        let terminals = |stock: &MemStock| stock.as_index_provider().debug_terminal_index().len();
+
+        // Index data are restored if a transaction re-creating the index fails
+        let res = stock.transaction(|stock| {
+            stock.reindex()?;
+            assert_eq!(terminals(&*stock), 0);
+            Err::<(), _>(StockError::<_, _, _, Infallible>::Resolver(s!("failure")))
+        });
+        assert!(matches!(res, Err(StockError::Resolver(_))));
+        assert_eq!(terminals(&stock), 1);
+
+        // Terminals not backed by the stash data are dropped
+        assert!(stock.rebuild_index().unwrap().is_empty());
+        assert_eq!(terminals(&stock), 0);
+    }
+
+    #[test]
+    fn rebuild_unanchored() {
+        let contract_id = ContractId::from_byte_array([1u8; 32]);
+        let bundle = bundle(contract_id);
+        let bundle_id = bundle.bundle_id();
+
+        let mut stock = MemStock::default();
+        stock.stash.consume_bundle(bundle).unwrap();
+        // The bundle is not anchored by any witness, so it is skipped instead of
+        // failing the rebuild
+        let unanchored = bmap! { contract_id => bset![bundle_id] };
+        assert_eq!(stock.rebuild_index().unwrap(), unanchored);
+        assert_eq!(stock.rebuild(&mut DumbResolver).unwrap(), unanchored);
+        assert!(stock.stash_bundles().unwrap().0.is_empty());
+    }
+
+    #[test]
+    fn audit_issues() {
+        let stock = MemStock::default();
+        let report = stock.audit().unwrap();
+        assert!(report.issues.is_empty());
+        assert!(report.is_consistent());
+
+        let genesis: Genesis = strict_dumb!();
+        let contract_id = genesis.contract_id();
+        let mut stash = MemStash::default();
+        stash.replace_genesis(genesis.clone()).unwrap();
+        let mut stock = MemStock::with(stash, MemState::default(), MemIndex::default());
+        let seal = XChain::Bitcoin(GraphSeal::with_blinding(
+            CloseMethod::TapretFirst,
+            Txid::coinbase(),
+            1u32,
+            42,
+        ));
+        stock.store_secret_seal(seal).unwrap();
+        let unknown = ContractId::from_byte_array([1u8; 32]);
+        stock.import_kit(suppl_kit(unknown)).unwrap();
+        let bundle = bundle(contract_id);
+        let bundle_id = bundle.bundle_id();
+        stock.stash.consume_bundle(bundle).unwrap();
+
+        let report = stock.audit().unwrap();
+        assert_eq!(report.issues, vec![
+            AuditIssue::ContractSchemaAbsent {
+                contract_id,
+                schema_id: genesis.schema_id
+            },
+            AuditIssue::ContractWithoutState(contract_id),
+            AuditIssue::ContractNotIndexed(contract_id),
+            AuditIssue::SupplUnknownContract(unknown),
+            AuditIssue::BundleWithoutWitness(bundle_id),
+            AuditIssue::OrphanSecretSeal(seal),
+        ]);
+        assert!(!report.is_consistent());
+        assert_eq!(report.errors().count(), 5);
+        assert_eq!(report.warnings().collect::<Vec<_>>(), vec![&AuditIssue::OrphanSecretSeal(
+            seal
+        )]);
+
+        // Rebuilding the index and state leaves only the issues with the stash data
+        stock.rebuild(&mut DumbResolver).unwrap();
+        let report = stock.audit().unwrap();
+        assert_eq!(report.issues, vec![
+            AuditIssue::ContractSchemaAbsent {
+                contract_id,
+                schema_id: genesis.schema_id
+            },
+            AuditIssue::SupplUnknownContract(unknown),
+            AuditIssue::BundleWithoutWitness(bundle_id),
+            AuditIssue::OrphanSecretSeal(seal),
+        ]);
+    }
+
+    #[test]
+    fn prune_secret_seals() {
+        let seal = |blinding| {
+            XChain::Bitcoin(GraphSeal::with_blinding(
+                CloseMethod::TapretFirst,
+                Txid::coinbase(),
+                1u32,
+                blinding,
+            ))
+        };
+        let expired = SecretSealInfo::with(1, Some(2), None);
+        let mut index = MemIndex::default();
+        index
+            .index_terminal(seal(3).conceal(), strict_dumb!())
+            .unwrap();
+        let mut stock = MemStock::with(MemStash::default(), MemState::default(), index);
+        stock.store_secret_seal(seal(1)).unwrap();
+        stock
+            .store_secret_seal_with(seal(2), expired.clone())
+            .unwrap();
+        // Expired seals which have received some state are kept
+        stock
+            .store_secret_seal_with(seal(3), expired.clone())
+            .unwrap();
+        assert_eq!(stock.expired_seals().unwrap(), bmap! { seal(2) => expired });
+
+        assert_eq!(stock.prune_secret_seals().unwrap(), bset![seal(2)]);
+        assert!(stock.prune_secret_seals().unwrap().is_empty());
+        let seals = stock
+            .as_stash_provider()
+            .debug_secret_seals()
+            .keys()
+            .copied()
+            .collect::<BTreeSet<_>>();
+        assert_eq!(seals, bset![seal(1), seal(3)]);
+        assert_eq!(
+            stock
+                .pending_seals()
+                .unwrap()
+                .into_keys()
+                .collect::<Vec<_>>(),
+            vec![seal(1)]
+        );
+        assert_eq!(
+            stock
+                .received_seals()
+                .unwrap()
+                .into_keys()
+                .collect::<Vec<_>>(),
+            vec![seal(3)]
+        );
+    }
+
+    #[test]
+    fn forget_contract() {
+        let genesis: Genesis = strict_dumb!();
+        let contract_id = genesis.contract_id();
+        let mut stash = MemStash::default();
+        stash.replace_genesis(genesis).unwrap();
+        let mut stock = MemStock::with(stash, MemState::default(), MemIndex::default());
+        let other = ContractId::from_byte_array([1u8; 32]);
+        stock.import_kit(suppl_kit(contract_id)).unwrap();
+        stock.import_kit(suppl_kit(other)).unwrap();
+        stock.stash.consume_bundle(bundle(contract_id)).unwrap();
+        stock.rebuild(&mut DumbResolver).unwrap();
+        let known = |stock: &MemStock| {
+            (
+                stock
+                    .as_state_provider()
+                    .debug_history()
+                    .contains_key(&contract_id),
+                stock
+                    .as_index_provider()
+                    .debug_contract_index()
+                    .contains_key(&contract_id),
+            )
+        };
+        assert_eq!(known(&stock), (true, true));
+
+        stock.forget_contract(contract_id).unwrap();
+        let stash = stock.as_stash_provider();
+        assert!(stash.debug_geneses().is_empty());
+        assert!(stash.debug_bundles().is_empty());
+        // Supplements of other contracts are kept
+        assert_eq!(stash.suppl_contract_ids().unwrap().collect::<Vec<_>>(), vec![other]);
+        assert_eq!(known(&stock), (false, false));
+        assert!(matches!(
+            stock.forget_contract(contract_id),
+            Err(StockError::StashInconsistency(_))
+        ));
+    }
+
+    #[test]
+    fn replay_archived() {
+        let op = |no: u8| OpId::from_inner([no; 32].into());
+        let bundle = |witness_anchor, opid, spent: &[OpId]| ReplayedBundle {
+            witness_anchor,
+            opids: bset![opid],
+            spent: spent.iter().copied().collect(),
+        };
+
+        let mut bundles = vec![
+            bundle(Some(2), op(1), &[]),
+            bundle(None, op(2), &[]),
+            bundle(Some(1), op(3), &[op(2)]),
+            bundle(Some(0), op(4), &[op(1)]),
+        ];
+        // Bundles spending from the archived witness are skipped, and bundles
+        // spending from a later witness wait for it
+        assert_eq!(replay_order(&bundles), vec![(0, 2), (3, 0)]);
+
+        // Bundles are restored once the witness is mined again
+        bundles[1].witness_anchor = Some(3);
+        assert_eq!(replay_order(&bundles), vec![(0, 2), (3, 0), (1, 3), (2, 1)]);
     }
 }
@@ -21,8 +21,27 @@
 
 use rgb::{WitnessAnchor, XWitnessId};
 
+/// Status of a witness transaction as reported by a resolver.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum WitnessStatus {
+    /// Witness is known to the network (mined or present in the mempool).
+    Resolved(WitnessAnchor),
+    /// Witness was re-orged out or replaced and will never be mined; the
+    /// state it has created is not valid anymore.
+    Archived,
+}
+
 pub trait ResolveHeight {
     type Error: std::error::Error;
 
     fn resolve_height(&mut self, witness_id: XWitnessId) -> Result<WitnessAnchor, Self::Error>;
+
+    /// Resolves witness status, detecting witnesses which are not valid
+    /// anymore.
+    ///
+    /// Resolvers which are not able to detect replaced transactions may rely
+    /// on the default implementation, which considers all witnesses valid.
+    fn resolve_witness(&mut self, witness_id: XWitnessId) -> Result<WitnessStatus, Self::Error> {
+        self.resolve_height(witness_id).map(WitnessStatus::Resolved)
+    }
 }